@@ -0,0 +1,98 @@
+use std::fmt::Debug;
+use rand::{self, Rng};
+use serde::{Serialize, Deserialize};
+use pid::Pid;
+use msg::Msg;
+use envelope::Envelope;
+use correlation_id::CorrelationId;
+
+/// Exponential backoff with jitter
+///
+/// The delay before attempt `n` (starting at 1) is `initial * multiplier^(n-1)`, capped at `max`.
+/// A random amount of up to `jitter` percent of the delay is then added or subtracted so that
+/// many nodes retrying at the same time don't synchronize.
+///
+/// This is the policy the cluster server uses to reconnect to lost peers. Processes can use it for
+/// their own retry loops via `Retry`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: usize, // ms
+    pub max: usize, // ms
+    pub multiplier: u32,
+    pub jitter: u32 // percent
+}
+
+impl Backoff {
+    pub fn new(initial: usize, max: usize, multiplier: u32, jitter: u32) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            multiplier: multiplier,
+            jitter: jitter
+        }
+    }
+
+    /// Return the delay in ms before attempt number `attempt`
+    pub fn delay(&self, attempt: u32) -> usize {
+        let mut delay = self.initial;
+        for _ in 1..attempt {
+            delay = delay.saturating_mul(self.multiplier as usize);
+            if delay >= self.max {
+                break;
+            }
+        }
+        let delay = delay.min(self.max);
+        let spread = delay * self.jitter.min(100) as usize / 100;
+        if spread == 0 {
+            return delay;
+        }
+        delay - spread + rand::thread_rng().gen_range(0, 2 * spread + 1)
+    }
+}
+
+/// Tracks the attempts of a retry loop in a process, which is driven by rabble timers
+///
+/// When an attempt fails, the process returns the envelope from `retry_after` to start a timer for
+/// the next attempt. It then receives a `Msg::Timeout` with the given correlation id when it's time
+/// to try again. Once an attempt succeeds, `reset` starts the next retry loop from the initial
+/// delay.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    policy: Backoff,
+    attempts: u32
+}
+
+impl Retry {
+    pub fn new(policy: Backoff) -> Retry {
+        Retry {
+            policy: policy,
+            attempts: 0
+        }
+    }
+
+    /// The number of retries scheduled since the last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Return the delay in ms before the next attempt and count it
+    pub fn next_delay(&mut self) -> usize {
+        self.attempts = self.attempts.saturating_add(1);
+        self.policy.delay(self.attempts)
+    }
+
+    /// Return an envelope to the executor that starts a timer for the next attempt
+    pub fn retry_after<'de, T>(&mut self,
+                               executor_pid: Pid,
+                               pid: Pid,
+                               correlation_id: Option<CorrelationId>) -> Envelope<T>
+        where T: Serialize + Deserialize<'de> + Debug + Clone
+    {
+        let delay = self.next_delay();
+        Envelope::new(executor_pid, pid, Msg::StartTimer(delay), correlation_id)
+    }
+}
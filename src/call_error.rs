@@ -0,0 +1,45 @@
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use pid::Pid;
+use msg::Msg;
+use envelope::Envelope;
+
+/// The standard error reply to a request
+///
+/// Replying with `Msg::CallError` rather than an application specific message lets clients on any
+/// node, or written in any language, distinguish routing failures from application errors.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CallError {
+    /// No process or service was running at the destination pid. This is sent by the executor in
+    /// reply to any undeliverable envelope with a correlation id.
+    NoProcess(Pid),
+
+    /// The destination service doesn't accept this kind of message
+    Unsupported(Pid),
+
+    /// The deadline of the request, set with `Envelope::with_ttl`, passed before it was delivered
+    Timeout,
+
+    /// The destination process failed to handle the request
+    HandlerError(String)
+}
+
+/// Build the reply from `from` to a request that failed with the error built by `error` from its
+/// destination
+///
+/// Returns `None` if the envelope has no correlation id, since only requests carry one, or if it's
+/// a `Msg::CallError` itself, since errors are never replied to, to prevent loops.
+pub fn error_reply<'de, T, F>(envelope: Envelope<T>, from: Pid, error: F) -> Option<Envelope<T>>
+    where T: Serialize + Deserialize<'de> + Debug + Clone,
+          F: FnOnce(Pid) -> CallError
+{
+    if envelope.correlation_id.is_none() {
+        return None;
+    }
+    if let Msg::CallError(_) = envelope.msg {
+        return None;
+    }
+    let reply_to = envelope.reply_address().clone();
+    let Envelope {to, correlation_id, ..} = envelope;
+    Some(Envelope::new(reply_to, from, Msg::CallError(error(to)), correlation_id))
+}
@@ -0,0 +1,84 @@
+use std::env;
+use rand::{self, Rng};
+
+/// Fault injection settings for resilience testing. All faults are disabled by default.
+///
+/// Only available with the `chaos` feature, so it can't be enabled by accident in production
+/// builds.
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    /// The probability that a random local process is killed on each executor tick (every 100ms)
+    pub kill_probability: f64,
+
+    /// The probability that an envelope sent to another node is dropped
+    pub drop_probability: f64,
+
+    /// Timers are delayed by a random amount of up to this many ms
+    pub max_timer_delay: usize
+}
+
+impl Default for ChaosConfig {
+    fn default() -> ChaosConfig {
+        ChaosConfig {
+            kill_probability: 0.0,
+            drop_probability: 0.0,
+            max_timer_delay: 0
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Read the settings from the `RABBLE_CHAOS_KILL_PROBABILITY`,
+    /// `RABBLE_CHAOS_DROP_PROBABILITY` and `RABBLE_CHAOS_MAX_TIMER_DELAY` environment variables.
+    /// Unset or invalid variables leave the corresponding fault disabled.
+    ///
+    /// This is what `Config::default()` uses, so a soak cluster can be configured entirely from its
+    /// environment.
+    pub fn from_env() -> ChaosConfig {
+        fn var<V: ::std::str::FromStr>(name: &str, default: V) -> V {
+            env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+        ChaosConfig {
+            kill_probability: var("RABBLE_CHAOS_KILL_PROBABILITY", 0.0),
+            drop_probability: var("RABBLE_CHAOS_DROP_PROBABILITY", 0.0),
+            max_timer_delay: var("RABBLE_CHAOS_MAX_TIMER_DELAY", 0)
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.kill_probability > 0.0 || self.drop_probability > 0.0 || self.max_timer_delay > 0
+    }
+}
+
+/// Makes the random decisions for the faults configured in a `ChaosConfig`
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    config: ChaosConfig
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Chaos {
+        Chaos {
+            config: config
+        }
+    }
+
+    pub fn kill_process(&self) -> bool {
+        chance(self.config.kill_probability)
+    }
+
+    pub fn drop_envelope(&self) -> bool {
+        chance(self.config.drop_probability)
+    }
+
+    pub fn timer_delay(&self) -> usize {
+        if self.config.max_timer_delay == 0 {
+            return 0;
+        }
+        rand::thread_rng().gen_range(0, self.config.max_timer_delay + 1)
+    }
+}
+
+fn chance(probability: f64) -> bool {
+    probability > 0.0 && rand::thread_rng().gen::<f64>() < probability
+}
@@ -0,0 +1,102 @@
+use node_id::NodeId;
+
+/// The role of a peer node, which determines what it is permitted to do over the cluster protocol
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Role {
+    /// A regular cluster member. It may exchange membership and envelopes.
+    Member,
+
+    /// A member that may additionally issue control messages such as remove and shutdown.
+    Operator
+}
+
+impl Role {
+    pub fn can_issue_control_msgs(&self) -> bool {
+        *self == Role::Operator
+    }
+}
+
+/// Administrative messages that one node can send to another over the cluster protocol.
+///
+/// Only peers with the `Operator` role are allowed to issue these. Membership changes are not
+/// gated by role: any member may remove failed or downed nodes, and the leave started by a
+/// `Remove` is announced by the node that received it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ControlMsg {
+    /// Remove the given node from the cluster
+    Remove(NodeId),
+
+    /// Shutdown the receiving node
+    Shutdown
+}
+
+/// Attributes of the certificate a peer presented during a mutual TLS handshake
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PeerIdentity {
+    pub subject_alt_names: Vec<String>,
+    pub organizational_units: Vec<String>
+}
+
+/// A certificate attribute that a `RoleMap` rule matches against
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IdentityMatch {
+    SubjectAltName(String),
+    OrganizationalUnit(String)
+}
+
+impl IdentityMatch {
+    fn matches(&self, identity: &PeerIdentity) -> bool {
+        match *self {
+            IdentityMatch::SubjectAltName(ref san) =>
+                identity.subject_alt_names.iter().any(|s| s == san),
+            IdentityMatch::OrganizationalUnit(ref ou) =>
+                identity.organizational_units.iter().any(|o| o == ou)
+        }
+    }
+}
+
+/// Maps peer identities to roles.
+///
+/// Rules are checked in the order they were added and the first match wins. Peers presenting a
+/// certificate that matches no rule are given the `Member` role. Peers that didn't present a
+/// certificate at all, because mutual TLS is not enabled, are given the `unauthenticated` role.
+/// This defaults to the least privileged `Member` role, so clusters without mutual TLS that use
+/// control messages must opt in with `RoleMap::new(Role::Operator)`.
+#[derive(Debug, Clone)]
+pub struct RoleMap {
+    rules: Vec<(IdentityMatch, Role)>,
+    pub unauthenticated: Role
+}
+
+impl Default for RoleMap {
+    fn default() -> RoleMap {
+        RoleMap {
+            rules: Vec::new(),
+            unauthenticated: Role::Member
+        }
+    }
+}
+
+impl RoleMap {
+    pub fn new(unauthenticated: Role) -> RoleMap {
+        RoleMap {
+            rules: Vec::new(),
+            unauthenticated: unauthenticated
+        }
+    }
+
+    /// Give peers with a certificate matching `attribute` the given `role`
+    pub fn add_rule(mut self, attribute: IdentityMatch, role: Role) -> RoleMap {
+        self.rules.push((attribute, role));
+        self
+    }
+
+    pub fn role(&self, identity: Option<&PeerIdentity>) -> Role {
+        match identity {
+            None => self.unauthenticated,
+            Some(identity) => self.rules.iter()
+                .find(|&&(ref attribute, _)| attribute.matches(identity))
+                .map_or(Role::Member, |&(_, role)| role)
+        }
+    }
+}
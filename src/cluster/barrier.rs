@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::Duration;
+
+/// A latch that opens once the cluster reaches the minimum size required to serve traffic. Once
+/// open it stays open, even if members are later lost.
+#[derive(Debug, Clone)]
+pub struct StartupBarrier {
+    inner: Arc<(Mutex<bool>, Condvar)>
+}
+
+impl StartupBarrier {
+    pub fn new(open: bool) -> StartupBarrier {
+        StartupBarrier {
+            inner: Arc::new((Mutex::new(open), Condvar::new()))
+        }
+    }
+
+    pub fn open(&self) {
+        let &(ref lock, ref cvar) = &*self.inner;
+        let mut open = lock.lock().unwrap();
+        if !*open {
+            *open = true;
+            cvar.notify_all();
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Block until the barrier opens or `timeout` ms elapse. Wait forever if `timeout` is `None`.
+    ///
+    /// Return whether the barrier is open.
+    pub fn wait(&self, timeout: Option<usize>) -> bool {
+        let &(ref lock, ref cvar) = &*self.inner;
+        let mut open = lock.lock().unwrap();
+        match timeout {
+            None => {
+                while !*open {
+                    open = cvar.wait(open).unwrap();
+                }
+            },
+            Some(timeout) => {
+                let timeout = Duration::from_millis(timeout as u64);
+                open = cvar.wait_timeout_while(open, timeout, |open| !*open).unwrap().0;
+            }
+        }
+        *open
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use msgpack::{Serializer, Deserializer};
+use serde_cbor;
+use bincode;
+use super::ExternalMsg;
+use errors::*;
+
+/// Encodes and decodes the messages exchanged between cluster servers
+///
+/// All nodes in a cluster must use the same codec. Implement this trait to use a wire format other
+/// than the provided ones, and pass it to `rabble::rouse_with_codec`.
+pub trait Codec<T>: Send {
+    /// The name exchanged with peers on connection, to reject peers using a different codec
+    fn name(&self) -> &str;
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>>;
+    fn decode(&self, data: &[u8]) -> Result<ExternalMsg<T>>;
+}
+
+/// The codecs provided by rabble, selected with `ClusterConfig::codec`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WireCodec {
+    Msgpack,
+    Cbor,
+    Bincode
+}
+
+impl WireCodec {
+    pub fn codec<'de, T>(&self) -> Box<Codec<T>>
+        where T: Serialize + Deserialize<'de> + Debug + Clone
+    {
+        match *self {
+            WireCodec::Msgpack => Box::new(MsgpackCodec),
+            WireCodec::Cbor => Box::new(CborCodec),
+            WireCodec::Bincode => Box::new(BincodeCodec)
+        }
+    }
+}
+
+/// The default codec
+pub struct MsgpackCodec;
+
+impl<'de, T> Codec<T> for MsgpackCodec
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    fn name(&self) -> &str {
+        "msgpack"
+    }
+
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        try!(msg.serialize(&mut Serializer::new(&mut encoded)));
+        Ok(encoded)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ExternalMsg<T>> {
+        let mut decoder = Deserializer::new(data);
+        Ok(try!(Deserialize::deserialize(&mut decoder)))
+    }
+}
+
+pub struct CborCodec;
+
+impl<'de, T> Codec<T> for CborCodec
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    fn name(&self) -> &str {
+        "cbor"
+    }
+
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        serde_cbor::to_vec(msg).chain_err(|| "Failed to encode CBOR message")
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ExternalMsg<T>> {
+        serde_cbor::from_slice(data).chain_err(|| "Failed to decode CBOR message")
+    }
+}
+
+/// A compact codec. Since bincode isn't self describing, messages sent by nodes running a different
+/// version of rabble or of the user message type can't be decoded.
+pub struct BincodeCodec;
+
+impl<'de, T> Codec<T> for BincodeCodec
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    fn name(&self) -> &str {
+        "bincode"
+    }
+
+    fn encode(&self, msg: &ExternalMsg<T>) -> Result<Vec<u8>> {
+        bincode::serialize(msg, bincode::Infinite).chain_err(|| "Failed to encode bincode message")
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ExternalMsg<T>> {
+        bincode::deserialize(data).chain_err(|| "Failed to decode bincode message")
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Cursor;
+use lz4;
+use zstd;
+use errors::*;
+
+// zstd level 1 favors speed, since frames are compressed on the cluster server thread
+const ZSTD_LEVEL: i32 = 1;
+
+/// Algorithms that frames between nodes can be compressed with
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    Lz4,
+    Zstd
+}
+
+impl Compression {
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Compression::Lz4 => lz4::block::compress(data, None, true)
+                .chain_err(|| "Failed to compress frame with lz4"),
+            Compression::Zstd => zstd::encode_all(Cursor::new(data), ZSTD_LEVEL)
+                .chain_err(|| "Failed to compress frame with zstd")
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Compression::Lz4 => lz4::block::decompress(data, None)
+                .chain_err(|| "Failed to decompress lz4 frame"),
+            Compression::Zstd => zstd::decode_all(Cursor::new(data))
+                .chain_err(|| "Failed to decompress zstd frame")
+        }
+    }
+
+    /// Choose the first algorithm in our order of preference that the peer supports as well
+    pub fn negotiate(ours: &[Compression], theirs: &[Compression]) -> Option<Compression> {
+        ours.iter().find(|c| theirs.contains(c)).cloned()
+    }
+}
@@ -0,0 +1,219 @@
+use super::{RoleMap, SplitBrainStrategy, TlsConfig, Compression, WireCodec, Throttle, Proxy};
+use members::NodeMetadata;
+use node_id::NodeId;
+use backoff::Backoff;
+use socket::SocketOptions;
+
+/// Configuration for the cluster server
+///
+/// All times are in milliseconds.
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// How often a single member is probed by the SWIM failure detector
+    pub probe_interval: usize,
+
+    /// How long to wait for a direct ack before asking other members to probe on our behalf
+    pub probe_timeout: usize,
+
+    /// The number of members asked to indirectly probe an unresponsive member
+    pub indirect_probes: usize,
+
+    /// How long a member remains suspected before it is declared failed and removed from the
+    /// cluster. When `split_brain` is set, failed members are only removed if it decides so.
+    pub suspicion_timeout: usize,
+
+    /// Each membership update is piggybacked on `gossip_multiplier * log2(cluster size)` probes
+    pub gossip_multiplier: usize,
+
+    /// How often a heartbeat ping is sent over each connection
+    pub heartbeat_interval: usize,
+
+    /// A connection is closed when this many heartbeats in a row go unanswered. This detects
+    /// half-open connections, where our writes never reach the peer, long before TCP does.
+    pub max_missed_heartbeats: u32,
+
+    /// The phi value above which the accrual failure detector suspects a peer
+    pub phi_threshold: f64,
+
+    /// The number of heartbeat inter-arrival times used to compute phi
+    pub phi_window: usize,
+
+    /// The minimum standard deviation of heartbeat inter-arrival times used to compute phi
+    pub phi_min_std_dev: usize,
+
+    /// A pause in heartbeats of this length is tolerated without increasing phi much
+    pub phi_acceptable_pause: usize,
+
+    /// Tags published to the rest of the cluster along with membership. They can be changed later
+    /// with `Node::set_metadata`.
+    pub metadata: NodeMetadata,
+
+    /// Maps the certificates presented by peers to the roles they are granted
+    pub roles: RoleMap,
+
+    /// The address the cluster server listens on, if it differs from the address of the `NodeId`
+    ///
+    /// Peers always connect to the address of the `NodeId`, which is what the node advertises in
+    /// membership. Behind NAT, in a container, or behind a load balancer, that should be the
+    /// externally reachable address, while this is the local one, such as `0.0.0.0:11000`.
+    pub bind_addr: Option<String>,
+
+    /// Nodes joined automatically on startup. Connection attempts to seeds are retried with backoff
+    /// until they succeed. The local node is ignored if present, so every node in a cluster can be
+    /// given the same list.
+    pub seeds: Vec<NodeId>,
+
+    /// The number of connected members, including the local node, needed before the node is
+    /// considered ready. See `Node::wait_until_ready`.
+    pub min_members: usize,
+
+    /// How to resolve a partition. Partitions are left unresolved if this is `None`.
+    pub split_brain: Option<SplitBrainStrategy>,
+
+    /// How long the set of unreachable members must remain unchanged before a partition is resolved
+    pub split_brain_stable_after: usize,
+
+    /// The number of points each member occupies on the consistent hash ring used by `Node::route`
+    pub virtual_nodes: usize,
+
+    /// The capacity of this node relative to other members, advertised in its metadata. A node with
+    /// weight 2 owns about twice as many keys on the hash ring as a node with weight 1. A weight of
+    /// 0 keeps the node off the ring.
+    pub weight: u32,
+
+    /// The number of nodes the cluster is meant to have. Quorum in `ClusterStatus` is computed
+    /// against this. If it's `None`, the current number of members is used instead, which can't
+    /// detect members that were removed while this node was partitioned.
+    pub expected_size: Option<usize>,
+
+    /// Encrypt connections to other nodes. All nodes in a cluster must either enable or disable TLS.
+    pub tls: Option<TlsConfig>,
+
+    /// The compression algorithms this node supports, in order of preference. Each connection uses
+    /// the first one the peer supports too. Compression is disabled if this is empty.
+    pub compression: Vec<Compression>,
+
+    /// Envelopes that encode to fewer bytes than this are sent uncompressed
+    pub compression_threshold: usize,
+
+    /// The most bytes that may be queued for a peer that isn't reading them fast enough. Once either
+    /// limit is reached, `Node::send` returns an `ErrorKind::PeerSaturated` error for envelopes to
+    /// that peer until the queue drains.
+    pub max_queued_bytes: usize,
+
+    /// The most messages that may be queued for a peer that isn't reading them fast enough
+    pub max_queued_msgs: usize,
+
+    /// Envelopes that encode to more bytes than this are split into chunks of this size. Chunks are
+    /// only written while the connection keeps up, so heartbeats and smaller messages sent in the
+    /// meantime are interleaved with them rather than waiting for the whole envelope. Those smaller
+    /// messages may therefore arrive before a large envelope sent earlier.
+    pub chunk_size: usize,
+
+    /// Options for the TCP sockets of connections to other nodes
+    pub socket: SocketOptions,
+
+    /// Tunnel outgoing connections to other nodes through a proxy. Connections over a proxy are
+    /// set up synchronously, so the cluster server blocks for up to `socket.connect_timeout` ms
+    /// while the proxy connects. Connections to unix socket addresses never use the proxy.
+    pub proxy: Option<Proxy>,
+
+    /// Limit the rate envelopes are sent to each peer. Use `Node::set_throttle` to change the limit
+    /// of a single peer at runtime. Other traffic between nodes, such as heartbeats and membership
+    /// changes, is never throttled.
+    pub throttle: Option<Throttle>,
+
+    /// Coalesce envelopes to the same peer into a single frame. Disabled if `None`.
+    pub batching: Option<Batching>,
+
+    /// How often connections to unreachable members are retried
+    pub reconnect: ReconnectPolicy,
+
+    /// The wire format of messages between nodes. All nodes in a cluster must use the same codec.
+    /// Use `ClusterServer::set_codec` to provide your own.
+    pub codec: WireCodec
+}
+
+impl Default for ClusterConfig {
+    fn default() -> ClusterConfig {
+        ClusterConfig {
+            probe_interval: 1000,
+            probe_timeout: 500,
+            indirect_probes: 3,
+            suspicion_timeout: 5000,
+            gossip_multiplier: 3,
+            heartbeat_interval: 1000,
+            max_missed_heartbeats: 5,
+            phi_threshold: 8.0,
+            phi_window: 100,
+            phi_min_std_dev: 100,
+            phi_acceptable_pause: 1000,
+            metadata: NodeMetadata::new(),
+            roles: RoleMap::default(),
+            bind_addr: None,
+            seeds: Vec::new(),
+            min_members: 1,
+            split_brain: None,
+            split_brain_stable_after: 20000,
+            virtual_nodes: 100,
+            weight: 1,
+            expected_size: None,
+            tls: None,
+            compression: Vec::new(),
+            compression_threshold: 4096,
+            max_queued_bytes: 64 * 1024 * 1024,
+            max_queued_msgs: 100000,
+            chunk_size: 64 * 1024,
+            socket: SocketOptions::default(),
+            proxy: None,
+            throttle: None,
+            batching: None,
+            reconnect: ReconnectPolicy::default(),
+            codec: WireCodec::Msgpack
+        }
+    }
+}
+
+/// How the cluster server retries connections to members it can't reach
+///
+/// Attempts are checked once per cluster server tick (1 second), so shorter delays are rounded up.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// The delay before each attempt
+    pub backoff: Backoff,
+
+    /// Stop trying to connect to a member after this many attempts in a row fail. The member is not
+    /// removed from the cluster, and attempts resume if it's joined again. Retry forever if `None`.
+    pub max_attempts: Option<u32>
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            backoff: Backoff::new(1000, 30000, 2, 20),
+            max_attempts: None
+        }
+    }
+}
+
+/// How envelopes to the same peer are coalesced into a single frame
+///
+/// Each frame costs a write syscall and a frame header, which dominates when sending many small
+/// envelopes. Batching trades up to `max_delay` ms of latency for fewer, larger frames.
+#[derive(Debug, Clone)]
+pub struct Batching {
+    /// A batch is sent as soon as it holds this many envelopes
+    pub max_envelopes: usize,
+
+    /// A batch is sent no later than this many ms after its first envelope was queued
+    pub max_delay: usize
+}
+
+impl Batching {
+    pub fn new(max_envelopes: usize, max_delay: usize) -> Batching {
+        Batching {
+            max_envelopes: max_envelopes,
+            max_delay: max_delay
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use bytes::{Bytes, BufMut, BigEndian};
+
+/// Writes length prefixed frames to a nonblocking socket
+///
+/// This produces the same framing as the amy `FrameWriter`, a 4 byte big endian length followed by
+/// the frame, which peers read with an amy `FrameReader`. Frames are reference counted buffers
+/// rather than vectors, so a message encoded once can be queued on many connections without being
+/// copied.
+pub struct FrameWriter {
+    // Headers and frames are queued separately so frames are never copied into a larger buffer
+    pending: VecDeque<Bytes>,
+    // The number of bytes of the front buffer that were already written
+    offset: usize,
+    writable: bool
+}
+
+impl FrameWriter {
+    pub fn new() -> FrameWriter {
+        FrameWriter {
+            pending: VecDeque::new(),
+            offset: 0,
+            writable: true
+        }
+    }
+
+    /// Queue a frame if there is one, and write as much as possible if the socket is writable
+    ///
+    /// Returns false if the socket stopped accepting data before everything queued was written. In
+    /// that case `writable` must be called once the poller reports the socket writable again.
+    pub fn write<W: Write>(&mut self, writer: &mut W, frame: Option<Bytes>) -> io::Result<bool> {
+        if let Some(frame) = frame {
+            let mut header = Vec::with_capacity(4);
+            header.put_u32::<BigEndian>(frame.len() as u32);
+            self.pending.push_back(Bytes::from(header));
+            self.pending.push_back(frame);
+        }
+        if !self.writable {
+            return Ok(false);
+        }
+        while let Some(buf) = self.pending.pop_front() {
+            match writer.write(&buf[self.offset..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write frame"));
+                },
+                Ok(n) if self.offset + n < buf.len() => {
+                    self.offset += n;
+                    self.pending.push_front(buf);
+                },
+                Ok(_) => self.offset = 0,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.pending.push_front(buf);
+                    self.writable = false;
+                    return Ok(false);
+                },
+                Err(e) => {
+                    self.pending.push_front(buf);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// The socket became writable again
+    pub fn writable(&mut self) {
+        self.writable = true;
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
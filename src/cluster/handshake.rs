@@ -0,0 +1,70 @@
+use serde::{Serialize, Deserialize};
+use msgpack::{Serializer, Deserializer};
+use super::Compression;
+use errors::*;
+
+/// The version of the protocol between cluster servers. Bump it once per release whenever
+/// `ExternalMsg` changed in a way older nodes can't decode, rather than once per change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest protocol version this node can still talk to
+///
+/// Only raise it when support for older releases is dropped. Nodes whose version ranges overlap can
+/// run side by side, which is what lets a cluster be upgraded one node at a time.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The first frame sent on every connection between cluster servers
+///
+/// It's always encoded with msgpack regardless of the configured codec, so that peers with
+/// different codecs can still tell each other what's wrong rather than failing to decode frames.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub min_version: u32,
+    pub codec: String,
+    pub tls: bool,
+    pub compression: Vec<Compression>
+}
+
+impl Handshake {
+    pub fn new(codec: String, tls: bool, compression: Vec<Compression>) -> Handshake {
+        Handshake {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            codec: codec,
+            tls: tls,
+            compression: compression
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        try!(self.serialize(&mut Serializer::new(&mut encoded)));
+        Ok(encoded)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Handshake> {
+        let mut decoder = Deserializer::new(data);
+        Ok(try!(Deserialize::deserialize(&mut decoder)))
+    }
+
+    /// Return why a peer that sent `theirs` can't be talked to, if it can't
+    pub fn check(&self, theirs: &Handshake) -> Option<String> {
+        if theirs.version < self.min_version || self.version < theirs.min_version {
+            return Some(format!("protocol version {} is not supported, this node supports {} to {}",
+                                theirs.version, self.min_version, self.version));
+        }
+        if theirs.codec != self.codec {
+            return Some(format!("peer uses codec {}, this node uses {}", theirs.codec, self.codec));
+        }
+        if theirs.tls != self.tls {
+            return Some(format!("peer has TLS {}, this node has it {}",
+                                enabled(theirs.tls), enabled(self.tls)));
+        }
+        None
+    }
+}
+
+fn enabled(flag: bool) -> &'static str {
+    if flag { "enabled" } else { "disabled" }
+}
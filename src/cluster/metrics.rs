@@ -1,4 +1,8 @@
+use std::collections::HashMap;
 use metrics::{Metric, Metrics};
+use metrics_registry::{MetricsRegistry, Counter, Gauge, SharedHistogram};
+use histogram::TimeUnit;
+use node_id::NodeId;
 
 metrics!(ClusterMetrics {
     errors: u64,
@@ -9,5 +13,86 @@ metrics!(ClusterMetrics {
     received_remote_envelopes: u64,
     status_requests: u64,
     accepted_connections: u64,
-    connection_attempts: u64
+    connection_attempts: u64,
+    reconnect_attempts: u64,
+    abandoned_reconnects: u64,
+    probes_sent: u64,
+    indirect_probes_sent: u64,
+    suspicions: u64,
+    failures: u64,
+    control_msgs: u64,
+    unauthorized_control_msgs: u64,
+    split_brain_resolutions: u64,
+    stale_connections: u64,
+    stale_envelopes: u64,
+    broadcasts: u64,
+    broadcast_grafts: u64,
+    broadcast_prunes: u64,
+    reconciliations: u64,
+    dropped_leaving_envelopes: u64,
+    tls_reloads: u64,
+    compressed_frames: u64,
+    compression_saved_bytes: u64,
+    incompatible_peers: u64,
+    heartbeat_timeouts: u64,
+    chunked_messages: u64,
+    saturated_peers: u64,
+    dropped_saturated_envelopes: u64,
+    batches: u64,
+    batched_envelopes: u64,
+    connect_timeouts: u64,
+    expired_envelopes: u64,
+    stream_chunks_sent: u64,
+    stream_chunks_received: u64,
+    closed_streams: u64
 });
+
+/// Metrics of the cluster server kept in the node's `MetricsRegistry`
+///
+/// Unlike `ClusterMetrics`, which are only read by asking the cluster server, these can be read
+/// from any thread, and include a gauge of the outbound queue of every peer.
+pub struct PeerMetrics {
+    registry: MetricsRegistry,
+    pub frames_sent: Counter,
+    pub frames_received: Counter,
+    pub handshake_failures: Counter,
+    pub encode_time: SharedHistogram,
+    pub decode_time: SharedHistogram,
+    // The queued messages and bytes of each peer we connected to
+    queues: HashMap<NodeId, (Gauge, Gauge)>
+}
+
+impl PeerMetrics {
+    pub fn new(registry: MetricsRegistry) -> PeerMetrics {
+        PeerMetrics {
+            frames_sent: registry.counter("cluster_frames_sent"),
+            frames_received: registry.counter("cluster_frames_received"),
+            handshake_failures: registry.counter("cluster_handshake_failures"),
+            encode_time: registry.histogram("cluster_encode_time_ns", TimeUnit::Nanoseconds),
+            decode_time: registry.histogram("cluster_decode_time_ns", TimeUnit::Nanoseconds),
+            queues: HashMap::new(),
+            registry: registry
+        }
+    }
+
+    pub fn registry(&self) -> &MetricsRegistry {
+        &self.registry
+    }
+
+    /// Set the depth of the outbound queue to `node`
+    pub fn set_queue_depth(&mut self, node: &NodeId, msgs: usize, bytes: usize) {
+        if !self.queues.contains_key(node) {
+            let gauges = (self.registry.gauge(&format!("cluster_queued_msgs:{}", node)),
+                          self.registry.gauge(&format!("cluster_queued_bytes:{}", node)));
+            self.queues.insert(node.clone(), gauges);
+        }
+        let &(ref queued_msgs, ref queued_bytes) = &self.queues[node];
+        queued_msgs.set(msgs as i64);
+        queued_bytes.set(bytes as i64);
+    }
+
+    /// Count a reconnection attempt to `node`
+    pub fn reconnect(&self, node: &NodeId) {
+        self.registry.counter(&format!("cluster_reconnects:{}", node)).inc();
+    }
+}
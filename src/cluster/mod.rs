@@ -2,11 +2,48 @@ mod server;
 mod status;
 mod msg;
 mod metrics;
+mod config;
+mod swim;
+mod auth;
+mod phi;
+mod barrier;
+mod split_brain;
+mod registry;
+mod plumtree;
+mod reconcile;
+mod topology;
+mod tls;
+mod transport;
+mod x509;
+mod compression;
+mod codec;
+mod handshake;
+mod frame_writer;
+mod throttle;
+mod proxy;
 
 pub use self::server::ClusterServer;
-pub use self::status::ClusterStatus;
+pub use self::status::{ClusterStatus, PeerState, QuorumStatus};
 pub use self::msg::{
     ClusterMsg,
     ExternalMsg
 };
-pub use self::metrics::ClusterMetrics;
+pub use self::metrics::{ClusterMetrics, PeerMetrics};
+pub use self::config::{ClusterConfig, ReconnectPolicy, Batching};
+pub use self::swim::{Swim, SwimMsg, MemberState, MemberUpdate};
+pub use self::phi::PhiAccrual;
+pub use self::auth::{Role, ControlMsg, PeerIdentity, IdentityMatch, RoleMap};
+pub use self::barrier::StartupBarrier;
+pub use self::registry::{GlobalRegistry, NodeRegistry, Registration};
+pub use self::split_brain::{SplitBrainStrategy, SplitBrainDecision, STARTED_AT_KEY};
+pub use self::plumtree::{Plumtree, PlumtreeMsg, BroadcastId};
+pub use self::reconcile::Reconciler;
+pub use self::topology::{Topology, Link};
+pub use self::tls::{TlsConfig, TlsContext, load_certs, load_key};
+pub use self::transport::Transport;
+pub use self::frame_writer::FrameWriter;
+pub use self::throttle::{Throttle, TokenBucket};
+pub use self::proxy::Proxy;
+pub use self::compression::Compression;
+pub use self::handshake::{Handshake, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION};
+pub use self::codec::{Codec, WireCodec, MsgpackCodec, CborCodec, BincodeCodec};
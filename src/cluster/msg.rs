@@ -1,16 +1,37 @@
+use std::sync::mpsc::Sender;
 use amy::Notification;
 use orset::{ORSet, Delta};
 use node_id::NodeId;
 use envelope::Envelope;
 use correlation_id::CorrelationId;
+use pid::Pid;
+use dump::ClusterDump;
+use std::collections::HashMap;
+use members::NodeMetadata;
+use stream::{StreamId, StreamChunk, StreamState};
+use std::sync::Arc;
+use super::{SwimMsg, ControlMsg, NodeRegistry, PlumtreeMsg, Reconciler, Compression, Throttle};
 
 /// Messages sent to the Cluster Server
 pub enum ClusterMsg<T> {
     PollNotifications(Vec<Notification>),
     Join(NodeId),
-    Leave(NodeId),
+    Leave(NodeId, Option<CorrelationId>),
     Envelope(Envelope<T>),
     GetStatus(CorrelationId),
+    GetTopology(CorrelationId),
+    Control(NodeId, ControlMsg),
+    Broadcast(Envelope<T>),
+    Subscribe(Pid),
+    Unsubscribe(Pid),
+    RegisterGlobal(String, Pid),
+    UnregisterGlobal(String),
+    DumpState(Sender<ClusterDump>),
+    RegisterReconciler(String, Box<Reconciler>),
+    SetThrottle(NodeId, Option<Throttle>),
+    OpenStream(StreamId, NodeId, Arc<StreamState>),
+    Stream(StreamChunk),
+    SetMetadata(NodeMetadata),
     Shutdown
 }
 
@@ -18,8 +39,37 @@ pub enum ClusterMsg<T> {
 ///
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExternalMsg<T> {
-   Members {from: NodeId, orset: ORSet<NodeId>},
+   Members {
+       from: NodeId,
+       orset: ORSet<NodeId>,
+       #[serde(default)]
+       metadata: HashMap<NodeId, NodeMetadata>,
+       // The start time in ms of the sending node, which distinguishes it from earlier lives
+       // with the same NodeId. Peers that don't send one are never fenced.
+       #[serde(default)]
+       incarnation: u64
+   },
    Ping,
+   Pong,
    Envelope(Envelope<T>),
-   Delta(Delta<NodeId>)
+   Batch(Vec<Envelope<T>>),
+   Delta(Delta<NodeId>),
+   Swim {from: NodeId, msg: SwimMsg},
+   // A failure detector message for `to`, sent to a peer connected to it because the sender isn't
+   SwimRelay {from: NodeId, to: NodeId, msg: SwimMsg},
+   Control {from: NodeId, msg: ControlMsg},
+   Registry {from: NodeId, registry: NodeRegistry},
+   Plumtree {from: NodeId, msg: PlumtreeMsg},
+   Reconcile {from: NodeId, views: HashMap<String, Vec<u8>>},
+   Leaving {from: NodeId, node: NodeId},
+   // The metadata of `from` after it changed
+   Metadata {from: NodeId, metadata: NodeMetadata},
+   // Another encoded ExternalMsg, compressed with the algorithm negotiated for the connection
+   Compressed {algorithm: Compression, data: Vec<u8>},
+   // Part of another encoded ExternalMsg that was too large to send in one frame. Chunks of a
+   // message are sent in order without chunks of other messages in between.
+   Chunk {last: bool, data: Vec<u8>},
+   Stream(StreamChunk),
+   // Acknowledges that `bytes` of a stream were handed to the receiving executor
+   StreamAck {id: StreamId, bytes: usize}
 }
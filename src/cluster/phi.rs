@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+use time::SteadyTime;
+use node_id::NodeId;
+
+struct History {
+    last: SteadyTime,
+    intervals: VecDeque<f64>, // ms
+    sum: f64,
+    sum_of_squares: f64
+}
+
+impl History {
+    fn new(now: SteadyTime) -> History {
+        History {
+            last: now,
+            intervals: VecDeque::new(),
+            sum: 0.0,
+            sum_of_squares: 0.0
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.intervals.len() as f64
+    }
+
+    fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self.sum_of_squares / self.intervals.len() as f64 - mean * mean;
+        variance.max(0.0).sqrt()
+    }
+}
+
+/// An adaptive failure detector as described in "The φ Accrual Failure Detector" by Hayashibara et
+/// al.
+///
+/// Instead of declaring a peer down after a fixed timeout, the detector tracks the distribution of
+/// heartbeat inter-arrival times for each peer and computes `phi`, the suspicion level that the
+/// peer has failed given the time elapsed since its last heartbeat. A phi of 1 means roughly a 10%
+/// chance of a mistake, a phi of 2 a 1% chance, a phi of 3 a 0.1% chance, etc.. Peers with
+/// naturally jittery heartbeats therefore need a longer silence before being suspected.
+pub struct PhiAccrual {
+    window: usize,
+    min_std_dev: f64,
+    acceptable_pause: f64,
+    histories: HashMap<NodeId, History>
+}
+
+impl PhiAccrual {
+    /// Create a new detector
+    ///
+    /// `window` is the number of inter-arrival samples kept per peer. `min_std_dev` (ms) prevents
+    /// very regular heartbeats from making the detector overly sensitive, and `acceptable_pause`
+    /// (ms) is added to the mean to tolerate occasional pauses.
+    pub fn new(window: usize, min_std_dev: usize, acceptable_pause: usize) -> PhiAccrual {
+        PhiAccrual {
+            window: window,
+            min_std_dev: min_std_dev as f64,
+            acceptable_pause: acceptable_pause as f64,
+            histories: HashMap::new()
+        }
+    }
+
+    /// Record a heartbeat from `node`
+    pub fn heartbeat(&mut self, node: &NodeId, now: SteadyTime) {
+        if let Some(history) = self.histories.get_mut(node) {
+            let interval = (now - history.last).num_milliseconds() as f64;
+            history.last = now;
+            history.intervals.push_back(interval);
+            history.sum += interval;
+            history.sum_of_squares += interval * interval;
+            if history.intervals.len() > self.window {
+                let oldest = history.intervals.pop_front().unwrap();
+                history.sum -= oldest;
+                history.sum_of_squares -= oldest * oldest;
+            }
+            return;
+        }
+        self.histories.insert(node.clone(), History::new(now));
+    }
+
+    /// Stop tracking `node`. Its history is discarded.
+    pub fn remove(&mut self, node: &NodeId) {
+        self.histories.remove(node);
+    }
+
+    /// Return the current suspicion level of `node`, or `None` if there isn't enough history to
+    /// compute it.
+    pub fn phi(&self, node: &NodeId, now: SteadyTime) -> Option<f64> {
+        let history = match self.histories.get(node) {
+            Some(history) if !history.intervals.is_empty() => history,
+            _ => return None
+        };
+        let elapsed = (now - history.last).num_milliseconds() as f64;
+        let mean = history.mean() + self.acceptable_pause;
+        let std_dev = history.std_dev().max(self.min_std_dev);
+        Some(phi(elapsed, mean, std_dev))
+    }
+}
+
+/// Compute phi using the logistic approximation of the normal cumulative distribution function.
+fn phi(elapsed: f64, mean: f64, std_dev: f64) -> f64 {
+    let y = (elapsed - mean) / std_dev;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    if elapsed > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    }
+}
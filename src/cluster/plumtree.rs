@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use time::{SteadyTime, Duration};
+use node_id::NodeId;
+
+/// Uniquely identifies a broadcast across the cluster
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BroadcastId {
+    pub origin: NodeId,
+    pub seq: u64
+}
+
+/// Broadcast tree messages sent between cluster servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PlumtreeMsg {
+    Gossip {id: BroadcastId, round: u32, payload: Vec<u8>},
+    IHave {ids: Vec<BroadcastId>},
+    Graft {ids: Vec<BroadcastId>},
+    Prune
+}
+
+/// An epidemic broadcast tree as described in "Epidemic Broadcast Trees" by Leitão, Pereira and
+/// Rodrigues.
+///
+/// Payloads are pushed eagerly along the edges of a spanning tree, and only the ids of payloads are
+/// lazily announced over the remaining links. Every peer starts out as an eager peer. When a
+/// payload arrives a second time, the link it arrived on is pruned from the tree, so a full mesh
+/// quickly converges to a tree and each payload crosses each node once rather than once per peer.
+///
+/// The tree heals itself when links fail. If a payload is announced by a lazy peer but doesn't
+/// arrive over the tree within `graft_timeout`, it's requested from the announcing peer, whose link
+/// is grafted back into the tree.
+///
+/// Like `Swim`, this is a pure state machine. All messages to be sent are put in `output`, and the
+/// cluster server is responsible for sending them.
+pub struct Plumtree {
+    node: NodeId,
+    seq: u64,
+    eager: HashSet<NodeId>,
+    lazy: HashSet<NodeId>,
+    // Recently received payloads, kept to answer grafts and to detect duplicates
+    received: HashMap<BroadcastId, (u32, Vec<u8>)>,
+    received_order: VecDeque<BroadcastId>,
+    cache_size: usize,
+    // Payloads that were announced but not yet received, along with the peers that announced them
+    missing: HashMap<BroadcastId, (Vec<NodeId>, SteadyTime)>,
+    graft_timeout: Duration,
+    // Payloads received since the last call to `drain_delivered`
+    delivered: Vec<Vec<u8>>
+}
+
+impl Plumtree {
+    /// Create a new broadcast tree
+    ///
+    /// `first_seq` should differ across restarts of the node, so that new broadcasts aren't
+    /// mistaken for duplicates of broadcasts from its previous life.
+    pub fn new(node: NodeId, first_seq: u64, cache_size: usize, graft_timeout: usize) -> Plumtree {
+        Plumtree {
+            node: node,
+            seq: first_seq,
+            eager: HashSet::new(),
+            lazy: HashSet::new(),
+            received: HashMap::new(),
+            received_order: VecDeque::new(),
+            cache_size: cache_size,
+            missing: HashMap::new(),
+            graft_timeout: Duration::milliseconds(graft_timeout as i64),
+            delivered: Vec::new()
+        }
+    }
+
+    /// Update the set of peers with established connections
+    ///
+    /// New peers are added to the tree. Removed peers are dropped, and payloads they announced are
+    /// requested from other peers that announced them.
+    pub fn set_peers(&mut self, peers: &HashSet<NodeId>) {
+        self.eager.retain(|node| peers.contains(node));
+        self.lazy.retain(|node| peers.contains(node));
+        for node in peers.iter() {
+            if *node != self.node && !self.lazy.contains(node) {
+                self.eager.insert(node.clone());
+            }
+        }
+        for &mut (ref mut announcers, _) in self.missing.values_mut() {
+            announcers.retain(|node| peers.contains(node));
+        }
+        self.missing.retain(|_, &mut (ref announcers, _)| !announcers.is_empty());
+    }
+
+    pub fn eager_peers(&self) -> &HashSet<NodeId> {
+        &self.eager
+    }
+
+    pub fn lazy_peers(&self) -> &HashSet<NodeId> {
+        &self.lazy
+    }
+
+    /// Broadcast a payload originating at this node to all other nodes
+    ///
+    /// The payload is not delivered locally.
+    pub fn broadcast(&mut self, payload: Vec<u8>, output: &mut Vec<(NodeId, PlumtreeMsg)>) {
+        let id = BroadcastId {origin: self.node.clone(), seq: self.seq};
+        self.seq += 1;
+        self.remember(id.clone(), 0, payload.clone());
+        self.push(&id, 0, &payload, None, output);
+    }
+
+    /// Handle a message from a peer
+    ///
+    /// Newly received payloads are available from `drain_delivered`.
+    pub fn handle(&mut self,
+                  from: NodeId,
+                  msg: PlumtreeMsg,
+                  now: SteadyTime,
+                  output: &mut Vec<(NodeId, PlumtreeMsg)>)
+    {
+        match msg {
+            PlumtreeMsg::Gossip {id, round, payload} => {
+                if self.received.contains_key(&id) || id.origin == self.node {
+                    // A duplicate. Remove the link from the tree.
+                    if self.eager.remove(&from) {
+                        self.lazy.insert(from.clone());
+                        output.push((from, PlumtreeMsg::Prune));
+                    }
+                    return;
+                }
+                self.missing.remove(&id);
+                self.make_eager(&from);
+                self.remember(id.clone(), round, payload.clone());
+                self.push(&id, round + 1, &payload, Some(&from), output);
+                self.delivered.push(payload);
+            },
+            PlumtreeMsg::IHave {ids} => {
+                for id in ids {
+                    if self.received.contains_key(&id) || id.origin == self.node {
+                        continue;
+                    }
+                    let entry = self.missing.entry(id).or_insert_with(|| (Vec::new(), now));
+                    if !entry.0.contains(&from) {
+                        entry.0.push(from.clone());
+                    }
+                }
+            },
+            PlumtreeMsg::Graft {ids} => {
+                self.make_eager(&from);
+                for id in ids {
+                    if let Some(&(round, ref payload)) = self.received.get(&id) {
+                        output.push((from.clone(), PlumtreeMsg::Gossip {
+                            id: id,
+                            round: round,
+                            payload: payload.clone()
+                        }));
+                    }
+                }
+            },
+            PlumtreeMsg::Prune => {
+                if self.eager.remove(&from) {
+                    self.lazy.insert(from);
+                }
+            }
+        }
+    }
+
+    /// Request any payloads that were announced more than `graft_timeout` ago but never arrived
+    pub fn tick(&mut self, now: SteadyTime, output: &mut Vec<(NodeId, PlumtreeMsg)>) {
+        let mut grafts: HashMap<NodeId, Vec<BroadcastId>> = HashMap::new();
+        for (id, &mut (ref mut announcers, ref mut since)) in self.missing.iter_mut() {
+            if now - *since < self.graft_timeout || announcers.is_empty() {
+                continue;
+            }
+            // Ask the next announcer if this one doesn't deliver before the timeout
+            let node = announcers.remove(0);
+            *since = now;
+            grafts.entry(node).or_insert_with(Vec::new).push(id.clone());
+        }
+        self.missing.retain(|_, &mut (ref announcers, _)| !announcers.is_empty());
+        for (node, ids) in grafts {
+            self.make_eager(&node);
+            output.push((node, PlumtreeMsg::Graft {ids: ids}));
+        }
+    }
+
+    /// Return all payloads received since the last call
+    pub fn drain_delivered(&mut self) -> Vec<Vec<u8>> {
+        ::std::mem::replace(&mut self.delivered, Vec::new())
+    }
+
+    fn make_eager(&mut self, node: &NodeId) {
+        self.lazy.remove(node);
+        self.eager.insert(node.clone());
+    }
+
+    /// Send a payload to eager peers and announce it to lazy peers
+    fn push(&self,
+            id: &BroadcastId,
+            round: u32,
+            payload: &Vec<u8>,
+            except: Option<&NodeId>,
+            output: &mut Vec<(NodeId, PlumtreeMsg)>)
+    {
+        for node in self.eager.iter().filter(|&node| Some(node) != except) {
+            output.push((node.clone(), PlumtreeMsg::Gossip {
+                id: id.clone(),
+                round: round,
+                payload: payload.clone()
+            }));
+        }
+        for node in self.lazy.iter().filter(|&node| Some(node) != except) {
+            output.push((node.clone(), PlumtreeMsg::IHave {ids: vec![id.clone()]}));
+        }
+    }
+
+    fn remember(&mut self, id: BroadcastId, round: u32, payload: Vec<u8>) {
+        self.received.insert(id.clone(), (round, payload));
+        self.received_order.push_back(id);
+        while self.received_order.len() > self.cache_size {
+            if let Some(old) = self.received_order.pop_front() {
+                self.received.remove(&old);
+            }
+        }
+    }
+}
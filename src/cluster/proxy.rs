@@ -0,0 +1,133 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, IpAddr};
+use std::time::Duration;
+use socket::resolve;
+
+// The longest HTTP response header accepted from a proxy
+const MAX_HTTP_HEADER: usize = 8192;
+
+/// A proxy that connections to other nodes are tunneled through
+///
+/// Only the TCP connection is tunneled, so TLS between nodes still works end to end. Proxies that
+/// require authentication aren't supported.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Proxy {
+    /// The address of a SOCKS5 proxy. Peer addresses are resolved by the proxy.
+    Socks5(String),
+
+    /// The address of an HTTP proxy that supports the CONNECT method
+    HttpConnect(String)
+}
+
+impl Proxy {
+    /// Connect to `target` through the proxy
+    ///
+    /// This blocks until the proxy has established the tunnel or `timeout` ms have passed.
+    pub fn connect(&self, target: &str, timeout: usize) -> io::Result<TcpStream> {
+        let timeout = Duration::from_millis(timeout as u64);
+        let addr = match *self {
+            Proxy::Socks5(ref addr) | Proxy::HttpConnect(ref addr) => addr
+        };
+        let mut sock = try!(TcpStream::connect_timeout(&try!(resolve(addr)), timeout));
+        try!(sock.set_read_timeout(Some(timeout)));
+        try!(sock.set_write_timeout(Some(timeout)));
+        match *self {
+            Proxy::Socks5(_) => try!(socks5_handshake(&mut sock, target)),
+            Proxy::HttpConnect(_) => try!(http_connect(&mut sock, target))
+        }
+        try!(sock.set_read_timeout(None));
+        try!(sock.set_write_timeout(None));
+        Ok(sock)
+    }
+}
+
+/// Ask a SOCKS5 proxy to connect to `target`, as described in RFC 1928
+fn socks5_handshake(sock: &mut TcpStream, target: &str) -> io::Result<()> {
+    let (host, port) = try!(split_host_port(target));
+
+    // Offer only the "no authentication" method
+    try!(sock.write_all(&[5, 1, 0]));
+    let mut reply = [0; 2];
+    try!(sock.read_exact(&mut reply));
+    if reply != [5, 0] {
+        return Err(proxy_error("SOCKS5 proxy requires an unsupported authentication method"));
+    }
+
+    let mut request = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(proxy_error("Host name too long for SOCKS5"));
+            }
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    try!(sock.write_all(&request));
+
+    let mut reply = [0; 4];
+    try!(sock.read_exact(&mut reply));
+    if reply[1] != 0 {
+        return Err(proxy_error(&format!("SOCKS5 proxy failed to connect, reply code {}", reply[1])));
+    }
+    // Skip the address the proxy bound for the tunnel, followed by a 2 byte port
+    let addr_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0; 1];
+            try!(sock.read_exact(&mut len));
+            len[0] as usize
+        },
+        _ => return Err(proxy_error("SOCKS5 proxy replied with an invalid address type"))
+    };
+    let mut bound = vec![0; addr_len + 2];
+    sock.read_exact(&mut bound)
+}
+
+/// Ask an HTTP proxy to open a tunnel to `target` with the CONNECT method
+fn http_connect(sock: &mut TcpStream, target: &str) -> io::Result<()> {
+    let request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+    try!(sock.write_all(request.as_bytes()));
+
+    // Read a byte at a time, so that nothing the peer sends after the header is consumed
+    let mut header = Vec::new();
+    let mut byte = [0; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_HTTP_HEADER {
+            return Err(proxy_error("HTTP proxy response header too long"));
+        }
+        try!(sock.read_exact(&mut byte));
+        header.push(byte[0]);
+    }
+    let header = String::from_utf8_lossy(&header);
+    let status_line = header.lines().next().unwrap_or("");
+    let status = status_line.split_whitespace().nth(1);
+    if !status_line.starts_with("HTTP/1.") || status != Some("200") {
+        return Err(proxy_error(&format!("HTTP proxy refused to connect: {}", status_line)));
+    }
+    Ok(())
+}
+
+/// Split a `host:port` address, removing the brackets around IPv6 literals
+fn split_host_port(addr: &str) -> io::Result<(&str, u16)> {
+    let i = try!(addr.rfind(':').ok_or_else(|| proxy_error("Address has no port")));
+    let port = try!(addr[i + 1..].parse().map_err(|_| proxy_error("Address has an invalid port")));
+    let host = addr[..i].trim_left_matches('[').trim_right_matches(']');
+    Ok((host, port))
+}
+
+fn proxy_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg.to_string())
+}
@@ -0,0 +1,19 @@
+use node_id::NodeId;
+
+/// A hook that merges state which diverged while the cluster was partitioned
+///
+/// When a connection to a peer is re-established after it was lost, both nodes exchange the views
+/// of every registered reconciler, and each calls `reconcile` with its own view and the peer's.
+/// The reconciler decides the merged state, rather than whichever side writes last winning.
+///
+/// Reconcilers run on the cluster server thread, so they must not block. Views are opaque bytes
+/// that only need to be understood by the reconciler registered under the same name on other
+/// nodes. The global name registry is reconciled the same way internally, with the oldest
+/// registration of each name winning.
+pub trait Reconciler: Send {
+    /// Return this node's view of the state
+    fn view(&self) -> Vec<u8>;
+
+    /// Merge the view of `peer`, which was on the other side of a partition, with the local view
+    fn reconcile(&mut self, peer: &NodeId, local: Vec<u8>, remote: Vec<u8>);
+}
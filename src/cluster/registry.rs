@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+use node_id::NodeId;
+use pid::Pid;
+use envelope::now_ms;
+
+/// A pid registered under a global name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registration {
+    pub pid: Pid,
+    /// When the name was registered in ms since the epoch. The oldest registration wins conflicts.
+    pub registered_at: u64
+}
+
+/// The names registered on a single node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeRegistry {
+    pub version: u64,
+    pub names: HashMap<String, Registration>
+}
+
+impl NodeRegistry {
+    fn new() -> NodeRegistry {
+        NodeRegistry {
+            version: 0,
+            names: HashMap::new()
+        }
+    }
+}
+
+/// A cluster wide registry of names
+///
+/// Each node is authoritative for the names registered on it, and replicates them to all other
+/// nodes as a versioned `NodeRegistry`. Normally a name can't be registered if another node already
+/// holds it. However, during a partition both sides may register the same name. Once the partition
+/// heals, every node resolves the conflict the same way: the oldest registration wins, with ties
+/// broken by pid. The node holding the losing registration then drops it.
+pub struct GlobalRegistry {
+    me: NodeId,
+    nodes: HashMap<NodeId, NodeRegistry>
+}
+
+impl GlobalRegistry {
+    pub fn new(me: NodeId) -> GlobalRegistry {
+        let mut nodes = HashMap::new();
+        nodes.insert(me.clone(), NodeRegistry::new());
+        GlobalRegistry {
+            me: me,
+            nodes: nodes
+        }
+    }
+
+    /// Return the registrations of the local node
+    pub fn local(&self) -> &NodeRegistry {
+        &self.nodes[&self.me]
+    }
+
+    /// Register `pid` under `name`. Return false if another pid already holds the name.
+    pub fn register(&mut self, name: String, pid: Pid) -> bool {
+        if let Some(registration) = self.lookup(&name) {
+            if registration.pid != pid {
+                return false;
+            }
+        }
+        let local = self.nodes.get_mut(&self.me).unwrap();
+        local.names.insert(name, Registration {
+            pid: pid,
+            registered_at: now_ms()
+        });
+        local.version += 1;
+        true
+    }
+
+    /// Remove a name registered on this node. Return false if it wasn't registered here.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        let local = self.nodes.get_mut(&self.me).unwrap();
+        if local.names.remove(name).is_none() {
+            return false;
+        }
+        local.version += 1;
+        true
+    }
+
+    /// Merge the registry of another node. Return any local registrations that lost a conflict with
+    /// it and were removed.
+    pub fn merge(&mut self, from: NodeId, registry: NodeRegistry) -> Vec<(String, Pid)> {
+        if from == self.me ||
+            self.nodes.get(&from).map_or(false, |r| r.version >= registry.version)
+        {
+            return Vec::new();
+        }
+        let mut lost = Vec::new();
+        {
+            let local = self.nodes.get_mut(&self.me).unwrap();
+            for (name, remote) in registry.names.iter() {
+                let local_wins = match local.names.get(name) {
+                    Some(registration) => wins(registration, remote),
+                    None => continue
+                };
+                if !local_wins {
+                    let registration = local.names.remove(name).unwrap();
+                    lost.push((name.clone(), registration.pid));
+                }
+            }
+            if !lost.is_empty() {
+                local.version += 1;
+            }
+        }
+        self.nodes.insert(from, registry);
+        lost
+    }
+
+    /// Discard the registries of nodes that are no longer members. Return true if any were.
+    pub fn prune(&mut self, members: &HashSet<NodeId>) -> bool {
+        let me = self.me.clone();
+        let before = self.nodes.len();
+        self.nodes.retain(|node, _| *node == me || members.contains(node));
+        self.nodes.len() != before
+    }
+
+    /// Return the winning registration for `name`
+    pub fn lookup(&self, name: &str) -> Option<&Registration> {
+        self.nodes.values().filter_map(|r| r.names.get(name)).fold(None, |winner, r| {
+            match winner {
+                Some(w) if wins(w, r) => Some(w),
+                _ => Some(r)
+            }
+        })
+    }
+
+    /// Return the winning pid for every registered name
+    pub fn names(&self) -> HashMap<String, Pid> {
+        let mut names = HashMap::new();
+        for registry in self.nodes.values() {
+            for name in registry.names.keys() {
+                if !names.contains_key(name) {
+                    let pid = self.lookup(name).unwrap().pid.clone();
+                    names.insert(name.clone(), pid);
+                }
+            }
+        }
+        names
+    }
+}
+
+/// Return true if registration `a` wins a conflict with `b`
+fn wins(a: &Registration, b: &Registration) -> bool {
+    (a.registered_at, &a.pid) <= (b.registered_at, &b.pid)
+}
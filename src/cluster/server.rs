@@ -1,25 +1,46 @@
 use std::sync::mpsc::{self, Sender, Receiver};
-use std::collections::{HashMap, HashSet};
-use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::unix::net::UnixStream;
 use std::fmt::Debug;
+use std::io::Write;
+use std::mem;
 use libc::EINPROGRESS;
 use net2::{TcpBuilder, TcpStreamExt};
+use socket::{Listener, Stream, SocketOptions, unix_path, resolve};
 use serde::{Serialize, Deserialize};
-use msgpack::{Serializer, Deserializer};
 use slog;
-use amy::{Registrar, Notification, Event, FrameReader, FrameWriter};
-use members::Members;
+use time::{SteadyTime, Duration, precise_time_ns};
+use amy::{Registrar, Notification, Event, FrameReader};
+use bytes::Bytes;
+use rand::{self, Rng};
+use members::{Members, NodeMetadata};
+use ring::{Ring, WEIGHT_KEY};
+use dump::{EventLog, ClusterDump};
+#[cfg(feature = "chaos")]
+use chaos::Chaos;
 use node_id::NodeId;
 use msg::Msg;
 use executor::ExecutorMsg;
 use timer_wheel::TimerWheel;
-use envelope::Envelope;
+use envelope::{Envelope, Priority, now_ms};
+use seq_trace::{SeqTraceHop, seq_trace_event};
 use orset::{ORSet, Delta};
 use pid::Pid;
 use correlation_id::CorrelationId;
+use call_error::{CallError, error_reply};
+use stream::{StreamId, StreamChunk, StreamState};
 use errors::*;
-use metrics::Metrics;
-use super::{ClusterStatus, ClusterMsg, ExternalMsg, ClusterMetrics};
+use metrics::{Metrics, filter_metrics};
+use metrics_registry::MetricsRegistry;
+use super::{ClusterStatus, ClusterMsg, ExternalMsg, ClusterMetrics, ClusterConfig, PeerState};
+use super::{ReconnectPolicy, Batching, Throttle, TokenBucket, Proxy, PeerMetrics};
+use super::{QuorumStatus, StartupBarrier, SplitBrainStrategy, SplitBrainDecision, STARTED_AT_KEY};
+use super::{Swim, SwimMsg, MemberState, PhiAccrual};
+use super::{RoleMap, ControlMsg, PeerIdentity, GlobalRegistry, NodeRegistry};
+use super::{Plumtree, PlumtreeMsg, BroadcastId, Reconciler, Topology, Link};
+use super::{TlsContext, Transport, Compression, Codec, Handshake, FrameWriter};
 
 // TODO: This is totally arbitrary right now and should probably be user configurable
 const MAX_FRAME_SIZE: u32 = 100*1024*1024; // 100 MB
@@ -29,30 +50,105 @@ const REQUEST_TIMEOUT: usize = 5000; // milliseconds
 // This tick allows process specific timers to fire
 const EXECUTOR_TICK_TIME: usize = 100; // milliseconds
 
+// This tick drives the SWIM failure detector. It must be shorter than the probe timeout.
+const SWIM_TICK_TIME: usize = 100; // milliseconds
+
+// How often envelopes held back by a throttle are reconsidered
+const THROTTLE_TICK_TIME: usize = 10; // milliseconds
+
+// The number of recent broadcasts remembered to answer grafts and filter duplicates
+const BROADCAST_CACHE_SIZE: usize = 1000;
+// How long to wait for an announced broadcast to arrive over the tree before requesting it
+const GRAFT_TIMEOUT: usize = 1000; // milliseconds
+
+// The number of recent cluster events included in state dumps
+const EVENT_LOG_SIZE: usize = 200;
+
+// The longest a graceful leave waits for outbound messages to be flushed
+const DRAIN_TIMEOUT: usize = 5000; // milliseconds
+
 struct Conn {
-    sock: TcpStream,
+    sock: Transport,
     node: Option<NodeId>,
     is_client: bool,
     members_sent: bool,
     timer_wheel_index: usize,
+    // The identity presented by the peer if the connection is authenticated
+    identity: Option<PeerIdentity>,
+    // The incarnation of the peer, known once it sends its members
+    incarnation: u64,
+    // False while the writer has data it couldn't write to the socket yet
+    flushed: bool,
+    // The handshake sent by the peer, which must be the first frame it sends
+    handshake: Option<Handshake>,
+    // The compression algorithm negotiated with the peer
+    compression: Option<Compression>,
+    // Heartbeats sent since the peer last answered one
+    missed_heartbeats: u32,
+    // Encoded envelopes that weren't written yet, because they are chunks of a large message or
+    // the peer is throttled, ordered by priority
+    chunks: VecDeque<QueuedFrame>,
+    // Limits the rate at which `chunks` are written, if the peer is throttled
+    bucket: Option<TokenBucket>,
+    // The chunks received so far of a large message from the peer
+    partial: Vec<u8>,
+    // The bytes and messages written since the writer was last flushed
+    queued_bytes: usize,
+    queued_msgs: usize,
+    created_at: SteadyTime,
     reader: FrameReader,
     writer: FrameWriter
 }
 
 impl Conn {
-    pub fn new(sock: TcpStream, node: Option<NodeId>, is_client: bool) -> Conn {
+    pub fn new(sock: Transport, node: Option<NodeId>, is_client: bool) -> Conn {
         Conn {
             sock: sock,
             node: node,
             is_client: is_client,
             members_sent: false,
             timer_wheel_index: 0, // Initialize with a fake value
+            identity: None,
+            incarnation: 0,
+            flushed: true,
+            handshake: None,
+            compression: None,
+            missed_heartbeats: 0,
+            chunks: VecDeque::new(),
+            bucket: None,
+            partial: Vec::new(),
+            queued_bytes: 0,
+            queued_msgs: 0,
+            created_at: SteadyTime::now(),
             reader: FrameReader::new(MAX_FRAME_SIZE),
             writer: FrameWriter::new(),
         }
     }
 }
 
+/// A frame in the outbound queue of a connection
+struct QueuedFrame {
+    data: Bytes,
+    priority: Priority,
+    // True for every chunk of a large message but the first. Frames of a higher priority are only
+    // queued ahead of the first chunk, so that the chunks of a message are never split up.
+    continuation: bool
+}
+
+/// A graceful leave waiting for outbound messages to be flushed
+struct Drain {
+    correlation_id: Option<CorrelationId>,
+    started: SteadyTime
+}
+
+/// Tracks connection attempts to a peer that we aren't connected to
+struct Reconnect {
+    attempts: u32,
+    next_attempt: SteadyTime,
+    // True once `max_attempts` was reached and no more attempts are made
+    abandoned: bool
+}
+
 /// A struct that handles cluster membership connection and routing of messages to processes on
 /// other nodes.
 pub struct ClusterServer<T> {
@@ -63,14 +159,77 @@ pub struct ClusterServer<T> {
     executor_timer_id: usize,
     timer_id: usize,
     timer_wheel: TimerWheel<usize>,
-    listener: TcpListener,
+    swim_timer_id: usize,
+    heartbeat_timer_id: usize,
+    batch_timer_id: usize,
+    // Only set once a peer is throttled, so unthrottled nodes don't wake up every tick
+    throttle_timer_id: Option<usize>,
+    heartbeat_interval: usize,
+    max_missed_heartbeats: u32,
+    swim: Swim,
+    phi: PhiAccrual,
+    phi_threshold: f64,
+    roles: RoleMap,
+    listener: Listener,
+    tls: Option<TlsContext>,
+    compression_threshold: usize,
+    chunk_size: usize,
+    max_queued_bytes: usize,
+    max_queued_msgs: usize,
+    socket_options: SocketOptions,
+    proxy: Option<Proxy>,
+    throttle: Option<Throttle>,
+    // Throttles set at runtime, which override `throttle`
+    throttles: HashMap<NodeId, Option<Throttle>>,
+    batching: Option<Batching>,
+    // Envelopes waiting to be sent as a batch, keyed by connection id
+    batches: HashMap<usize, Vec<Envelope<T>>>,
+    saturated: Arc<RwLock<HashSet<NodeId>>>,
+    // Streams opened on this node that aren't finished yet, along with their receiving node
+    streams: HashMap<StreamId, (NodeId, Arc<StreamState>)>,
+    codec: Box<Codec<T>>,
+    handshake: Handshake,
     listener_id: usize,
     members: Members,
     connections: HashMap<usize, Conn>,
     established: HashMap<NodeId, usize>,
+    subscribers: HashSet<Pid>,
+    peer_states: HashMap<NodeId, PeerState>,
+    reconnects: HashMap<NodeId, Reconnect>,
+    reconnect_policy: ReconnectPolicy,
+    seeds: Vec<NodeId>,
+    min_members: usize,
+    ready: StartupBarrier,
+    split_brain: Option<SplitBrainStrategy>,
+    split_brain_stable_after: usize,
+    // The currently unreachable members and when they became unreachable
+    unreachable: Option<(HashSet<NodeId>, SteadyTime)>,
+    ring: Arc<RwLock<Ring>>,
+    expected_size: Option<usize>,
+    registry: GlobalRegistry,
+    // Our start time in ms, sent to peers so they can tell us apart from an earlier life
+    incarnation: u64,
+    // The latest known incarnation of every peer
+    incarnations: HashMap<NodeId, u64>,
+    plumtree: Plumtree,
+    events: EventLog,
+    reconcilers: HashMap<String, Box<Reconciler>>,
+    drains: HashMap<NodeId, Drain>,
+    // The last direct probe sent to each peer, used to measure round trip times
+    probes_sent: HashMap<NodeId, (u64, SteadyTime)>,
+    rtts: HashMap<NodeId, u64>,
+    // Nodes announced to be leaving. New envelopes to them are dropped.
+    leaving: HashSet<NodeId>,
+    // Peers we sent our views to after a partition healed, that haven't sent theirs yet
+    reconciling: HashSet<NodeId>,
+    // The winning pid of every global name, shared with `Node`
+    global_names: Arc<RwLock<HashMap<String, Pid>>>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
     registrar: Registrar,
     logger: slog::Logger,
-    metrics: ClusterMetrics
+    metrics: ClusterMetrics,
+    peer_metrics: PeerMetrics
 }
 
 impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
@@ -78,14 +237,29 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                rx: Receiver<ClusterMsg<T>>,
                executor_tx: Sender<ExecutorMsg<T>>,
                registrar: Registrar,
+               config: ClusterConfig,
                logger: slog::Logger) -> ClusterServer<T> {
         let pid = Pid {
             group: Some("rabble".to_string()),
             name: "cluster_server".to_string(),
-            node: node.clone()
+            node: node.clone(),
+            generation: None
         };
-        let listener = TcpListener::bind(&node.addr[..]).unwrap();
-        listener.set_nonblocking(true).unwrap();
+        let listener = Listener::bind(config.bind_addr.as_ref().unwrap_or(&node.addr)).unwrap();
+        // The config has already been validated by `rouse`
+        let tls = config.tls.as_ref().map(|tls| TlsContext::load(tls).expect("Invalid TLS config"));
+        // Publish our start time so that partitions can be resolved in favor of the oldest member
+        let incarnation = now_ms();
+        let mut metadata = config.metadata.clone();
+        metadata.insert(STARTED_AT_KEY.to_string(), incarnation.to_string());
+        metadata.insert(WEIGHT_KEY.to_string(), config.weight.to_string());
+        let members = Members::new(node.clone(), metadata);
+        let mut ring = Ring::new(config.virtual_nodes);
+        ring.set_weighted_nodes(&ring_weights(&members));
+        let codec = config.codec.codec();
+        let handshake = Handshake::new(codec.name().to_string(),
+                                       config.tls.is_some(),
+                                       config.compression.clone());
         ClusterServer {
             pid: pid,
             node: node.clone(),
@@ -94,42 +268,302 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
             executor_timer_id: 0,
             timer_id: 0,
             timer_wheel: TimerWheel::new(REQUEST_TIMEOUT / TICK_TIME),
+            swim_timer_id: 0,
+            heartbeat_timer_id: 0,
+            batch_timer_id: 0,
+            throttle_timer_id: None,
+            heartbeat_interval: config.heartbeat_interval,
+            max_missed_heartbeats: config.max_missed_heartbeats,
+            phi: PhiAccrual::new(config.phi_window,
+                                 config.phi_min_std_dev,
+                                 config.phi_acceptable_pause),
+            phi_threshold: config.phi_threshold,
+            roles: config.roles.clone(),
+            seeds: config.seeds.clone(),
+            min_members: config.min_members,
+            ready: StartupBarrier::new(config.min_members <= 1),
+            split_brain: config.split_brain.clone(),
+            split_brain_stable_after: config.split_brain_stable_after,
+            unreachable: None,
+            ring: Arc::new(RwLock::new(ring)),
+            expected_size: config.expected_size,
+            registry: GlobalRegistry::new(node.clone()),
+            incarnation: incarnation,
+            incarnations: HashMap::new(),
+            plumtree: Plumtree::new(node.clone(), incarnation, BROADCAST_CACHE_SIZE, GRAFT_TIMEOUT),
+            events: EventLog::new(EVENT_LOG_SIZE),
+            reconcilers: HashMap::new(),
+            drains: HashMap::new(),
+            probes_sent: HashMap::new(),
+            rtts: HashMap::new(),
+            leaving: HashSet::new(),
+            reconciling: HashSet::new(),
+            global_names: Arc::new(RwLock::new(HashMap::new())),
+            #[cfg(feature = "chaos")]
+            chaos: None,
             listener: listener,
+            tls: tls,
+            compression_threshold: config.compression_threshold,
+            chunk_size: config.chunk_size,
+            max_queued_bytes: config.max_queued_bytes,
+            max_queued_msgs: config.max_queued_msgs,
+            socket_options: config.socket.clone(),
+            proxy: config.proxy.clone(),
+            throttle: config.throttle,
+            throttles: HashMap::new(),
+            batching: config.batching.clone(),
+            batches: HashMap::new(),
+            saturated: Arc::new(RwLock::new(HashSet::new())),
+            streams: HashMap::new(),
+            codec: codec,
+            handshake: handshake,
             listener_id: 0,
-            members: Members::new(node),
+            members: members,
             connections: HashMap::new(),
             established: HashMap::new(),
+            subscribers: HashSet::new(),
+            peer_states: HashMap::new(),
+            reconnects: HashMap::new(),
+            reconnect_policy: config.reconnect.clone(),
+            // Last, since it takes the config
+            swim: Swim::new(node.clone(), config),
             registrar: registrar,
             logger: logger.new(o!("component" => "cluster_server")),
-            metrics: ClusterMetrics::new()
+            metrics: ClusterMetrics::new(),
+            peer_metrics: PeerMetrics::new(MetricsRegistry::new())
         }
     }
 
+    /// Use a custom codec for messages between nodes instead of the one selected in the config
+    pub fn set_codec(&mut self, codec: Box<Codec<T>>) {
+        self.handshake.codec = codec.name().to_string();
+        self.codec = codec;
+    }
+
+    /// Inject faults into the cluster server
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&mut self, chaos: Chaos) {
+        self.chaos = Some(chaos);
+    }
+
+    /// Return the hash ring over the current members, which is kept up to date by the server
+    pub fn ring(&self) -> Arc<RwLock<Ring>> {
+        self.ring.clone()
+    }
+
+    /// Return the global names and the pids they are registered to, which are kept up to date by
+    /// the server
+    pub fn global_names(&self) -> Arc<RwLock<HashMap<String, Pid>>> {
+        self.global_names.clone()
+    }
+
+    /// Return the peers whose outbound queues are full, which are kept up to date by the server
+    pub fn saturated_peers(&self) -> Arc<RwLock<HashSet<NodeId>>> {
+        self.saturated.clone()
+    }
+
+    /// Return the metrics registry of the node, which the server records the state of its peers in
+    pub fn metrics_registry(&self) -> MetricsRegistry {
+        self.peer_metrics.registry().clone()
+    }
+
+    /// Return the barrier that opens once `min_members` members are connected
+    pub fn ready(&self) -> StartupBarrier {
+        self.ready.clone()
+    }
+
     pub fn run(mut self) {
         info!(self.logger, "Starting");
         self.timer_id = self.registrar.set_interval(TICK_TIME).unwrap();
         self.executor_timer_id = self.registrar.set_interval(EXECUTOR_TICK_TIME).unwrap();
+        self.swim_timer_id = self.registrar.set_interval(SWIM_TICK_TIME).unwrap();
+        self.heartbeat_timer_id = self.registrar.set_interval(self.heartbeat_interval).unwrap();
+        if self.throttle.is_some() {
+            self.start_throttle_timer().unwrap();
+        }
+        if let Some(max_delay) = self.batching.as_ref().map(|batching| batching.max_delay) {
+            self.batch_timer_id = self.registrar.set_interval(max_delay).unwrap();
+        }
         self.listener_id = self.registrar.register(&self.listener, Event::Read).unwrap();
+        self.join_seeds();
         while let Ok(msg) = self.rx.recv() {
-            if let Err(e) = self.handle_cluster_msg(msg) {
-                self.metrics.errors += 1;
-                for id in e.kind().get_ids() {
-                    self.close(id)
-                }
-                match *e.kind() {
-                    ErrorKind::EncodeError(..) | ErrorKind::DecodeError(..) |
-                    ErrorKind::RegistrarError(..) | ErrorKind::SendError(..) => {
-                        error!(self.logger, e.to_string());
-                        break;
-                    }
+            // Envelopes don't change connection state, so don't bother checking for it
+            let is_envelope = if let ClusterMsg::Envelope(_) = msg { true } else { false };
+            let established = self.established_nodes();
+            let exit = match self.handle_cluster_msg(msg) {
+                Ok(()) => false,
+                Err(e) => self.handle_error(e)
+            };
+            self.publish_membership_events(established);
+            if !is_envelope {
+                self.update_peer_states();
+                self.update_ring();
+                self.update_broadcast_peers();
+                if let Err(e) = self.check_drains() {
+                    warn!(self.logger, e.to_string());
+                }
+            }
+            if exit {
+                break;
+            }
+        }
+    }
 
-                    ErrorKind::Shutdown(..) => {
-                        info!(self.logger, e.to_string());
-                        break;
-                    },
+    /// Handle an error from a cluster msg. Return true if the cluster server should exit.
+    fn handle_error(&mut self, e: Error) -> bool {
+        self.metrics.errors += 1;
+        for id in e.kind().get_ids() {
+            self.close(id)
+        }
+        match *e.kind() {
+            ErrorKind::EncodeError(..) | ErrorKind::DecodeError(..) |
+            ErrorKind::RegistrarError(..) | ErrorKind::SendError(..) => {
+                error!(self.logger, e.to_string());
+                true
+            }
+
+            ErrorKind::Shutdown(..) => {
+                info!(self.logger, e.to_string());
+                true
+            },
+
+            _ => {
+                warn!(self.logger, e.to_string());
+                false
+            }
+        }
+    }
 
-                    _ => warn!(self.logger, e.to_string())
+    /// Return the set of peers with established connections, if anyone is interested in changes to
+    /// it.
+    fn established_nodes(&self) -> HashSet<NodeId> {
+        if self.subscribers.is_empty() {
+            return HashSet::new();
+        }
+        self.established.keys().cloned().collect()
+    }
+
+    /// Notify subscribers of any peers that became connected or disconnected since `before` was
+    /// taken. Comparing snapshots rather than reacting to each connection change prevents
+    /// spurious events when a duplicate connection between two nodes is replaced.
+    fn publish_membership_events(&mut self, before: HashSet<NodeId>) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let after = self.established_nodes();
+        let mut msgs: Vec<Msg<T>> = after.difference(&before)
+            .map(|node| Msg::NodeUp(node.clone())).collect();
+        msgs.extend(before.difference(&after).map(|node| Msg::NodeDown(node.clone())));
+        for msg in msgs {
+            self.notify_subscribers(msg);
+        }
+    }
+
+    /// Recompute the state of each peer connection, logging and publishing any transitions.
+    ///
+    /// Peers we have never been connected to don't have a state.
+    fn update_peer_states(&mut self) {
+        let mut states = HashMap::new();
+        for node in self.members.all() {
+            if node == self.node {
+                continue;
+            }
+            let state = if self.established.contains_key(&node) {
+                if self.swim.state(&node) == Some(MemberState::Suspect) {
+                    PeerState::Suspected
+                } else {
+                    PeerState::Connected
                 }
+            } else if self.peer_states.contains_key(&node) {
+                PeerState::Reconnecting
+            } else {
+                continue;
+            };
+            states.insert(node, state);
+        }
+        let mut healed = Vec::new();
+        for (node, state) in states.iter() {
+            if self.peer_states.get(node) == Some(&PeerState::Reconnecting) &&
+                *state == PeerState::Connected
+            {
+                healed.push(node.clone());
+            }
+            if self.peer_states.get(node) != Some(state) {
+                info!(self.logger, "Peer state changed";
+                      "peer" => node.to_string(), "state" => format!("{:?}", state));
+                self.events.record(format!("Peer {} is {:?}", node, state));
+                self.notify_subscribers(Msg::PeerState(node.clone(), *state));
+            }
+        }
+        self.peer_states = states;
+        for node in healed {
+            if let Err(e) = self.send_views(&node) {
+                warn!(self.logger, "Failed to send reconciliation views";
+                      "peer" => node.to_string(), "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Send the views of all reconcilers to a peer after a partition healed
+    fn send_views(&mut self, node: &NodeId) -> Result<()> {
+        if self.reconcilers.is_empty() {
+            return Ok(());
+        }
+        let id = match self.established.get(node).cloned() {
+            Some(id) => id,
+            None => return Err(ErrorKind::ConnectError(node.clone()).into())
+        };
+        let views = self.reconcilers.iter().map(|(name, r)| (name.clone(), r.view())).collect();
+        let msg = ExternalMsg::Reconcile::<T> {from: self.node.clone(), views: views};
+        let encoded = try!(self.codec.encode(&msg)
+             .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node.clone()))));
+        self.reconciling.insert(node.clone());
+        self.write(id, Some(encoded.into()))
+    }
+
+    /// Merge the views of a peer on the other side of a healed partition
+    fn reconcile(&mut self, from: NodeId, views: HashMap<String, Vec<u8>>) -> Result<()> {
+        // The peer may not have noticed the partition, so make sure it gets our views as well
+        if !self.reconciling.remove(&from) {
+            try!(self.send_views(&from));
+            self.reconciling.remove(&from);
+        }
+        self.metrics.reconciliations += 1;
+        self.events.record(format!("Reconciling with {} after a partition", from));
+        info!(self.logger, "Reconciling after partition"; "peer" => from.to_string());
+        for (name, remote) in views {
+            match self.reconcilers.get_mut(&name) {
+                Some(reconciler) => {
+                    let local = reconciler.view();
+                    reconciler.reconcile(&from, local, remote);
+                },
+                None => warn!(self.logger, "No reconciler registered";
+                              "name" => name, "peer" => from.to_string())
+            }
+        }
+        Ok(())
+    }
+
+    fn update_ring(&mut self) {
+        let weights = ring_weights(&self.members);
+        if self.ring.read().unwrap().weights() != &weights {
+            self.ring.write().unwrap().set_weighted_nodes(&weights);
+        }
+    }
+
+    fn update_broadcast_peers(&mut self) {
+        let peers = self.established.keys().cloned().collect();
+        self.plumtree.set_peers(&peers);
+    }
+
+    fn notify_subscribers(&self, msg: Msg<T>) {
+        for pid in self.subscribers.iter() {
+            let envelope = Envelope::new(pid.clone(), self.pid.clone(), msg.clone(), None);
+            if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
+                self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+            {
+                error!(self.logger, "Failed to send to executor";
+                       "envelope" => format!("{:?}", envelope));
             }
         }
     }
@@ -142,11 +576,13 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
             },
             ClusterMsg::Join(node) => {
                 self.metrics.joins += 1;
+                self.events.record(format!("Joining {}", node));
                 self.join(node)
             },
-            ClusterMsg::Leave(node) => {
+            ClusterMsg::Leave(node, correlation_id) => {
                 self.metrics.leaves += 1;
-                self.leave(node)
+                self.events.record(format!("Removing {}", node));
+                self.start_drain(node, correlation_id)
             },
             ClusterMsg::Envelope(envelope) => {
                 self.metrics.received_local_envelopes += 1;
@@ -161,22 +597,141 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                 self.metrics.status_requests += 1;
                 self.get_status(correlation_id)
             },
+            ClusterMsg::GetTopology(correlation_id) => {
+                self.metrics.status_requests += 1;
+                self.get_topology(correlation_id)
+            },
+            ClusterMsg::Control(node, msg) => self.send_control_msg(node, msg),
+            ClusterMsg::SetThrottle(node, throttle) => {
+                info!(self.logger, "Set throttle";
+                      "peer" => node.to_string(), "throttle" => format!("{:?}", throttle));
+                if throttle.is_some() {
+                    try!(self.start_throttle_timer());
+                }
+                self.throttles.insert(node.clone(), throttle);
+                if let Some(id) = self.established.get(&node).cloned() {
+                    self.reset_bucket(id);
+                    return self.queue_frames(id, VecDeque::new(), Priority::Normal);
+                }
+                Ok(())
+            },
+            ClusterMsg::OpenStream(stream, node, state) => {
+                if node != self.node && !self.established.contains_key(&node) {
+                    state.closed.store(true, Ordering::Release);
+                    return Ok(());
+                }
+                self.streams.insert(stream, (node, state));
+                Ok(())
+            },
+            ClusterMsg::Stream(chunk) => self.send_stream_chunk(chunk),
+            ClusterMsg::Broadcast(envelope) => {
+                self.metrics.broadcasts += 1;
+                let msg = ExternalMsg::Envelope(envelope.clone());
+                let encoded = try!(self.codec.encode(&msg)
+                     .chain_err(|| ErrorKind::EncodeError(None, None)));
+                try!(self.broadcast_tree(encoded));
+                self.deliver_local(envelope)
+            },
+            ClusterMsg::Subscribe(pid) => {
+                self.subscribers.insert(pid);
+                Ok(())
+            },
+            ClusterMsg::Unsubscribe(pid) => {
+                self.subscribers.remove(&pid);
+                Ok(())
+            },
+            ClusterMsg::RegisterGlobal(name, pid) => {
+                if !self.registry.register(name.clone(), pid.clone()) {
+                    warn!(self.logger, "Global name already registered";
+                          "name" => name, "pid" => pid.to_string());
+                    return Ok(());
+                }
+                self.registry_changed()
+            },
+            ClusterMsg::UnregisterGlobal(name) => {
+                if self.registry.unregister(&name) {
+                    return self.registry_changed();
+                }
+                Ok(())
+            },
+            ClusterMsg::RegisterReconciler(name, reconciler) => {
+                self.reconcilers.insert(name, reconciler);
+                Ok(())
+            },
+            ClusterMsg::SetMetadata(metadata) => {
+                let metadata = self.members.set_metadata(metadata);
+                info!(self.logger, "Set metadata"; "metadata" => format!("{:?}", metadata));
+                let msg = ExternalMsg::Metadata::<T> {from: self.node.clone(), metadata: metadata};
+                let encoded = try!(self.codec.encode(&msg)
+                     .chain_err(|| ErrorKind::EncodeError(None, None)));
+                self.broadcast_tree(encoded)
+            },
+            ClusterMsg::DumpState(tx) => {
+                let dump = ClusterDump {
+                    status: self.status(),
+                    metrics: self.metrics.data(),
+                    events: self.events.events()
+                };
+                // The caller may have given up waiting
+                let _ = tx.send(dump);
+                Ok(())
+            },
             ClusterMsg::Shutdown => Err(ErrorKind::Shutdown(self.pid.clone()).into())
         }
     }
 
-    fn get_status(&self, correlation_id: CorrelationId) -> Result<()> {
-        let status = ClusterStatus {
-            members: self.members.all(),
+    fn status(&self) -> ClusterStatus {
+        let members = self.members.all();
+        let expected_size = self.expected_size.unwrap_or(members.len());
+        let reachable = self.established.len() + if members.contains(&self.node) { 1 } else { 0 };
+        ClusterStatus {
+            quorum: QuorumStatus::new(expected_size, reachable),
+            members: members,
             established: self.established.keys().cloned().collect(),
+            suspected: self.swim.members_in_state(MemberState::Suspect),
+            failed: self.swim.members_in_state(MemberState::Failed),
+            metadata: self.members.metadata().clone(),
+            peers: self.peer_states.clone(),
             num_connections: self.connections.len()
+        }
+    }
+
+    fn get_status(&self, correlation_id: CorrelationId) -> Result<()> {
+        let envelope = Envelope::new(correlation_id.pid.clone(),
+                                     self.pid.clone(),
+                                     Msg::ClusterStatus(self.status()),
+                                     Some(correlation_id));
+        // Route the response through the executor since it knows how to contact all Pids
+        if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
+            self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+        {
+            return Err(ErrorKind::SendError("ExecutorMsg::Envelope".to_string(),
+                                            Some(envelope.to)).into());
+        }
+        Ok(())
+    }
+
+    fn get_topology(&self, correlation_id: CorrelationId) -> Result<()> {
+        let now = SteadyTime::now();
+        let mut links: Vec<Link> = self.connections.values().filter_map(|conn| {
+            conn.node.as_ref().map(|peer| Link {
+                peer: peer.clone(),
+                established: self.established.contains_key(peer),
+                is_client: conn.is_client,
+                age_ms: (now - conn.created_at).num_milliseconds() as u64,
+                rtt_ms: self.rtts.get(peer).cloned(),
+                queued_bytes: conn.queued_bytes
+            })
+        }).collect();
+        links.sort_by(|a, b| a.peer.cmp(&b.peer));
+        let topology = Topology {
+            node: self.node.clone(),
+            links: links
         };
-        let envelope = Envelope {
-            to: correlation_id.pid.clone(),
-            from: self.pid.clone(),
-            msg: Msg::ClusterStatus(status),
-            correlation_id: Some(correlation_id)
-        };
+        let envelope = Envelope::new(correlation_id.pid.clone(),
+                                     self.pid.clone(),
+                                     Msg::Topology(topology),
+                                     Some(correlation_id));
         // Route the response through the executor since it knows how to contact all Pids
         if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
             self.executor_tx.send(ExecutorMsg::Envelope(envelope))
@@ -187,18 +742,249 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
+    /// Record the round trip time of a direct probe when it's acked
+    fn record_rtt(&mut self, from: &NodeId, seq: u64) {
+        if self.probes_sent.get(from).map_or(false, |&(sent_seq, _)| sent_seq == seq) {
+            let (_, sent_at) = self.probes_sent.remove(from).unwrap();
+            let rtt = (SteadyTime::now() - sent_at).num_milliseconds() as u64;
+            self.rtts.insert(from.clone(), rtt);
+        }
+    }
+
     fn send_remote(&mut self, envelope: Envelope<T>) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        {
+            if self.chaos.as_ref().map_or(false, |c| c.drop_envelope()) {
+                debug!(self.logger, "Chaos: dropped envelope"; "to" => envelope.to.to_string());
+                return Ok(());
+            }
+        }
+        if envelope.is_expired(now_ms()) {
+            self.expire(envelope);
+            return Ok(());
+        }
+        if self.leaving.contains(&envelope.to.node) {
+            self.metrics.dropped_leaving_envelopes += 1;
+            debug!(self.logger, "Dropping envelope to leaving node"; "to" => envelope.to.to_string());
+            return Ok(());
+        }
         if let Some(id) = self.established.get(&envelope.to.node).cloned() {
             trace!(self.logger, "send remote"; "to" => envelope.to.to_string());
-            let mut encoded = Vec::new();
+            // Senders are told the queue is full by `Node::send`, but envelopes already on their way
+            // to the cluster server still arrive. High priority envelopes are always sent.
+            let priority = envelope.priority;
+            if priority < Priority::High &&
+                self.connections.get(&id).map_or(false, |conn| self.is_saturated(conn))
+            {
+                self.metrics.dropped_saturated_envelopes += 1;
+                debug!(self.logger, "Dropping envelope to saturated peer";
+                       "to" => envelope.to.to_string());
+                return Ok(());
+            }
+            self.seq_trace(&envelope, SeqTraceHop::LeaveNode);
+            // High priority envelopes don't wait for a batch to fill up
+            let max_envelopes = self.batching.as_ref()
+                .and_then(|b| if priority < Priority::High { Some(b.max_envelopes) } else { None });
+            if let Some(max_envelopes) = max_envelopes {
+                let full = {
+                    let batch = self.batches.entry(id).or_insert_with(Vec::new);
+                    batch.push(envelope);
+                    batch.len() >= max_envelopes
+                };
+                if full {
+                    try!(self.flush_batch(id));
+                }
+                return Ok(());
+            }
             let node = envelope.to.node.clone();
-            try!(ExternalMsg::Envelope(envelope).serialize(&mut Serializer::new(&mut encoded))
-                .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node))));
-            try!(self.write(id, Some(encoded)));
+            try!(self.send_envelopes(id, node, ExternalMsg::Envelope(envelope), priority));
+        }
+        Ok(())
+    }
+
+    /// Drop an envelope whose deadline passed, and answer it with `CallError::Timeout` if it's a
+    /// request. The reply is routed by the executor, since it knows how to contact all pids.
+    fn expire(&mut self, envelope: Envelope<T>) {
+        self.metrics.expired_envelopes += 1;
+        debug!(self.logger, "Dropping expired envelope";
+               "from" => envelope.from.to_string(), "to" => envelope.to.to_string());
+        if let Some(reply) = error_reply(envelope, self.pid.clone(), |_| CallError::Timeout) {
+            if let Err(mpsc::SendError(ExecutorMsg::Envelope(reply))) =
+                self.executor_tx.send(ExecutorMsg::Envelope(reply))
+            {
+                error!(self.logger, "Failed to send to executor";
+                       "envelope" => format!("{:?}", reply));
+            }
+        }
+    }
+
+    /// Encode, compress and write envelopes to a peer
+    fn send_envelopes(&mut self,
+                      id: usize,
+                      node: NodeId,
+                      msg: ExternalMsg<T>,
+                      priority: Priority) -> Result<()>
+    {
+        let start = precise_time_ns();
+        let encoded = try!(self.codec.encode(&msg)
+            .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node.clone()))));
+        self.peer_metrics.encode_time.record(precise_time_ns() - start);
+        let encoded = try!(self.maybe_compress(id, encoded)
+                           .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node))));
+        // Write errors only close this connection, so they aren't reported as encoding errors
+        self.write_chunked(id, encoded, priority)
+    }
+
+    /// Send the envelopes batched for connection `id`
+    fn flush_batch(&mut self, id: usize) -> Result<()> {
+        let batch = match self.batches.remove(&id) {
+            Some(batch) => batch,
+            None => return Ok(())
+        };
+        let now = now_ms();
+        let (expired, mut batch): (Vec<_>, Vec<_>) =
+            batch.into_iter().partition(|envelope| envelope.is_expired(now));
+        for envelope in expired {
+            self.expire(envelope);
+        }
+        let node = match batch.first() {
+            Some(envelope) => envelope.to.node.clone(),
+            None => return Ok(())
+        };
+        // A batch is sent with the priority of its most urgent envelope
+        let priority = batch.iter().map(|envelope| envelope.priority).max().unwrap();
+        if batch.len() == 1 {
+            let msg = ExternalMsg::Envelope(batch.pop().unwrap());
+            return self.send_envelopes(id, node, msg, priority);
+        }
+        self.metrics.batches += 1;
+        self.metrics.batched_envelopes += batch.len() as u64;
+        self.send_envelopes(id, node, ExternalMsg::Batch(batch), priority)
+    }
+
+    fn flush_batches(&mut self) -> Result<()> {
+        let ids: Vec<usize> = self.batches.keys().cloned().collect();
+        let mut errors = Vec::new();
+        for id in ids {
+            if let Err(e) = self.flush_batch(id) {
+                errors.push(e);
+            }
+        }
+        if errors.len() != 0 {
+            return Err(ErrorKind::BroadcastError(errors).into());
+        }
+        Ok(())
+    }
+
+    /// Write an encoded message, splitting it into chunks if it's larger than `chunk_size`
+    ///
+    /// Small envelopes are queued too while frames are still queued for the peer, so that they
+    /// don't overtake envelopes of the same priority. Envelopes to a throttled peer are always
+    /// queued, unless they have a high priority.
+    fn write_chunked(&mut self, id: usize, encoded: Vec<u8>, priority: Priority) -> Result<()> {
+        let queue = self.connections.get(&id).map_or(false, |conn| {
+            !conn.chunks.is_empty() || (priority < Priority::High && conn.bucket.is_some())
+        });
+        if encoded.len() <= self.chunk_size && !queue {
+            return self.write(id, Some(encoded.into()));
+        }
+        let mut frames = VecDeque::new();
+        if encoded.len() <= self.chunk_size {
+            frames.push_back(Bytes::from(encoded));
+            return self.queue_frames(id, frames, priority);
+        }
+        self.metrics.chunked_messages += 1;
+        let mut chunks = encoded.chunks(self.chunk_size).peekable();
+        while let Some(chunk) = chunks.next() {
+            let msg = ExternalMsg::Chunk::<T> {last: chunks.peek().is_none(), data: chunk.to_vec()};
+            frames.push_back(Bytes::from(try!(self.codec.encode(&msg))));
+        }
+        self.queue_frames(id, frames, priority)
+    }
+
+    /// Queue the frames of a message ahead of every queued message of a lower priority, and write
+    /// as many queued frames as possible
+    fn queue_frames(&mut self,
+                    id: usize,
+                    frames: VecDeque<Bytes>,
+                    priority: Priority) -> Result<()>
+    {
+        self.peer_metrics.frames_sent.add(frames.len() as u64);
+        {
+            let registrar = &self.registrar;
+            if let Some(mut conn) = self.connections.get_mut(&id) {
+                let mut index = conn.chunks.iter()
+                    .position(|frame| !frame.continuation && frame.priority < priority)
+                    .unwrap_or(conn.chunks.len());
+                for (i, data) in frames.into_iter().enumerate() {
+                    let frame = QueuedFrame {
+                        data: data,
+                        priority: priority,
+                        continuation: i > 0
+                    };
+                    conn.chunks.insert(index, frame);
+                    index += 1;
+                }
+                try!(conn_write_chunks(id, &mut conn, &registrar));
+            }
+        }
+        self.update_saturation(id);
+        Ok(())
+    }
+
+    /// Write envelopes to throttled peers that have tokens available again
+    fn write_throttled(&mut self) -> Result<()> {
+        let ids: Vec<usize> = self.connections.iter()
+            .filter(|&(_, conn)| conn.bucket.is_some() && !conn.chunks.is_empty() && conn.flushed)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            try!(self.queue_frames(id, VecDeque::new(), Priority::Normal));
+        }
+        Ok(())
+    }
+
+    /// Start reconsidering throttled envelopes every `THROTTLE_TICK_TIME`, if not already started
+    fn start_throttle_timer(&mut self) -> Result<()> {
+        if self.throttle_timer_id.is_none() {
+            let id = try!(self.registrar.set_interval(THROTTLE_TICK_TIME)
+                          .chain_err(|| ErrorKind::RegistrarError(None, None)));
+            self.throttle_timer_id = Some(id);
         }
         Ok(())
     }
 
+    /// Apply the current throttle of the peer on connection `id`
+    fn reset_bucket(&mut self, id: usize) {
+        let throttle = match self.connections.get(&id).and_then(|conn| conn.node.as_ref()) {
+            Some(node) => self.throttles.get(node).cloned().unwrap_or(self.throttle),
+            None => return
+        };
+        if let Some(conn) = self.connections.get_mut(&id) {
+            conn.bucket = throttle.map(|throttle| TokenBucket::new(throttle, SteadyTime::now()));
+        }
+    }
+
+    /// Compress an encoded message if it's large and compression was negotiated for connection `id`
+    fn maybe_compress(&mut self, id: usize, encoded: Vec<u8>) -> Result<Vec<u8>> {
+        if encoded.len() < self.compression_threshold {
+            return Ok(encoded);
+        }
+        let algorithm = match self.connections.get(&id).and_then(|conn| conn.compression) {
+            Some(algorithm) => algorithm,
+            None => return Ok(encoded)
+        };
+        let data = try!(algorithm.compress(&encoded));
+        if data.len() >= encoded.len() {
+            // Incompressible, so don't make the peer decompress it
+            return Ok(encoded);
+        }
+        self.metrics.compressed_frames += 1;
+        self.metrics.compression_saved_bytes += (encoded.len() - data.len()) as u64;
+        let msg = ExternalMsg::Compressed::<T> {algorithm: algorithm, data: data};
+        self.codec.encode(&msg)
+    }
+
     fn handle_poll_notifications(&mut self, notifications: Vec<Notification>) -> Result<()> {
         trace!(self.logger, "handle_poll_notification"; "num_notifications" => notifications.len());
         let mut errors = Vec::new();
@@ -207,6 +993,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                 id if id == self.listener_id => self.accept_connection(),
                 id if id == self.timer_id => self.tick(),
                 id if id == self.executor_timer_id => self.tick_executor(),
+                id if id == self.swim_timer_id => self.tick_swim(),
+                id if id == self.heartbeat_timer_id => self.heartbeat(),
+                id if id == self.batch_timer_id && self.batching.is_some() => self.flush_batches(),
+                id if Some(id) == self.throttle_timer_id => self.write_throttled(),
                 _ => self.do_socket_io(n)
             };
 
@@ -214,6 +1004,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                 errors.push(e);
             }
         }
+        // A shutdown requested by a peer must not get lost among the other errors
+        if let Some(pos) = errors.iter().position(|e| is_shutdown(e)) {
+            return Err(errors.swap_remove(pos));
+        }
         if errors.len() != 0 {
             return Err(ErrorKind::PollNotificationErrors(errors).into());
         }
@@ -258,20 +1052,52 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn handle_decoded_message(&mut self, id: usize, msg: ExternalMsg<T>) -> Result<()> {
         match msg {
-            ExternalMsg::Members{from, orset} => {
-                info!(self.logger, "Got Members"; "id" => id, "from" => from.to_string());
+            ExternalMsg::Members{from, orset, metadata, incarnation} => {
+                info!(self.logger, "Got Members";
+                      "id" => id, "from" => from.to_string(), "incarnation" => incarnation);
+                if !self.check_incarnation(id, &from, incarnation) {
+                    self.close(id);
+                    return Ok(());
+                }
+                self.members.merge_metadata(&from, metadata);
                 self.establish_connection(id, from, orset);
                 self.check_connections();
+                try!(self.send_registry(id));
             },
+            ExternalMsg::Registry{from, registry} => try!(self.merge_registry(from, registry)),
+            ExternalMsg::Metadata{from, metadata} => self.update_metadata(from, metadata),
             ExternalMsg::Ping => {
                 trace!(self.logger, "Got Ping"; "id" => id);
                 self.reset_timer(id);
-            }
+                if let Some(node) = self.connections.get(&id).and_then(|conn| conn.node.clone()) {
+                    self.phi.heartbeat(&node, SteadyTime::now());
+                }
+                let encoded = try!(self.codec.encode(&ExternalMsg::Pong::<T>)
+                                   .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
+                try!(self.write(id, Some(encoded.into())));
+            },
+            ExternalMsg::Pong => {
+                trace!(self.logger, "Got Pong"; "id" => id);
+                if let Some(conn) = self.connections.get_mut(&id) {
+                    conn.missed_heartbeats = 0;
+                }
+            },
             ExternalMsg::Envelope(envelope) => {
+                if self.is_stale(id) {
+                    self.metrics.stale_envelopes += 1;
+                    warn!(self.logger, "Dropping envelope from a previous incarnation";
+                          "id" => id, "from" => envelope.from.to_string());
+                    return Ok(());
+                }
                 self.metrics.received_remote_envelopes += 1;
+                if envelope.is_expired(now_ms()) {
+                    self.expire(envelope);
+                    return Ok(());
+                }
                 debug!(self.logger, "Got User Message";
                        "from" => envelope.from.to_string(),
                        "to" => envelope.to.to_string());
+                self.seq_trace(&envelope, SeqTraceHop::EnterNode);
                 if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope)))
                     = self.executor_tx.send(ExecutorMsg::Envelope(envelope))
                 {
@@ -279,19 +1105,207 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                                                     Some(envelope.to)).into());
                 }
             },
+            ExternalMsg::Stream(chunk) => {
+                let (stream, bytes) = (chunk.id, chunk.data.len());
+                try!(self.deliver_stream_chunk(chunk));
+                if bytes > 0 {
+                    let msg = ExternalMsg::StreamAck::<T> {id: stream, bytes: bytes};
+                    let encoded = try!(self.codec.encode(&msg)
+                                       .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
+                    try!(self.write(id, Some(encoded.into())));
+                }
+            },
+            ExternalMsg::StreamAck {id: stream, bytes} => self.ack_stream(stream, bytes),
+            ExternalMsg::Batch(envelopes) => {
+                for envelope in envelopes {
+                    try!(self.handle_decoded_message(id, ExternalMsg::Envelope(envelope)));
+                }
+            },
             ExternalMsg::Delta(delta) => {
                 debug!(self.logger, "Got Delta mutator";
                        "id" => id, "delta" => format!("{:?}", delta));
                 if self.members.join_delta(delta.clone()) {
                     try!(self.broadcast_delta(delta));
                 }
+            },
+            ExternalMsg::Swim {from, msg} => {
+                trace!(self.logger, "Got Swim msg";
+                       "id" => id, "from" => from.to_string(), "msg" => format!("{:?}", msg));
+                try!(self.handle_swim_msg(from, msg));
+            },
+            ExternalMsg::SwimRelay {from, to, msg} => {
+                trace!(self.logger, "Got relayed Swim msg"; "id" => id, "from" => from.to_string(),
+                       "to" => to.to_string(), "msg" => format!("{:?}", msg));
+                if to == self.node {
+                    try!(self.handle_swim_msg(from, msg));
+                } else if let Some(to_id) = self.established.get(&to).cloned() {
+                    let msg = ExternalMsg::SwimRelay::<T> {from: from, to: to.clone(), msg: msg};
+                    let encoded = try!(self.codec.encode(&msg)
+                         .chain_err(|| ErrorKind::EncodeError(Some(to_id), Some(to))));
+                    try!(self.write(to_id, Some(encoded.into())));
+                }
+            },
+            ExternalMsg::Plumtree {from, msg} => {
+                let mut output = Vec::new();
+                self.plumtree.handle(from, msg, SteadyTime::now(), &mut output);
+                try!(self.send_plumtree_msgs(output));
+                for payload in self.plumtree.drain_delivered() {
+                    try!(self.deliver_broadcast(payload));
+                }
+            },
+            ExternalMsg::Reconcile {from, views} => try!(self.reconcile(from, views)),
+            ExternalMsg::Leaving {node, ..} => {
+                self.leaving.insert(node);
+            },
+            ExternalMsg::Compressed {algorithm, data} => {
+                let node = self.connections.get(&id).and_then(|conn| conn.node.clone());
+                let decompressed = try!(algorithm.decompress(&data)
+                                        .chain_err(|| ErrorKind::DecodeError(id, node.clone())));
+                let msg = try!(self.codec.decode(&decompressed)
+                               .chain_err(|| ErrorKind::DecodeError(id, node)));
+                try!(self.handle_decoded_message(id, msg));
+            },
+            ExternalMsg::Chunk {last, data} => {
+                let mut node = None;
+                let mut complete = None;
+                if let Some(conn) = self.connections.get_mut(&id) {
+                    node = conn.node.clone();
+                    conn.partial.extend_from_slice(&data);
+                    if last {
+                        complete = Some(mem::replace(&mut conn.partial, Vec::new()));
+                    } else if conn.partial.len() > MAX_FRAME_SIZE as usize {
+                        warn!(self.logger, "Chunked message exceeds the maximum frame size";
+                              "id" => id, "peer" => format!("{:?}", node));
+                        return Err(ErrorKind::ReadError(id, node).into());
+                    }
+                }
+                if let Some(encoded) = complete {
+                    let msg = try!(self.codec.decode(&encoded)
+                                   .chain_err(|| ErrorKind::DecodeError(id, node)));
+                    try!(self.handle_decoded_message(id, msg));
+                }
+            },
+            ExternalMsg::Control {from, msg} => {
+                // Only peers that exchanged members over this connection are trusted at all
+                let established = self.established.get(&from) == Some(&id);
+                let role = self.connections.get(&id)
+                    .map(|conn| self.roles.role(conn.identity.as_ref()));
+                if !established || !role.map_or(false, |role| role.can_issue_control_msgs()) {
+                    self.metrics.unauthorized_control_msgs += 1;
+                    warn!(self.logger, "Rejected control msg from unauthorized peer";
+                          "id" => id, "from" => from.to_string(), "msg" => format!("{:?}", msg));
+                    return Ok(());
+                }
+                self.metrics.control_msgs += 1;
+                info!(self.logger, "Got control msg";
+                      "id" => id, "from" => from.to_string(), "msg" => format!("{:?}", msg));
+                try!(self.handle_control_msg(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the registrations of a peer, notifying the local owners of any names that were lost
+    fn merge_registry(&mut self, from: NodeId, registry: NodeRegistry) -> Result<()> {
+        let lost = self.registry.merge(from, registry);
+        for (name, pid) in lost.iter() {
+            warn!(self.logger, "Lost global name to an older registration";
+                  "name" => name.clone(), "pid" => pid.to_string());
+            let envelope = Envelope::new(pid.clone(),
+                                         self.pid.clone(),
+                                         Msg::NameConflict(name.clone()),
+                                         None);
+            if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
+                self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+            {
+                error!(self.logger, "Failed to send to executor";
+                       "envelope" => format!("{:?}", envelope));
             }
         }
+        if lost.is_empty() {
+            self.publish_global_names();
+        } else {
+            try!(self.registry_changed());
+        }
         Ok(())
     }
 
-    fn write(&mut self, id: usize, msg: Option<Vec<u8>>) -> Result<()> {
+    /// Record the incarnation of the peer on connection `id`
+    ///
+    /// Return false if the peer is an earlier life of a node that has since restarted. If the peer
+    /// is a newer life, any connection to its previous incarnation is closed.
+    fn check_incarnation(&mut self, id: usize, from: &NodeId, incarnation: u64) -> bool {
+        let known = self.incarnations.get(from).cloned().unwrap_or(0);
+        if incarnation < known {
+            self.metrics.stale_connections += 1;
+            warn!(self.logger, "Rejecting connection from a previous incarnation";
+                  "id" => id, "peer" => from.to_string(),
+                  "incarnation" => incarnation, "current" => known);
+            return false;
+        }
+        if incarnation > known {
+            if known != 0 {
+                info!(self.logger, "Peer restarted";
+                      "peer" => from.to_string(), "incarnation" => incarnation);
+                self.events.record(format!("Peer {} restarted", from));
+            }
+            self.incarnations.insert(from.clone(), incarnation);
+            let stale: Vec<usize> = self.connections.iter()
+                .filter(|&(&conn_id, conn)| {
+                    conn_id != id && conn.node.as_ref() == Some(from) && conn.incarnation < incarnation
+                        && conn.incarnation != 0
+                }).map(|(&conn_id, _)| conn_id).collect();
+            for conn_id in stale {
+                self.metrics.stale_connections += 1;
+                self.close(conn_id);
+            }
+        }
+        if let Some(conn) = self.connections.get_mut(&id) {
+            conn.incarnation = incarnation;
+        }
+        true
+    }
+
+    /// Return true if the peer on connection `id` has restarted since the connection was made
+    fn is_stale(&self, id: usize) -> bool {
+        self.connections.get(&id).map_or(false, |conn| {
+            conn.node.as_ref()
+                .and_then(|node| self.incarnations.get(node))
+                .map_or(false, |&known| conn.incarnation < known)
+        })
+    }
+
+    fn handle_control_msg(&mut self, msg: ControlMsg) -> Result<()> {
+        match msg {
+            ControlMsg::Remove(node) => self.start_drain(node, None),
+            ControlMsg::Shutdown => {
+                // The executor shuts down the cluster server once services are drained. If it's
+                // already gone, there is nothing to wait for.
+                if self.executor_tx.send(ExecutorMsg::Shutdown).is_err() {
+                    return Err(ErrorKind::Shutdown(self.pid.clone()).into());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a control message to an established peer
+    fn send_control_msg(&mut self, node: NodeId, msg: ControlMsg) -> Result<()> {
+        let id = match self.established.get(&node).cloned() {
+            Some(id) => id,
+            None => return Err(ErrorKind::ConnectError(node).into())
+        };
+        let msg = ExternalMsg::Control::<T> {from: self.node.clone(), msg: msg};
+        let encoded = try!(self.codec.encode(&msg)
+             .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node))));
+        self.write(id, Some(encoded.into()))
+    }
+
+    fn write(&mut self, id: usize, msg: Option<Bytes>) -> Result<()> {
         trace!(self.logger, "write"; "id" => id);
+        if msg.is_some() {
+            self.peer_metrics.frames_sent.inc();
+        }
         let registrar = &self.registrar;
         if let Some(mut conn) = self.connections.get_mut(&id) {
             if msg.is_none() {
@@ -307,10 +1321,45 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                 conn.writer.writable();
             }
             try!(conn_write(id, &mut conn, msg, &registrar));
+            try!(conn_write_chunks(id, &mut conn, &registrar));
         }
+        self.update_saturation(id);
         Ok(())
     }
 
+    fn is_saturated(&self, conn: &Conn) -> bool {
+        conn.queued_bytes >= self.max_queued_bytes ||
+            conn.queued_msgs + conn.chunks.len() >= self.max_queued_msgs
+    }
+
+    /// Publish whether the outbound queue of connection `id` is full, so that `Node::send` can
+    /// reject envelopes to the peer
+    fn update_saturation(&mut self, id: usize) {
+        let (node, saturated) = match self.connections.get(&id) {
+            Some(conn) => match conn.node {
+                Some(ref node) => {
+                    self.peer_metrics.set_queue_depth(node,
+                                                      conn.queued_msgs + conn.chunks.len(),
+                                                      conn.queued_bytes);
+                    (node.clone(), self.is_saturated(conn))
+                },
+                None => return
+            },
+            None => return
+        };
+        if self.saturated.read().unwrap().contains(&node) == saturated {
+            return;
+        }
+        if saturated {
+            self.metrics.saturated_peers += 1;
+            warn!(self.logger, "Outbound queue full"; "peer" => node.to_string());
+            self.saturated.write().unwrap().insert(node);
+        } else {
+            info!(self.logger, "Outbound queue drained"; "peer" => node.to_string());
+            self.saturated.write().unwrap().remove(&node);
+        }
+    }
+
     fn reset_timer(&mut self, id: usize) {
         if let Some(conn) = self.connections.get_mut(&id) {
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
@@ -338,8 +1387,18 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
             conn.node = Some(from.clone());
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
             conn.timer_wheel_index = self.timer_wheel.insert(id);
+            self.swim.alive(&from);
+            // Start a fresh heartbeat history for the new connection
+            self.phi.remove(&from);
+            self.phi.heartbeat(&from, SteadyTime::now());
+            self.reconnects.remove(&from);
             self.established.insert(from, id);
+            if self.established.len() + 1 >= self.min_members {
+                self.ready.open();
+            }
         }
+        self.reset_bucket(id);
+        self.handle_member_changes();
     }
 
     /// We only want a single connection between nodes. Choose the connection where the client side
@@ -363,15 +1422,51 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn decode_messages(&mut self, id: usize) -> Result<Vec<ExternalMsg<T>>> {
         let mut output = Vec::new();
+        let codec = &self.codec;
+        let handshake = &self.handshake;
+        let peer_metrics = &self.peer_metrics;
         if let Some(conn) = self.connections.get_mut(&id) {
             let node = conn.node.clone();
             try!(conn.reader.read(&mut conn.sock)
                  .chain_err(|| ErrorKind::ReadError(id, node.clone())));
+            // Handshake messages that didn't fit in the socket must be written once it's writable
+            if conn.sock.wants_write() {
+                try!(self.registrar.reregister(id, &conn.sock, Event::Both)
+                     .chain_err(|| ErrorKind::RegistrarError(Some(id), node.clone())));
+            }
+            // Record the certificate of the peer as soon as the TLS handshake completes, before
+            // any of its messages are handled
+            if conn.identity.is_none() && !conn.sock.is_handshaking() {
+                conn.identity = conn.sock.peer_identity();
+            }
 
             for frame in conn.reader.iter_mut() {
-                let mut decoder = Deserializer::new(&frame[..]);
-                let msg = try!(Deserialize::deserialize(&mut decoder)
+                peer_metrics.frames_received.inc();
+                if conn.handshake.is_none() {
+                    let mut ours = handshake.clone();
+                    ours.tls = conn.sock.is_tls();
+                    let theirs = match Handshake::decode(&frame) {
+                        Ok(theirs) => ours.check(&theirs).map_or(Ok(theirs), Err),
+                        Err(_) => Err("the first frame is not a handshake, the peer probably runs \
+                                       an older version of rabble".to_string())
+                    };
+                    let theirs = match theirs {
+                        Ok(theirs) => theirs,
+                        Err(reason) => {
+                            self.metrics.incompatible_peers += 1;
+                            peer_metrics.handshake_failures.inc();
+                            return Err(ErrorKind::IncompatiblePeer(id, node.clone(), reason).into());
+                        }
+                    };
+                    conn.compression = Compression::negotiate(&handshake.compression,
+                                                              &theirs.compression);
+                    conn.handshake = Some(theirs);
+                    continue;
+                }
+                let start = precise_time_ns();
+                let msg = try!(codec.decode(&frame)
                                .chain_err(|| ErrorKind::DecodeError(id, node.clone())));
+                peer_metrics.decode_time.record(precise_time_ns() - start);
                 output.push(msg);
             }
         }
@@ -379,12 +1474,30 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     }
 
     fn join(&mut self, node: NodeId) -> Result<()> {
+        self.leaving.remove(&node);
+        // An explicit join retries even a peer we gave up on
+        self.reconnects.remove(&node);
         let delta = self.members.add(node.clone());
         try!(self.broadcast_delta(delta));
+        self.swim.join(&node);
         self.metrics.connection_attempts += 1;
+        self.schedule_reconnect(&node, SteadyTime::now());
         self.connect(node)
     }
 
+    /// Join the configured seed nodes. If a seed isn't reachable yet, it remains a member and
+    /// connection attempts are retried in `check_connections`.
+    fn join_seeds(&mut self) {
+        let seeds: Vec<NodeId> = self.seeds.iter().filter(|&s| *s != self.node).cloned().collect();
+        for seed in seeds {
+            info!(self.logger, "Joining seed node"; "seed" => seed.to_string());
+            self.metrics.joins += 1;
+            if let Err(e) = self.join(seed) {
+                warn!(self.logger, e.to_string());
+            }
+        }
+    }
+
     fn leave(&mut self, node: NodeId) -> Result<()> {
         if let Some(delta) = self.members.leave(node.clone()) {
             try!(self.broadcast_delta(delta));
@@ -392,22 +1505,101 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
+    /// Gracefully remove a node from the cluster
+    ///
+    /// The leave is announced to all members, which stop sending new envelopes to the node. Once
+    /// all messages already queued for the node are written, or `DRAIN_TIMEOUT` passes, the node is
+    /// removed from membership and its connections are closed. If this node is the one leaving, all
+    /// of its connections are drained.
+    fn start_drain(&mut self, node: NodeId, correlation_id: Option<CorrelationId>) -> Result<()> {
+        info!(self.logger, "Draining connections before leave"; "node" => node.to_string());
+        self.leaving.insert(node.clone());
+        self.drains.insert(node.clone(), Drain {
+            correlation_id: correlation_id,
+            started: SteadyTime::now()
+        });
+        let msg = ExternalMsg::Leaving::<T> {from: self.node.clone(), node: node};
+        let encoded = try!(self.codec.encode(&msg)
+             .chain_err(|| ErrorKind::EncodeError(None, None)));
+        self.broadcast_tree(encoded)
+    }
+
+    /// Complete any graceful leaves whose connections have been flushed or timed out
+    fn check_drains(&mut self) -> Result<()> {
+        if self.drains.is_empty() {
+            return Ok(());
+        }
+        let now = SteadyTime::now();
+        let timeout = Duration::milliseconds(DRAIN_TIMEOUT as i64);
+        let done: Vec<NodeId> = self.drains.iter().filter(|&(node, drain)| {
+            self.is_flushed(node) || now - drain.started > timeout
+        }).map(|(node, _)| node.clone()).collect();
+        for node in done {
+            let drain = self.drains.remove(&node).unwrap();
+            if !self.is_flushed(&node) {
+                warn!(self.logger, "Timed out draining connections before leave";
+                      "node" => node.to_string());
+            }
+            try!(self.leave(node.clone()));
+            if let Some(correlation_id) = drain.correlation_id {
+                let envelope = Envelope::new(correlation_id.pid.clone(),
+                                             self.pid.clone(),
+                                             Msg::LeaveComplete(node),
+                                             Some(correlation_id));
+                if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope))) =
+                    self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+                {
+                    return Err(ErrorKind::SendError("ExecutorMsg::Envelope".to_string(),
+                                                    Some(envelope.to)).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if there is no unwritten data queued for `node`
+    fn is_flushed(&self, node: &NodeId) -> bool {
+        if *node == self.node {
+            return self.connections.values().all(|conn| conn.flushed);
+        }
+        self.established.get(node)
+            .and_then(|id| self.connections.get(id))
+            .map_or(true, |conn| conn.flushed)
+    }
+
     fn connect(&mut self, node: NodeId) -> Result<()> {
         debug!(self.logger, "connect"; "to" => node.to_string());
-        let sock = try!(TcpBuilder::new_v4().chain_err(|| "Failed to create a IPv4 socket"));
+        if let Some(path) = unix_path(&node.addr).map(|path| path.to_string()) {
+            // Connecting to a local socket doesn't block
+            let sock = try!(UnixStream::connect(&path)
+                            .chain_err(|| ErrorKind::ConnectError(node.clone())));
+            try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+            try!(self.init_connection(Stream::Unix(sock), Some(node)));
+            return Ok(());
+        }
+        if let Some(ref proxy) = self.proxy {
+            let sock = try!(proxy.connect(&node.addr, self.socket_options.connect_timeout)
+                            .chain_err(|| ErrorKind::ConnectError(node.clone())));
+            try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+            try!(self.init_connection(Stream::Tcp(sock), Some(node)));
+            return Ok(());
+        }
+        let addr = try!(resolve(&node.addr).chain_err(|| ErrorKind::ConnectError(node.clone())));
+        let sock = if addr.is_ipv4() { TcpBuilder::new_v4() } else { TcpBuilder::new_v6() };
+        let sock = try!(sock.chain_err(|| "Failed to create a TCP socket"));
         let sock = try!(sock.to_tcp_stream().chain_err(|| "Failed to create TcpStream"));
         try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
-        if let Err(e) = sock.connect(&node.addr[..]) {
+        if let Err(e) = sock.connect(addr) {
             if e.raw_os_error().is_some() && *e.raw_os_error().as_ref().unwrap() != EINPROGRESS {
                 return Err(e).chain_err(|| ErrorKind::ConnectError(node));
             }
         }
-        try!(self.init_connection(sock, Some(node)));
+        try!(self.init_connection(Stream::Tcp(sock), Some(node)));
         Ok(())
     }
 
     fn accept_connection(&mut self) -> Result<()> {
-        while let Ok((sock, _)) = self.listener.accept() {
+        while let Ok(sock) = self.listener.accept() {
             self.metrics.accepted_connections += 1;
             debug!(self.logger, "accepted connection");
             try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
@@ -417,25 +1609,40 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
-    fn init_connection(&mut self, sock: TcpStream, node: Option<NodeId>) -> Result<usize> {
+    fn init_connection(&mut self, sock: Stream, node: Option<NodeId>) -> Result<usize> {
+        try!(self.socket_options.apply(&sock).chain_err(|| "Failed to set socket options"));
         let id = try!(self.registrar.register(&sock, Event::Read)
                       .chain_err(|| ErrorKind::RegistrarError(None, None)));
         debug!(self.logger, "init_connection()";
                "id" => id, "is_client" => node.is_some(), "peer" => format!("{:?}", node));
         let is_client = node.is_some();
+        let sock = match (sock, &self.tls, &node) {
+            (Stream::Tcp(sock), &Some(ref tls), &Some(ref peer)) =>
+                Transport::Tls(sock, tls.client_session(peer)),
+            (Stream::Tcp(sock), &Some(ref tls), &None) => Transport::Tls(sock, tls.server_session()),
+            // Unix domain sockets are protected by file permissions rather than TLS
+            (sock, _, _) => Transport::Plain(sock)
+        };
         let mut conn = Conn::new(sock, node, is_client);
         conn.timer_wheel_index = self.timer_wheel.insert(id);
         self.connections.insert(id, conn);
         Ok(id)
     }
 
+    /// Send the handshake followed by our members. This is always the first thing sent on a
+    /// connection.
     fn send_members(&mut self, id: usize) -> Result<()> {
+        let mut handshake = self.handshake.clone();
+        handshake.tls = self.connections.get(&id).map_or(false, |conn| conn.sock.is_tls());
+        let handshake = try!(handshake.encode().chain_err(|| ErrorKind::EncodeError(Some(id), None)));
         let encoded = try!(self.encode_members(id));
         let registrar = &self.registrar;
         if let Some(mut conn) = self.connections.get_mut(&id) {
             info!(self.logger, "Send members"; "id" => id);
-            try!(conn_write(id, &mut conn, Some(encoded), &registrar));
+            try!(conn_write(id, &mut conn, Some(handshake.into()), &registrar));
+            try!(conn_write(id, &mut conn, Some(encoded.into()), &registrar));
             conn.members_sent = true;
+            self.peer_metrics.frames_sent.add(2);
         }
         Ok(())
     }
@@ -444,9 +1651,90 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         trace!(self.logger, "tick");
         let expired = self.timer_wheel.expire();
         self.deregister(expired);
-        try!(self.broadcast_pings());
+        self.reload_tls();
+        self.check_connect_timeouts();
         self.check_connections();
-        Ok(())
+        self.check_phi();
+        self.check_split_brain();
+        self.peer_metrics.registry().tick();
+        let mut output = Vec::new();
+        self.plumtree.tick(SteadyTime::now(), &mut output);
+        self.send_plumtree_msgs(output)
+    }
+
+    /// Pick up rotated TLS certificates
+    fn reload_tls(&mut self) {
+        if let Some(ref mut tls) = self.tls {
+            match tls.reload_if_changed() {
+                Ok(true) => {
+                    self.metrics.tls_reloads += 1;
+                    info!(self.logger, "Reloaded TLS certificates");
+                },
+                Ok(false) => (),
+                Err(e) => warn!(self.logger, "Failed to reload TLS certificates";
+                                "error" => e.to_string())
+            }
+        }
+    }
+
+    /// Resolve a partition using the configured strategy once the set of unreachable members has
+    /// been stable for `split_brain_stable_after` ms.
+    fn check_split_brain(&mut self) {
+        let strategy = match self.split_brain {
+            Some(ref strategy) => strategy.clone(),
+            None => return
+        };
+        let all = self.members.all();
+        let mut reachable: HashSet<NodeId> = self.established.keys().cloned().collect();
+        reachable.insert(self.node.clone());
+        let unreachable: HashSet<NodeId> = all.difference(&reachable).cloned().collect();
+        if !all.contains(&self.node) || unreachable.is_empty() {
+            self.unreachable = None;
+            return;
+        }
+
+        let now = SteadyTime::now();
+        let since = match self.unreachable {
+            Some((ref prev, since)) if *prev == unreachable => since,
+            _ => {
+                self.unreachable = Some((unreachable, now));
+                return;
+            }
+        };
+        if now - since < Duration::milliseconds(self.split_brain_stable_after as i64) {
+            return;
+        }
+        self.unreachable = None;
+        self.metrics.split_brain_resolutions += 1;
+
+        let downed: Vec<NodeId> = match strategy.decide(&all, &reachable, self.members.metadata()) {
+            SplitBrainDecision::DownUnreachable => unreachable.into_iter().collect(),
+            SplitBrainDecision::DownSelf => vec![self.node.clone()]
+        };
+        for node in downed {
+            self.events.record(format!("Downing {} to resolve a partition", node));
+            warn!(self.logger, "Downing node to resolve partition";
+                  "node" => node.to_string(), "strategy" => format!("{:?}", strategy),
+                  "reachable" => reachable.len(), "members" => all.len());
+            self.notify_subscribers(Msg::Downed(node.clone()));
+            if let Err(e) = self.leave(node) {
+                warn!(self.logger, e.to_string());
+            }
+        }
+    }
+
+    /// Suspect any established peers whose heartbeats have been missing for too long, given their
+    /// history. The failure detector will then probe them and fail them if they don't respond.
+    fn check_phi(&mut self) {
+        let now = SteadyTime::now();
+        let suspected: Vec<NodeId> = self.established.keys().filter(|node| {
+            self.phi.phi(node, now).map_or(false, |phi| phi > self.phi_threshold)
+        }).cloned().collect();
+        for node in suspected {
+            debug!(self.logger, "Phi threshold exceeded"; "peer" => node.to_string());
+            self.swim.suspect(&node);
+        }
+        self.handle_member_changes();
     }
 
     fn tick_executor(&mut self) -> Result<()> {
@@ -456,33 +1744,179 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         Ok(())
     }
 
+    fn handle_swim_msg(&mut self, from: NodeId, msg: SwimMsg) -> Result<()> {
+        if let SwimMsg::Ack {seq, ..} = msg {
+            self.record_rtt(&from, seq);
+        }
+        let mut output = Vec::new();
+        self.swim.handle(from, msg, &mut output);
+        let result = self.send_swim_msgs(output);
+        self.handle_member_changes();
+        result
+    }
+
+    fn tick_swim(&mut self) -> Result<()> {
+        let mut output = Vec::new();
+        self.swim.tick(&mut output);
+        let result = self.send_swim_msgs(output);
+        self.handle_member_changes();
+        result
+    }
+
+    /// Send failure detector messages to their members
+    ///
+    /// Messages to members without an established connection are relayed through a random peer,
+    /// which forwards them if it's connected to the member. Otherwise they are dropped, which the
+    /// failure detector treats as a lost probe.
+    fn send_swim_msgs(&mut self, msgs: Vec<(NodeId, SwimMsg)>) -> Result<()> {
+        let mut errors = Vec::new();
+        for (node, msg) in msgs {
+            match msg {
+                SwimMsg::Ping {seq, ..} => {
+                    self.metrics.probes_sent += 1;
+                    self.probes_sent.insert(node.clone(), (seq, SteadyTime::now()));
+                },
+                SwimMsg::PingReq {..} => self.metrics.indirect_probes_sent += 1,
+                _ => ()
+            }
+            let (id, msg) = match self.established.get(&node).cloned() {
+                Some(id) => (id, ExternalMsg::Swim::<T> {from: self.node.clone(), msg: msg}),
+                None => {
+                    let relays: Vec<usize> = self.established.values().cloned().collect();
+                    match rand::thread_rng().choose(&relays) {
+                        Some(id) => (*id, ExternalMsg::SwimRelay::<T> {
+                            from: self.node.clone(),
+                            to: node.clone(),
+                            msg: msg
+                        }),
+                        None => continue
+                    }
+                }
+            };
+            let encoded = match self.codec.encode(&msg)
+                .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node.clone())))
+            {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.write(id, Some(encoded.into())) {
+                errors.push(e);
+            }
+        }
+        if errors.len() != 0 {
+            return Err(ErrorKind::BroadcastError(errors).into());
+        }
+        Ok(())
+    }
+
+    /// Log and act upon any changes in membership and member liveness detected by the failure
+    /// detector.
+    ///
+    /// Nodes the failure detector learned about from other members are joined. Failed members are
+    /// removed from the cluster, unless a split brain strategy is configured, in which case it
+    /// decides which members to down and connections to failed members are only closed. They will
+    /// be re-established once the member becomes reachable again.
+    fn handle_member_changes(&mut self) {
+        let all = self.members.all();
+        for node in self.swim.drain_joins() {
+            if all.contains(&node) {
+                continue;
+            }
+            info!(self.logger, "Member joined"; "peer" => node.to_string());
+            self.events.record(format!("Member {} joined", node));
+            if let Err(e) = self.join(node) {
+                warn!(self.logger, e.to_string());
+            }
+        }
+        for change in self.swim.drain_changes() {
+            self.events.record(format!("Member {} is {:?} at incarnation {}",
+                                       change.node, change.state, change.incarnation));
+            match change.state {
+                MemberState::Alive => {
+                    info!(self.logger, "Member alive";
+                          "peer" => change.node.to_string(), "incarnation" => change.incarnation);
+                },
+                MemberState::Suspect => {
+                    self.metrics.suspicions += 1;
+                    warn!(self.logger, "Member suspected";
+                          "peer" => change.node.to_string(), "incarnation" => change.incarnation);
+                },
+                MemberState::Failed => {
+                    self.metrics.failures += 1;
+                    warn!(self.logger, "Member failed";
+                          "peer" => change.node.to_string(), "incarnation" => change.incarnation);
+                    if let Some(id) = self.established.get(&change.node).cloned() {
+                        self.close(id);
+                    }
+                    if self.split_brain.is_none() {
+                        if let Err(e) = self.leave(change.node) {
+                            warn!(self.logger, e.to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     fn encode_members(&self, id: usize) -> Result<Vec<u8>> {
         let orset = self.members.get_orset();
-        let mut encoded = Vec::new();
-        let msg = ExternalMsg::Members::<T> {from: self.node.clone(), orset: orset};
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
+        let msg = ExternalMsg::Members::<T> {
+            from: self.node.clone(),
+            orset: orset,
+            metadata: self.members.metadata().clone(),
+            incarnation: self.incarnation
+        };
+        let encoded = try!(self.codec.encode(&msg)
              .chain_err(|| ErrorKind::EncodeError(Some(id), None)));
         Ok(encoded)
     }
 
     fn deregister(&mut self, expired: HashSet<usize>) {
+        let now = SteadyTime::now();
         for id in expired.iter() {
+            if self.tolerate_timeout(*id, now) {
+                if let Some(conn) = self.connections.get_mut(id) {
+                    conn.timer_wheel_index = self.timer_wheel.insert(*id);
+                }
+                continue;
+            }
             warn!(self.logger, "Connection timeout"; "id" => *id);
             self.close(*id);
         }
     }
 
+    /// Established connections to peers with a history of jittery heartbeats are not closed on a
+    /// fixed timeout. They are kept open until phi crosses the threshold.
+    fn tolerate_timeout(&self, id: usize, now: SteadyTime) -> bool {
+        let node = match self.connections.get(&id).and_then(|conn| conn.node.as_ref()) {
+            Some(node) => node,
+            None => return false
+        };
+        if self.established.get(node) != Some(&id) {
+            return false;
+        }
+        self.phi.phi(node, now).map_or(false, |phi| phi <= self.phi_threshold)
+    }
+
     /// Close an existing connection and remove all related state.
     fn close(&mut self, id: usize) {
+        self.batches.remove(&id);
         if let Some(conn) = self.connections.remove(&id) {
             let _ = self.registrar.deregister(conn.sock);
             self.timer_wheel.remove(&id, conn.timer_wheel_index);
             if let Some(node) = conn.node {
+                self.saturated.write().unwrap().remove(&node);
+                self.peer_metrics.set_queue_depth(&node, 0, 0);
                 // Remove established connection if it matches this id
                 if let Some(established_id) = self.established.remove(&node) {
                     if established_id == id {
                         info!(self.logger, "Closing established connection";
                               "id" => id,"peer" => node.to_string());
+                        self.phi.remove(&node);
+                        self.close_streams(&node);
                         return;
                     }
                     // The established node didn't correspond to this id, so put it back
@@ -495,23 +1929,246 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
 
     fn broadcast_delta(&mut self, delta: Delta<NodeId>) -> Result<()> {
         debug!(self.logger, "Broadcasting delta"; "delta" => format!("{:?}", delta));
-        let mut encoded = Vec::new();
         let msg = ExternalMsg::Delta::<T>(delta);
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
+        let encoded = try!(self.codec.encode(&msg)
              .chain_err(|| ErrorKind::EncodeError(None, None)));
-        self.broadcast(encoded)
+        self.broadcast_tree(encoded)
+    }
+
+    /// Disseminate an encoded `ExternalMsg` to all other members over the broadcast tree
+    fn broadcast_tree(&mut self, encoded: Vec<u8>) -> Result<()> {
+        let mut output = Vec::new();
+        self.plumtree.broadcast(encoded, &mut output);
+        self.send_plumtree_msgs(output)
+    }
+
+    /// Send broadcast tree messages to established peers
+    fn send_plumtree_msgs(&mut self, msgs: Vec<(NodeId, PlumtreeMsg)>) -> Result<()> {
+        let mut errors = Vec::new();
+        // A payload pushed to several peers is the same message for each of them, so it's only
+        // encoded once and the buffer is shared by all the connections
+        let mut encoded_gossip: HashMap<(BroadcastId, u32), Bytes> = HashMap::new();
+        for (node, msg) in msgs {
+            let gossip_key = match msg {
+                PlumtreeMsg::Graft {..} => {
+                    self.metrics.broadcast_grafts += 1;
+                    None
+                },
+                PlumtreeMsg::Prune => {
+                    self.metrics.broadcast_prunes += 1;
+                    None
+                },
+                PlumtreeMsg::Gossip {ref id, round, ..} => Some((id.clone(), round)),
+                PlumtreeMsg::IHave {..} => None
+            };
+            if let Some(id) = self.established.get(&node).cloned() {
+                let shared = gossip_key.as_ref().and_then(|key| encoded_gossip.get(key)).cloned();
+                if let Some(encoded) = shared {
+                    if let Err(e) = self.write(id, Some(encoded)) {
+                        errors.push(e);
+                    }
+                    continue;
+                }
+                let msg = ExternalMsg::Plumtree::<T> {from: self.node.clone(), msg: msg};
+                let encoded = match self.codec.encode(&msg)
+                    .chain_err(|| ErrorKind::EncodeError(Some(id), Some(node.clone())))
+                {
+                    Ok(encoded) => encoded,
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                };
+                let encoded = Bytes::from(encoded);
+                if let Some(key) = gossip_key {
+                    encoded_gossip.insert(key, encoded.clone());
+                }
+                if let Err(e) = self.write(id, Some(encoded)) {
+                    errors.push(e);
+                }
+            }
+        }
+        if errors.len() != 0 {
+            return Err(ErrorKind::BroadcastError(errors).into());
+        }
+        Ok(())
+    }
+
+    /// Handle a message received over the broadcast tree
+    ///
+    /// The tree already forwards the message to other members, so it must not be broadcast again.
+    fn deliver_broadcast(&mut self, payload: Vec<u8>) -> Result<()> {
+        let msg = try!(self.codec.decode(&payload).chain_err(|| ErrorKind::DecodeError(0, None)));
+        match msg {
+            ExternalMsg::Delta(delta) => {
+                debug!(self.logger, "Got broadcast Delta mutator"; "delta" => format!("{:?}", delta));
+                self.members.join_delta(delta);
+                Ok(())
+            },
+            ExternalMsg::Registry {from, registry} => self.merge_registry(from, registry),
+            ExternalMsg::Metadata {from, metadata} => {
+                self.update_metadata(from, metadata);
+                Ok(())
+            },
+            ExternalMsg::Envelope(envelope) => self.deliver_local(envelope),
+            ExternalMsg::Leaving {from, node} => {
+                info!(self.logger, "Node is leaving";
+                      "node" => node.to_string(), "from" => from.to_string());
+                self.leaving.insert(node);
+                Ok(())
+            },
+            msg => {
+                warn!(self.logger, "Ignoring unexpected broadcast"; "msg" => format!("{:?}", msg));
+                Ok(())
+            }
+        }
+    }
+
+    /// Deliver a broadcast envelope to the process with the same name on this node
+    fn deliver_local(&mut self, mut envelope: Envelope<T>) -> Result<()> {
+        envelope.to.node = self.node.clone();
+        envelope.to.generation = None;
+        if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope)))
+            = self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+        {
+            return Err(ErrorKind::SendError("ExecutorMsg::Envelope".to_string(),
+                                            Some(envelope.to)).into());
+        }
+        Ok(())
+    }
+
+    /// Send a chunk of a stream opened on this node to its receiving node
+    fn send_stream_chunk(&mut self, chunk: StreamChunk) -> Result<()> {
+        let node = match self.streams.get(&chunk.id) {
+            Some(&(ref node, _)) => node.clone(),
+            // The stream was closed, and its handle will find out on the next write
+            None => return Ok(())
+        };
+        self.metrics.stream_chunks_sent += 1;
+        let (stream, bytes) = (chunk.id, chunk.data.len());
+        if chunk.last {
+            self.streams.remove(&stream);
+        }
+        if node == self.node {
+            try!(self.deliver_stream_chunk(chunk));
+            self.ack_stream(stream, bytes);
+            return Ok(());
+        }
+        match self.established.get(&node).cloned() {
+            Some(id) => self.send_envelopes(id, node, ExternalMsg::Stream(chunk), Priority::Normal),
+            None => {
+                self.close_streams(&node);
+                Ok(())
+            }
+        }
+    }
+
+    /// Hand a chunk of a stream to the executor as an envelope to the receiving process
+    fn deliver_stream_chunk(&mut self, chunk: StreamChunk) -> Result<()> {
+        self.metrics.stream_chunks_received += 1;
+        let msg = Msg::StreamChunk {
+            stream: chunk.id,
+            seq: chunk.seq,
+            data: chunk.data,
+            last: chunk.last
+        };
+        let envelope = Envelope::new(chunk.to, chunk.from, msg, None);
+        if let Err(mpsc::SendError(ExecutorMsg::Envelope(envelope)))
+            = self.executor_tx.send(ExecutorMsg::Envelope(envelope))
+        {
+            return Err(ErrorKind::SendError("ExecutorMsg::Envelope".to_string(),
+                                            Some(envelope.to)).into());
+        }
+        Ok(())
+    }
+
+    /// Open up the window of a stream once the receiving node has acknowledged `bytes` of it
+    fn ack_stream(&mut self, stream: StreamId, bytes: usize) {
+        if let Some(&(_, ref state)) = self.streams.get(&stream) {
+            state.unacked.fetch_sub(bytes, Ordering::AcqRel);
+        }
+    }
+
+    /// Fail all unfinished streams to `node`, since chunks already sent to it may have been lost
+    fn close_streams(&mut self, node: &NodeId) {
+        let closed: Vec<StreamId> = self.streams.iter()
+            .filter(|&(_, &(ref to, _))| to == node)
+            .map(|(stream, _)| *stream)
+            .collect();
+        for stream in closed {
+            let (_, state) = self.streams.remove(&stream).unwrap();
+            state.closed.store(true, Ordering::Release);
+            self.metrics.closed_streams += 1;
+        }
+    }
+
+    /// Publish the new global names locally and replicate our registrations to all peers
+    fn registry_changed(&mut self) -> Result<()> {
+        self.publish_global_names();
+        let encoded = try!(self.encode_registry(None));
+        self.broadcast_tree(encoded)
+    }
+
+    fn send_registry(&mut self, id: usize) -> Result<()> {
+        let encoded = try!(self.encode_registry(Some(id)));
+        self.write(id, Some(encoded.into()))
+    }
+
+    fn encode_registry(&self, id: Option<usize>) -> Result<Vec<u8>> {
+        let msg = ExternalMsg::Registry::<T> {
+            from: self.node.clone(),
+            registry: self.registry.local().clone()
+        };
+        let encoded = try!(self.codec.encode(&msg)
+             .chain_err(|| ErrorKind::EncodeError(id, None)));
+        Ok(encoded)
+    }
+
+    fn update_metadata(&mut self, from: NodeId, metadata: NodeMetadata) {
+        if self.members.update_metadata(from.clone(), metadata) {
+            debug!(self.logger, "Got newer metadata"; "from" => from.to_string());
+        }
+    }
+
+    fn publish_global_names(&self) {
+        *self.global_names.write().unwrap() = self.registry.names();
+    }
+
+    /// Close connections whose peer stopped answering heartbeats, and send a heartbeat over the
+    /// rest
+    ///
+    /// Receiving pings from a peer doesn't prove that our writes reach it, so each side tracks the
+    /// pongs answering its own pings.
+    fn heartbeat(&mut self) -> Result<()> {
+        let mut dead = Vec::new();
+        for (id, conn) in self.connections.iter_mut() {
+            if !conn.members_sent || conn.handshake.is_none() {
+                continue;
+            }
+            if conn.missed_heartbeats >= self.max_missed_heartbeats {
+                dead.push((*id, conn.node.clone()));
+            } else {
+                conn.missed_heartbeats += 1;
+            }
+        }
+        for (id, node) in dead {
+            self.metrics.heartbeat_timeouts += 1;
+            warn!(self.logger, "Closing connection after missed heartbeats";
+                  "id" => id, "peer" => format!("{:?}", node));
+            self.close(id);
+        }
+        self.broadcast_pings()
     }
 
     fn broadcast_pings(&mut self) -> Result<()> {
-        let mut encoded = Vec::new();
         let msg = ExternalMsg::Ping::<T>;
-        try!(msg.serialize(&mut Serializer::new(&mut encoded))
+        let encoded = try!(self.codec.encode(&msg)
              .chain_err(|| ErrorKind::EncodeError(None, None)));
-        self.broadcast(encoded)
+        self.broadcast(encoded.into())
     }
 
     // Write encoded values to all connections and return the id of any connections with errors
-    fn broadcast(&mut self, encoded: Vec<u8>) -> Result<()> {
+    fn broadcast(&mut self, encoded: Bytes) -> Result<()> {
         let mut errors = Vec::new();
         let registrar = &self.registrar;
         for (id, mut conn) in self.connections.iter_mut() {
@@ -532,6 +2189,11 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     // Ensure connections are correct based on membership state
     fn check_connections(&mut self) {
         let all = self.members.all();
+        self.swim.set_members(&all);
+        self.members.prune_metadata();
+        if self.registry.prune(&all) {
+            self.publish_global_names();
+        }
 
         // If this node is no longer a member of the cluster disconnect from all nodes
         if !all.contains(&self.node) {
@@ -551,8 +2213,24 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
                "to_connect" => format!("{:?}", to_connect),
                "to_disconnect" => format!("{:?}", to_disconnect));
 
+        let now = SteadyTime::now();
+        self.reconnects.retain(|node, _| all.contains(node));
+        self.leaving.retain(|node| all.contains(node));
+        self.probes_sent.retain(|node, _| all.contains(node));
+        self.rtts.retain(|node, _| all.contains(node));
         for node in to_connect {
+            if self.reconnects.get(&node).map_or(false, |r| now < r.next_attempt) {
+                continue;
+            }
+            if self.reconnect_exhausted(&node) {
+                continue;
+            }
             self.metrics.connection_attempts += 1;
+            if self.peer_states.contains_key(&node) {
+                self.metrics.reconnect_attempts += 1;
+                self.peer_metrics.reconnect(&node);
+            }
+            self.schedule_reconnect(&node, now);
             if let Err(e) = self.connect(node) {
                 warn!(self.logger, e.to_string());
             }
@@ -561,6 +2239,61 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         self.disconnect_established(to_disconnect);
     }
 
+    /// Record a connection attempt to `node` and back off before trying again. The backoff is
+    /// reset once a connection is established.
+    fn schedule_reconnect(&mut self, node: &NodeId, now: SteadyTime) {
+        let reconnect = self.reconnects.entry(node.clone()).or_insert(Reconnect {
+            attempts: 0,
+            next_attempt: now,
+            abandoned: false
+        });
+        reconnect.attempts += 1;
+        let delay = self.reconnect_policy.backoff.delay(reconnect.attempts);
+        reconnect.next_attempt = now + Duration::milliseconds(delay as i64);
+        debug!(self.logger, "Scheduled next connection attempt";
+               "peer" => node.to_string(), "attempt" => reconnect.attempts, "delay_ms" => delay);
+    }
+
+    /// Close outgoing connections that weren't established within the connect timeout, so that
+    /// `check_connections` tries again
+    fn check_connect_timeouts(&mut self) {
+        let timeout = Duration::milliseconds(self.socket_options.connect_timeout as i64);
+        let now = SteadyTime::now();
+        let expired: Vec<usize> = self.connections.iter()
+            .filter(|&(_, conn)| conn.is_client && conn.handshake.is_none())
+            .filter(|&(_, conn)| now - conn.created_at > timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.metrics.connect_timeouts += 1;
+            warn!(self.logger, "Connect timeout"; "id" => id);
+            self.close(id);
+        }
+    }
+
+    /// Return true if we gave up connecting to `node` because `max_attempts` attempts in a row
+    /// failed. The first time this happens is logged and counted.
+    fn reconnect_exhausted(&mut self, node: &NodeId) -> bool {
+        let max_attempts = match self.reconnect_policy.max_attempts {
+            Some(max_attempts) => max_attempts,
+            None => return false
+        };
+        let reconnect = match self.reconnects.get_mut(node) {
+            Some(reconnect) => reconnect,
+            None => return false
+        };
+        if reconnect.attempts < max_attempts {
+            return false;
+        }
+        if !reconnect.abandoned {
+            reconnect.abandoned = true;
+            self.metrics.abandoned_reconnects += 1;
+            warn!(self.logger, "Giving up on connecting to peer";
+                  "peer" => node.to_string(), "attempts" => reconnect.attempts);
+        }
+        true
+    }
+
     fn disconnect_all(&mut self) {
         self.established = HashMap::new();
         for (id, conn) in self.connections.drain() {
@@ -587,14 +2320,26 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
         }
     }
 
+    /// Report a hop of an envelope to the collector of its sequence trace, if it has one
+    fn seq_trace(&self, envelope: &Envelope<T>, hop: SeqTraceHop) {
+        if let Some(report) = seq_trace_event(envelope, hop, &self.node, &self.pid) {
+            // Route the report through the executor since it knows how to contact all Pids
+            if let Err(mpsc::SendError(ExecutorMsg::Envelope(report))) =
+                self.executor_tx.send(ExecutorMsg::Envelope(report))
+            {
+                error!(self.logger, "Failed to send to executor";
+                       "envelope" => format!("{:?}", report));
+            }
+        }
+    }
+
     fn send_metrics(&mut self, envelope: Envelope<T>) {
-        if let Msg::GetMetrics = envelope.msg {
-            let new_envelope = Envelope {
-                to: envelope.from,
-                from: self.pid.clone(),
-                msg: Msg::Metrics(self.metrics.data()),
-                correlation_id: envelope.correlation_id
-            };
+        if let Msg::GetMetrics(ref filter) = envelope.msg {
+            let data = filter_metrics(filter.as_ref(), self.metrics.data());
+            let new_envelope = Envelope::new(envelope.reply_address().clone(),
+                                             self.pid.clone(),
+                                             Msg::Metrics(data),
+                                             envelope.correlation_id.clone());
             // Route the response through the executor since it knows how to contact all Pids
             if let Err(mpsc::SendError(ExecutorMsg::Envelope(new_envelope))) =
                 self.executor_tx.send(ExecutorMsg::Envelope(new_envelope))
@@ -609,14 +2354,61 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> ClusterServer<T> {
     }
 }
 
+/// Return the ring weight advertised by each member. Members that don't advertise one get 1.
+fn ring_weights(members: &Members) -> HashMap<NodeId, u32> {
+    let metadata = members.metadata();
+    members.all().into_iter().map(|node| {
+        let weight = metadata.get(&node)
+            .and_then(|m| m.get(WEIGHT_KEY))
+            .and_then(|w| w.parse().ok())
+            .unwrap_or(1);
+        (node, weight)
+    }).collect()
+}
+
+fn is_shutdown(e: &Error) -> bool {
+    if let ErrorKind::Shutdown(_) = *e.kind() {
+        return true;
+    }
+    false
+}
+
+/// Write the queued chunks of large messages for as long as the socket keeps up. Once it doesn't,
+/// the rest wait for it to become writable, so messages written in the meantime go out first.
+///
+/// Throttled peers are only written to while their bucket has tokens left.
+fn conn_write_chunks(id: usize, conn: &mut Conn, registrar: &Registrar) -> Result<()> {
+    let now = SteadyTime::now();
+    while conn.flushed {
+        let len = match conn.chunks.front() {
+            Some(frame) => frame.data.len(),
+            None => break
+        };
+        if !conn.bucket.as_mut().map_or(true, |bucket| bucket.take(len, now)) {
+            break;
+        }
+        let chunk = conn.chunks.pop_front().map(|frame| frame.data);
+        try!(conn_write(id, conn, chunk, registrar));
+    }
+    Ok(())
+}
+
 fn conn_write(id: usize,
               conn: &mut Conn,
-              msg: Option<Vec<u8>>,
+              msg: Option<Bytes>,
               registrar: &Registrar) -> Result<()>
 {
+        let len = msg.as_ref().map_or(0, |msg| msg.len());
+        let msg_count = if msg.is_some() { 1 } else { 0 };
         let writable = try!(conn.writer.write(&mut conn.sock, msg).chain_err(|| {
             ErrorKind::WriteError(id, conn.node.clone())
         }));
+        // Encrypted data may still be buffered in the TLS session
+        try!(conn.sock.flush().chain_err(|| ErrorKind::WriteError(id, conn.node.clone())));
+        let writable = writable && !conn.sock.wants_write();
+        conn.flushed = writable;
+        conn.queued_bytes = if writable { 0 } else { conn.queued_bytes + len };
+        conn.queued_msgs = if writable { 0 } else { conn.queued_msgs + msg_count };
         if !writable {
             return registrar.reregister(id, &conn.sock, Event::Both)
                 .chain_err(|| ErrorKind::RegistrarError(Some(id), conn.node.clone()));
@@ -0,0 +1,73 @@
+use std::collections::{HashMap, HashSet};
+use std::u64;
+use node_id::NodeId;
+use members::NodeMetadata;
+
+/// The metadata key under which each node publishes the time it started, in ms since the epoch
+pub const STARTED_AT_KEY: &'static str = "rabble.started_at";
+
+/// How to resolve a partition of the cluster once it has been stable for long enough
+///
+/// Every node applies the strategy to its own view of which members are reachable. The nodes on
+/// the winning side remove the unreachable members from the cluster, and the nodes on the losing
+/// side remove themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitBrainStrategy {
+    /// The side with more than half of the members survives. If both sides are exactly half the
+    /// cluster, the side containing the lowest sorting node survives.
+    KeepMajority,
+
+    /// The side containing the member that started first survives
+    KeepOldest,
+
+    /// Any side with at least this many members survives. Choose a quorum larger than half the
+    /// cluster size to guarantee at most one side survives.
+    StaticQuorum(usize)
+}
+
+/// The outcome of applying a `SplitBrainStrategy` on one side of a partition
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SplitBrainDecision {
+    /// Remove the unreachable members
+    DownUnreachable,
+
+    /// Remove the local node
+    DownSelf
+}
+
+impl SplitBrainStrategy {
+    /// Decide the fate of the side of the partition containing the `reachable` members
+    pub fn decide(&self,
+                  members: &HashSet<NodeId>,
+                  reachable: &HashSet<NodeId>,
+                  metadata: &HashMap<NodeId, NodeMetadata>) -> SplitBrainDecision
+    {
+        let survives = match *self {
+            SplitBrainStrategy::KeepMajority => {
+                let size = reachable.len() * 2;
+                if size == members.len() {
+                    members.iter().min().map_or(false, |lowest| reachable.contains(lowest))
+                } else {
+                    size > members.len()
+                }
+            },
+            SplitBrainStrategy::KeepOldest => {
+                let started_at = |node: &NodeId| {
+                    metadata.get(node)
+                        .and_then(|m| m.get(STARTED_AT_KEY))
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .unwrap_or(u64::MAX)
+                };
+                members.iter()
+                    .min_by_key(|&node| (started_at(node), node.clone()))
+                    .map_or(false, |oldest| reachable.contains(oldest))
+            },
+            SplitBrainStrategy::StaticQuorum(quorum) => reachable.len() >= quorum
+        };
+        if survives {
+            SplitBrainDecision::DownUnreachable
+        } else {
+            SplitBrainDecision::DownSelf
+        }
+    }
+}
@@ -1,9 +1,48 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 use node_id::NodeId;
+use members::NodeMetadata;
+
+/// The state of the connection to a peer
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PeerState {
+    Connected,
+    /// Still connected, but the failure detector suspects the peer is down
+    Suspected,
+    /// The connection was lost and is being re-established
+    Reconnecting
+}
+
+/// Whether this node can reach a majority of the cluster
+///
+/// Processes implementing replicated state machines should refuse writes when `has_quorum` is
+/// false, since this node may be in a minority partition.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct QuorumStatus {
+    /// `ClusterConfig::expected_size` if set, otherwise the current number of members
+    pub expected_size: usize,
+    /// The number of members with established connections, including this node
+    pub reachable: usize,
+    pub has_quorum: bool
+}
+
+impl QuorumStatus {
+    pub fn new(expected_size: usize, reachable: usize) -> QuorumStatus {
+        QuorumStatus {
+            expected_size: expected_size,
+            reachable: reachable,
+            has_quorum: reachable > expected_size / 2
+        }
+    }
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ClusterStatus {
     pub members: HashSet<NodeId>,
     pub established: HashSet<NodeId>,
+    pub suspected: HashSet<NodeId>,
+    pub failed: HashSet<NodeId>,
+    pub metadata: HashMap<NodeId, NodeMetadata>,
+    pub peers: HashMap<NodeId, PeerState>,
+    pub quorum: QuorumStatus,
     pub num_connections: usize
 }
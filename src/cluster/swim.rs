@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+use rand::{self, Rng};
+use time::{SteadyTime, Duration};
+use node_id::NodeId;
+use super::ClusterConfig;
+
+/// The liveness of a member as seen by the SWIM failure detector
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Failed
+}
+
+/// A change in the liveness of a member. Updates are disseminated by piggybacking them on probe
+/// messages.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MemberUpdate {
+    pub node: NodeId,
+    pub state: MemberState,
+    pub incarnation: u64
+}
+
+/// Failure detector messages sent between cluster servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SwimMsg {
+    Ping {seq: u64, updates: Vec<MemberUpdate>},
+    PingReq {seq: u64, target: NodeId, updates: Vec<MemberUpdate>},
+    Ack {seq: u64, updates: Vec<MemberUpdate>}
+}
+
+struct Member {
+    state: MemberState,
+    incarnation: u64,
+    changed_at: SteadyTime
+}
+
+struct Probe {
+    seq: u64,
+    target: NodeId,
+    started_at: SteadyTime,
+    indirect: bool
+}
+
+/// A SWIM style failure detector and membership dissemination component.
+///
+/// Every `probe_interval` a single member is probed in round robin order. If it doesn't ack within
+/// `probe_timeout`, `indirect_probes` other members are asked to probe it on our behalf. If no ack
+/// arrives by the end of the probe interval the member becomes suspected, and if it doesn't refute
+/// the suspicion within `suspicion_timeout` it is declared failed.
+///
+/// Joins are disseminated the same way as changes in liveness: an alive update about a node we
+/// don't know yet adds it as a member, and is reported by `drain_joins`. Nodes removed with
+/// `set_members` are remembered, so that updates about them still being gossiped don't add them
+/// back.
+///
+/// Like processes, Swim doesn't perform any I/O. Messages to send are pushed onto an output vec
+/// and changes in member state can be retrieved with `drain_changes`.
+pub struct Swim {
+    me: NodeId,
+    incarnation: u64,
+    config: ClusterConfig,
+    members: HashMap<NodeId, Member>,
+    probe_order: Vec<NodeId>,
+    probe: Option<Probe>,
+    last_probe: SteadyTime,
+    // Indirect probes we are performing on behalf of another member
+    // Maps our seq to the requester and their seq
+    relays: HashMap<u64, (NodeId, u64)>,
+    // Updates to piggyback along with the number of remaining transmissions
+    updates: Vec<(MemberUpdate, usize)>,
+    changes: Vec<MemberUpdate>,
+    joins: Vec<NodeId>,
+    removed: HashSet<NodeId>,
+    seq: u64
+}
+
+impl Swim {
+    pub fn new(me: NodeId, config: ClusterConfig) -> Swim {
+        Swim {
+            me: me,
+            incarnation: 0,
+            config: config,
+            members: HashMap::new(),
+            probe_order: Vec::new(),
+            probe: None,
+            last_probe: SteadyTime::now(),
+            relays: HashMap::new(),
+            updates: Vec::new(),
+            changes: Vec::new(),
+            joins: Vec::new(),
+            removed: HashSet::new(),
+            seq: 0
+        }
+    }
+
+    /// Ensure the tracked members match the current cluster membership
+    ///
+    /// Updates about members that were removed are ignored until they become members again.
+    pub fn set_members(&mut self, all: &HashSet<NodeId>) {
+        let removed: Vec<NodeId> =
+            self.members.keys().filter(|node| !all.contains(node)).cloned().collect();
+        for node in removed {
+            self.members.remove(&node);
+            self.removed.insert(node);
+        }
+        {
+            let removed = &self.removed;
+            self.updates.retain(|&(ref u, _)| !removed.contains(&u.node));
+        }
+        for node in all {
+            if *node != self.me && !self.members.contains_key(node) {
+                self.removed.remove(node);
+                self.add(node.clone(), 0);
+            }
+        }
+    }
+
+    /// Add `node` as an alive member and disseminate the join to the rest of the cluster
+    ///
+    /// This also resets a member that was removed or declared failed.
+    pub fn join(&mut self, node: &NodeId) {
+        if *node == self.me {
+            return;
+        }
+        self.removed.remove(node);
+        let incarnation = self.members.get(node).map_or(0, |m| m.incarnation);
+        self.add(node.clone(), incarnation);
+        self.gossip(MemberUpdate {
+            node: node.clone(),
+            state: MemberState::Alive,
+            incarnation: incarnation
+        });
+    }
+
+    /// A connection to `node` was established, so it must be alive.
+    pub fn alive(&mut self, node: &NodeId) {
+        if let Some(member) = self.members.get_mut(node) {
+            if member.state != MemberState::Alive {
+                member.state = MemberState::Alive;
+                member.changed_at = SteadyTime::now();
+                self.changes.push(MemberUpdate {
+                    node: node.clone(),
+                    state: MemberState::Alive,
+                    incarnation: member.incarnation
+                });
+            }
+        }
+    }
+
+    /// Suspect `node` based on evidence from outside the failure detector
+    pub fn suspect(&mut self, node: &NodeId) {
+        let incarnation = match self.members.get(node) {
+            Some(member) if member.state == MemberState::Alive => member.incarnation,
+            _ => return
+        };
+        self.apply(MemberUpdate {
+            node: node.clone(),
+            state: MemberState::Suspect,
+            incarnation: incarnation
+        });
+    }
+
+    pub fn state(&self, node: &NodeId) -> Option<MemberState> {
+        self.members.get(node).map(|m| m.state)
+    }
+
+    /// Return all members currently in `state`
+    pub fn members_in_state(&self, state: MemberState) -> HashSet<NodeId> {
+        self.members.iter().filter(|&(_, m)| m.state == state).map(|(n, _)| n.clone()).collect()
+    }
+
+    /// Return any changes in member state since the last call
+    pub fn drain_changes(&mut self) -> Vec<MemberUpdate> {
+        self.changes.drain(..).collect()
+    }
+
+    /// Return the nodes that were learned to have joined from other members since the last call
+    pub fn drain_joins(&mut self) -> Vec<NodeId> {
+        self.joins.drain(..).collect()
+    }
+
+    /// Drive the protocol forward. This should be called much more often than `probe_interval`.
+    pub fn tick(&mut self, output: &mut Vec<(NodeId, SwimMsg)>) {
+        let now = SteadyTime::now();
+        self.check_probe(now, output);
+        self.expire_suspicions(now);
+        if self.probe.is_none() &&
+            now - self.last_probe >= Duration::milliseconds(self.config.probe_interval as i64)
+        {
+            self.start_probe(now, output);
+        }
+    }
+
+    /// Handle a failure detector message from another member
+    pub fn handle(&mut self, from: NodeId, msg: SwimMsg, output: &mut Vec<(NodeId, SwimMsg)>) {
+        match msg {
+            SwimMsg::Ping {seq, updates} => {
+                self.apply_updates(updates);
+                let updates = self.piggyback();
+                output.push((from, SwimMsg::Ack {seq: seq, updates: updates}));
+            },
+            SwimMsg::PingReq {seq, target, updates} => {
+                self.apply_updates(updates);
+                let relay_seq = self.next_seq();
+                self.relays.insert(relay_seq, (from, seq));
+                let updates = self.piggyback();
+                output.push((target, SwimMsg::Ping {seq: relay_seq, updates: updates}));
+            },
+            SwimMsg::Ack {seq, updates} => {
+                self.apply_updates(updates);
+                if self.probe.as_ref().map_or(false, |p| p.seq == seq) {
+                    self.probe = None;
+                }
+                if let Some((requester, requester_seq)) = self.relays.remove(&seq) {
+                    let updates = self.piggyback();
+                    output.push((requester, SwimMsg::Ack {seq: requester_seq, updates: updates}));
+                }
+            }
+        }
+    }
+
+    fn check_probe(&mut self, now: SteadyTime, output: &mut Vec<(NodeId, SwimMsg)>) {
+        let (target, started_at, indirect, seq) = match self.probe {
+            Some(ref p) => (p.target.clone(), p.started_at, p.indirect, p.seq),
+            None => return
+        };
+        let elapsed = now - started_at;
+        if !indirect && elapsed >= Duration::milliseconds(self.config.probe_timeout as i64) {
+            let mut helpers: Vec<NodeId> = self.members.iter()
+                .filter(|&(n, m)| *n != target && m.state == MemberState::Alive)
+                .map(|(n, _)| n.clone())
+                .collect();
+            rand::thread_rng().shuffle(&mut helpers);
+            for helper in helpers.into_iter().take(self.config.indirect_probes) {
+                let updates = self.piggyback();
+                output.push((helper, SwimMsg::PingReq {
+                    seq: seq,
+                    target: target.clone(),
+                    updates: updates
+                }));
+            }
+            self.probe.as_mut().unwrap().indirect = true;
+        } else if elapsed >= Duration::milliseconds(self.config.probe_interval as i64) {
+            self.probe = None;
+            let incarnation = self.members.get(&target).map_or(0, |m| m.incarnation);
+            self.apply(MemberUpdate {
+                node: target,
+                state: MemberState::Suspect,
+                incarnation: incarnation
+            });
+        }
+    }
+
+    fn expire_suspicions(&mut self, now: SteadyTime) {
+        let timeout = Duration::milliseconds(self.config.suspicion_timeout as i64);
+        let expired: Vec<MemberUpdate> = self.members.iter()
+            .filter(|&(_, m)| m.state == MemberState::Suspect && now - m.changed_at >= timeout)
+            .map(|(n, m)| MemberUpdate {
+                node: n.clone(),
+                state: MemberState::Failed,
+                incarnation: m.incarnation
+            }).collect();
+        for update in expired {
+            self.apply(update);
+        }
+    }
+
+    fn start_probe(&mut self, now: SteadyTime, output: &mut Vec<(NodeId, SwimMsg)>) {
+        self.last_probe = now;
+        let target = match self.next_probe_target() {
+            Some(target) => target,
+            None => return
+        };
+        let seq = self.next_seq();
+        self.probe = Some(Probe {
+            seq: seq,
+            target: target.clone(),
+            started_at: now,
+            indirect: false
+        });
+        let updates = self.piggyback();
+        output.push((target, SwimMsg::Ping {seq: seq, updates: updates}));
+    }
+
+    /// Probe members in a randomized round robin order. Failed members are not probed.
+    fn next_probe_target(&mut self) -> Option<NodeId> {
+        loop {
+            if self.probe_order.is_empty() {
+                self.probe_order = self.members.iter()
+                    .filter(|&(_, m)| m.state != MemberState::Failed)
+                    .map(|(n, _)| n.clone())
+                    .collect();
+                if self.probe_order.is_empty() {
+                    return None;
+                }
+                rand::thread_rng().shuffle(&mut self.probe_order);
+            }
+            let target = self.probe_order.pop().unwrap();
+            if self.members.get(&target).map_or(false, |m| m.state != MemberState::Failed) {
+                return Some(target);
+            }
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn apply_updates(&mut self, updates: Vec<MemberUpdate>) {
+        for update in updates {
+            self.apply(update);
+        }
+    }
+
+    /// Apply an update using the SWIM precedence rules. Higher incarnations always win. For the
+    /// same incarnation Failed overrides Suspect which overrides Alive.
+    fn apply(&mut self, update: MemberUpdate) {
+        if update.node == self.me {
+            if update.state != MemberState::Alive && update.incarnation >= self.incarnation {
+                // Refute the suspicion
+                self.incarnation = update.incarnation + 1;
+                let refutation = MemberUpdate {
+                    node: self.me.clone(),
+                    state: MemberState::Alive,
+                    incarnation: self.incarnation
+                };
+                self.gossip(refutation);
+            }
+            return;
+        }
+
+        if self.removed.contains(&update.node) {
+            return;
+        }
+
+        let applied = match self.members.get_mut(&update.node) {
+            // Only an alive update can introduce a new member
+            None => update.state == MemberState::Alive,
+            Some(member) => {
+                let overrides = match (update.state, member.state) {
+                    (MemberState::Alive, _) => update.incarnation > member.incarnation,
+                    (MemberState::Suspect, MemberState::Alive) =>
+                        update.incarnation >= member.incarnation,
+                    (MemberState::Suspect, _) => update.incarnation > member.incarnation,
+                    (MemberState::Failed, MemberState::Failed) =>
+                        update.incarnation > member.incarnation,
+                    (MemberState::Failed, _) => update.incarnation >= member.incarnation
+                };
+                if overrides {
+                    member.state = update.state;
+                    member.incarnation = update.incarnation;
+                    member.changed_at = SteadyTime::now();
+                }
+                overrides
+            }
+        };
+
+        if applied {
+            if !self.members.contains_key(&update.node) {
+                self.add(update.node.clone(), update.incarnation);
+                self.joins.push(update.node.clone());
+            }
+            self.changes.push(update.clone());
+            self.gossip(update);
+        }
+    }
+
+    fn add(&mut self, node: NodeId, incarnation: u64) {
+        self.members.insert(node, Member {
+            state: MemberState::Alive,
+            incarnation: incarnation,
+            changed_at: SteadyTime::now()
+        });
+    }
+
+    fn gossip(&mut self, update: MemberUpdate) {
+        self.updates.retain(|&(ref u, _)| u.node != update.node);
+        let transmissions = self.config.gossip_multiplier * log2(self.members.len() + 1);
+        self.updates.push((update, transmissions));
+    }
+
+    /// Return the updates to piggyback on an outgoing message, retiring any that have been sent
+    /// enough times.
+    fn piggyback(&mut self) -> Vec<MemberUpdate> {
+        let updates = self.updates.iter().map(|&(ref u, _)| u.clone()).collect();
+        for update in self.updates.iter_mut() {
+            update.1 = update.1.saturating_sub(1);
+        }
+        self.updates.retain(|&(_, remaining)| remaining > 0);
+        updates
+    }
+}
+
+/// ceil(log2(n)), with a minimum of 1
+fn log2(n: usize) -> usize {
+    let mut log = 1;
+    while (1 << log) < n {
+        log += 1;
+    }
+    log
+}
@@ -0,0 +1,69 @@
+use time::SteadyTime;
+
+/// Limits the rate at which envelopes are written to a peer
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Throttle {
+    /// The sustained rate in bytes per second
+    pub bytes_per_sec: u64,
+
+    /// The most bytes that may be written at once after the connection was idle
+    pub burst: u64
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64, burst: u64) -> Throttle {
+        Throttle {
+            bytes_per_sec: bytes_per_sec,
+            burst: burst
+        }
+    }
+}
+
+/// A token bucket enforcing a `Throttle`
+///
+/// A frame is let through as long as any tokens are left, even if it's larger than that, and the
+/// bucket goes into debt. Frames larger than the burst are therefore delayed rather than stuck.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    throttle: Throttle,
+    tokens: i64,
+    last_refill: SteadyTime
+}
+
+impl TokenBucket {
+    pub fn new(throttle: Throttle, now: SteadyTime) -> TokenBucket {
+        TokenBucket {
+            throttle: throttle,
+            tokens: throttle.burst as i64,
+            last_refill: now
+        }
+    }
+
+    pub fn throttle(&self) -> Throttle {
+        self.throttle
+    }
+
+    /// Take `bytes` tokens if the bucket isn't empty, and return whether they were taken
+    pub fn take(&mut self, bytes: usize, now: SteadyTime) -> bool {
+        if !self.has_tokens(now) {
+            return false;
+        }
+        self.tokens -= bytes as i64;
+        true
+    }
+
+    /// Return whether `take` would succeed, without taking any tokens
+    pub fn has_tokens(&mut self, now: SteadyTime) -> bool {
+        self.refill(now);
+        self.tokens > 0
+    }
+
+    fn refill(&mut self, now: SteadyTime) {
+        let elapsed_ms = (now - self.last_refill).num_milliseconds();
+        let added = elapsed_ms * self.throttle.bytes_per_sec as i64 / 1000;
+        if added > 0 {
+            self.tokens = (self.tokens + added).min(self.throttle.burst as i64);
+            self.last_refill = now;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::SystemTime;
+use rustls::{self, ServerConfig, ClientConfig, ServerSession, ClientSession, Certificate, PrivateKey};
+use rustls::internal::pemfile;
+use node_id::NodeId;
+use errors::*;
+
+/// Paths to the PEM encoded material used to encrypt connections between nodes
+///
+/// Every node presents the certificate at `cert_path` to its peers, and only accepts peers whose
+/// certificates are signed by the CA at `ca_path`. Since node addresses are usually IP addresses,
+/// which can't be verified, peers are verified against `server_name` if it's set, and against
+/// their node name otherwise. The certificate of each node must be valid for that name.
+///
+/// With `mutual` set, peers connecting to this node must present a certificate signed by the CA as
+/// well. Its subject alt names and organizational units then determine the role of the peer via
+/// `ClusterConfig::roles`.
+///
+/// The files are checked for changes every second, and rotated certificates are used for new
+/// connections without restarting the node. Established connections are not affected.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub ca_path: String,
+    pub server_name: Option<String>,
+    pub mutual: bool
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: &str, key_path: &str, ca_path: &str) -> TlsConfig {
+        TlsConfig {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            ca_path: ca_path.to_string(),
+            server_name: None,
+            mutual: false
+        }
+    }
+
+    /// Require connecting peers to present a certificate signed by the CA
+    pub fn mutual(mut self) -> TlsConfig {
+        self.mutual = true;
+        self
+    }
+
+    /// Verify all peers against a single name rather than their node names
+    pub fn server_name(mut self, name: &str) -> TlsConfig {
+        self.server_name = Some(name.to_string());
+        self
+    }
+}
+
+/// The loaded TLS configuration used to create sessions for new connections
+#[derive(Clone)]
+pub struct TlsContext {
+    config: TlsConfig,
+    // The modification times of the files when they were loaded
+    modified: Vec<Option<SystemTime>>,
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>
+}
+
+impl TlsContext {
+    /// Read and parse the certificates and key named in `config`
+    pub fn load(config: &TlsConfig) -> Result<TlsContext> {
+        let modified = modification_times(config);
+        let certs = try!(load_certs(&config.cert_path));
+        let key = try!(load_key(&config.key_path));
+
+        let mut server = ServerConfig::new();
+        server.set_single_cert(certs.clone(), key.clone());
+        if config.mutual {
+            let roots = try!(load_certs(&config.ca_path));
+            server.set_client_auth_roots(roots, true);
+        }
+
+        let mut client = ClientConfig::new();
+        let file = try!(File::open(&config.ca_path)
+                        .chain_err(|| format!("Failed to open CA file {}", config.ca_path)));
+        let (added, _) = try!(client.root_store.add_pem_file(&mut BufReader::new(file))
+            .map_err(|_| Error::from(format!("Failed to parse CA file {}", config.ca_path))));
+        if added == 0 {
+            return Err(format!("No CA certificates found in {}", config.ca_path).into());
+        }
+        client.set_single_client_cert(certs, key);
+
+        Ok(TlsContext {
+            config: config.clone(),
+            modified: modified,
+            server: Arc::new(server),
+            client: Arc::new(client)
+        })
+    }
+
+    /// Reload the certificates and key if any of the files changed. Return true if they were
+    /// reloaded.
+    ///
+    /// If the new files can't be loaded, for instance because they are only partially written, the
+    /// current ones remain in use and an error is returned.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        if modification_times(&self.config) == self.modified {
+            return Ok(false);
+        }
+        match TlsContext::load(&self.config) {
+            Ok(context) => {
+                *self = context;
+                Ok(true)
+            },
+            Err(e) => {
+                // Don't try again until the files change again
+                self.modified = modification_times(&self.config);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn server_session(&self) -> Box<rustls::Session> {
+        Box::new(ServerSession::new(&self.server))
+    }
+
+    pub fn client_session(&self, peer: &NodeId) -> Box<rustls::Session> {
+        let name = self.config.server_name.as_ref().unwrap_or(&peer.name);
+        Box::new(ClientSession::new(&self.client, name))
+    }
+}
+
+fn modification_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [&config.cert_path, &config.key_path, &config.ca_path].iter().map(|path| {
+        ::std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }).collect()
+}
+
+pub fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let file = try!(File::open(path).chain_err(|| format!("Failed to open certificate file {}", path)));
+    let certs = try!(pemfile::certs(&mut BufReader::new(file))
+        .map_err(|_| Error::from(format!("Failed to parse certificate file {}", path))));
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path).into());
+    }
+    Ok(certs)
+}
+
+pub fn load_key(path: &str) -> Result<PrivateKey> {
+    let file = try!(File::open(path).chain_err(|| format!("Failed to open key file {}", path)));
+    let mut keys = try!(pemfile::pkcs8_private_keys(&mut BufReader::new(file))
+        .map_err(|_| Error::from(format!("Failed to parse key file {}", path))));
+    if keys.is_empty() {
+        // Fall back to the older RSA format
+        let file = try!(File::open(path).chain_err(|| format!("Failed to open key file {}", path)));
+        keys = try!(pemfile::rsa_private_keys(&mut BufReader::new(file))
+            .map_err(|_| Error::from(format!("Failed to parse key file {}", path))));
+    }
+    match keys.pop() {
+        Some(key) => Ok(key),
+        None => Err(format!("No private key found in {}", path).into())
+    }
+}
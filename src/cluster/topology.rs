@@ -0,0 +1,28 @@
+use node_id::NodeId;
+
+/// A connection from this node to a peer
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub peer: NodeId,
+    /// False while the connection is still being set up
+    pub established: bool,
+    /// True if this node initiated the connection
+    pub is_client: bool,
+    /// Time since the connection was opened
+    pub age_ms: u64,
+    /// The round trip time of the last answered failure detector probe
+    pub rtt_ms: Option<u64>,
+    /// Bytes handed to the connection that haven't been fully written to the socket yet. This is an
+    /// upper bound, since a partially written message is counted in full.
+    pub queued_bytes: usize
+}
+
+/// The direct connections of a single node
+///
+/// Ask each node for its topology to find asymmetric connectivity, where a node can reach a peer
+/// that can't reach it back.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    pub node: NodeId,
+    pub links: Vec<Link>
+}
@@ -0,0 +1,154 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use rustls::Session;
+use socket::Stream;
+use super::PeerIdentity;
+use super::x509;
+
+/// A nonblocking connection to a peer, which is encrypted if TLS is configured
+///
+/// Connections over unix domain sockets are never encrypted, since they don't leave the host.
+///
+/// Writes to a TLS connection are buffered in the session when the socket isn't writable, even
+/// though they are reported as written. Callers must check `wants_write` and call `flush` again
+/// once the socket becomes writable.
+pub enum Transport {
+    Plain(Stream),
+    Tls(TcpStream, Box<Session>)
+}
+
+impl Transport {
+    pub fn is_tls(&self) -> bool {
+        match *self {
+            Transport::Plain(_) => false,
+            Transport::Tls(..) => true
+        }
+    }
+
+    /// Return true if encrypted data is waiting to be written to the socket
+    pub fn wants_write(&self) -> bool {
+        match *self {
+            Transport::Plain(_) => false,
+            Transport::Tls(_, ref session) => session.wants_write()
+        }
+    }
+
+    /// Return true until the TLS handshake completes
+    pub fn is_handshaking(&self) -> bool {
+        match *self {
+            Transport::Plain(_) => false,
+            Transport::Tls(_, ref session) => session.is_handshaking()
+        }
+    }
+
+    /// Return the protocol negotiated via ALPN during the TLS handshake, if any
+    pub fn alpn_protocol(&self) -> Option<String> {
+        match *self {
+            Transport::Plain(_) => None,
+            Transport::Tls(_, ref session) => session.get_alpn_protocol()
+        }
+    }
+
+    /// The address of the remote end of a TCP connection
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            Transport::Plain(ref sock) => sock.peer_addr(),
+            Transport::Tls(ref sock, _) => sock.peer_addr().ok()
+        }
+    }
+
+    /// Read from the socket directly, bypassing TLS
+    ///
+    /// This is for data sent before the TLS handshake, such as a PROXY protocol header.
+    pub fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut sock) => sock.read(buf),
+            Transport::Tls(ref mut sock, _) => sock.read(buf)
+        }
+    }
+
+    /// Return and clear the pending error of the socket, such as the failure of a nonblocking
+    /// connect
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match *self {
+            Transport::Plain(ref sock) => sock.take_error(),
+            Transport::Tls(ref sock, _) => sock.take_error()
+        }
+    }
+
+    /// Return the identity in the certificate the peer presented, if it presented one
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        match *self {
+            Transport::Plain(_) => None,
+            Transport::Tls(_, ref session) => session.get_peer_certificates()
+                .and_then(|certs| certs.into_iter().next())
+                .map(|cert| x509::identity(&cert.0))
+        }
+    }
+}
+
+/// Write as much buffered TLS data to the socket as it accepts without blocking
+fn write_tls(sock: &mut TcpStream, session: &mut Box<Session>) -> io::Result<()> {
+    while session.wants_write() {
+        match session.write_tls(sock) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(())
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut sock) => sock.read(buf),
+            Transport::Tls(ref mut sock, ref mut session) => {
+                loop {
+                    let n = try!(session.read(buf));
+                    if n > 0 {
+                        return Ok(n);
+                    }
+                    if try!(session.read_tls(sock)) == 0 {
+                        // The peer closed the connection
+                        return Ok(0);
+                    }
+                    try!(session.process_new_packets()
+                         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))));
+                    // Send any handshake messages produced by the packets just processed
+                    try!(write_tls(sock, session));
+                }
+            }
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut sock) => sock.write(buf),
+            Transport::Tls(ref mut sock, ref mut session) => {
+                let n = try!(session.write(buf));
+                try!(write_tls(sock, session));
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Transport::Plain(ref mut sock) => sock.flush(),
+            Transport::Tls(ref mut sock, ref mut session) => write_tls(sock, session)
+        }
+    }
+}
+
+impl AsRawFd for Transport {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Transport::Plain(ref sock) => sock.as_raw_fd(),
+            Transport::Tls(ref sock, _) => sock.as_raw_fd()
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Just enough DER parsing to extract the attributes of a peer certificate that `RoleMap` rules
+//! match against. Anything that doesn't parse yields an empty identity rather than an error.
+
+use super::PeerIdentity;
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_OID: u8 = 0x06;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+
+// General name choices in a subject alt name extension
+const TAG_DNS_NAME: u8 = 0x82;
+const TAG_URI: u8 = 0x86;
+const TAG_IP_ADDRESS: u8 = 0x87;
+
+const OID_ORGANIZATIONAL_UNIT: &'static [u8] = &[0x55, 0x04, 0x0b];
+const OID_SUBJECT_ALT_NAME: &'static [u8] = &[0x55, 0x1d, 0x11];
+
+/// Return the subject alt names and organizational units of a DER encoded certificate
+pub fn identity(der: &[u8]) -> PeerIdentity {
+    let mut identity = PeerIdentity::default();
+    let _ = parse_certificate(der, &mut identity);
+    identity
+}
+
+fn parse_certificate(der: &[u8], identity: &mut PeerIdentity) -> Option<()> {
+    let (_, cert, _) = read_tlv(der)?;
+    let (_, tbs, _) = read_tlv(cert)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&TAG_VERSION) {
+        rest = read_tlv(rest)?.2;
+    }
+    // Skip the serial number, signature algorithm, issuer and validity
+    for _ in 0..4 {
+        rest = read_tlv(rest)?.2;
+    }
+    let (_, subject, mut rest) = read_tlv(rest)?;
+    identity.organizational_units = parse_organizational_units(subject)?;
+    // Skip the public key, then look for the extensions among the optional fields
+    rest = read_tlv(rest)?.2;
+    while !rest.is_empty() {
+        let (tag, value, next) = read_tlv(rest)?;
+        if tag == TAG_EXTENSIONS {
+            identity.subject_alt_names = parse_subject_alt_names(read_tlv(value)?.1)?;
+        }
+        rest = next;
+    }
+    Some(())
+}
+
+fn parse_organizational_units(mut name: &[u8]) -> Option<Vec<String>> {
+    let mut units = Vec::new();
+    while !name.is_empty() {
+        let (tag, rdn, next) = read_tlv(name)?;
+        if tag == TAG_SET {
+            let (_, attribute, _) = read_tlv(rdn)?;
+            let (tag, oid, value) = read_tlv(attribute)?;
+            if tag == TAG_OID && oid == OID_ORGANIZATIONAL_UNIT {
+                let (_, unit, _) = read_tlv(value)?;
+                units.push(String::from_utf8_lossy(unit).into_owned());
+            }
+        }
+        name = next;
+    }
+    Some(units)
+}
+
+fn parse_subject_alt_names(mut extensions: &[u8]) -> Option<Vec<String>> {
+    while !extensions.is_empty() {
+        let (tag, extension, next) = read_tlv(extensions)?;
+        extensions = next;
+        if tag != TAG_SEQUENCE {
+            continue;
+        }
+        let (_, oid, mut rest) = read_tlv(extension)?;
+        if oid != OID_SUBJECT_ALT_NAME {
+            continue;
+        }
+        if rest.first() == Some(&TAG_BOOLEAN) {
+            rest = read_tlv(rest)?.2;
+        }
+        let (tag, value, _) = read_tlv(rest)?;
+        if tag != TAG_OCTET_STRING {
+            return None;
+        }
+        let (_, mut general_names, _) = read_tlv(value)?;
+        let mut names = Vec::new();
+        while !general_names.is_empty() {
+            let (tag, name, next) = read_tlv(general_names)?;
+            match tag {
+                TAG_DNS_NAME | TAG_URI => names.push(String::from_utf8_lossy(name).into_owned()),
+                TAG_IP_ADDRESS if name.len() == 4 => {
+                    names.push(format!("{}.{}.{}.{}", name[0], name[1], name[2], name[3]))
+                },
+                _ => ()
+            }
+            general_names = next;
+        }
+        return Some(names);
+    }
+    Some(Vec::new())
+}
+
+/// Split the first tag-length-value off of `der`, returning the tag, value and remaining bytes
+fn read_tlv(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if der.len() < 2 {
+        return None;
+    }
+    let tag = der[0];
+    let (len, header) = if der[1] & 0x80 == 0 {
+        (der[1] as usize, 2)
+    } else {
+        let num_bytes = (der[1] & 0x7f) as usize;
+        if num_bytes == 0 || num_bytes > 4 || der.len() < 2 + num_bytes {
+            return None;
+        }
+        let len = der[2..2 + num_bytes].iter().fold(0, |len, &b| (len << 8) | b as usize);
+        (len, 2 + num_bytes)
+    };
+    if der.len() < header + len {
+        return None;
+    }
+    Some((tag, &der[header..header + len], &der[header + len..]))
+}
@@ -0,0 +1,34 @@
+use cluster::ClusterConfig;
+use executor::ExecutorConfig;
+use push_exporter::PushExporterConfig;
+#[cfg(feature = "chaos")]
+use chaos::ChaosConfig;
+
+/// Configuration for a single node
+///
+/// Pass this to `rabble::rouse_with_config` to override the defaults used by `rabble::rouse`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub cluster: ClusterConfig,
+    pub executor: ExecutorConfig,
+
+    /// Push the metrics of the node's registry to a StatsD or Graphite endpoint. Disabled by
+    /// default.
+    pub push_exporter: Option<PushExporterConfig>,
+
+    /// Fault injection for resilience testing. Defaults to `ChaosConfig::from_env()`.
+    #[cfg(feature = "chaos")]
+    pub chaos: ChaosConfig
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            cluster: ClusterConfig::default(),
+            executor: ExecutorConfig::default(),
+            push_exporter: None,
+            #[cfg(feature = "chaos")]
+            chaos: ChaosConfig::from_env()
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::f64;
+use errors::*;
+
+// The default limit on the number of bins. With 1% accuracy, this covers values spanning 13 orders
+// of magnitude without collapsing any bins.
+const DEFAULT_MAX_BINS: usize = 2048;
+
+// Values smaller than this are counted as zero, since their logarithm isn't usable as a bin key
+const MIN_VALUE: f64 = 1e-9;
+
+/// A quantile sketch with relative error guarantees, as described in the DDSketch paper
+///
+/// Values are counted in bins of logarithmically growing size, so every quantile is within
+/// `relative_accuracy` of the exact one, whatever the range of recorded values. Unlike `Histogram`,
+/// there are no bounds to configure, and memory grows with the logarithm of the range rather than
+/// with the range itself. Memory is capped at `max_bins`; once reached, the lowest bins are merged,
+/// which only affects the accuracy of the lowest quantiles.
+///
+/// Values are unitless, non-negative f64s. Negative values are counted as zero. Sketches with the
+/// same relative accuracy can be merged, which makes them a good fit for combining the metrics of
+/// many nodes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DDSketch {
+    relative_accuracy: f64,
+    gamma: f64,
+    max_bins: usize,
+    bins: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    // Both are 0 while the sketch is empty, since infinities can't be serialized to JSON
+    min: f64,
+    max: f64
+}
+
+impl DDSketch {
+    /// Create a sketch whose quantiles are accurate to within `relative_accuracy`, such as 0.01
+    ///
+    /// Panics if `relative_accuracy` isn't between 0 and 1.
+    pub fn new(relative_accuracy: f64) -> DDSketch {
+        DDSketch::with_max_bins(relative_accuracy, DEFAULT_MAX_BINS)
+    }
+
+    /// Create a sketch that keeps at most `max_bins` bins
+    ///
+    /// Panics if `relative_accuracy` isn't between 0 and 1, or `max_bins` is 0.
+    pub fn with_max_bins(relative_accuracy: f64, max_bins: usize) -> DDSketch {
+        assert!(relative_accuracy > 0.0 && relative_accuracy < 1.0,
+                "The relative accuracy of a sketch must be between 0 and 1");
+        assert!(max_bins > 0, "A sketch needs at least one bin");
+        DDSketch {
+            relative_accuracy: relative_accuracy,
+            gamma: (1.0 + relative_accuracy) / (1.0 - relative_accuracy),
+            max_bins: max_bins,
+            bins: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0
+        }
+    }
+
+    pub fn record(&mut self, value: f64) {
+        let value = if value > 0.0 { value } else { 0.0 };
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        if value < MIN_VALUE {
+            self.zero_count += 1;
+            return;
+        }
+        let key = self.key(value);
+        *self.bins.entry(key).or_insert(0) += 1;
+        self.collapse();
+    }
+
+    /// Add the values recorded by `other` to this sketch
+    ///
+    /// Returns an `IncompatibleHistograms` error if the sketches have a different relative
+    /// accuracy, since their bins don't line up.
+    pub fn merge(&mut self, other: &DDSketch) -> Result<()> {
+        if (self.gamma - other.gamma).abs() > f64::EPSILON {
+            let reason = format!("cannot merge a sketch with relative accuracy {} into one with {}",
+                                 other.relative_accuracy, self.relative_accuracy);
+            return Err(ErrorKind::IncompatibleHistograms(reason).into());
+        }
+        if other.count == 0 {
+            return Ok(());
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if self.count == 0 || other.max > self.max {
+            self.max = other.max;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.zero_count += other.zero_count;
+        for (key, n) in other.bins.iter() {
+            *self.bins.entry(*key).or_insert(0) += *n;
+        }
+        self.collapse();
+        Ok(())
+    }
+
+    /// The value at quantile `q`, between 0 and 1, or 0 if nothing was recorded
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        if q <= 0.0 {
+            return self.min;
+        }
+        if q >= 1.0 {
+            return self.max;
+        }
+        let rank = (q * (self.count - 1) as f64) as u64;
+        let mut seen = self.zero_count;
+        if rank < seen {
+            return 0.0;
+        }
+        for (key, n) in self.bins.iter() {
+            seen += *n;
+            if rank < seen {
+                return self.value(*key).max(self.min).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// The number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum / self.count as f64
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    fn key(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// The value that all values in the bin `key` are within the relative accuracy of
+    fn value(&self, key: i32) -> f64 {
+        2.0 * self.gamma.powi(key) / (self.gamma + 1.0)
+    }
+
+    /// Merge the lowest bins until there are no more than `max_bins`
+    fn collapse(&mut self) {
+        while self.bins.len() > self.max_bins {
+            let (lowest, n) = self.bins.iter().next().map(|(key, n)| (*key, *n)).unwrap();
+            self.bins.remove(&lowest);
+            let next = self.bins.keys().next().cloned().unwrap();
+            *self.bins.get_mut(&next).unwrap() += n;
+        }
+    }
+}
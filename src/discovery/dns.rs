@@ -0,0 +1,47 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use node_id::NodeId;
+use errors::*;
+use super::Discovery;
+
+/// Discover nodes by resolving the A/AAAA records of a host name
+///
+/// This fits clusters running behind a headless service, where the host name resolves to the
+/// addresses of all peers and every node listens on the same port. Since DNS doesn't carry node
+/// names, each address is mapped to a name with a naming function. By default a node is named
+/// after its address, so nodes started with `NodeId {name: addr, addr: addr}` find each other
+/// without further configuration.
+///
+/// SRV records aren't supported, since the standard library resolver can't look them up.
+pub struct DnsDiscovery {
+    host: String,
+    port: u16,
+    naming: Box<Fn(&SocketAddr) -> String + Send>
+}
+
+impl DnsDiscovery {
+    pub fn new(host: &str, port: u16) -> DnsDiscovery {
+        DnsDiscovery {
+            host: host.to_string(),
+            port: port,
+            naming: Box::new(|addr| addr.to_string())
+        }
+    }
+
+    /// Use `naming` to derive the name of the node listening at each resolved address
+    pub fn with_naming<F>(mut self, naming: F) -> DnsDiscovery
+        where F: Fn(&SocketAddr) -> String + Send + 'static
+    {
+        self.naming = Box::new(naming);
+        self
+    }
+}
+
+impl Discovery for DnsDiscovery {
+    fn discover(&mut self) -> Result<Vec<NodeId>> {
+        let addrs = (&self.host[..], self.port).to_socket_addrs()?;
+        Ok(addrs.map(|addr| NodeId {
+            name: (self.naming)(&addr),
+            addr: addr.to_string()
+        }).collect())
+    }
+}
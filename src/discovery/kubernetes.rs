@@ -0,0 +1,332 @@
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use rustls::{ClientConfig, ClientSession};
+use serde_json;
+use node_id::NodeId;
+use socket::{host_port, Stream};
+use cluster::Transport;
+use errors::*;
+use super::Discovery;
+
+/// Where the token and CA certificate of the pod's service account are mounted
+const SERVICE_ACCOUNT_DIR: &'static str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// The name the API server certificate is valid for, which can be verified unlike its IP address
+const API_SERVER_NAME: &'static str = "kubernetes.default.svc";
+
+/// How long in seconds the API server keeps a watch open before it's renewed
+const WATCH_TIMEOUT: u64 = 60;
+
+/// How long to wait before retrying a failed request to the API server, in ms
+const RETRY_INTERVAL: u64 = 1000;
+
+/// Discover nodes from the Endpoints of a Kubernetes service
+///
+/// By default the API server is reached at the address in the `KUBERNETES_SERVICE_HOST` and
+/// `KUBERNETES_SERVICE_PORT` environment variables of the pod. Connections are verified with the CA
+/// certificate of the pod's service account, and requests are authenticated with its token, so the
+/// service account must be allowed to get, list and watch endpoints in the namespace. Outside of a
+/// cluster, `api_addr` can point at a `kubectl proxy` instead, which is reached over plain HTTP.
+///
+/// The first call to `discover` lists the Endpoints and starts a thread that watches them for
+/// changes. Later calls return the Endpoints last seen by the watch without contacting the API
+/// server. The thread exits when its current watch ends after the discovery was dropped.
+///
+/// Each ready address of the service becomes a node named after its pod, so every pod should be
+/// started with `NodeId {name: <pod name>, addr: "<pod ip>:<port>"}`. The pod name is available in
+/// the `HOSTNAME` environment variable.
+pub struct KubernetesDiscovery {
+    api: Api,
+    namespace: String,
+    service: String,
+    port_name: Option<String>,
+    watch: Option<Arc<Mutex<Watch>>>
+}
+
+impl KubernetesDiscovery {
+    /// Discover the pods of `service` in `namespace`, using the API server of the cluster
+    pub fn new(namespace: &str, service: &str) -> KubernetesDiscovery {
+        KubernetesDiscovery {
+            api: Api::InCluster,
+            namespace: namespace.to_string(),
+            service: service.to_string(),
+            port_name: None,
+            watch: None
+        }
+    }
+
+    /// Use an API proxy listening on `addr`, such as "127.0.0.1:8001" for `kubectl proxy`
+    pub fn api_addr(mut self, addr: &str) -> KubernetesDiscovery {
+        self.api = Api::Proxy(addr.to_string());
+        self
+    }
+
+    /// Connect to the named service port. By default the first port of the service is used.
+    pub fn port_name(mut self, name: &str) -> KubernetesDiscovery {
+        self.port_name = Some(name.to_string());
+        self
+    }
+
+    /// Watch the Endpoints for changes, starting after the `endpoints` that were just listed
+    fn start_watch(&self, endpoints: Endpoints) -> Arc<Mutex<Watch>> {
+        let mut version = endpoints.metadata.resource_version.clone();
+        let watch = Arc::new(Mutex::new(Watch {
+            endpoints: endpoints,
+            error: None
+        }));
+        let state = watch.clone();
+        let api = self.api.clone();
+        let namespace = self.namespace.clone();
+        let service = self.service.clone();
+        let name = format!("kubernetes_watch::{}", service);
+        thread::Builder::new().name(name).spawn(move || {
+            // Only the watch thread holds a reference once the discovery is dropped
+            while Arc::strong_count(&state) > 1 {
+                let result = match version.take() {
+                    Some(version) => api.watch_endpoints(&namespace, &service, &version, &state),
+                    None => api.get_endpoints(&namespace, &service).map(|endpoints| {
+                        let version = endpoints.metadata.resource_version.clone();
+                        state.lock().unwrap().endpoints = endpoints;
+                        version
+                    })
+                };
+                match result {
+                    Ok(next) => {
+                        state.lock().unwrap().error = None;
+                        version = next;
+                    },
+                    Err(e) => {
+                        state.lock().unwrap().error = Some(e.to_string());
+                        thread::sleep(Duration::from_millis(RETRY_INTERVAL));
+                    }
+                }
+            }
+        }).unwrap();
+        watch
+    }
+}
+
+impl Discovery for KubernetesDiscovery {
+    fn discover(&mut self) -> Result<Vec<NodeId>> {
+        if self.watch.is_none() {
+            let endpoints = self.api.get_endpoints(&self.namespace, &self.service)?;
+            let watch = self.start_watch(endpoints);
+            self.watch = Some(watch);
+        }
+        let watch = self.watch.as_ref().unwrap().lock().unwrap();
+        if let Some(ref error) = watch.error {
+            return Err(format!("Failed to watch endpoints: {}", error).into());
+        }
+        let mut nodes = Vec::new();
+        for subset in watch.endpoints.subsets.iter() {
+            let port = match self.port_name {
+                Some(ref name) => subset.ports.iter().find(|p| p.name.as_ref() == Some(name)),
+                None => subset.ports.first()
+            };
+            let port = match port {
+                Some(port) => port.port,
+                None => continue
+            };
+            // Only addresses of ready pods are listed in `addresses`
+            for address in subset.addresses.iter() {
+                let addr = host_port(&address.ip, port);
+                let name = address.target_ref.as_ref().map_or(addr.clone(), |r| r.name.clone());
+                nodes.push(NodeId {
+                    name: name,
+                    addr: addr
+                });
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+/// The latest state of the watched Endpoints
+struct Watch {
+    endpoints: Endpoints,
+    // The error of the last request, if it failed
+    error: Option<String>
+}
+
+/// How the API server is reached
+#[derive(Clone)]
+enum Api {
+    InCluster,
+    Proxy(String)
+}
+
+impl Api {
+    fn get_endpoints(&self, namespace: &str, service: &str) -> Result<Endpoints> {
+        let path = format!("/api/v1/namespaces/{}/endpoints/{}", namespace, service);
+        let mut response = self.get(&path)?;
+        let mut body = String::new();
+        response.read_to_string(&mut body)?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Apply changes to the Endpoints after `version` to `state` until the watch times out
+    ///
+    /// Returns the version to continue watching from, or None if the Endpoints must be listed
+    /// again, because the version is too old to be watched.
+    fn watch_endpoints(&self,
+                       namespace: &str,
+                       service: &str,
+                       version: &str,
+                       state: &Mutex<Watch>) -> Result<Option<String>> {
+        let path = format!("/api/v1/namespaces/{}/endpoints?watch=true&resourceVersion={}\
+                            &fieldSelector=metadata.name%3D{}&timeoutSeconds={}",
+                           namespace, version, service, WATCH_TIMEOUT);
+        let response = self.get(&path)?;
+        let mut version = version.to_string();
+        for line in response.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: WatchEvent = serde_json::from_str(&line)?;
+            match &event.kind[..] {
+                "ADDED" | "MODIFIED" => {
+                    let endpoints: Endpoints = serde_json::from_value(event.object)?;
+                    if let Some(ref v) = endpoints.metadata.resource_version {
+                        version = v.clone();
+                    }
+                    state.lock().unwrap().endpoints = endpoints;
+                },
+                "DELETED" => {
+                    let endpoints: Endpoints = serde_json::from_value(event.object)?;
+                    if let Some(ref v) = endpoints.metadata.resource_version {
+                        version = v.clone();
+                    }
+                    state.lock().unwrap().endpoints = Endpoints::default();
+                },
+                // The API server ends the watch with an error when the version has expired
+                "ERROR" => return Ok(None),
+                _ => ()
+            }
+        }
+        Ok(Some(version))
+    }
+
+    /// Send a GET request for `path` and return the body of the response
+    fn get(&self, path: &str) -> Result<BufReader<Transport>> {
+        let (host, mut conn, token) = match *self {
+            Api::Proxy(ref addr) => {
+                let sock = connect(addr)?;
+                (addr.clone(), Transport::Plain(Stream::Tcp(sock)), None)
+            },
+            Api::InCluster => {
+                let host = env::var("KUBERNETES_SERVICE_HOST")
+                    .chain_err(|| "KUBERNETES_SERVICE_HOST is not set")?;
+                let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or("443".to_string());
+                let addr = host_port(&host, port);
+                let sock = connect(&addr)?;
+                let session = ClientSession::new(&Arc::new(client_config()?), API_SERVER_NAME);
+                let token = read_file(&format!("{}/token", SERVICE_ACCOUNT_DIR))?;
+                (addr, Transport::Tls(sock, Box::new(session)), Some(token))
+            }
+        };
+        // HTTP/1.0 responses aren't chunked, and end when the connection is closed
+        write!(conn, "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n", path, host)?;
+        if let Some(token) = token {
+            write!(conn, "Authorization: Bearer {}\r\n", token.trim())?;
+        }
+        write!(conn, "\r\n")?;
+        conn.flush()?;
+
+        let mut response = BufReader::new(conn);
+        let mut status = String::new();
+        response.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("200") {
+            return Err(format!("Failed to get {}: {}", path, status.trim()).into());
+        }
+        // Skip the headers
+        loop {
+            let mut header = String::new();
+            if response.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Connect to the API server, giving up on reads once a watch should have ended
+fn connect(addr: &str) -> Result<TcpStream> {
+    let sock = TcpStream::connect(addr)?;
+    sock.set_read_timeout(Some(Duration::from_secs(WATCH_TIMEOUT * 2)))?;
+    Ok(sock)
+}
+
+/// Trust the CA of the cluster, which the service account's CA certificate belongs to
+fn client_config() -> Result<ClientConfig> {
+    let path = format!("{}/ca.crt", SERVICE_ACCOUNT_DIR);
+    let file = File::open(&path).chain_err(|| format!("Failed to open CA file {}", path))?;
+    let mut config = ClientConfig::new();
+    let (added, _) = config.root_store.add_pem_file(&mut BufReader::new(file))
+        .map_err(|_| Error::from(format!("Failed to parse CA file {}", path)))?;
+    if added == 0 {
+        return Err(format!("No CA certificates found in {}", path).into());
+    }
+    Ok(config)
+}
+
+fn read_file(path: &str) -> Result<String> {
+    let mut file = File::open(path).chain_err(|| format!("Failed to open {}", path))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+// The subset of the Endpoints resource and watch events used for discovery
+
+#[derive(Deserialize)]
+struct WatchEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: serde_json::Value
+}
+
+#[derive(Deserialize, Default)]
+struct Endpoints {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    subsets: Vec<EndpointSubset>
+}
+
+#[derive(Deserialize, Default)]
+struct ObjectMeta {
+    #[serde(rename = "resourceVersion")]
+    resource_version: Option<String>
+}
+
+#[derive(Deserialize)]
+struct EndpointSubset {
+    #[serde(default)]
+    addresses: Vec<EndpointAddress>,
+    #[serde(default)]
+    ports: Vec<EndpointPort>
+}
+
+#[derive(Deserialize)]
+struct EndpointAddress {
+    ip: String,
+    #[serde(rename = "targetRef")]
+    target_ref: Option<ObjectReference>
+}
+
+#[derive(Deserialize)]
+struct ObjectReference {
+    name: String
+}
+
+#[derive(Deserialize)]
+struct EndpointPort {
+    name: Option<String>,
+    port: u16
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{UdpSocket, Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use net2::UdpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixUdpBuilderExt;
+use node_id::NodeId;
+use errors::*;
+use super::Discovery;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const TYPE_TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+const TXT_PREFIX: &'static str = "rabble-node=";
+
+/// Discover nodes on the local network using multicast DNS
+///
+/// Each call to `discover` announces the local node with an unsolicited mDNS response containing a
+/// TXT record for `<cluster>._rabble._tcp.local`, and then listens for the announcements of other
+/// nodes for `listen_time` ms. Announcements arriving between calls are buffered by the socket, so
+/// as long as every node calls `discover` periodically, all nodes find each other. A node is
+/// forgotten if it hasn't announced itself for `expiry` ms.
+///
+/// Only TXT records for our own service are interpreted. This is not a general mDNS responder.
+pub struct MdnsDiscovery {
+    me: NodeId,
+    service: String,
+    socket: UdpSocket,
+    listen_time: Duration,
+    expiry: Duration,
+    seen: HashMap<NodeId, Instant>
+}
+
+impl MdnsDiscovery {
+    /// Announce `me` as a member of `cluster`, which must be a valid DNS label.
+    pub fn new(me: NodeId, cluster: &str) -> Result<MdnsDiscovery> {
+        let builder = UdpBuilder::new_v4()?;
+        builder.reuse_address(true)?;
+        #[cfg(unix)]
+        builder.reuse_port(true)?;
+        let socket = builder.bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), MDNS_PORT))?;
+        socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::new(0, 0, 0, 0))?;
+        // Allow multiple nodes on the same host to find each other
+        socket.set_multicast_loop_v4(true)?;
+        Ok(MdnsDiscovery {
+            me: me,
+            service: format!("{}._rabble._tcp.local", cluster),
+            socket: socket,
+            listen_time: Duration::from_millis(500),
+            expiry: Duration::from_millis(30000),
+            seen: HashMap::new()
+        })
+    }
+
+    /// Listen for announcements for `ms` milliseconds during each call to `discover`
+    pub fn listen_time(mut self, ms: u64) -> MdnsDiscovery {
+        self.listen_time = Duration::from_millis(ms);
+        self
+    }
+
+    /// Forget nodes that haven't announced themselves within `ms` milliseconds
+    pub fn expiry(mut self, ms: u64) -> MdnsDiscovery {
+        self.expiry = Duration::from_millis(ms);
+        self
+    }
+
+    fn announce(&self) -> Result<()> {
+        let packet = encode_announcement(&self.service, &format!("{}{}", TXT_PREFIX, self.me));
+        self.socket.send_to(&packet, SocketAddrV4::new(MDNS_ADDR, MDNS_PORT))?;
+        Ok(())
+    }
+
+    fn listen(&mut self) -> Result<()> {
+        let deadline = Instant::now() + self.listen_time;
+        let mut buf = [0u8; 9000];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+            let len = match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => return Ok(()),
+                Err(e) => return Err(e.into())
+            };
+            for txt in decode_txt_records(&buf[..len], &self.service) {
+                if txt.starts_with(TXT_PREFIX) {
+                    if let Ok(node) = txt[TXT_PREFIX.len()..].parse::<NodeId>() {
+                        self.seen.insert(node, Instant::now());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Discovery for MdnsDiscovery {
+    fn discover(&mut self) -> Result<Vec<NodeId>> {
+        self.announce()?;
+        self.listen()?;
+        let now = Instant::now();
+        let expiry = self.expiry;
+        self.seen.retain(|_, last_seen| now.duration_since(*last_seen) < expiry);
+        Ok(self.seen.keys().cloned().collect())
+    }
+}
+
+/// Encode an mDNS response containing a single TXT record
+fn encode_announcement(service: &str, txt: &str) -> Vec<u8> {
+    let mut packet = Vec::new();
+    // Header: id, flags (response, authoritative), questions, answers, authority, additional
+    for field in &[0u16, 0x8400, 0, 1, 0, 0] {
+        push_u16(&mut packet, *field);
+    }
+    for label in service.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    push_u16(&mut packet, TYPE_TXT);
+    push_u16(&mut packet, CLASS_IN);
+    // TTL in seconds
+    push_u16(&mut packet, 0);
+    push_u16(&mut packet, 120);
+    let txt = &txt.as_bytes()[..txt.len().min(255)];
+    push_u16(&mut packet, txt.len() as u16 + 1);
+    packet.push(txt.len() as u8);
+    packet.extend_from_slice(txt);
+    packet
+}
+
+fn push_u16(packet: &mut Vec<u8>, n: u16) {
+    packet.push((n >> 8) as u8);
+    packet.push(n as u8);
+}
+
+/// Return the strings of all TXT records for `service` in the answers of an mDNS packet.
+/// Malformed packets are ignored.
+fn decode_txt_records(packet: &[u8], service: &str) -> Vec<String> {
+    let mut txts = Vec::new();
+    let _ = decode(packet, service, &mut txts);
+    txts
+}
+
+fn decode(packet: &[u8], service: &str, txts: &mut Vec<String>) -> Option<()> {
+    let flags = read_u16(packet, 2)?;
+    if flags & 0x8000 == 0 {
+        // Not a response
+        return Some(());
+    }
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (_, next) = read_name(packet, pos)?;
+        pos = next + 4;
+    }
+    for _ in 0..answers {
+        let (name, next) = read_name(packet, pos)?;
+        let rtype = read_u16(packet, next)?;
+        let rdlen = read_u16(packet, next + 8)? as usize;
+        let rdata = packet.get(next + 10..next + 10 + rdlen)?;
+        pos = next + 10 + rdlen;
+        if rtype != TYPE_TXT || !name.eq_ignore_ascii_case(service) {
+            continue;
+        }
+        let mut i = 0;
+        while i < rdata.len() {
+            let len = rdata[i] as usize;
+            let s = rdata.get(i + 1..i + 1 + len)?;
+            txts.push(String::from_utf8_lossy(s).into_owned());
+            i += 1 + len;
+        }
+    }
+    Some(())
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    let bytes = packet.get(pos..pos + 2)?;
+    Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+/// Read a possibly compressed name starting at `pos`. Return the name and the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bound the number of compression pointers followed to guard against loops
+    let mut jumps = 0;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len & 0xC0 == 0xC0 {
+            let offset = (read_u16(packet, pos)? & 0x3FFF) as usize;
+            if end.is_none() {
+                end = Some(pos + 2);
+            }
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            pos = offset;
+        } else if len == 0 {
+            let end = end.unwrap_or(pos + 1);
+            return Some((labels.join("."), end));
+        } else {
+            let label = packet.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+}
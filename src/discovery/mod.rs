@@ -0,0 +1,59 @@
+mod dns;
+mod kubernetes;
+mod mdns;
+
+pub use self::dns::DnsDiscovery;
+pub use self::kubernetes::KubernetesDiscovery;
+pub use self::mdns::MdnsDiscovery;
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use node::Node;
+use node_id::NodeId;
+use errors::*;
+
+/// A source of peer nodes for a cluster, such as DNS or an orchestrator API
+pub trait Discovery: Send {
+    /// Return all nodes that should currently be members of the cluster. The result may include
+    /// the local node.
+    fn discover(&mut self) -> Result<Vec<NodeId>>;
+}
+
+/// Spawn a thread that calls `discovery.discover()` every `interval` ms and joins any nodes that
+/// weren't in the previous result.
+///
+/// Discovery only ever adds members. Nodes that disappear from the results are not removed from
+/// the cluster, since that is the job of the failure detector. If they reappear they are joined
+/// again. The thread exits when the node shuts down.
+pub fn start_discovery<'de, T>(node: Node<T>,
+                               mut discovery: Box<Discovery>,
+                               interval: usize) -> JoinHandle<()>
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    let name = format!("discovery::{}", node.id);
+    thread::Builder::new().name(name).spawn(move || {
+        let logger = node.logger.new(o!("component" => "discovery"));
+        let mut known = HashSet::new();
+        loop {
+            match discovery.discover() {
+                Ok(nodes) => {
+                    let discovered: HashSet<NodeId> =
+                        nodes.into_iter().filter(|n| *n != node.id).collect();
+                    for new_node in discovered.difference(&known) {
+                        info!(logger, "Discovered node"; "peer" => new_node.to_string());
+                        if let Err(_) = node.join(new_node) {
+                            // The node is shutting down
+                            return;
+                        }
+                    }
+                    known = discovered;
+                },
+                Err(e) => warn!(logger, "Discovery failed"; "error" => e.to_string())
+            }
+            thread::sleep(Duration::from_millis(interval as u64));
+        }
+    }).unwrap()
+}
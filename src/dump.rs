@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use node_id::NodeId;
+use pid::Pid;
+use metrics::Metric;
+use cluster::ClusterStatus;
+use envelope::now_ms;
+
+/// A notable change in cluster state, kept in a bounded log for postmortems
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ClusterEvent {
+    pub at: u64, // ms since the epoch
+    pub description: String
+}
+
+/// A bounded log of the most recent cluster events
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    capacity: usize,
+    events: VecDeque<ClusterEvent>
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> EventLog {
+        EventLog {
+            capacity: capacity,
+            events: VecDeque::new()
+        }
+    }
+
+    /// Record an event, dropping the oldest one if the log is full
+    pub fn record(&mut self, description: String) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(ClusterEvent {
+            at: now_ms(),
+            description: description
+        });
+    }
+
+    /// Return the events from oldest to newest
+    pub fn events(&self) -> Vec<ClusterEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+/// The state of the cluster server at the time of a dump
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterDump {
+    pub status: ClusterStatus,
+    pub metrics: Vec<(String, Metric)>,
+    pub events: Vec<ClusterEvent>
+}
+
+/// The state of the executor at the time of a dump
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutorDump {
+    pub processes: Vec<Pid>,
+    pub services: Vec<Pid>,
+    pub migrating: Vec<Pid>,
+    pub metrics: Vec<(String, Metric)>
+}
+
+/// Everything written by `Node::dump_state`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDump {
+    pub node: NodeId,
+    pub taken_at: u64, // ms since the epoch
+    pub cluster: ClusterDump,
+    pub executor: ExecutorDump
+}
@@ -1,7 +1,10 @@
 use std::fmt::Debug;
 use serde::{Serialize, Deserialize};
+use time;
 use pid::Pid;
 use correlation_id::CorrelationId;
+use trace::TraceContext;
+use seq_trace::SeqTrace;
 use msg::Msg;
 
 /// Envelopes are routable to processes on all nodes and threads running on the same node as this
@@ -11,16 +14,188 @@ pub struct Envelope<T> {
     pub to: Pid,
     pub from: Pid,
     pub msg: Msg<T>,
-    pub correlation_id: Option<CorrelationId>
+    pub correlation_id: Option<CorrelationId>,
+
+    /// The wall clock time the envelope was created in ms since the UNIX epoch. This is used to
+    /// measure end to end latency, so it is only approximate across nodes.
+    #[serde(default)]
+    pub sent_at: Option<u64>,
+
+    /// The wall clock time in ms since the UNIX epoch after which the envelope is dropped rather
+    /// than delivered. Like `sent_at`, this is only approximate across nodes, so TTLs should be
+    /// much longer than the clock skew between them.
+    #[serde(default)]
+    pub deadline: Option<u64>,
+
+    /// The trace this envelope is part of. Envelopes sent by a process while it handles a traced
+    /// envelope join the same trace automatically.
+    #[serde(default)]
+    pub trace: Option<TraceContext>,
+
+    /// Report every hop of this envelope, and of the envelopes sent while handling it, to a
+    /// collector. See `with_seq_trace`.
+    #[serde(default)]
+    pub seq_trace: Option<SeqTrace>,
+
+    /// The order in which the executor delivers, and the cluster server sends, queued envelopes
+    #[serde(default)]
+    pub priority: Priority,
+
+    /// The pid replies should be sent to, if not `from`. Routers that forward a request set it to
+    /// the reply address of the request, so that the reply skips them. See `reply_address`.
+    #[serde(default)]
+    pub reply_to: Option<Pid>,
+
+    /// Metadata that middleware, such as auth or routing layers, attaches to envelopes without
+    /// changing the message type. Headers aren't copied to envelopes sent in response.
+    ///
+    /// An empty list doesn't allocate, and is serialized as just its length. It's always
+    /// serialized, since formats that aren't self describing, such as bincode, can't tell that a
+    /// field was left out.
+    #[serde(default)]
+    pub headers: Vec<(String, Vec<u8>)>
 }
 
 impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Envelope<T> {
     pub fn new(to: Pid, from: Pid, msg: Msg<T>, c_id: Option<CorrelationId>) -> Envelope<T> {
+        let priority = Priority::of(&msg);
         Envelope {
             to: to,
             from: from,
             msg: msg,
-            correlation_id: c_id
+            correlation_id: c_id,
+            sent_at: Some(now_ms()),
+            deadline: None,
+            trace: None,
+            seq_trace: None,
+            priority: priority,
+            reply_to: None,
+            headers: Vec::new()
+        }
+    }
+
+    /// Drop the envelope instead of delivering it if it's still queued `ttl` ms from now
+    pub fn with_ttl(mut self, ttl: u64) -> Envelope<T> {
+        self.deadline = Some(now_ms() + ttl);
+        self
+    }
+
+    /// Make the envelope part of the trace `trace`
+    pub fn with_trace(mut self, trace: TraceContext) -> Envelope<T> {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Start a sequence trace labeled `label`, whose hops are reported to `collector`
+    pub fn with_seq_trace(mut self, label: u64, collector: Pid) -> Envelope<T> {
+        self.seq_trace = Some(SeqTrace {
+            label: label,
+            serial: 0,
+            collector: collector
+        });
+        self
+    }
+
+    /// Override the priority chosen for the message by `Envelope::new`
+    pub fn with_priority(mut self, priority: Priority) -> Envelope<T> {
+        self.priority = priority;
+        self
+    }
+
+    /// Send replies to `pid` instead of the sender
+    pub fn with_reply_to(mut self, pid: Pid) -> Envelope<T> {
+        self.reply_to = Some(pid);
+        self
+    }
+
+    /// Return the pid a reply to this envelope should be sent to: `reply_to` if it's set, and
+    /// `from` otherwise
+    pub fn reply_address(&self) -> &Pid {
+        self.reply_to.as_ref().unwrap_or(&self.from)
+    }
+
+    /// Set header `name`, replacing any earlier value
+    pub fn with_header(mut self, name: &str, value: Vec<u8>) -> Envelope<T> {
+        self.set_header(name, value);
+        self
+    }
+
+    /// Return the value of header `name`, if it's set
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| &value[..])
+    }
+
+    /// Set header `name`, replacing any earlier value
+    pub fn set_header(&mut self, name: &str, value: Vec<u8>) {
+        let index = self.headers.iter().position(|&(ref key, _)| key == name);
+        match index {
+            Some(i) => self.headers[i].1 = value,
+            None => self.headers.push((name.to_string(), value))
+        }
+    }
+
+    /// Remove header `name` and return its value, if it was set
+    pub fn remove_header(&mut self, name: &str) -> Option<Vec<u8>> {
+        let index = self.headers.iter().position(|&(ref key, _)| key == name);
+        index.map(|i| self.headers.remove(i).1)
+    }
+
+    /// Return true if the deadline of the envelope passed before `now`, in ms since the UNIX epoch
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.deadline.map_or(false, |deadline| now > deadline)
+    }
+}
+
+/// The scheduling priority of an envelope
+///
+/// The executor delivers queued envelopes of a higher priority first, and the cluster server sends
+/// them ahead of queued envelopes of a lower priority to the same peer. Envelopes of the same
+/// priority keep their order, but a higher priority envelope may overtake earlier envelopes to
+/// the same process.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    /// Bulk traffic that can wait behind everything else
+    Low,
+    Normal,
+    /// Control plane messages, such as membership changes and shutdowns. These are sent even to
+    /// saturated peers.
+    High
+}
+
+impl Priority {
+    /// The default priority of a message: `High` for shutdowns, membership events and mailbox
+    /// alerts, and `Normal` for everything else
+    pub fn of<T>(msg: &Msg<T>) -> Priority {
+        match *msg {
+            Msg::Shutdown |
+            Msg::NodeUp(_) |
+            Msg::NodeDown(_) |
+            Msg::PeerState(..) |
+            Msg::Downed(_) |
+            Msg::LeaveComplete(_) |
+            Msg::MailboxHighWater(..) => Priority::High,
+            _ => Priority::Normal
+        }
+    }
+
+    /// The index of the executor mailbox queue for this priority, with the highest first
+    pub fn index(&self) -> usize {
+        match *self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2
         }
     }
 }
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::Normal
+    }
+}
+
+/// Return the current wall clock time in ms since the UNIX epoch
+pub fn now_ms() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
@@ -1,8 +1,10 @@
 use std::io;
 use msgpack;
+use serde_json;
 use protobuf;
 use pid::Pid;
 use node_id::NodeId;
+use validation::ValidationReport;
 
 /// Used by the error-chain crate to generate errors
 error_chain! {
@@ -11,6 +13,7 @@ error_chain! {
         msgpack::encode::Error, MsgpackEncode;
         msgpack::decode::Error, MsgpackDecode;
         protobuf::error::ProtobufError, Protobuf;
+        serde_json::Error, Json;
     }
 
     errors {
@@ -50,6 +53,38 @@ error_chain! {
             description("Failed to send")
             display("Failed to send {} to {:?}", msg, pid)
         }
+        PeerSaturated(node: NodeId) {
+            description("Peer outbound queue is full")
+            display("The outbound queue to {} is full", node)
+        }
+        StreamClosed(id: u64) {
+            description("Stream closed")
+            display("Stream {} was closed because the connection to the receiver was lost", id)
+        }
+        NoRoute(key: String) {
+            description("No route for key")
+            display("No route for key {}", key)
+        }
+        IncompatiblePeer(id: usize, node: Option<NodeId>, reason: String) {
+            description("Incompatible peer")
+            display("Rejected incompatible peer {:?}, id={}: {}", node, id, reason)
+        }
+        InvalidConfig(report: ValidationReport) {
+            description("Invalid configuration")
+            display("Invalid configuration: {}", report)
+        }
+        HandlerPanic(id: usize, msg: String) {
+            description("Connection handler panicked")
+            display("Connection handler panicked: id={}, {}", id, msg)
+        }
+        IncompatibleHistograms(reason: String) {
+            description("Incompatible histograms")
+            display("Incompatible histograms: {}", reason)
+        }
+        InvalidHistogramConfig(reason: String) {
+            description("Invalid histogram configuration")
+            display("Invalid histogram configuration: {}", reason)
+        }
         Shutdown(pid: Pid) {
             description("Shutting down")
             display("Shutting down {}", pid)
@@ -66,6 +101,7 @@ impl ErrorKind {
             ErrorKind::RegistrarError(id, _) => id.map_or(vec![], |id| vec![id]),
             ErrorKind::WriteError(id, _) => vec![id],
             ErrorKind::ReadError(id, _) => vec![id],
+            ErrorKind::IncompatiblePeer(id, ..) => vec![id],
             ErrorKind::BroadcastError(ref errors) =>
                 errors.iter().flat_map(|e| e.kind().get_ids()).collect(),
             ErrorKind::PollNotificationErrors(ref errors) =>
@@ -0,0 +1,40 @@
+use pid::Pid;
+
+/// Configuration for the executor
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    /// The maximum acceptable time in ms between an envelope being sent and its destination
+    /// process finishing handling it. Envelopes exceeding it are counted in the `slo_violations`
+    /// metric. No check is performed if this is `None`.
+    pub processing_slo: Option<u64>,
+
+    /// Log the route and latency of every envelope that exceeds the processing SLO
+    pub log_slo_violations: bool,
+
+    /// The number of envelopes that may wait for a single process before it's reported as backed
+    /// up. Each time a mailbox grows past this, the `mailbox_high_water_alerts` metric is
+    /// incremented and `mailbox_monitor` is notified. No check is performed if this is `None`.
+    pub mailbox_high_water: Option<usize>,
+
+    /// The process or service sent a `Msg::MailboxHighWater` when a mailbox exceeds
+    /// `mailbox_high_water`
+    pub mailbox_monitor: Option<Pid>,
+
+    /// The longest time in ms that a shutdown waits for services to drain their connections. Until
+    /// every service stopped or this passes, the executor keeps routing envelopes, so that replies
+    /// to outstanding requests still reach their clients.
+    pub shutdown_timeout: u64
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> ExecutorConfig {
+        ExecutorConfig {
+            processing_slo: None,
+            log_slo_violations: false,
+            mailbox_high_water: None,
+            mailbox_monitor: None,
+            // A little longer than the default drain timeout of services
+            shutdown_timeout: 6000
+        }
+    }
+}
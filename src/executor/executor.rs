@@ -1,33 +1,80 @@
 use serde::{Serialize, Deserialize};
 use std::mem;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Sender, Receiver};
-use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+use std::collections::{HashMap, HashSet, VecDeque};
 use amy;
+#[cfg(feature = "chaos")]
+use rand::{self, Rng};
 use slog;
-use time::Duration;
+use time::{Duration, precise_time_ns};
 use ferris::{Wheel, CopyWheel, Resolution};
-use envelope::Envelope;
+use envelope::{Envelope, Priority, now_ms};
 use pid::Pid;
 use process::Process;
 use node_id::NodeId;
-use msg::Msg;
+use msg::{Msg, TimerId, MsgFilter};
+use call_error::{CallError, error_reply};
+use migration::ProcessFactory;
 use cluster::ClusterMsg;
 use correlation_id::CorrelationId;
-use metrics::Metrics;
-use super::{ExecutorStatus, ExecutorMetrics, ExecutorMsg};
+use metrics::{Metric, Metrics, MetricsFilter, filter_metrics};
+use resources::ResourceMetrics;
+use dump::ExecutorDump;
+use trace::{TraceContext, Span, SpanExporter, now_ns};
+use seq_trace::{SeqTrace, SeqTraceHop, seq_trace_event};
+use log_context::LogContext;
+#[cfg(feature = "chaos")]
+use chaos::Chaos;
+use super::{ExecutorStatus, ExecutorMetrics, ExecutorMsg, ExecutorConfig};
+
+// The window over which scheduler utilization and the longest handle call are measured
+const SCHEDULER_WINDOW_NS: u64 = 1_000_000_000;
 
 pub struct Executor<T> {
     pid: Pid,
     node: NodeId,
     envelopes: Vec<Envelope<T>>,
     processes: HashMap<Pid, Box<Process<T>>>,
+    // The generation of each running process that was assigned one
+    generations: HashMap<Pid, u64>,
+    factories: HashMap<String, ProcessFactory<T>>,
+    // Processes being migrated to another node, along with envelopes received for them meanwhile
+    migrating: HashMap<Pid, (Box<Process<T>>, Vec<Envelope<T>>)>,
+    // The nodes that migrated processes now live on
+    migrated: HashMap<Pid, NodeId>,
     service_senders: HashMap<Pid, amy::Sender<Envelope<T>>>,
+    service_filters: HashMap<Pid, MsgFilter<T>>,
     tx: Sender<ExecutorMsg<T>>,
     rx: Receiver<ExecutorMsg<T>>,
+    // Messages taken off the channel but not yet handled, in one queue per priority with the
+    // highest first
+    mailbox: [VecDeque<ExecutorMsg<T>>; 3],
+    // The number of envelopes in the mailbox for each process that has any
+    mailbox_lens: HashMap<Pid, usize>,
+    // Processes whose mailbox exceeded the high water mark and wasn't drained since
+    high_water: HashSet<Pid>,
+    // The time in ns at which a shutdown stops waiting for services to drain
+    shutdown_deadline: Option<u64>,
+    // The start of the current scheduler window, the time spent handling messages, and the longest
+    // call to `Process::handle` during it, in ns
+    window_start: u64,
+    busy: u64,
+    longest_handle: u64,
     cluster_tx: Sender<ClusterMsg<T>>,
     timer_wheel: CopyWheel<(Pid, Option<CorrelationId>)>,
+    // Processes that want simultaneous timer expirations delivered in a single `Msg::Timeouts`
+    batched_timeouts: HashSet<Pid>,
+    config: ExecutorConfig,
+    span_exporter: Option<Box<SpanExporter>>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
     logger: slog::Logger,
+    // The logger handed to processes, without the executor component
+    process_logger: slog::Logger,
     metrics: ExecutorMetrics
 }
 
@@ -36,32 +83,60 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
                tx: Sender<ExecutorMsg<T>>,
                rx: Receiver<ExecutorMsg<T>>,
                cluster_tx: Sender<ClusterMsg<T>>,
+               config: ExecutorConfig,
                logger: slog::Logger) -> Executor<T> {
         let pid = Pid {
             group: Some("rabble".to_string()),
             name: "executor".to_string(),
-            node: node.clone()
+            node: node.clone(),
+            generation: None
         };
         Executor {
             pid: pid,
             node: node,
             envelopes: Vec::new(),
             processes: HashMap::new(),
+            generations: HashMap::new(),
+            factories: HashMap::new(),
+            migrating: HashMap::new(),
+            migrated: HashMap::new(),
             service_senders: HashMap::new(),
+            service_filters: HashMap::new(),
             tx: tx,
             rx: rx,
+            mailbox: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            mailbox_lens: HashMap::new(),
+            high_water: HashSet::new(),
+            shutdown_deadline: None,
+            window_start: precise_time_ns(),
+            busy: 0,
+            longest_handle: 0,
             cluster_tx: cluster_tx,
             timer_wheel: CopyWheel::new(vec![Resolution::TenMs, Resolution::Sec, Resolution::Min]),
+            batched_timeouts: HashSet::new(),
+            config: config,
+            span_exporter: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
             logger: logger.new(o!("component" => "executor")),
+            process_logger: logger,
             metrics: ExecutorMetrics::new()
         }
     }
 
+    /// Inject faults into the executor
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&mut self, chaos: Chaos) {
+        self.chaos = Some(chaos);
+    }
+
     /// Run the executor
     ///
-    ///This call blocks the current thread indefinitely.
+    /// This call blocks the current thread until the node shuts down and its services stopped, at
+    /// which point the cluster server is shut down as well.
     pub fn run(mut self) {
-        while let Ok(msg) = self.rx.recv() {
+        while let Some(msg) = self.next_msg() {
+            let start = precise_time_ns();
             match msg {
                 ExecutorMsg::Envelope(envelope) => {
                     self.metrics.received_envelopes += 1;
@@ -69,34 +144,81 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
                 },
                 ExecutorMsg::Start(pid, process) => self.start(pid, process),
                 ExecutorMsg::Stop(pid) => self.stop(pid),
-                ExecutorMsg::RegisterService(pid, tx) => {
+                ExecutorMsg::RegisterService(pid, tx, accepts) => {
+                    self.service_filters.insert(pid.clone(), accepts);
                     self.service_senders.insert(pid, tx);
                 },
+                ExecutorMsg::UnregisterService(pid) => {
+                    self.service_filters.remove(&pid);
+                    self.service_senders.remove(&pid);
+                },
                 ExecutorMsg::GetStatus(correlation_id) => self.get_status(correlation_id),
+                ExecutorMsg::RegisterFactory(kind, factory) => {
+                    self.factories.insert(kind, factory);
+                },
+                ExecutorMsg::Migrate(pid, node) => self.migrate(pid, node),
+                ExecutorMsg::Signal(pid, pending) => self.signal(pid, pending),
+                ExecutorMsg::DumpState(tx) => {
+                    // The caller may have given up waiting
+                    let _ = tx.send(self.dump());
+                },
+                ExecutorMsg::SetSpanExporter(exporter) => self.span_exporter = Some(exporter),
                 ExecutorMsg::Tick => self.tick(),
 
-                // Just return so the thread exits
-                ExecutorMsg::Shutdown => return
+                ExecutorMsg::Shutdown => {
+                    if self.shutdown_deadline.is_none() {
+                        self.shutdown_services();
+                        let timeout = self.config.shutdown_timeout * 1_000_000;
+                        self.shutdown_deadline = Some(precise_time_ns() + timeout);
+                    }
+                }
+            }
+            self.busy += precise_time_ns() - start;
+            if self.is_shut_down() {
+                break;
             }
         }
+        // Replies from draining services may be addressed to other nodes, so the cluster server is
+        // only stopped once they are done. It may already have stopped on its own.
+        let _ = self.cluster_tx.send(ClusterMsg::Shutdown);
+    }
+
+    /// Return true once a shutdown started and every service stopped or the shutdown timeout passed
+    fn is_shut_down(&self) -> bool {
+        match self.shutdown_deadline {
+            Some(deadline) => self.service_senders.is_empty() || precise_time_ns() >= deadline,
+            None => false
+        }
     }
 
-    fn get_status(&self, correlation_id: CorrelationId) {
+    /// Tell all services to drain their connections and stop
+    fn shutdown_services(&self) {
+        for (pid, tx) in &self.service_senders {
+            // The service may already have stopped
+            let _ = tx.send(Envelope::new(pid.clone(), self.pid.clone(), Msg::Shutdown, None));
+        }
+    }
+
+    fn get_status(&mut self, correlation_id: CorrelationId) {
         let status = ExecutorStatus {
             total_processes: self.processes.len(),
+            processes: self.processes.keys().cloned().collect(),
             services: self.service_senders.keys().cloned().collect()
         };
-        let envelope = Envelope {
-            to: correlation_id.pid.clone(),
-            from: self.pid.clone(),
-            msg: Msg::ExecutorStatus(status),
-            correlation_id: Some(correlation_id)
-        };
+        let envelope = Envelope::new(correlation_id.pid.clone(),
+                                     self.pid.clone(),
+                                     Msg::ExecutorStatus(status),
+                                     Some(correlation_id));
         self.route_to_service(envelope);
     }
 
     fn start(&mut self, pid: Pid, mut process: Box<Process<T>>) {
+        self.migrated.remove(&pid);
         let envelopes = process.init(self.pid.clone());
+        match pid.generation {
+            Some(generation) => self.generations.insert(pid.clone(), generation),
+            None => self.generations.remove(&pid)
+        };
         self.processes.insert(pid, process);
         for envelope in envelopes {
             if envelope.to == self.pid {
@@ -108,14 +230,73 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
     }
 
     fn stop(&mut self, pid: Pid) {
+        self.batched_timeouts.remove(&pid);
+        self.generations.remove(&pid);
         self.processes.remove(&pid);
     }
 
+    /// Deliver the notifications of a `Signal` to a process as a single message
+    fn signal(&mut self, pid: Pid, pending: Arc<AtomicUsize>) {
+        let count = pending.swap(0, Ordering::AcqRel);
+        if count == 0 {
+            return;
+        }
+        self.metrics.signals += 1;
+        let envelope = Envelope::new(pid, self.pid.clone(), Msg::Signal(count), None);
+        if let Err(envelope) = self.route_to_process(envelope) {
+            debug!(self.logger, "Dropping signal to missing process"; "pid" => envelope.to.to_string());
+        }
+    }
+
     fn tick(&mut self) {
+        #[cfg(feature = "chaos")]
+        self.chaos_kill();
+        self.update_scheduler_metrics();
+        let mut batches: HashMap<Pid, Vec<TimerId>> = HashMap::new();
         for (pid, c_id) in self.timer_wheel.expire() {
+            if self.batched_timeouts.contains(&pid) {
+                batches.entry(pid).or_insert_with(Vec::new).push(c_id);
+                continue;
+            }
             let envelope = Envelope::new(pid, self.pid.clone(), Msg::Timeout, c_id);
             let _ = self.route_to_process(envelope);
         }
+        for (pid, mut timer_ids) in batches {
+            // A lone expiration is delivered as usual
+            let envelope = if timer_ids.len() == 1 {
+                Envelope::new(pid, self.pid.clone(), Msg::Timeout, timer_ids.pop().unwrap())
+            } else {
+                self.metrics.batched_timeouts += 1;
+                Envelope::new(pid, self.pid.clone(), Msg::Timeouts(timer_ids), None)
+            };
+            let _ = self.route_to_process(envelope);
+        }
+    }
+
+    /// Publish the utilization and longest handle call of the scheduler window once it's over
+    fn update_scheduler_metrics(&mut self) {
+        let now = precise_time_ns();
+        let elapsed = now - self.window_start;
+        if elapsed < SCHEDULER_WINDOW_NS {
+            return;
+        }
+        self.metrics.scheduler_utilization = (self.busy * 100 / elapsed) as i64;
+        self.metrics.longest_handle_us = (self.longest_handle / 1000) as i64;
+        self.window_start = now;
+        self.busy = 0;
+        self.longest_handle = 0;
+    }
+
+    /// Maybe kill a random process
+    #[cfg(feature = "chaos")]
+    fn chaos_kill(&mut self) {
+        if !self.chaos.as_ref().map_or(false, |c| c.kill_process()) || self.processes.is_empty() {
+            return;
+        }
+        let pids: Vec<Pid> = self.processes.keys().cloned().collect();
+        let pid = rand::thread_rng().choose(&pids).unwrap().clone();
+        warn!(self.logger, "Chaos: killed process"; "pid" => pid.to_string());
+        self.stop(pid);
     }
 
     /// Route envelopes to local or remote processes
@@ -126,6 +307,10 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
     /// Note that all envelopes sent to an executor are sent from the local cluster server and must
     /// be addressed to local processes.
     fn route(&mut self, envelope: Envelope<T>) {
+        if envelope.is_expired(now_ms()) {
+            self.expire(envelope);
+            return;
+        }
         if self.node != envelope.to.node {
             self.cluster_tx.send(ClusterMsg::Envelope(envelope)).unwrap();
             return;
@@ -151,12 +336,64 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
             return Ok(());
         }
 
+        if let Some(&mut (_, ref mut buffered)) = self.migrating.get_mut(&envelope.to) {
+            buffered.push(envelope);
+            return Ok(());
+        }
+
+        if let Some(node) = self.migrated.get(&envelope.to).cloned() {
+            self.forward(envelope, node);
+            return Ok(());
+        }
+
+        if let Some(generation) = envelope.to.generation {
+            if self.generations.get(&envelope.to) != Some(&generation) {
+                self.dead_letter(envelope);
+                return Ok(());
+            }
+        }
+
+        let sent_at = envelope.sent_at;
+        let route = if self.config.log_slo_violations {
+            Some((envelope.from.clone(), envelope.to.clone()))
+        } else {
+            None
+        };
+
+        // The handling of a traced envelope is a new span, whose parent sent the envelope
+        let trace = envelope.trace.map(|parent| (parent, parent.child()));
+        let start = if trace.is_some() { now_ns() } else { 0 };
+        let received = seq_trace_event(&envelope, SeqTraceHop::Receive, &self.node, &self.pid);
         if let Some(process) = self.processes.get_mut(&envelope.to) {
-            let Envelope {from, msg, correlation_id, ..} = envelope;
-            process.handle(msg, from, correlation_id, &mut self.envelopes);
+            let Envelope {to, from, msg, correlation_id, seq_trace, reply_to, ..} = envelope;
+            {
+                let ctx = LogContext::new(&self.process_logger, &to)
+                    .with_correlation_id(correlation_id.as_ref())
+                    .with_reply_to(reply_to.as_ref());
+                let handle_start = precise_time_ns();
+                process.handle_in_context(&ctx,
+                                          msg,
+                                          from.clone(),
+                                          correlation_id.clone(),
+                                          &mut self.envelopes);
+                let handle_time = precise_time_ns() - handle_start;
+                if handle_time > self.longest_handle {
+                    self.longest_handle = handle_time;
+                }
+            }
+            if let Some((parent, span)) = trace {
+                self.end_span(parent, span, start, to, from);
+            }
+            if let Some(received) = received {
+                self.send(received);
+            }
+            if let Some(token) = seq_trace {
+                self.propagate_seq_trace(token);
+            }
         } else {
             return Err(envelope);
         };
+        self.check_slo(sent_at, route);
 
         // Take envelopes out of self temporarily so we don't get a borrowck error
         let mut envelopes = mem::replace(&mut self.envelopes, Vec::new());
@@ -165,6 +402,7 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
                 self.handle_executor_envelope(envelope);
                 continue;
             }
+            self.seq_trace(&envelope, SeqTraceHop::Send);
             if envelope.to.node == self.node {
                 // This won't ever fail because we hold a ref to both ends of the channel
                 self.tx.send(ExecutorMsg::Envelope(envelope)).unwrap();
@@ -177,19 +415,324 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
         Ok(())
     }
 
+    /// Return the next message to handle, after moving every message waiting in the channel to the
+    /// mailbox, so that the length of the mailbox of each process is known
+    ///
+    /// Returns `None` once the channel is closed, or the shutdown deadline passed with nothing left
+    /// to handle.
+    fn next_msg(&mut self) -> Option<ExecutorMsg<T>> {
+        if self.mailbox.iter().all(|queue| queue.is_empty()) {
+            let msg = match self.shutdown_deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_sub(precise_time_ns());
+                    self.rx.recv_timeout(StdDuration::from_nanos(timeout)).ok()
+                },
+                None => self.rx.recv().ok()
+            };
+            match msg {
+                Some(msg) => self.enqueue(msg),
+                None => return None
+            }
+        }
+        while let Ok(msg) = self.rx.try_recv() {
+            self.enqueue(msg);
+        }
+        let msg = self.mailbox.iter_mut().filter_map(|queue| queue.pop_front()).next();
+        if let Some(ExecutorMsg::Envelope(ref envelope)) = msg {
+            self.dequeued(&envelope.to);
+        }
+        msg
+    }
+
+    fn enqueue(&mut self, msg: ExecutorMsg<T>) {
+        if let ExecutorMsg::Envelope(ref envelope) = msg {
+            if self.processes.contains_key(&envelope.to) {
+                let len = match self.mailbox_lens.get_mut(&envelope.to) {
+                    Some(len) => {
+                        *len += 1;
+                        *len
+                    },
+                    None => 1
+                };
+                if len == 1 {
+                    self.mailbox_lens.insert(envelope.to.clone(), 1);
+                }
+                self.check_high_water(&envelope.to, len);
+            }
+        }
+        let index = queue_priority(&msg).index();
+        self.mailbox[index].push_back(msg);
+    }
+
+    fn dequeued(&mut self, pid: &Pid) {
+        let len = match self.mailbox_lens.get_mut(pid) {
+            Some(len) => {
+                *len -= 1;
+                *len
+            },
+            None => return
+        };
+        if len == 0 {
+            self.mailbox_lens.remove(pid);
+        }
+        // Only alert again once the backlog mostly cleared, so that a mailbox hovering around the
+        // high water mark doesn't flood the monitor
+        if self.config.mailbox_high_water.map_or(true, |high_water| len <= high_water / 2) {
+            self.high_water.remove(pid);
+        }
+    }
+
+    /// Notify the mailbox monitor the first time the mailbox of `pid` exceeds the high water mark
+    fn check_high_water(&mut self, pid: &Pid, len: usize) {
+        if len as i64 > self.metrics.peak_mailbox_len {
+            self.metrics.peak_mailbox_len = len as i64;
+        }
+        let high_water = match self.config.mailbox_high_water {
+            Some(high_water) => high_water,
+            None => return
+        };
+        if len <= high_water || self.high_water.contains(pid) {
+            return;
+        }
+        self.high_water.insert(pid.clone());
+        self.metrics.mailbox_high_water_alerts += 1;
+        warn!(self.logger, "Mailbox exceeded high water mark";
+              "pid" => pid.to_string(), "len" => len, "high_water" => high_water);
+        if let Some(monitor) = self.config.mailbox_monitor.clone() {
+            let msg = Msg::MailboxHighWater(pid.clone(), len);
+            let envelope = Envelope::new(monitor, self.pid.clone(), msg, None);
+            self.send(envelope);
+        }
+    }
+
+    /// Propagate the trace to the envelopes sent while handling a traced envelope, and export the
+    /// span if the trace is sampled
+    fn end_span(&mut self,
+                parent: TraceContext,
+                span: TraceContext,
+                start: u64,
+                pid: Pid,
+                from: Pid)
+    {
+        for envelope in self.envelopes.iter_mut() {
+            if envelope.trace.is_none() {
+                envelope.trace = Some(span);
+            }
+        }
+        if !span.is_sampled() {
+            return;
+        }
+        if let Some(ref mut exporter) = self.span_exporter {
+            exporter.export(Span {
+                context: span,
+                parent_span_id: parent.span_id,
+                pid: pid,
+                from: from,
+                start: start,
+                end: now_ns()
+            });
+        }
+    }
+
+    /// Add the envelopes sent while handling an envelope of a sequence trace to the trace
+    fn propagate_seq_trace(&mut self, token: SeqTrace) {
+        for envelope in self.envelopes.iter_mut() {
+            if envelope.seq_trace.is_none() {
+                envelope.seq_trace = Some(SeqTrace {
+                    label: token.label,
+                    serial: token.serial + 1,
+                    collector: token.collector.clone()
+                });
+            }
+        }
+    }
+
+    /// Report a hop of an envelope to the collector of its sequence trace, if it has one
+    fn seq_trace(&self, envelope: &Envelope<T>, hop: SeqTraceHop) {
+        if let Some(report) = seq_trace_event(envelope, hop, &self.node, &self.pid) {
+            self.send(report);
+        }
+    }
+
+    /// Send an envelope created by the executor to a process or service on any node
+    fn send(&self, envelope: Envelope<T>) {
+        // This won't ever fail because we hold a ref to both ends of the channel
+        if envelope.to.node == self.node {
+            self.tx.send(ExecutorMsg::Envelope(envelope)).unwrap();
+        } else {
+            self.cluster_tx.send(ClusterMsg::Envelope(envelope)).unwrap();
+        }
+    }
+
+    /// Start moving a process to another node by sending its state to the executor there
+    fn migrate(&mut self, pid: Pid, node: NodeId) {
+        if node == self.node {
+            return;
+        }
+        let (kind, state) = match self.processes.get(&pid).and_then(|p| p.as_migratable()) {
+            Some(process) => (process.kind(), process.save()),
+            None => {
+                warn!(self.logger, "Cannot migrate process: not found or not migratable";
+                      "pid" => pid.to_string());
+                return;
+            }
+        };
+        info!(self.logger, "Migrating process"; "pid" => pid.to_string(), "to" => node.to_string());
+        let process = self.processes.remove(&pid).unwrap();
+        self.generations.remove(&pid);
+        self.batched_timeouts.remove(&pid);
+        self.migrating.insert(pid.clone(), (process, Vec::new()));
+        let executor_pid = Pid {
+            group: Some("rabble".to_string()),
+            name: "executor".to_string(),
+            node: node,
+            generation: None
+        };
+        let msg = Msg::MigrateIn {pid: pid, kind: kind, state: state};
+        let envelope = Envelope::new(executor_pid, self.pid.clone(), msg, None);
+        self.route(envelope);
+    }
+
+    /// Restore a process migrated from another node and tell the source executor the outcome
+    fn migrate_in(&mut self, from: Pid, pid: Pid, kind: String, state: Vec<u8>) {
+        let process = self.factories.get(&kind).map(|factory| factory(state));
+        let reply = match process {
+            Some(process) => {
+                let new_pid = Pid {
+                    group: pid.group.clone(),
+                    name: pid.name.clone(),
+                    node: self.node.clone(),
+                    generation: None
+                };
+                self.start(new_pid.clone(), process);
+                Msg::Migrated(pid, new_pid)
+            },
+            None => Msg::MigrateFailed(pid, format!("No factory registered for {}", kind))
+        };
+        let envelope = Envelope::new(from, self.pid.clone(), reply, None);
+        self.route(envelope);
+    }
+
+    /// The process was restarted on the destination, so forward it any buffered envelopes
+    fn migrated(&mut self, old_pid: Pid, new_pid: Pid) {
+        if let Some((_, buffered)) = self.migrating.remove(&old_pid) {
+            info!(self.logger, "Migrated process";
+                  "pid" => old_pid.to_string(), "new_pid" => new_pid.to_string());
+            self.metrics.migrations += 1;
+            self.migrated.insert(old_pid, new_pid.node.clone());
+            for envelope in buffered {
+                self.forward(envelope, new_pid.node.clone());
+            }
+        }
+    }
+
+    /// The destination couldn't restore the process, so resume running it here
+    fn migrate_failed(&mut self, pid: Pid, reason: String) {
+        if let Some((process, buffered)) = self.migrating.remove(&pid) {
+            warn!(self.logger, "Failed to migrate process";
+                  "pid" => pid.to_string(), "reason" => reason);
+            self.processes.insert(pid, process);
+            for envelope in buffered {
+                self.route(envelope);
+            }
+        }
+    }
+
+    fn forward(&mut self, mut envelope: Envelope<T>, node: NodeId) {
+        self.metrics.forwarded_envelopes += 1;
+        envelope.to.node = node;
+        envelope.to.generation = None;
+        self.seq_trace(&envelope, SeqTraceHop::Forward);
+        self.route(envelope);
+    }
+
+    /// Drop an envelope addressed to a previous incarnation of a process
+    fn dead_letter(&mut self, envelope: Envelope<T>) {
+        self.metrics.dead_letters += 1;
+        warn!(self.logger, "Dead letter: process generation no longer running";
+              "to" => envelope.to.to_string(),
+              "generation" => envelope.to.generation.unwrap_or(0),
+              "from" => envelope.from.to_string());
+    }
+
+    /// Drop an envelope whose deadline passed while it was queued, and answer it with
+    /// `CallError::Timeout` if it's a request
+    fn expire(&mut self, envelope: Envelope<T>) {
+        self.metrics.expired_envelopes += 1;
+        debug!(self.logger, "Dropping expired envelope";
+               "to" => envelope.to.to_string(),
+               "from" => envelope.from.to_string(),
+               "deadline" => envelope.deadline.unwrap_or(0));
+        self.reply_error(envelope, |_| CallError::Timeout);
+    }
+
+    /// Record an SLO violation if an envelope took too long between being sent and being handled
+    fn check_slo(&mut self, sent_at: Option<u64>, route: Option<(Pid, Pid)>) {
+        let (slo, sent_at) = match (self.config.processing_slo, sent_at) {
+            (Some(slo), Some(sent_at)) => (slo, sent_at),
+            _ => return
+        };
+        let latency = now_ms().saturating_sub(sent_at);
+        if latency > slo {
+            self.metrics.slo_violations += 1;
+            if let Some((from, to)) = route {
+                warn!(self.logger, "Envelope exceeded processing SLO";
+                      "from" => from.to_string(), "to" => to.to_string(),
+                      "latency_ms" => latency, "slo_ms" => slo);
+            }
+        }
+    }
+
     /// Route an envelope to a service on this node
-    fn route_to_service(&self, envelope: Envelope<T>) {
+    fn route_to_service(&mut self, envelope: Envelope<T>) {
         if let Some(tx) = self.service_senders.get(&envelope.to) {
+            if !self.service_accepts(&envelope) {
+                self.metrics.rejected_service_envelopes += 1;
+                debug!(self.logger, "Service rejected envelope";
+                       "to" => envelope.to.to_string(),
+                       "from" => envelope.from.to_string());
+                self.reply_error(envelope, CallError::Unsupported);
+                return;
+            }
+            self.seq_trace(&envelope, SeqTraceHop::Receive);
             tx.send(envelope).unwrap();
         } else {
             warn!(self.logger, "Failed to find service"; "pid" => envelope.to.to_string());
+            self.reply_no_process(envelope);
+        }
+    }
+
+    /// Return true if the filter registered by the destination service accepts the message
+    ///
+    /// Shutdown, metrics and connection requests are handled by every service, so they are always
+    /// accepted.
+    fn service_accepts(&self, envelope: &Envelope<T>) -> bool {
+        match envelope.msg {
+            Msg::Shutdown | Msg::GetMetrics(_) | Msg::GetConnections => true,
+            ref msg => self.service_filters.get(&envelope.to).map_or(true, |accepts| accepts(msg))
+        }
+    }
+
+    /// Tell the sender of an undeliverable request that the destination doesn't exist
+    fn reply_no_process(&self, envelope: Envelope<T>) {
+        self.reply_error(envelope, CallError::NoProcess);
+    }
+
+    /// Reply to an undeliverable request with the error built by `error` from its destination
+    fn reply_error<F>(&self, envelope: Envelope<T>, error: F)
+        where F: FnOnce(Pid) -> CallError
+    {
+        if let Some(reply) = error_reply(envelope, self.pid.clone(), error) {
+            self.send(reply);
         }
     }
 
     fn handle_executor_envelope(&mut self, envelope: Envelope<T>) {
-        let Envelope {from, msg, correlation_id, ..} = envelope;
+        let Envelope {from, msg, correlation_id, reply_to, ..} = envelope;
         match msg {
             Msg::StartTimer(time_in_ms) => {
+                #[cfg(feature = "chaos")]
+                let time_in_ms = time_in_ms + self.chaos.as_ref().map_or(0, |c| c.timer_delay());
                 self.timer_wheel.start((from, correlation_id),
                                        Duration::milliseconds(time_in_ms as i64));
                 self.metrics.timers_started += 1;
@@ -198,22 +741,85 @@ impl<'de, T: Serialize + Deserialize<'de> + Send + Debug + Clone> Executor<T> {
                 self.timer_wheel.stop((from, correlation_id));
                 self.metrics.timers_cancelled += 1;
             }
-            Msg::GetMetrics => self.send_metrics(from, correlation_id),
+            Msg::BatchTimeouts(enabled) => {
+                if enabled {
+                    self.batched_timeouts.insert(from);
+                } else {
+                    self.batched_timeouts.remove(&from);
+                }
+            },
+            Msg::Yield => {
+                // Put the continuation at the back of the queue so that other processes get a
+                // chance to run first.
+                let envelope = Envelope::new(from, self.pid.clone(), Msg::Continue, correlation_id);
+                // This won't ever fail because we hold a ref to both ends of the channel
+                self.tx.send(ExecutorMsg::Envelope(envelope)).unwrap();
+                self.metrics.yields += 1;
+            },
+            Msg::GetMetrics(filter) => {
+                self.send_metrics(reply_to.unwrap_or(from), filter, correlation_id)
+            },
+            Msg::MigrateIn {pid, kind, state} => self.migrate_in(from, pid, kind, state),
+            Msg::Migrated(old_pid, new_pid) => self.migrated(old_pid, new_pid),
+            Msg::MigrateFailed(pid, reason) => self.migrate_failed(pid, reason),
             _ => error!(self.logger, "Invalid message sent to executor";
                         "from" => from.to_string(), "msg" => format!("{:?}", msg))
         }
     }
 
-    fn send_metrics(&mut self, from: Pid, correlation_id: Option<CorrelationId>) {
+    fn dump(&mut self) -> ExecutorDump {
+        let mut processes: Vec<Pid> = self.processes.keys().cloned().collect();
+        processes.sort();
+        let mut services: Vec<Pid> = self.service_senders.keys().cloned().collect();
+        services.sort();
+        let mut migrating: Vec<Pid> = self.migrating.keys().cloned().collect();
+        migrating.sort();
+        ExecutorDump {
+            processes: processes,
+            services: services,
+            migrating: migrating,
+            metrics: self.metrics_data()
+        }
+    }
+
+    fn metrics_data(&mut self) -> Vec<(String, Metric)> {
         self.metrics.processes = self.processes.len() as i64;
         self.metrics.services = self.service_senders.len() as i64;
-        let envelope = Envelope {
-            to: from,
-            from: self.pid.clone(),
-            msg: Msg::Metrics(self.metrics.data()),
-            correlation_id: correlation_id
-        };
+        let queued: usize = self.mailbox.iter().map(|queue| queue.len()).sum();
+        self.metrics.run_queue_len = queued as i64;
+        // Resource usage is per OS process, so report it along with the executor metrics
+        let mut data = self.metrics.data();
+        data.extend(ResourceMetrics::collect().data());
+        // Empty mailboxes are left out, since there may be many processes
+        let mut mailboxes: Vec<(&Pid, &usize)> = self.mailbox_lens.iter().collect();
+        mailboxes.sort();
+        data.extend(mailboxes.into_iter().map(|(pid, len)| {
+            (format!("mailbox_len:{}", pid), Metric::Gauge(*len as i64))
+        }));
+        data
+    }
+
+    fn send_metrics(&mut self,
+                    from: Pid,
+                    filter: Option<MetricsFilter>,
+                    correlation_id: Option<CorrelationId>)
+    {
+        let data = filter_metrics(filter.as_ref(), self.metrics_data());
+        let envelope = Envelope::new(from,
+                                     self.pid.clone(),
+                                     Msg::Metrics(data),
+                                     correlation_id);
         self.route(envelope);
     }
 }
 
+/// The mailbox queue of a message. Envelopes are queued by their priority. Stopping and migrating
+/// a process wait for the envelopes queued before them, while all other executor messages are
+/// handled ahead of envelopes, so that a process is started before anything sent to it.
+fn queue_priority<T>(msg: &ExecutorMsg<T>) -> Priority {
+    match *msg {
+        ExecutorMsg::Envelope(ref envelope) => envelope.priority,
+        ExecutorMsg::Stop(_) | ExecutorMsg::Migrate(..) => Priority::Normal,
+        _ => Priority::High
+    }
+}
@@ -5,5 +5,19 @@ metrics!(ExecutorMetrics {
     services: i64,
     received_envelopes: u64,
     timers_started: u64,
-    timers_cancelled: u64
+    timers_cancelled: u64,
+    batched_timeouts: u64,
+    yields: u64,
+    slo_violations: u64,
+    dead_letters: u64,
+    rejected_service_envelopes: u64,
+    expired_envelopes: u64,
+    migrations: u64,
+    forwarded_envelopes: u64,
+    signals: u64,
+    peak_mailbox_len: i64,
+    mailbox_high_water_alerts: u64,
+    run_queue_len: i64,
+    scheduler_utilization: i64, // The percentage of the last window spent handling messages
+    longest_handle_us: i64 // The longest call to `Process::handle` in the last window
 });
@@ -2,8 +2,10 @@ mod executor;
 mod status;
 mod msg;
 mod metrics;
+mod config;
 
 pub use self::executor::Executor;
 pub use self::status::ExecutorStatus;
 pub use self::msg::ExecutorMsg;
 pub use self::metrics::ExecutorMetrics;
+pub use self::config::ExecutorConfig;
@@ -1,15 +1,29 @@
 use envelope::Envelope;
 use process::Process;
 use pid::Pid;
+use msg::MsgFilter;
+use node_id::NodeId;
+use migration::ProcessFactory;
 use correlation_id::CorrelationId;
+use dump::ExecutorDump;
+use trace::SpanExporter;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::atomic::AtomicUsize;
 use amy;
 
 pub enum ExecutorMsg<T> {
     Start(Pid, Box<Process<T>>),
     Stop(Pid),
     Envelope(Envelope<T>),
-    RegisterService(Pid, amy::Sender<Envelope<T>>),
+    RegisterService(Pid, amy::Sender<Envelope<T>>, MsgFilter<T>),
+    UnregisterService(Pid),
     GetStatus(CorrelationId),
+    RegisterFactory(String, ProcessFactory<T>),
+    Migrate(Pid, NodeId),
+    Signal(Pid, Arc<AtomicUsize>), // The count of pending notifications
+    DumpState(Sender<ExecutorDump>),
+    SetSpanExporter(Box<SpanExporter>),
     Shutdown,
     Tick
 }
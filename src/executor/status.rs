@@ -3,6 +3,7 @@ use pid::Pid;
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ExecutorStatus {
     pub total_processes: usize,
+    pub processes: Vec<Pid>,
     pub services: Vec<Pid>,
     //... Some stats
 }
@@ -1,5 +1,11 @@
-use std::fmt::{self, Debug, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use serde::ser::Error as SerError;
 use hdrsample;
+use hdrsample::serialization::{V2Serializer, Deserializer as V2Deserializer};
+use errors::*;
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum TimeUnit {
@@ -9,14 +15,307 @@ pub enum TimeUnit {
     Nanoseconds
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
+impl TimeUnit {
+    /// The number of ns in one of this unit
+    fn nanos(&self) -> u64 {
+        match *self {
+            TimeUnit::Seconds => 1_000_000_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Nanoseconds => 1
+        }
+    }
+
+    /// Convert a duration to a whole number of this unit, rounding down
+    pub fn from_duration(&self, duration: Duration) -> u64 {
+        let nanos = duration.as_secs()
+            .saturating_mul(1_000_000_000)
+            .saturating_add(duration.subsec_nanos() as u64);
+        nanos / self.nanos()
+    }
+
+    /// Convert a number of this unit to a duration
+    pub fn to_duration(&self, value: u64) -> Duration {
+        let nanos = value.saturating_mul(self.nanos());
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// The abbreviation of the unit, used when rendering values
+    pub fn abbreviation(&self) -> &'static str {
+        match *self {
+            TimeUnit::Seconds => "s",
+            TimeUnit::Milliseconds => "ms",
+            TimeUnit::Microseconds => "us",
+            TimeUnit::Nanoseconds => "ns"
+        }
+    }
+}
+
+/// The precision and range of values of a `Histogram`
+///
+/// The memory used by a histogram grows with the number of significant figures and with the ratio
+/// of `highest_trackable` to `lowest_discernible`. The default keeps 3 significant figures and
+/// resizes itself to fit any value, which suits latencies. Coarse metrics can use fewer figures,
+/// and metrics with a known range can set fixed bounds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistogramConfig {
+    /// The number of significant figures kept for recorded values, from 0 to 5
+    pub sigfigs: u8,
+    /// The lowest value that is told apart from 0, at least 1
+    pub lowest_discernible: u64,
+    /// The highest value that can be recorded, at least twice `lowest_discernible`
+    pub highest_trackable: u64,
+    /// Grow the histogram to fit values above `highest_trackable`
+    ///
+    /// When disabled, larger values are recorded as `highest_trackable`.
+    pub auto_resize: bool
+}
+
+impl Default for HistogramConfig {
+    fn default() -> HistogramConfig {
+        HistogramConfig {
+            sigfigs: 3,
+            lowest_discernible: 1,
+            highest_trackable: 2,
+            auto_resize: true
+        }
+    }
+}
+
+/// A histogram of durations that can be sent between nodes as part of `Msg::Metrics`
+///
+/// By default the underlying HdrHistogram resizes itself to fit any recorded value. It's
+/// serialized in the compact V2 format of HdrHistogram, which keeps its precision and bounds. A
+/// deserialized histogram always resizes itself, since values from other nodes shouldn't be lost.
+#[derive(Clone, PartialEq)]
 pub struct Histogram {
     pub unit: TimeUnit,
     pub histogram: hdrsample::Histogram<u64>
 }
 
+impl Histogram {
+    /// Create a histogram with the default `HistogramConfig`
+    pub fn new(unit: TimeUnit) -> Histogram {
+        // The default config is always valid
+        Histogram::with_config(unit, HistogramConfig::default()).unwrap()
+    }
+
+    /// Create a histogram with the given precision and bounds
+    ///
+    /// Returns an `InvalidHistogramConfig` error if the number of significant figures or the bounds
+    /// are out of range.
+    pub fn with_config(unit: TimeUnit, config: HistogramConfig) -> Result<Histogram> {
+        let histogram = hdrsample::Histogram::new_with_bounds(config.lowest_discernible,
+                                                              config.highest_trackable,
+                                                              config.sigfigs);
+        let mut histogram = try!(histogram.map_err(|e| {
+            ErrorKind::InvalidHistogramConfig(format!("{:?}", e))
+        }));
+        histogram.auto(config.auto_resize);
+        Ok(Histogram {
+            unit: unit,
+            histogram: histogram
+        })
+    }
+
+    /// Record a single value, in the unit of the histogram
+    pub fn record(&mut self, value: u64) {
+        // This only fails for values beyond the range of a histogram that doesn't resize itself
+        if self.histogram.record(value).is_err() {
+            let highest = self.histogram.high();
+            let _ = self.histogram.record(highest);
+        }
+    }
+
+    /// Add the values recorded by `other` to this histogram
+    ///
+    /// Both histograms are expected to record values in the same unit. No values are lost: if
+    /// `other` holds values beyond the range of a histogram that doesn't resize itself, it grows to
+    /// fit them.
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.histogram.add(&other.histogram).is_err() {
+            self.histogram.auto(true);
+            let _ = self.histogram.add(&other.histogram);
+            self.histogram.auto(false);
+        }
+    }
+
+    /// Remove the values recorded by `other` from this histogram
+    ///
+    /// This is the inverse of `merge`, so `other` must hold a subset of the values of this
+    /// histogram, such as an earlier snapshot of it. Otherwise an `IncompatibleHistograms` error is
+    /// returned, and some values may have been removed already. Use `since` to keep the original.
+    pub fn subtract(&mut self, other: &Histogram) -> Result<()> {
+        if self.unit != other.unit {
+            let reason = format!("cannot subtract {:?} from {:?}", other.unit, self.unit);
+            return Err(ErrorKind::IncompatibleHistograms(reason).into());
+        }
+        self.histogram.subtract(&other.histogram).map_err(|e| {
+            ErrorKind::IncompatibleHistograms(format!("{:?}", e)).into()
+        })
+    }
+
+    /// Return the values recorded since `earlier`, a snapshot taken from this histogram before
+    pub fn since(&self, earlier: &Histogram) -> Result<Histogram> {
+        let mut interval = self.clone();
+        try!(interval.subtract(earlier));
+        Ok(interval)
+    }
+
+    /// Return a histogram of the values recorded so far, and start over with an empty one
+    ///
+    /// Calling this at a fixed interval yields a histogram per interval, rather than one of all
+    /// values ever recorded.
+    pub fn take_interval(&mut self) -> Histogram {
+        let interval = self.clone();
+        self.reset();
+        interval
+    }
+
+    /// Remove all recorded values
+    pub fn reset(&mut self) {
+        self.histogram.reset();
+    }
+
+    /// The number of recorded values
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    /// The value at percentile `p`, between 0 and 100
+    pub fn percentile(&self, p: f64) -> u64 {
+        self.histogram.value_at_percentile(p)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.histogram.mean()
+    }
+
+    /// Summarize the distribution of values, in the unit of the histogram
+    pub fn summary(&self) -> Summary {
+        Summary {
+            unit: self.unit.clone(),
+            count: self.count(),
+            mean: self.mean(),
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p99: self.percentile(99.0),
+            p999: self.percentile(99.9),
+            max: self.histogram.max()
+        }
+    }
+}
+
+/// The common percentiles of a histogram, as reported by the admin service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub unit: TimeUnit,
+    pub count: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let unit = self.unit.abbreviation();
+        write!(f, "count={} mean={:.1}{} p50={}{} p90={}{} p99={}{} p99.9={}{} max={}{}",
+               self.count, self.mean, unit, self.p50, unit, self.p90, unit, self.p99, unit,
+               self.p999, unit, self.max, unit)
+    }
+}
+
+/// A histogram of durations, recorded and reported as `std::time::Duration`s
+///
+/// Durations are stored in the unit given on creation, rounding down, so the unit should be fine
+/// enough that the durations of interest are at least a few units long. Measuring request latencies
+/// in ms, for instance, records every request faster than 1ms as 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    histogram: Histogram
+}
+
+impl LatencyHistogram {
+    pub fn new(unit: TimeUnit) -> LatencyHistogram {
+        LatencyHistogram {
+            histogram: Histogram::new(unit)
+        }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let value = self.histogram.unit.from_duration(duration);
+        self.histogram.record(value);
+    }
+
+    /// Record the time elapsed since `start`
+    pub fn record_since(&mut self, start: Instant) {
+        self.record(start.elapsed());
+    }
+
+    /// The duration at percentile `p`, between 0 and 100
+    pub fn percentile(&self, p: f64) -> Duration {
+        self.histogram.unit.to_duration(self.histogram.percentile(p))
+    }
+
+    pub fn count(&self) -> u64 {
+        self.histogram.count()
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.histogram.summary()
+    }
+
+    /// The underlying histogram, to send as a `Metric::Histogram`
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+}
+
+impl Display for LatencyHistogram {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.summary(), f)
+    }
+}
+
 impl Debug for Histogram {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "Histogram ({:?})", self.unit)
     }
 }
+
+#[derive(Serialize, Deserialize)]
+struct EncodedHistogram {
+    unit: TimeUnit,
+    data: Vec<u8>
+}
+
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        let mut data = Vec::new();
+        try!(V2Serializer::new().serialize(&self.histogram, &mut data)
+             .map_err(|e| S::Error::custom(format!("Failed to encode histogram: {:?}", e))));
+        EncodedHistogram {
+            unit: self.unit.clone(),
+            data: data
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Histogram {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> ::std::result::Result<Histogram, D::Error>
+    {
+        let encoded = try!(EncodedHistogram::deserialize(deserializer));
+        let mut histogram: hdrsample::Histogram<u64> =
+            try!(V2Deserializer::new().deserialize(&mut &encoded.data[..])
+                 .map_err(|e| D::Error::custom(format!("Failed to decode histogram: {:?}", e))));
+        histogram.auto(true);
+        Ok(Histogram {
+            unit: encoded.unit,
+            histogram: histogram
+        })
+    }
+}
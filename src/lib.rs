@@ -11,13 +11,26 @@ extern crate time;
 extern crate net2;
 extern crate libc;
 extern crate ferris;
-//extern crate hdrsample;
+extern crate rand;
+extern crate rustls;
+extern crate lz4;
+extern crate zstd;
+extern crate serde_cbor;
+extern crate bincode;
+extern crate bytes;
+extern crate sha1;
+extern crate base64;
+#[cfg(feature = "tokio-runtime")]
+extern crate tokio;
+extern crate hdrsample;
 
 #[macro_use]
 extern crate slog;
 extern crate slog_stdlog;
 
 extern crate serde;
+#[macro_use]
+extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
@@ -25,49 +38,184 @@ extern crate serde_derive;
 #[macro_use]
 mod metrics;
 
+mod histogram;
+mod ddsketch;
+mod rotating_histogram;
+mod meter;
+mod metrics_registry;
+mod metrics_aggregator;
+mod metrics_snapshot;
+mod push_exporter;
+mod prometheus;
 mod node_id;
 mod node;
 mod members;
 mod pid;
 mod process;
 mod envelope;
+mod trace;
+mod seq_trace;
+mod log_context;
 mod executor;
 mod cluster;
 mod msg;
 mod timer_wheel;
 mod service;
 mod correlation_id;
+mod config;
+mod router;
+mod backoff;
+mod resources;
+mod discovery;
+mod ring;
+mod call_error;
+mod migration;
+mod pipeline;
+mod signal;
+mod validation;
+mod dump;
+mod socket;
+mod stream;
+#[cfg(feature = "chaos")]
+mod chaos;
 pub mod serialize;
 
 pub mod errors;
 
 pub use errors::Result;
 pub use node_id::NodeId;
+pub use members::NodeMetadata;
 pub use node::Node;
 pub use pid::Pid;
 pub use process::Process;
-pub use envelope::Envelope;
+pub use log_context::LogContext;
+pub use migration::{Migratable, ProcessFactory};
+pub use pipeline::{Pipeline, Stage};
+pub use signal::Signal;
+pub use stream::{StreamHandle, StreamId};
+pub use envelope::{Envelope, Priority};
+pub use trace::{TraceContext, Span, SpanExporter, FLAG_SAMPLED};
+pub use seq_trace::{SeqTrace, SeqTraceHop, SeqTraceEvent};
 pub use correlation_id::CorrelationId;
-pub use msg::Msg;
-pub use metrics::Metric;
+pub use msg::{Msg, TimerId, MsgFilter};
+pub use call_error::CallError;
+pub use metrics::{Metric, Labels, MetricsFilter};
+pub use histogram::{Histogram, HistogramConfig, LatencyHistogram, Summary, TimeUnit};
+pub use ddsketch::DDSketch;
+pub use rotating_histogram::RotatingHistogram;
+pub use meter::{Meter, MeterRates, METER_TICK_SECS};
+pub use metrics_registry::{
+    MetricsRegistry,
+    Counter,
+    Gauge,
+    SharedHistogram,
+    SharedMeter,
+    SharedRotatingHistogram,
+    SharedSketch
+};
+pub use metrics_aggregator::MetricsAggregator;
+pub use metrics_snapshot::{MetricsSnapshot, MetricsDelta};
+pub use push_exporter::{PushExporterConfig, PushProtocol, PushTransport};
+pub use resources::ResourceMetrics;
+pub use config::Config;
+pub use validation::{validate, ValidationReport, ConfigProblem};
+pub use dump::{StateDump, ClusterDump, ExecutorDump, ClusterEvent};
+#[cfg(feature = "chaos")]
+pub use chaos::ChaosConfig;
+pub use router::Router;
+pub use ring::{Ring, WEIGHT_KEY};
+pub use backoff::{Backoff, Retry};
+pub use socket::SocketOptions;
+pub use discovery::{
+    Discovery,
+    DnsDiscovery,
+    KubernetesDiscovery,
+    MdnsDiscovery,
+    start_discovery
+};
 
 pub use cluster::{
     ClusterServer,
     ClusterStatus,
+    ClusterConfig,
+    ExternalMsg,
+    ReconnectPolicy,
+    Batching,
+    Throttle,
+    Proxy,
+    Swim,
+    SwimMsg,
+    MemberState,
+    MemberUpdate,
+    PhiAccrual,
+    Plumtree,
+    PlumtreeMsg,
+    BroadcastId,
+    PeerState,
+    QuorumStatus,
+    Role,
+    RoleMap,
+    IdentityMatch,
+    PeerIdentity,
+    ControlMsg,
+    SplitBrainStrategy,
+    SplitBrainDecision,
+    Reconciler,
+    Topology,
+    Link,
+    TlsConfig,
+    Compression,
+    Codec,
+    WireCodec,
+    MsgpackCodec,
+    CborCodec,
+    BincodeCodec
 };
 
 pub use executor::{
     Executor,
     ExecutorStatus,
-    ExecutorMetrics
+    ExecutorMetrics,
+    ExecutorConfig
 };
 
 pub use service::{
     Service,
     ConnectionHandler,
     ConnectionMsg,
+    CloseReason,
     ServiceHandler,
+    ServiceTlsConfig,
+    ProxyHeader,
+    ProxyHeaderReader,
+    ServiceMetrics,
+    PendingRequests,
+    RateLimit,
+    RateLimiter,
+    RateLimitExceeded,
+    RateLimitAction,
     TcpServerHandler,
+    TcpClientHandler,
+    ConnectionLimit,
+    WsServerHandler,
+    WsMessage,
+    UdpServerHandler,
+    DatagramHandler,
+    DatagramMsg,
+    AdminHandler,
+    ServiceInfo,
+    ConnectionInfo,
+    ServiceHealth
+};
+
+pub use service::websocket;
+
+#[cfg(feature = "tokio-runtime")]
+pub use service::{
+    AsyncConnectionHandler,
+    AsyncConnectionMsg,
+    AsyncTcpServerHandler,
+    HandlerFuture
 };
 
 use std::thread::{self, JoinHandle};
@@ -77,6 +225,7 @@ use serde::{Deserialize, Serialize};
 use amy::Poller;
 use slog::DrainExt;
 use cluster::ClusterMsg;
+use push_exporter::PushExporter;
 
 const TIMEOUT: usize = 5000; // ms
 
@@ -86,6 +235,82 @@ const TIMEOUT: usize = 5000; // ms
 /// All nodes in a cluster must be parameterized by the same type.
 pub fn rouse<'de, T>(node_id: NodeId, logger: Option<slog::Logger>) -> (Node<T>, Vec<JoinHandle<()>>)
   where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    rouse_with_config(node_id, Config::default(), logger)
+}
+
+/// Start a node in the rabble cluster with a non-default configuration
+///
+/// Panics with a report of every configuration problem if the config is invalid. Use
+/// `try_rouse_with_config` to handle the report instead.
+pub fn rouse_with_config<'de, T>(node_id: NodeId,
+                                 config: Config,
+                                 logger: Option<slog::Logger>) -> (Node<T>, Vec<JoinHandle<()>>)
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    match try_rouse_with_config(node_id, config, logger) {
+        Ok(started) => started,
+        Err(e) => panic!("{}", e)
+    }
+}
+
+/// Validate the configuration and start a node in the rabble cluster
+///
+/// Returns an `ErrorKind::InvalidConfig` error containing all problems found if the config is
+/// invalid. No threads are started in that case.
+pub fn try_rouse_with_config<'de, T>(node_id: NodeId,
+                                     config: Config,
+                                     logger: Option<slog::Logger>)
+    -> Result<(Node<T>, Vec<JoinHandle<()>>)>
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    let report = validate(&node_id, &config);
+    if !report.is_ok() {
+        return Err(errors::ErrorKind::InvalidConfig(report).into());
+    }
+    let (node, loops) = assemble(node_id, config, logger);
+    let handles = loops.into_iter().map(|(name, f)| {
+        thread::Builder::new().name(name).spawn(move || f()).unwrap()
+    }).collect();
+    Ok((node, handles))
+}
+
+/// Validate the configuration and start a node in the rabble cluster on the blocking thread pool
+/// of an existing tokio runtime
+///
+/// Like `try_rouse_with_config`, this returns an `ErrorKind::InvalidConfig` error and starts
+/// nothing if the config is invalid.
+///
+/// The cluster server, executor and poller loops are not async tasks. They block on their channels
+/// and on epoll, so each of them occupies one blocking thread of the runtime until the node shuts
+/// down, and the returned handles complete when they exit. Services aren't started by this
+/// function; their loops block as well and can be run the same way with `handle.spawn_blocking`.
+/// Only the callbacks of an `AsyncConnectionHandler` run as async tasks.
+#[cfg(feature = "tokio-runtime")]
+pub fn rouse_on<'de, T>(handle: &tokio::runtime::Handle,
+                        node_id: NodeId,
+                        config: Config,
+                        logger: Option<slog::Logger>)
+    -> Result<(Node<T>, Vec<tokio::task::JoinHandle<()>>)>
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
+{
+    let report = validate(&node_id, &config);
+    if !report.is_ok() {
+        return Err(errors::ErrorKind::InvalidConfig(report).into());
+    }
+    let (node, loops) = assemble(node_id, config, logger);
+    let handles = loops.into_iter().map(|(_, f)| handle.spawn_blocking(move || f())).collect();
+    Ok((node, handles))
+}
+
+/// The event loops of a node along with their names. Each loop runs until the node shuts down.
+type Loops = Vec<(String, Box<FnOnce() + Send>)>;
+
+/// Create all the components of a node without starting them
+fn assemble<'de, T>(node_id: NodeId,
+                    config: Config,
+                    logger: Option<slog::Logger>) -> (Node<T>, Loops)
+  where T: Serialize + Deserialize<'de> + Send + 'static + Clone + Debug,
 {
     let logger = match logger {
         Some(logger) => logger.new(o!("node_id" => node_id.to_string())),
@@ -99,31 +324,57 @@ pub fn rouse<'de, T>(node_id: NodeId, logger: Option<slog::Logger>) -> (Node<T>,
                                             cluster_rx,
                                             exec_tx.clone(),
                                             poller.get_registrar().unwrap(),
+                                            config.cluster,
                                             logger.clone());
+    let ready = cluster_server.ready();
+    let ring = cluster_server.ring();
+    let global_names = cluster_server.global_names();
+    let saturated = cluster_server.saturated_peers();
+    let metrics = cluster_server.metrics_registry();
     let executor = Executor::new(node_id.clone(),
                                  exec_tx.clone(),
                                  exec_rx,
                                  cluster_tx.clone(),
+                                 config.executor,
                                  logger.clone());
+    #[cfg(feature = "chaos")]
+    let (cluster_server, executor) = {
+        let (mut cluster_server, mut executor) = (cluster_server, executor);
+        if config.chaos.is_enabled() {
+            warn!(logger, "Chaos mode enabled"; "config" => format!("{:?}", config.chaos));
+            let chaos = chaos::Chaos::new(config.chaos);
+            cluster_server.set_chaos(chaos.clone());
+            executor.set_chaos(chaos);
+        }
+        (cluster_server, executor)
+    };
 
-    let h1 = thread::Builder::new().name(format!("cluster_server::{}", node_id)).spawn(move || {
-        cluster_server.run()
-    }).unwrap();
-
-    let h2 = thread::Builder::new().name(format!("executor::{}", node_id)).spawn(move || {
-        executor.run()
-    }).unwrap();
-
+    // The push exporter stops once the cluster server has shut down and dropped `stop_tx`
+    let (stop_tx, stop_rx) = channel::<()>();
     let _cluster_tx = cluster_tx.clone();
-    let h3 = thread::Builder::new().name(format!("poller::{}", node_id)).spawn(move || {
-        loop {
-            let notifications = poller.wait(TIMEOUT).unwrap();
-            if let Err(_) = _cluster_tx.send(ClusterMsg::PollNotifications(notifications)) {
-                // The process is exiting
-                return;
+    let mut loops: Loops = vec![
+        (format!("cluster_server::{}", node_id), Box::new(move || {
+            let _stop_tx = stop_tx;
+            cluster_server.run()
+        })),
+        (format!("executor::{}", node_id), Box::new(move || executor.run())),
+        (format!("poller::{}", node_id), Box::new(move || {
+            loop {
+                let notifications = poller.wait(TIMEOUT).unwrap();
+                if let Err(_) = _cluster_tx.send(ClusterMsg::PollNotifications(notifications)) {
+                    // The process is exiting
+                    return;
+                }
             }
-        }
-    }).unwrap();
+        }))
+    ];
+    if let Some(push_config) = config.push_exporter {
+        let exporter =
+            PushExporter::new(push_config, &node_id.name, metrics.clone(), logger.clone());
+        loops.push((format!("push_exporter::{}", node_id),
+                    Box::new(move || exporter.run(stop_rx))));
+    }
 
-    (Node::new(node_id, exec_tx, cluster_tx, logger), vec![h1, h2, h3])
+    (Node::new(node_id, exec_tx, cluster_tx, ready, ring, global_names, saturated, metrics, logger),
+     loops)
 }
@@ -0,0 +1,75 @@
+use slog;
+use pid::Pid;
+use correlation_id::CorrelationId;
+
+/// The identity of the actor handling a message, used to tag its log records
+///
+/// The executor passes one to `Process::handle_in_context` for every envelope. Connection handlers
+/// build their own from the pid, connection id and node they are created with.
+///
+/// Since processes don't see the envelope itself, the context also carries its `reply_to`
+/// address, which isn't logged.
+#[derive(Clone, Copy)]
+pub struct LogContext<'a> {
+    pub pid: &'a Pid,
+    pub connection: Option<u64>,
+    pub correlation_id: Option<&'a CorrelationId>,
+    pub reply_to: Option<&'a Pid>,
+    logger: &'a slog::Logger
+}
+
+impl<'a> LogContext<'a> {
+    /// Create a context for the process or service `pid` that logs to `logger`, usually the logger
+    /// of its node
+    pub fn new(logger: &'a slog::Logger, pid: &'a Pid) -> LogContext<'a> {
+        LogContext {
+            pid: pid,
+            connection: None,
+            correlation_id: None,
+            reply_to: None,
+            logger: logger
+        }
+    }
+
+    /// Tag records with the id of the connection being handled
+    pub fn with_connection(mut self, id: u64) -> LogContext<'a> {
+        self.connection = Some(id);
+        self
+    }
+
+    /// Tag records with the correlation id of the envelope being handled
+    pub fn with_correlation_id(mut self,
+                               correlation_id: Option<&'a CorrelationId>) -> LogContext<'a>
+    {
+        self.correlation_id = correlation_id;
+        self
+    }
+
+    /// Set the `reply_to` address of the envelope being handled
+    pub fn with_reply_to(mut self, reply_to: Option<&'a Pid>) -> LogContext<'a> {
+        self.reply_to = reply_to;
+        self
+    }
+
+    /// Return the pid a reply to the message being handled should be sent to: the `reply_to`
+    /// address of the envelope if it has one, and its sender otherwise
+    pub fn reply_address(&self, from: &'a Pid) -> &'a Pid {
+        self.reply_to.unwrap_or(from)
+    }
+
+    /// Return a logger that tags every record with the node, pid and, when known, the connection
+    /// and correlation ids
+    ///
+    /// This creates a new child logger, so handlers that log a lot for every message should call it
+    /// once per message, not once per record.
+    pub fn logger(&self) -> slog::Logger {
+        let mut logger = self.logger.new(o!("pid" => self.pid.to_string()));
+        if let Some(id) = self.connection {
+            logger = logger.new(o!("connection_id" => id));
+        }
+        if let Some(correlation_id) = self.correlation_id {
+            logger = logger.new(o!("correlation_id" => format!("{:?}", correlation_id)));
+        }
+        logger
+    }
+}
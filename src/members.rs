@@ -1,12 +1,24 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap, BTreeMap};
 use std::fmt::{Display, Formatter, Error};
 use orset::{ORSet, Delta};
 use node_id::NodeId;
+use cluster::STARTED_AT_KEY;
+
+/// Small key/value tags, such as role, rack, or version, that a node publishes to the cluster
+pub type NodeMetadata = BTreeMap<String, String>;
+
+/// The prefix of the metadata keys that rabble sets itself, which `Node::set_metadata` can't change
+pub const RESERVED_KEY_PREFIX: &'static str = "rabble.";
+
+/// The metadata key holding the number of times a node updated its metadata since it started, so
+/// that peers can tell newer metadata from older
+pub const METADATA_VERSION_KEY: &'static str = "rabble.metadata_version";
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Members {
     pub me: NodeId,
-    orset: ORSet<NodeId>
+    orset: ORSet<NodeId>,
+    metadata: HashMap<NodeId, NodeMetadata>
 }
 
 impl Display for Members {
@@ -21,13 +33,75 @@ impl Display for Members {
 }
 
 impl Members {
-    pub fn new(node: NodeId) -> Members {
+    pub fn new(node: NodeId, metadata: NodeMetadata) -> Members {
         let mut orset = ORSet::new(node.to_string());
         orset.add(node.clone());
+        let mut all_metadata = HashMap::new();
+        all_metadata.insert(node.clone(), metadata);
         Members {
             me: node,
-            orset: orset
+            orset: orset,
+            metadata: all_metadata
+        }
+    }
+
+    /// Return the metadata of all nodes this node knows about
+    pub fn metadata(&self) -> &HashMap<NodeId, NodeMetadata> {
+        &self.metadata
+    }
+
+    /// Merge the metadata known by `from` into our own.
+    ///
+    /// Each node is authoritative for its own metadata, so whatever `from` says about itself always
+    /// wins. Metadata about other nodes is only added if we don't know it already, or it's newer
+    /// than ours. Our own metadata is never overwritten.
+    pub fn merge_metadata(&mut self, from: &NodeId, metadata: HashMap<NodeId, NodeMetadata>) {
+        for (node, data) in metadata {
+            if node == *from && node != self.me {
+                self.metadata.insert(node, data);
+            } else {
+                self.update_metadata(node, data);
+            }
+        }
+    }
+
+    /// Replace the metadata of `node` if it's newer than what we know, and return whether it was
+    ///
+    /// Metadata is newer if the node started later, or updated it more often since it started.
+    pub fn update_metadata(&mut self, node: NodeId, metadata: NodeMetadata) -> bool {
+        if node == self.me {
+            return false;
+        }
+        let newer = self.metadata.get(&node).map_or(true, |known| age(&metadata) > age(known));
+        if newer {
+            self.metadata.insert(node, metadata);
         }
+        newer
+    }
+
+    /// Replace the metadata of this node, except for the reserved keys, and bump its version
+    ///
+    /// Returns the new metadata, which must be disseminated to the other members.
+    pub fn set_metadata(&mut self, metadata: NodeMetadata) -> NodeMetadata {
+        let current = self.metadata.get(&self.me).cloned().unwrap_or_else(NodeMetadata::new);
+        let mut updated: NodeMetadata = current.iter()
+            .filter(|&(key, _)| key.starts_with(RESERVED_KEY_PREFIX))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        updated.extend(metadata.into_iter().filter(|&(ref key, _)| {
+            !key.starts_with(RESERVED_KEY_PREFIX)
+        }));
+        let version = age(&current).1 + 1;
+        updated.insert(METADATA_VERSION_KEY.to_string(), version.to_string());
+        self.metadata.insert(self.me.clone(), updated.clone());
+        updated
+    }
+
+    /// Discard the metadata of nodes that are no longer members
+    pub fn prune_metadata(&mut self) {
+        let all = self.all();
+        let me = self.me.clone();
+        self.metadata.retain(|node, _| *node == me || all.contains(node));
     }
 
     pub fn all(&self) -> HashSet<NodeId> {
@@ -58,3 +132,9 @@ impl Members {
         self.orset.add(element)
     }
 }
+
+/// The start time and version of metadata, which order updates from the same node
+fn age(metadata: &NodeMetadata) -> (u64, u64) {
+    let number = |key: &str| metadata.get(key).and_then(|value| value.parse().ok()).unwrap_or(0);
+    (number(STARTED_AT_KEY), number(METADATA_VERSION_KEY))
+}
@@ -0,0 +1,125 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// The interval in seconds at which the moving averages of a meter are updated
+pub const METER_TICK_SECS: u64 = 5;
+
+/// The rates of a `Meter`, in events per second, that can be sent between nodes as part of
+/// `Msg::Metrics`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeterRates {
+    /// The number of events marked since the meter was created
+    pub count: u64,
+    /// The average rate since the meter was created
+    pub mean: f64,
+    /// The exponentially weighted moving averages over 1, 5 and 15 minutes
+    pub m1: f64,
+    pub m5: f64,
+    pub m15: f64
+}
+
+/// A measure of the rate of events, such as messages handled by a process or received on a
+/// connection
+///
+/// Like the load averages of UNIX, the 1, 5 and 15 minute rates are exponentially weighted moving
+/// averages, updated every `METER_TICK_SECS` seconds. `tick` applies every update that is due, so
+/// it can be called at any shorter interval. Meters registered with a `MetricsRegistry` are ticked
+/// by the registry; standalone meters must be ticked by their owner.
+#[derive(Debug, Clone)]
+pub struct Meter {
+    count: u64,
+    // Events marked since the last update of the moving averages
+    uncounted: u64,
+    m1: Ewma,
+    m5: Ewma,
+    m15: Ewma,
+    start: Instant,
+    last_tick: Instant
+}
+
+impl Meter {
+    pub fn new() -> Meter {
+        let now = Instant::now();
+        Meter {
+            count: 0,
+            uncounted: 0,
+            m1: Ewma::new(1.0),
+            m5: Ewma::new(5.0),
+            m15: Ewma::new(15.0),
+            start: now,
+            last_tick: now
+        }
+    }
+
+    /// Record `n` events
+    pub fn mark(&mut self, n: u64) {
+        self.count += n;
+        self.uncounted += n;
+    }
+
+    /// Update the moving averages for every interval that ended since the last update
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let interval = Duration::from_secs(METER_TICK_SECS);
+        while now.duration_since(self.last_tick) >= interval {
+            // Events marked since the last interval ended are counted in the next one
+            let count = mem::replace(&mut self.uncounted, 0);
+            self.m1.update(count);
+            self.m5.update(count);
+            self.m15.update(count);
+            self.last_tick += interval;
+        }
+    }
+
+    /// The number of events marked since the meter was created
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn rates(&self) -> MeterRates {
+        let elapsed = self.start.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        MeterRates {
+            count: self.count,
+            mean: if secs > 0.0 { self.count as f64 / secs } else { 0.0 },
+            m1: self.m1.rate,
+            m5: self.m5.rate,
+            m15: self.m15.rate
+        }
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Meter {
+        Meter::new()
+    }
+}
+
+/// An exponentially weighted moving average of a rate per second
+#[derive(Debug, Clone)]
+struct Ewma {
+    alpha: f64,
+    rate: f64,
+    initialized: bool
+}
+
+impl Ewma {
+    fn new(minutes: f64) -> Ewma {
+        Ewma {
+            alpha: 1.0 - (-(METER_TICK_SECS as f64) / 60.0 / minutes).exp(),
+            rate: 0.0,
+            initialized: false
+        }
+    }
+
+    fn update(&mut self, count: u64) {
+        let rate = count as f64 / METER_TICK_SECS as f64;
+        if self.initialized {
+            self.rate += self.alpha * (rate - self.rate);
+        } else {
+            // Start at the first measured rate, rather than slowly climbing up from 0
+            self.rate = rate;
+            self.initialized = true;
+        }
+    }
+}
@@ -1,5 +1,10 @@
 use serde::{Serialize, Deserialize};
-use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fmt::{self, Debug, Display, Formatter};
+use histogram::Histogram;
+use ddsketch::DDSketch;
+use meter::MeterRates;
 
 // A container type for status information for a given component
 pub trait Metrics<'de>: Serialize + Deserialize<'de> + Debug + Clone {
@@ -9,7 +14,136 @@ pub trait Metrics<'de>: Serialize + Deserialize<'de> + Debug + Clone {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Metric {
     Gauge(i64),
-    Counter(u64)
+    Counter(u64),
+    Histogram(Histogram),
+    Sketch(DDSketch),
+    Meter(MeterRates),
+    /// The series of a metric with labels, such as `requests{service="api",status="ok"}`
+    ///
+    /// All series are of the same kind, sorted by their labels, and never labeled themselves.
+    Labeled(Vec<(Labels, Metric)>)
+}
+
+/// The label names and values that identify one series of a labeled metric
+///
+/// Labels are kept sorted by name, so equal sets compare, hash and serialize the same way
+/// whatever order they were added in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Labels(BTreeMap<String, String>);
+
+impl Labels {
+    pub fn new() -> Labels {
+        Labels::default()
+    }
+
+    /// Add a label, replacing any earlier value of `name`
+    pub fn with(mut self, name: &str, value: &str) -> Labels {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| &value[..])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return true if every label of `other` has the same value in this set
+    pub fn contains(&self, other: &Labels) -> bool {
+        other.0.iter().all(|(name, value)| self.0.get(name) == Some(value))
+    }
+
+    /// Iterate over the labels, sorted by name
+    pub fn iter(&self) -> btree_map::Iter<String, String> {
+        self.0.iter()
+    }
+}
+
+impl<'a> From<&'a [(&'a str, &'a str)]> for Labels {
+    fn from(labels: &'a [(&'a str, &'a str)]) -> Labels {
+        labels.iter().fold(Labels::new(), |labels, &(name, value)| labels.with(name, value))
+    }
+}
+
+/// Selects the metrics returned in reply to a `Msg::GetMetrics`
+///
+/// A metric is returned if its name starts with any of the prefixes, or there are no prefixes. If
+/// any labels are given, only the series of labeled metrics that have all of them are returned,
+/// and unlabeled metrics are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsFilter {
+    pub prefixes: Vec<String>,
+    pub labels: Labels
+}
+
+impl MetricsFilter {
+    pub fn new() -> MetricsFilter {
+        MetricsFilter::default()
+    }
+
+    /// Also return metrics whose names start with `prefix`
+    pub fn prefix(mut self, prefix: &str) -> MetricsFilter {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// Only return series that have label `name` with `value`
+    pub fn label(mut self, name: &str, value: &str) -> MetricsFilter {
+        self.labels = self.labels.with(name, value);
+        self
+    }
+
+    /// Return the metrics selected by the filter
+    pub fn apply(&self, metrics: Vec<(String, Metric)>) -> Vec<(String, Metric)> {
+        metrics.into_iter().filter_map(|(name, metric)| {
+            let prefixed = self.prefixes.iter().any(|prefix| name.starts_with(&prefix[..]));
+            if !self.prefixes.is_empty() && !prefixed {
+                return None;
+            }
+            if self.labels.is_empty() {
+                return Some((name, metric));
+            }
+            match metric {
+                Metric::Labeled(series) => {
+                    let series: Vec<_> = series.into_iter()
+                        .filter(|&(ref labels, _)| labels.contains(&self.labels))
+                        .collect();
+                    if series.is_empty() {
+                        None
+                    } else {
+                        Some((name, Metric::Labeled(series)))
+                    }
+                },
+                _ => None
+            }
+        }).collect()
+    }
+}
+
+/// Return the metrics selected by `filter`, or all metrics if there is none
+pub fn filter_metrics(filter: Option<&MetricsFilter>,
+                      metrics: Vec<(String, Metric)>) -> Vec<(String, Metric)>
+{
+    match filter {
+        Some(filter) => filter.apply(metrics),
+        None => metrics
+    }
+}
+
+/// Labels are displayed as `name="value"` pairs separated by commas, as inside the braces of a
+/// Prometheus series
+impl Display for Labels {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ","));
+            }
+            try!(write!(f, "{}={:?}", name, value));
+        }
+        Ok(())
+    }
 }
 
 /// Generate a struct: `$struct_name` from a set of metrics
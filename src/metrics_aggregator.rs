@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use pid::Pid;
+use msg::Msg;
+use envelope::Envelope;
+use correlation_id::CorrelationId;
+use process::Process;
+use node::Node;
+use node_id::NodeId;
+use metrics::{Metric, filter_metrics};
+use errors::*;
+
+// The components whose metrics are collected from every member
+const COMPONENTS: [&'static str; 2] = ["cluster_server", "executor"];
+
+/// A process that periodically collects the metrics of every member of the cluster and merges them
+///
+/// Each round, the aggregator asks its cluster server for the members, and sends `Msg::GetMetrics`
+/// to the cluster server and executor of this node and every connected member, with the
+/// correlation id of the round. Counters and gauges in the replies are summed, and histograms are
+/// added together, so their percentiles are over the whole cluster. A round ends once every
+/// component answered, or when the next round starts, leaving out members that didn't answer in
+/// time.
+///
+/// The aggregator replies to `Msg::GetMetrics` from any process or service with the merged metrics
+/// of the last finished round. Each is named `<component>_<metric>`, along with the gauges `nodes`
+/// and `responding_nodes`, which count the members asked and those that answered.
+pub struct MetricsAggregator<T> {
+    pid: Pid,
+    node: Node<T>,
+    executor_pid: Option<Pid>,
+    interval: usize, // ms
+    round: u64,
+    // The number of replies still expected in the current round
+    remaining: usize,
+    nodes: usize,
+    responding: HashSet<NodeId>,
+    merged: BTreeMap<String, Metric>,
+    latest: Vec<(String, Metric)>
+}
+
+impl<'de, T> MetricsAggregator<T>
+    where T: Serialize + Deserialize<'de> + Send + Debug + Clone + 'static
+{
+    /// Spawn an aggregator on `node` that collects metrics every `interval` ms, and return its pid
+    pub fn spawn(node: &Node<T>, interval: usize) -> Result<Pid> {
+        let pid = Pid {
+            group: Some("rabble".to_string()),
+            name: "metrics_aggregator".to_string(),
+            node: node.id.clone(),
+            generation: None
+        };
+        let aggregator = MetricsAggregator {
+            pid: pid.clone(),
+            node: node.clone(),
+            executor_pid: None,
+            interval: interval,
+            round: 0,
+            remaining: 0,
+            nodes: 0,
+            responding: HashSet::new(),
+            merged: BTreeMap::new(),
+            latest: Vec::new()
+        };
+        node.spawn(&pid, Box::new(aggregator))
+    }
+
+    fn round_id(&self) -> CorrelationId {
+        CorrelationId::request(self.pid.clone(), 0, self.round)
+    }
+
+    fn is_current(&self, correlation_id: &Option<CorrelationId>) -> bool {
+        correlation_id.as_ref().and_then(|id| id.request) == Some(self.round)
+    }
+
+    /// Finish the current round, if any, and ask the cluster server for the members to collect
+    /// metrics from next
+    fn start_round(&mut self, output: &mut Vec<Envelope<T>>) {
+        if self.remaining > 0 {
+            self.publish();
+        }
+        self.round += 1;
+        // A failure means the node is shutting down, so there is no one to report to anyway
+        let _ = self.node.cluster_status(self.round_id());
+        if let Some(ref executor_pid) = self.executor_pid {
+            output.push(Envelope::new(executor_pid.clone(),
+                                      self.pid.clone(),
+                                      Msg::StartTimer(self.interval),
+                                      None));
+        }
+    }
+
+    fn request_metrics(&mut self, members: Vec<NodeId>, output: &mut Vec<Envelope<T>>) {
+        self.nodes = members.len();
+        self.remaining = members.len() * COMPONENTS.len();
+        for member in members {
+            for name in COMPONENTS.iter() {
+                let to = Pid {
+                    group: Some("rabble".to_string()),
+                    name: name.to_string(),
+                    node: member.clone(),
+                    generation: None
+                };
+                output.push(Envelope::new(to,
+                                          self.pid.clone(),
+                                          Msg::GetMetrics(None),
+                                          Some(self.round_id())));
+            }
+        }
+    }
+
+    fn add(&mut self, from: Pid, data: Vec<(String, Metric)>) {
+        for (name, metric) in data {
+            let name = format!("{}_{}", from.name, name);
+            if let Some(merged) = self.merged.get_mut(&name) {
+                merge(merged, metric);
+                continue;
+            }
+            self.merged.insert(name, metric);
+        }
+        self.responding.insert(from.node);
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining == 0 {
+            self.publish();
+        }
+    }
+
+    fn publish(&mut self) {
+        let mut latest: Vec<(String, Metric)> =
+            ::std::mem::replace(&mut self.merged, BTreeMap::new()).into_iter().collect();
+        latest.push(("nodes".to_string(), Metric::Gauge(self.nodes as i64)));
+        latest.push(("responding_nodes".to_string(),
+                     Metric::Gauge(self.responding.len() as i64)));
+        self.latest = latest;
+        self.responding.clear();
+        self.remaining = 0;
+    }
+}
+
+/// Combine a metric of one node with the same metric of other nodes
+///
+/// Metrics of different kinds can't be combined, so the first one seen is kept.
+fn merge(merged: &mut Metric, metric: Metric) {
+    match (merged, metric) {
+        (&mut Metric::Counter(ref mut total), Metric::Counter(value)) => *total += value,
+        (&mut Metric::Gauge(ref mut total), Metric::Gauge(value)) => *total += value,
+        (&mut Metric::Histogram(ref mut total), Metric::Histogram(ref value)) => total.merge(value),
+        (&mut Metric::Sketch(ref mut total), Metric::Sketch(ref value)) => {
+            // Sketches of different accuracies are left out, like metrics of different kinds
+            let _ = total.merge(value);
+        },
+        (&mut Metric::Meter(ref mut total), Metric::Meter(ref rates)) => {
+            // The rates of the cluster are the sums of the rates of each node
+            total.count += rates.count;
+            total.mean += rates.mean;
+            total.m1 += rates.m1;
+            total.m5 += rates.m5;
+            total.m15 += rates.m15;
+        },
+        (&mut Metric::Labeled(ref mut total), Metric::Labeled(series)) => {
+            // Series are merged with the series of the same labels, and kept sorted by labels
+            for (labels, metric) in series {
+                match total.binary_search_by(|&(ref other, _)| other.cmp(&labels)) {
+                    Ok(i) => merge(&mut total[i].1, metric),
+                    Err(i) => total.insert(i, (labels, metric))
+                }
+            }
+        },
+        _ => ()
+    }
+}
+
+impl<'de, T> Process<T> for MetricsAggregator<T>
+    where T: Serialize + Deserialize<'de> + Send + Debug + Clone + 'static
+{
+    fn init(&mut self, executor_pid: Pid) -> Vec<Envelope<T>> {
+        self.executor_pid = Some(executor_pid);
+        let mut output = Vec::new();
+        self.start_round(&mut output);
+        output
+    }
+
+    fn handle(&mut self,
+              msg: Msg<T>,
+              from: Pid,
+              correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<T>>)
+    {
+        match msg {
+            Msg::Timeout => self.start_round(output),
+            Msg::ClusterStatus(status) => {
+                if !self.is_current(&correlation_id) {
+                    return;
+                }
+                let local = self.pid.node.clone();
+                let members = status.members.into_iter()
+                    .filter(|member| *member == local || status.established.contains(member))
+                    .collect();
+                self.request_metrics(members, output);
+            },
+            Msg::Metrics(data) => {
+                if self.is_current(&correlation_id) {
+                    self.add(from, data);
+                }
+            },
+            Msg::GetMetrics(filter) => {
+                let data = filter_metrics(filter.as_ref(), self.latest.clone());
+                output.push(Envelope::new(from,
+                                          self.pid.clone(),
+                                          Msg::Metrics(data),
+                                          correlation_id));
+            },
+            _ => ()
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicI64, Ordering};
+use std::time::Duration;
+use metrics::{Metric, Labels};
+use histogram::{Histogram, TimeUnit};
+use ddsketch::DDSketch;
+use rotating_histogram::RotatingHistogram;
+use meter::{Meter, MeterRates};
+
+/// A counter registered with a `MetricsRegistry`
+///
+/// Clones refer to the same counter, so it can be handed to any thread and updated without locks.
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A gauge registered with a `MetricsRegistry`
+///
+/// Clones refer to the same gauge, so it can be handed to any thread and updated without locks.
+#[derive(Debug, Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram registered with a `MetricsRegistry`
+///
+/// An HdrHistogram can't be updated atomically, so recording a value takes a lock. It's only held
+/// for the duration of the record, or the copy made by a snapshot.
+#[derive(Debug, Clone)]
+pub struct SharedHistogram(Arc<Mutex<Histogram>>);
+
+impl SharedHistogram {
+    pub fn record(&self, value: u64) {
+        self.0.lock().unwrap().record(value);
+    }
+
+    /// Record a duration, converted to the unit of the histogram
+    pub fn record_duration(&self, duration: Duration) {
+        let mut histogram = self.0.lock().unwrap();
+        let value = histogram.unit.from_duration(duration);
+        histogram.record(value);
+    }
+
+    /// Return a copy of the histogram
+    pub fn get(&self) -> Histogram {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Return a copy of the histogram and reset it, so the next call returns the values of the next
+    /// interval
+    pub fn take_interval(&self) -> Histogram {
+        self.0.lock().unwrap().take_interval()
+    }
+}
+
+/// A rotating histogram registered with a `MetricsRegistry`
+///
+/// Snapshots of the registry contain the values of all intervals still kept. Like
+/// `SharedHistogram`, recording a value takes a lock.
+#[derive(Debug, Clone)]
+pub struct SharedRotatingHistogram(Arc<Mutex<RotatingHistogram>>);
+
+impl SharedRotatingHistogram {
+    pub fn record(&self, value: u64) {
+        self.0.lock().unwrap().record(value);
+    }
+
+    /// Record a duration, converted to the unit of the histogram
+    pub fn record_duration(&self, duration: Duration) {
+        self.0.lock().unwrap().record_duration(duration);
+    }
+
+    /// Return the values recorded during the last `window`
+    pub fn last(&self, window: Duration) -> Histogram {
+        self.0.lock().unwrap().last(window)
+    }
+
+    /// Return the values of all intervals still kept
+    pub fn get(&self) -> Histogram {
+        self.0.lock().unwrap().recent()
+    }
+}
+
+/// A meter registered with a `MetricsRegistry`
+///
+/// Its moving averages are updated by `MetricsRegistry::tick`. Like `SharedHistogram`, marking an
+/// event takes a lock.
+#[derive(Debug, Clone)]
+pub struct SharedMeter(Arc<Mutex<Meter>>);
+
+impl SharedMeter {
+    pub fn mark(&self, n: u64) {
+        self.0.lock().unwrap().mark(n);
+    }
+
+    pub fn rates(&self) -> MeterRates {
+        self.0.lock().unwrap().rates()
+    }
+
+    fn tick(&self) {
+        self.0.lock().unwrap().tick();
+    }
+}
+
+/// A quantile sketch registered with a `MetricsRegistry`
+///
+/// Like `SharedHistogram`, recording a value takes a lock.
+#[derive(Debug, Clone)]
+pub struct SharedSketch(Arc<Mutex<DDSketch>>);
+
+impl SharedSketch {
+    pub fn record(&self, value: f64) {
+        self.0.lock().unwrap().record(value);
+    }
+
+    /// Return a copy of the sketch
+    pub fn get(&self) -> DDSketch {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Registered {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(SharedHistogram),
+    Rotating(SharedRotatingHistogram),
+    Sketch(SharedSketch),
+    Meter(SharedMeter)
+}
+
+/// Named metrics that are registered once and then updated cheaply from any thread
+///
+/// Every node has a registry, available via `Node::metrics`, that processes, services and
+/// connection handlers can share. Registering a name that already exists returns the existing
+/// metric, so components don't need to coordinate who registers it first. The registry produces
+/// snapshots in the same form as `Msg::Metrics`, and is served by the admin service along with the
+/// metrics of the built-in components.
+///
+/// Counters, gauges, histograms and meters can be registered once per label set under the same
+/// name. All series of a name must be of the same kind, and are reported together as a
+/// `Metric::Labeled`.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    metrics: Arc<RwLock<BTreeMap<(String, Labels), Registered>>>
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry::default()
+    }
+
+    /// Register a counter named `name`, or return the one already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn counter(&self, name: &str) -> Counter {
+        self.counter_with_labels(name, &Labels::new())
+    }
+
+    /// Register the series of counter `name` with the given labels, or return the one already
+    /// registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn counter_with_labels(&self, name: &str, labels: &Labels) -> Counter {
+        let counter = self.register(name, labels, || {
+            Registered::Counter(Counter(Arc::new(AtomicU64::new(0))))
+        });
+        match counter {
+            Registered::Counter(counter) => counter,
+            _ => panic!("Metric {} is already registered, but not as a counter", name)
+        }
+    }
+
+    /// Register a gauge named `name`, or return the one already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn gauge(&self, name: &str) -> Gauge {
+        self.gauge_with_labels(name, &Labels::new())
+    }
+
+    /// Register the series of gauge `name` with the given labels, or return the one already
+    /// registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn gauge_with_labels(&self, name: &str, labels: &Labels) -> Gauge {
+        let gauge = self.register(name, labels, || {
+            Registered::Gauge(Gauge(Arc::new(AtomicI64::new(0))))
+        });
+        match gauge {
+            Registered::Gauge(gauge) => gauge,
+            _ => panic!("Metric {} is already registered, but not as a gauge", name)
+        }
+    }
+
+    /// Register a histogram named `name`, or return the one already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn histogram(&self, name: &str, unit: TimeUnit) -> SharedHistogram {
+        self.histogram_with_labels(name, &Labels::new(), unit)
+    }
+
+    /// Register the series of histogram `name` with the given labels, or return the one already
+    /// registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn histogram_with_labels(&self,
+                                 name: &str,
+                                 labels: &Labels,
+                                 unit: TimeUnit) -> SharedHistogram
+    {
+        let histogram = self.register(name, labels, || {
+            Registered::Histogram(SharedHistogram(Arc::new(Mutex::new(Histogram::new(unit)))))
+        });
+        match histogram {
+            Registered::Histogram(histogram) => histogram,
+            _ => panic!("Metric {} is already registered, but not as a histogram", name)
+        }
+    }
+
+    /// Register a rotating histogram named `name`, that keeps `buckets` intervals of length
+    /// `interval`, or return the one already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn rotating_histogram(&self,
+                              name: &str,
+                              unit: TimeUnit,
+                              interval: Duration,
+                              buckets: usize) -> SharedRotatingHistogram
+    {
+        let histogram = self.register(name, &Labels::new(), || {
+            let histogram = RotatingHistogram::new(unit, interval, buckets);
+            Registered::Rotating(SharedRotatingHistogram(Arc::new(Mutex::new(histogram))))
+        });
+        match histogram {
+            Registered::Rotating(histogram) => histogram,
+            _ => panic!("Metric {} is already registered, but not as a rotating histogram", name)
+        }
+    }
+
+    /// Register a meter named `name`, or return the one already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn meter(&self, name: &str) -> SharedMeter {
+        self.meter_with_labels(name, &Labels::new())
+    }
+
+    /// Register the series of meter `name` with the given labels, or return the one already
+    /// registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn meter_with_labels(&self, name: &str, labels: &Labels) -> SharedMeter {
+        let meter = self.register(name, labels, || {
+            Registered::Meter(SharedMeter(Arc::new(Mutex::new(Meter::new()))))
+        });
+        match meter {
+            Registered::Meter(meter) => meter,
+            _ => panic!("Metric {} is already registered, but not as a meter", name)
+        }
+    }
+
+    /// Update the moving averages of every registered meter
+    ///
+    /// The cluster server calls this every second, so applications don't need to.
+    pub fn tick(&self) {
+        for registered in self.metrics.read().unwrap().values() {
+            if let Registered::Meter(ref meter) = *registered {
+                meter.tick();
+            }
+        }
+    }
+
+    /// Register a quantile sketch named `name` with the given relative accuracy, or return the one
+    /// already registered
+    ///
+    /// Panics if `name` is registered as a different kind of metric.
+    pub fn sketch(&self, name: &str, relative_accuracy: f64) -> SharedSketch {
+        let sketch = self.register(name, &Labels::new(), || {
+            Registered::Sketch(SharedSketch(Arc::new(Mutex::new(DDSketch::new(relative_accuracy)))))
+        });
+        match sketch {
+            Registered::Sketch(sketch) => sketch,
+            _ => panic!("Metric {} is already registered, but not as a sketch", name)
+        }
+    }
+
+    /// Return the current value of every registered metric, sorted by name
+    ///
+    /// Names with labeled series are reported as a `Metric::Labeled` of all their series.
+    pub fn snapshot(&self) -> Vec<(String, Metric)> {
+        let mut families: Vec<(String, Vec<(Labels, Metric)>)> = Vec::new();
+        for (&(ref name, ref labels), registered) in self.metrics.read().unwrap().iter() {
+            let metric = match *registered {
+                Registered::Counter(ref counter) => Metric::Counter(counter.get()),
+                Registered::Gauge(ref gauge) => Metric::Gauge(gauge.get()),
+                Registered::Histogram(ref histogram) => Metric::Histogram(histogram.get()),
+                Registered::Rotating(ref histogram) => Metric::Histogram(histogram.get()),
+                Registered::Sketch(ref sketch) => Metric::Sketch(sketch.get()),
+                Registered::Meter(ref meter) => Metric::Meter(meter.rates())
+            };
+            // Entries are sorted by name, so the series of a name are adjacent
+            let same_family = families.last().map_or(false, |&(ref family, _)| family == name);
+            if same_family {
+                families.last_mut().unwrap().1.push((labels.clone(), metric));
+            } else {
+                families.push((name.clone(), vec![(labels.clone(), metric)]));
+            }
+        }
+        families.into_iter().map(|(name, mut series)| {
+            if series.len() == 1 && series[0].0.is_empty() {
+                (name, series.pop().unwrap().1)
+            } else {
+                (name, Metric::Labeled(series))
+            }
+        }).collect()
+    }
+
+    fn register<F>(&self, name: &str, labels: &Labels, new: F) -> Registered
+        where F: FnOnce() -> Registered
+    {
+        let key = (name.to_string(), labels.clone());
+        if let Some(registered) = self.metrics.read().unwrap().get(&key) {
+            return registered.clone();
+        }
+        let mut metrics = self.metrics.write().unwrap();
+        if let Some(registered) = metrics.get(&key) {
+            return registered.clone();
+        }
+        let registered = new();
+        // All series of a name must be of the same kind. Returning another series of a different
+        // kind makes the caller report the conflict.
+        if let Some((&(ref other, _), series)) = metrics.range((name.to_string(), Labels::new())..)
+            .next()
+        {
+            if other == name && mem::discriminant(series) != mem::discriminant(&registered) {
+                return series.clone();
+            }
+        }
+        metrics.insert(key, registered.clone());
+        registered
+    }
+}
@@ -0,0 +1,133 @@
+use envelope::now_ms;
+use metrics::{Metric, Labels};
+
+/// The metrics of a component at the time they were received, such as the contents of a
+/// `Msg::Metrics`
+///
+/// Services and processes that poll `Msg::GetMetrics` can keep the previous snapshot and call
+/// `delta` on the next one to get what changed in between.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// The wall clock time the snapshot was taken, in ms since the UNIX epoch
+    pub time: u64,
+    pub metrics: Vec<(String, Metric)>
+}
+
+impl MetricsSnapshot {
+    /// Create a snapshot of `metrics`, taken now
+    pub fn new(metrics: Vec<(String, Metric)>) -> MetricsSnapshot {
+        MetricsSnapshot::at(now_ms(), metrics)
+    }
+
+    /// Create a snapshot of `metrics` taken at `time`, in ms since the UNIX epoch
+    pub fn at(time: u64, metrics: Vec<(String, Metric)>) -> MetricsSnapshot {
+        MetricsSnapshot {
+            time: time,
+            metrics: metrics
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Metric> {
+        find(&self.metrics, name)
+    }
+
+    /// Return the changes between `earlier`, a snapshot of the same component, and this one
+    ///
+    /// Each metric of the delta covers the time between the snapshots:
+    ///
+    ///  * Counters hold their increase. A counter lower than before was reset, for example because
+    ///    its node restarted, so its whole value is the increase.
+    ///  * Gauges hold their change, which may be negative.
+    ///  * Histograms hold the values recorded in between, or all values if they were reset.
+    ///  * Meters hold their current rates, and the increase of their count like counters.
+    ///  * Sketches can't be subtracted, so they hold all values recorded so far.
+    ///  * Labeled metrics hold the delta of each series.
+    ///
+    /// Metrics missing from `earlier`, or registered as a different kind, are treated as if they
+    /// started from zero. Metrics missing from this snapshot are left out.
+    pub fn delta(&self, earlier: &MetricsSnapshot) -> MetricsDelta {
+        let metrics = self.metrics.iter().map(|&(ref name, ref metric)| {
+            (name.clone(), delta(metric, find(&earlier.metrics, name)))
+        }).collect();
+        MetricsDelta {
+            elapsed_ms: self.time.saturating_sub(earlier.time),
+            metrics: metrics
+        }
+    }
+}
+
+/// The changes of the metrics of a component between two snapshots, returned by
+/// `MetricsSnapshot::delta`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsDelta {
+    /// The time between the snapshots in ms
+    pub elapsed_ms: u64,
+    pub metrics: Vec<(String, Metric)>
+}
+
+impl MetricsDelta {
+    pub fn get(&self, name: &str) -> Option<&Metric> {
+        find(&self.metrics, name)
+    }
+
+    /// The increase per second of counter `name`, or `None` if it isn't an unlabeled counter or no
+    /// time passed between the snapshots
+    pub fn rate(&self, name: &str) -> Option<f64> {
+        match self.get(name) {
+            Some(&Metric::Counter(increase)) if self.elapsed_ms > 0 => {
+                Some(increase as f64 * 1000.0 / self.elapsed_ms as f64)
+            },
+            _ => None
+        }
+    }
+}
+
+fn find<'a>(metrics: &'a [(String, Metric)], name: &str) -> Option<&'a Metric> {
+    metrics.iter().find(|&&(ref other, _)| other == name).map(|&(_, ref metric)| metric)
+}
+
+fn find_series<'a>(series: &'a [(Labels, Metric)], labels: &Labels) -> Option<&'a Metric> {
+    series.iter().find(|&&(ref other, _)| other == labels).map(|&(_, ref metric)| metric)
+}
+
+/// Return the change of `later` since `earlier`, treating a missing or mismatched earlier metric
+/// as zero
+fn delta(later: &Metric, earlier: Option<&Metric>) -> Metric {
+    match (later, earlier) {
+        (&Metric::Counter(later), Some(&Metric::Counter(earlier))) => {
+            Metric::Counter(increase(later, earlier))
+        },
+        (&Metric::Gauge(later), Some(&Metric::Gauge(earlier))) => {
+            Metric::Gauge(later.wrapping_sub(earlier))
+        },
+        (&Metric::Histogram(ref later), Some(&Metric::Histogram(ref earlier))) => {
+            // Subtracting fails if the histogram was reset in between
+            Metric::Histogram(later.since(earlier).unwrap_or_else(|_| later.clone()))
+        },
+        (&Metric::Meter(ref later), Some(&Metric::Meter(ref earlier))) => {
+            let mut rates = later.clone();
+            rates.count = increase(later.count, earlier.count);
+            Metric::Meter(rates)
+        },
+        (&Metric::Labeled(ref later), Some(&Metric::Labeled(ref earlier))) => {
+            Metric::Labeled(later.iter().map(|&(ref labels, ref metric)| {
+                (labels.clone(), delta(metric, find_series(earlier, labels)))
+            }).collect())
+        },
+        (&Metric::Labeled(ref later), _) => {
+            Metric::Labeled(later.iter().map(|&(ref labels, ref metric)| {
+                (labels.clone(), delta(metric, None))
+            }).collect())
+        },
+        (later, _) => later.clone()
+    }
+}
+
+/// The increase of a counter, which was reset if it's lower than before
+fn increase(later: u64, earlier: u64) -> u64 {
+    if later >= earlier {
+        later - earlier
+    } else {
+        later
+    }
+}
@@ -0,0 +1,20 @@
+use process::Process;
+
+/// A process whose state can be moved to another node with `Node::migrate`
+///
+/// The process is stopped on the source node, and its saved state is passed to the factory
+/// registered for its `kind` on the destination node with `Node::register_migratable`. Envelopes
+/// sent to the process during the move are buffered and forwarded once it has restarted, as are any
+/// sent to its old pid afterwards.
+///
+/// Processes opt in by returning themselves from `Process::as_migratable`.
+pub trait Migratable<T>: Process<T> {
+    /// The name of the factory that restores this process from its saved state
+    fn kind(&self) -> String;
+
+    /// Serialize the state of the process
+    fn save(&self) -> Vec<u8>;
+}
+
+/// Restores a migrated process from its saved state
+pub type ProcessFactory<T> = Box<Fn(Vec<u8>) -> Box<Process<T>> + Send>;
@@ -1,19 +1,59 @@
-use cluster::ClusterStatus;
+use cluster::{ClusterStatus, Topology};
 use executor::ExecutorStatus;
 use correlation_id::CorrelationId;
-use metrics::Metric;
+use metrics::{Metric, MetricsFilter};
+use node_id::NodeId;
+use cluster::PeerState;
+use call_error::CallError;
+use pid::Pid;
+use stream::StreamId;
+use service::ConnectionInfo;
+use seq_trace::SeqTraceEvent;
 
 type Name = String;
 
+/// Timers are identified by the correlation id they were started with
+pub type TimerId = Option<CorrelationId>;
+
+/// Decides whether a service accepts a message. See `ServiceHandler::accepts`.
+pub type MsgFilter<T> = fn(&Msg<T>) -> bool;
+
+/// A `MsgFilter` accepting every message
+pub fn accept_all<T>(_msg: &Msg<T>) -> bool {
+    true
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Msg<T> {
     User(T),
     ClusterStatus(ClusterStatus),
+    Topology(Topology),
     ExecutorStatus(ExecutorStatus),
     StartTimer(usize), // time in ms
     CancelTimer(Option<CorrelationId>),
     Timeout,
+    BatchTimeouts(bool), // Sent to the executor to opt in or out of receiving `Timeouts`
+    Timeouts(Vec<TimerId>), // Multiple timers for the same process expired in the same tick
+    Yield, // Sent to the executor to be re-scheduled behind other pending envelopes
+    Continue, // The reply to a Yield
     Shutdown,
-    GetMetrics,
+    GetMetrics(Option<MetricsFilter>), // `None` returns every metric of the component
+    GetConnections, // Sent to a service to inspect its open connections
+    Connections(Vec<ConnectionInfo>),
+    NodeUp(NodeId), // Sent to membership event subscribers
+    NodeDown(NodeId),
+    PeerState(NodeId, PeerState),
+    Downed(NodeId), // A node was removed from the cluster to resolve a partition
+    LeaveComplete(NodeId), // The reply to `Node::leave_with_reply`
+    CallError(CallError),
+    NameConflict(String), // A global name was lost to an older registration after a partition healed
+    MigrateIn {pid: Pid, kind: String, state: Vec<u8>}, // Sent between executors
+    Migrated(Pid, Pid), // The old and new pids of a migrated process
+    MigrateFailed(Pid, String),
+    Credit(usize), // Sent upstream by pipeline stages to allow more messages
+    Signal(usize), // The number of `Signal` notifications since the last delivery
+    StreamChunk {stream: StreamId, seq: u64, data: Vec<u8>, last: bool}, // See `Node::open_stream`
+    SeqTrace(SeqTraceEvent), // Sent to the collector of a sequence trace
+    MailboxHighWater(Pid, usize), // The length of a mailbox that exceeded `mailbox_high_water`
     Metrics(Vec<(Name, Metric)>)
 }
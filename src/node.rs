@@ -1,17 +1,39 @@
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Sender};
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::fmt::Debug;
 use serde::{Serialize, Deserialize};
 use node_id::NodeId;
 use executor::ExecutorMsg;
-use cluster::ClusterMsg;
+use cluster::{ClusterMsg, ControlMsg, StartupBarrier, Reconciler, Throttle};
 use pid::Pid;
 use correlation_id::CorrelationId;
 use process::Process;
-use envelope::Envelope;
+use envelope::{Envelope, Priority, now_ms};
+use msg::{Msg, MsgFilter, accept_all};
+use router::Router;
+use ring::Ring;
+use members::NodeMetadata;
+use service::ServiceInfo;
+use migration::ProcessFactory;
+use signal::Signal;
+use stream::{StreamHandle, StreamState};
+use dump::StateDump;
+use metrics_registry::MetricsRegistry;
+use trace::SpanExporter;
+use seq_trace::{SeqTraceHop, seq_trace_event};
+use serde_json;
 use amy;
 use errors::*;
 use slog;
 
+// How long `dump_state` waits for the cluster server and executor to respond
+const DUMP_TIMEOUT: u64 = 5000; // ms
+
 macro_rules! send {
     ($s:ident.$t:ident, $msg:expr, $pid:expr, $errmsg:expr) => {
         if let Err(_) = $s.$t.send($msg) {
@@ -31,7 +53,15 @@ pub struct Node<T> {
     pub id: NodeId,
     pub logger: slog::Logger,
     executor_tx: Sender<ExecutorMsg<T>>,
-    cluster_tx: Sender<ClusterMsg<T>>
+    cluster_tx: Sender<ClusterMsg<T>>,
+    router: Arc<RwLock<Option<Box<Router>>>>,
+    ready: StartupBarrier,
+    ring: Arc<RwLock<Ring>>,
+    global_names: Arc<RwLock<HashMap<String, Pid>>>,
+    saturated: Arc<RwLock<HashSet<NodeId>>>,
+    services: Arc<RwLock<HashMap<Pid, ServiceInfo>>>,
+    generation: Arc<AtomicU64>,
+    metrics: MetricsRegistry
 }
 
 impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
@@ -40,15 +70,52 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
     pub fn new(id: NodeId,
                executor_tx: Sender<ExecutorMsg<T>>,
                cluster_tx: Sender<ClusterMsg<T>>,
+               ready: StartupBarrier,
+               ring: Arc<RwLock<Ring>>,
+               global_names: Arc<RwLock<HashMap<String, Pid>>>,
+               saturated: Arc<RwLock<HashSet<NodeId>>>,
+               metrics: MetricsRegistry,
                logger: slog::Logger) -> Node<T> {
         Node {
             id: id,
             executor_tx: executor_tx,
             cluster_tx: cluster_tx,
+            router: Arc::new(RwLock::new(None)),
+            ready: ready,
+            ring: ring,
+            global_names: global_names,
+            saturated: saturated,
+            services: Arc::new(RwLock::new(HashMap::new())),
+            // Start from the wall clock so generations aren't reused when the node restarts
+            generation: Arc::new(AtomicU64::new(now_ms())),
+            metrics: metrics,
             logger: logger
         }
     }
 
+    /// Return the cluster member that owns `key` on the consistent hash ring
+    ///
+    /// The ring is updated as members join and leave, so the result may change over time. `None` is
+    /// only returned if this node has left the cluster and knows of no members.
+    pub fn route(&self, key: &str) -> Option<NodeId> {
+        self.ring.read().unwrap().get(key.as_bytes()).cloned()
+    }
+
+    /// Return true once the number of connected members, including this node, has reached
+    /// `ClusterConfig::min_members`. A node stays ready even if members are later lost.
+    pub fn is_ready(&self) -> bool {
+        self.ready.is_open()
+    }
+
+    /// Block until the node is ready or `timeout` ms elapse, and return whether it is ready. Wait
+    /// forever if `timeout` is `None`.
+    ///
+    /// Applications that need a quorum should call this before serving traffic. Members must be
+    /// joined, either via seeds, discovery or `join` on another thread, for it to ever return true.
+    pub fn wait_until_ready(&self, timeout: Option<usize>) -> bool {
+        self.ready.wait(timeout)
+    }
+
     /// Join 1 node to another to form a cluster.
     ///
     /// Node joins are transitive such that if `Node A` joins `Node B` which is already joined with
@@ -64,19 +131,90 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
               format!("ClusterMsg::Join({:?})", *node_id))
     }
 
+    /// Gracefully remove a node from the cluster
+    ///
+    /// Peers stop sending new envelopes to the node, and its connections are only closed after
+    /// the envelopes already queued for it have been written.
     pub fn leave(&self, node_id: &NodeId) -> Result<()> {
         send!(self.cluster_tx,
-              ClusterMsg::Leave(node_id.clone()),
+              ClusterMsg::Leave(node_id.clone(), None),
               None,
               format!("ClusterMsg::Leave({:?})", *node_id))
     }
 
-    /// Add a process to the executor that can be sent Envelopes addressed to its pid
-    pub fn spawn(&self, pid: &Pid, process: Box<Process<T>>) -> Result<()> {
-        send!(self.executor_tx,
-              ExecutorMsg::Start(pid.clone(), process),
+    /// Gracefully remove a node from the cluster and send `Msg::LeaveComplete` to
+    /// `correlation_id.pid` once its connections have been drained
+    pub fn leave_with_reply(&self, node_id: &NodeId, correlation_id: CorrelationId) -> Result<()> {
+        let to = correlation_id.pid.clone();
+        send!(self.cluster_tx,
+              ClusterMsg::Leave(node_id.clone(), Some(correlation_id)),
+              Some(&to),
+              format!("ClusterMsg::Leave({:?})", *node_id))
+    }
+
+    /// Send a control message, such as a remove or shutdown request, to another node.
+    ///
+    /// The receiving node only acts on the message if this node has the `Operator` role in its
+    /// `RoleMap`. Without mutual TLS, that requires the receiving node to set the `unauthenticated`
+    /// role to `Operator`.
+    pub fn control(&self, node_id: &NodeId, msg: ControlMsg) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::Control(node_id.clone(), msg),
+              None,
+              format!("ClusterMsg::Control({:?}, ..)", *node_id))
+    }
+
+    /// Limit the rate envelopes are sent to `node_id`, overriding `ClusterConfig::throttle`
+    ///
+    /// `None` removes the limit. This can be used to keep a bulk transfer to a peer from starving
+    /// other traffic, and to lift the limit once it's done.
+    pub fn set_throttle(&self, node_id: &NodeId, throttle: Option<Throttle>) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::SetThrottle(node_id.clone(), throttle),
+              None,
+              format!("ClusterMsg::SetThrottle({:?}, {:?})", *node_id, throttle))
+    }
+
+    /// Replace the metadata this node publishes to the cluster, and gossip it to the other members
+    ///
+    /// Keys starting with `rabble.` are set by rabble itself and are ignored.
+    pub fn set_metadata(&self, metadata: NodeMetadata) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::SetMetadata(metadata),
+              None,
+              "ClusterMsg::SetMetadata".to_string())
+    }
+
+    /// Subscribe `pid` to cluster membership events.
+    ///
+    /// The subscriber receives a `Msg::NodeUp` when a connection to a peer is established and a
+    /// `Msg::NodeDown` when it is lost.
+    pub fn subscribe_cluster_events(&self, pid: &Pid) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::Subscribe(pid.clone()),
               Some(pid),
-              format!("ExecutorMsg::Start({}, ..)", pid))
+              format!("ClusterMsg::Subscribe({})", pid))
+    }
+
+    pub fn unsubscribe_cluster_events(&self, pid: &Pid) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::Unsubscribe(pid.clone()),
+              Some(pid),
+              format!("ClusterMsg::Unsubscribe({})", pid))
+    }
+
+    /// Add a process to the executor that can be sent Envelopes addressed to its pid
+    ///
+    /// Return the pid tagged with a newly assigned generation. Envelopes addressed to the returned
+    /// pid are never delivered to a later process spawned with the same name.
+    pub fn spawn(&self, pid: &Pid, process: Box<Process<T>>) -> Result<Pid> {
+        let mut pid = pid.clone();
+        pid.generation = Some(self.generation.fetch_add(1, Ordering::SeqCst));
+        if let Err(_) = self.executor_tx.send(ExecutorMsg::Start(pid.clone(), process)) {
+            return Err(ErrorKind::SendError(format!("ExecutorMsg::Start({}, ..)", pid),
+                                            Some(pid)).into());
+        }
+        Ok(pid)
     }
 
     /// Remove a process from the executor
@@ -87,25 +225,254 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
               format!("ExecutorMsg::Start({}, ..)", pid))
     }
 
+    /// Register the factory used to restore migrated processes of the given kind on this node
+    pub fn register_migratable(&self, kind: &str, factory: ProcessFactory<T>) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::RegisterFactory(kind.to_string(), factory),
+              None,
+              format!("ExecutorMsg::RegisterFactory({}, ..)", kind))
+    }
+
+    /// Move a running `Migratable` process to another node
+    ///
+    /// The process keeps its name and group on the destination node. The destination must have a
+    /// factory registered for the kind of the process, otherwise the process is restarted locally.
+    pub fn migrate(&self, pid: &Pid, to: &NodeId) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::Migrate(pid.clone(), to.clone()),
+              Some(pid),
+              format!("ExecutorMsg::Migrate({}, {})", pid, to))
+    }
+
+    /// Export the spans of sampled traces handled by processes on this node to `exporter`
+    ///
+    /// This replaces any exporter set before.
+    pub fn set_span_exporter(&self, exporter: Box<SpanExporter>) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::SetSpanExporter(exporter),
+              None,
+              "ExecutorMsg::SetSpanExporter(..)".to_string())
+    }
+
+    /// Create a `Signal` used to notify the local process `pid` without sending envelopes
+    pub fn signal(&self, pid: &Pid) -> Signal<T> {
+        Signal::new(pid.clone(), self.executor_tx.clone())
+    }
+
+    /// Open a stream of bytes from `from` to the process `to`, on this or another node
+    ///
+    /// The stream fails immediately if there is no connection to the node of `to`. See
+    /// `StreamHandle` for how the data is delivered and flow controlled.
+    pub fn open_stream(&self, from: &Pid, to: &Pid) -> Result<StreamHandle<T>> {
+        // Generations are unique on this node, so they make fine stream ids
+        let id = self.generation.fetch_add(1, Ordering::SeqCst);
+        let state = Arc::new(StreamState::default());
+        if let Err(_) = self.cluster_tx.send(ClusterMsg::OpenStream(id,
+                                                                    to.node.clone(),
+                                                                    state.clone())) {
+            return Err(ErrorKind::SendError(format!("ClusterMsg::OpenStream({}, ..)", id),
+                                            Some(to.clone())).into());
+        }
+        Ok(StreamHandle::new(id, from.clone(), to.clone(), state, self.cluster_tx.clone()))
+    }
+
     /// Register a Service's sender with the executor so that it can be sent messages addressed to
     /// its pid
     pub fn register_service(&self, pid: &Pid, tx: &amy::Sender<Envelope<T>>) -> Result<()>
+    {
+        self.register_typed_service(pid, tx, accept_all)
+    }
+
+    /// Register a Service's sender along with a filter of the messages it handles
+    ///
+    /// Requests rejected by the filter are answered with `CallError::Unsupported` by the executor
+    /// without ever reaching the service.
+    pub fn register_typed_service(&self,
+                                  pid: &Pid,
+                                  tx: &amy::Sender<Envelope<T>>,
+                                  accepts: MsgFilter<T>) -> Result<()>
     {
         send!(self.executor_tx,
-              ExecutorMsg::RegisterService(pid.clone(), tx.try_clone()?),
+              ExecutorMsg::RegisterService(pid.clone(), tx.try_clone()?, accepts),
               Some(pid),
               format!("ExecutorMsg::RegisterService({}, ..)", pid))
     }
 
+    /// Stop routing envelopes to a service. Services call this once they stopped.
+    pub fn unregister_service(&self, pid: &Pid) -> Result<()> {
+        send!(self.executor_tx,
+              ExecutorMsg::UnregisterService(pid.clone()),
+              Some(pid),
+              format!("ExecutorMsg::UnregisterService({})", pid))
+    }
+
+    /// Return the pid, listening address, connection count and health of every service started on
+    /// this node
+    pub fn service_info(&self) -> Vec<ServiceInfo> {
+        self.services.read().unwrap().values().cloned().collect()
+    }
+
+    /// The metrics registry of this node, shared by all of its processes and services
+    pub fn metrics(&self) -> &MetricsRegistry {
+        &self.metrics
+    }
+
+    /// Record the current state of a service. This is called by services themselves.
+    pub fn update_service_info(&self, info: ServiceInfo) {
+        self.services.write().unwrap().insert(info.pid.clone(), info);
+    }
+
     /// Send an envelope to the executor so it gets routed to the appropriate process or service
-    pub fn send(&self, envelope: Envelope<T>) -> Result<()> {
+    ///
+    /// Envelopes addressed to a logical destination created with `Pid::routed(key)` are first sent
+    /// to the pid the installed router resolves `key` to, or fail with an `ErrorKind::NoRoute`
+    /// error if there is no router or it has no route for `key`.
+    ///
+    /// Returns an `ErrorKind::PeerSaturated` error without sending the envelope if it's addressed
+    /// to another node whose outbound queue is full. The caller can retry later or shed load.
+    /// Envelopes with `Priority::High` are sent regardless.
+    pub fn send(&self, mut envelope: Envelope<T>) -> Result<()> {
+        if envelope.to.is_routed() {
+            envelope.to = try!(self.resolve(&envelope.to.name));
+        }
         let to = envelope.to.clone();
+        if envelope.priority < Priority::High && to.node != self.id &&
+            self.saturated.read().unwrap().contains(&to.node)
+        {
+            return Err(ErrorKind::PeerSaturated(to.node).into());
+        }
+        let report = seq_trace_event(&envelope, SeqTraceHop::Send, &self.id, &envelope.from);
+        if let Some(report) = report {
+            // The envelope itself is sent below, so there's no need to handle the same error twice
+            let _ = self.executor_tx.send(ExecutorMsg::Envelope(report));
+        }
         send!(self.executor_tx,
               ExecutorMsg::Envelope(envelope),
               Some(&to),
               "ExecutorMsg::Envelope(envelope)".to_string())
     }
 
+    /// Install a router used by `send` to resolve the keys of `Pid::routed` destinations
+    ///
+    /// The router is shared among all clones of this node and replaces any existing router.
+    pub fn set_router(&self, router: Box<Router>) {
+        *self.router.write().unwrap() = Some(router);
+    }
+
+    /// Send a msg to the pid the installed router resolves `key` to.
+    ///
+    /// This is shorthand for sending an envelope addressed to `Pid::routed(key)`.
+    pub fn send_routed(&self,
+                       key: &str,
+                       from: Pid,
+                       msg: Msg<T>,
+                       correlation_id: Option<CorrelationId>) -> Result<()>
+    {
+        self.send(Envelope::new(Pid::routed(key), from, msg, correlation_id))
+    }
+
+    /// Return the pid the installed router resolves `key` to
+    fn resolve(&self, key: &str) -> Result<Pid> {
+        let to = match *self.router.read().unwrap() {
+            Some(ref router) => router.route(key),
+            None => None
+        };
+        to.ok_or_else(|| ErrorKind::NoRoute(key.to_string()).into())
+    }
+
+    /// Register `pid` under a cluster wide `name`
+    ///
+    /// Registration is asynchronous. It is ignored if another pid already holds the name. If the
+    /// same name was registered on both sides of a partition, the oldest registration wins once
+    /// the partition heals, and the loser is sent `Msg::NameConflict(name)`.
+    pub fn register_global(&self, name: &str, pid: &Pid) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::RegisterGlobal(name.to_string(), pid.clone()),
+              Some(pid),
+              format!("ClusterMsg::RegisterGlobal({}, {})", name, pid))
+    }
+
+    /// Remove a global name registered on this node
+    pub fn unregister_global(&self, name: &str) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::UnregisterGlobal(name.to_string()),
+              None,
+              format!("ClusterMsg::UnregisterGlobal({})", name))
+    }
+
+    /// Return the pid registered under a global name, if any
+    pub fn whereis_global(&self, name: &str) -> Option<Pid> {
+        self.global_names.read().unwrap().get(name).cloned()
+    }
+
+    /// Send a msg to the pid registered under a global name, on whichever node it lives.
+    ///
+    /// Returns an `ErrorKind::NoRoute` error if the name isn't registered.
+    pub fn send_global(&self,
+                       name: &str,
+                       from: Pid,
+                       msg: Msg<T>,
+                       correlation_id: Option<CorrelationId>) -> Result<()>
+    {
+        match self.whereis_global(name) {
+            Some(to) => self.send(Envelope::new(to, from, msg, correlation_id)),
+            None => Err(ErrorKind::NoRoute(name.to_string()).into())
+        }
+    }
+
+    /// Send an envelope to the process with the name and group of `envelope.to` on every member
+    /// of the cluster, including this node
+    ///
+    /// The node and generation of `envelope.to` are ignored. Envelopes are disseminated over a
+    /// broadcast tree, so each node only sends a broadcast to a few of its peers.
+    pub fn broadcast(&self, envelope: Envelope<T>) -> Result<()> {
+        let to = envelope.to.clone();
+        send!(self.cluster_tx,
+              ClusterMsg::Broadcast(envelope),
+              Some(&to),
+              format!("ClusterMsg::Broadcast({}, ..)", to))
+    }
+
+    /// Register a hook that merges state which diverged while the cluster was partitioned
+    ///
+    /// The same reconciler should be registered under `name` on every node.
+    pub fn register_reconciler(&self, name: &str, reconciler: Box<Reconciler>) -> Result<()> {
+        send!(self.cluster_tx,
+              ClusterMsg::RegisterReconciler(name.to_string(), reconciler),
+              None,
+              format!("ClusterMsg::RegisterReconciler({}, ..)", name))
+    }
+
+    /// Write the state of this node to a file as JSON, for attaching to incident reports
+    ///
+    /// The dump contains membership and connection states, the processes and services running on
+    /// the node, the metrics of the cluster server and executor, and recent cluster events. This
+    /// call blocks until both the cluster server and executor have responded.
+    pub fn dump_state<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let timeout = Duration::from_millis(DUMP_TIMEOUT);
+        let (cluster_tx, cluster_rx) = channel();
+        if let Err(_) = self.cluster_tx.send(ClusterMsg::DumpState(cluster_tx)) {
+            return Err(ErrorKind::SendError("ClusterMsg::DumpState".to_string(), None).into());
+        }
+        let (executor_tx, executor_rx) = channel();
+        if let Err(_) = self.executor_tx.send(ExecutorMsg::DumpState(executor_tx)) {
+            return Err(ErrorKind::SendError("ExecutorMsg::DumpState".to_string(), None).into());
+        }
+        let cluster = cluster_rx.recv_timeout(timeout)
+            .chain_err(|| "Timed out waiting for the cluster server state")?;
+        let executor = executor_rx.recv_timeout(timeout)
+            .chain_err(|| "Timed out waiting for the executor state")?;
+        let dump = StateDump {
+            node: self.id.clone(),
+            taken_at: now_ms(),
+            cluster: cluster,
+            executor: executor
+        };
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &dump)?;
+        Ok(())
+    }
+
     /// Get the status of the executor
     pub fn executor_status(&self, correlation_id: CorrelationId) -> Result<()> {
         let to = correlation_id.pid.clone();
@@ -124,9 +491,22 @@ impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> Node<T> {
               "ClusterMsg::GetStatus".to_string())
     }
 
+    /// Get the direct connections of this node to its peers, answered with `Msg::Topology`
+    pub fn topology(&self, correlation_id: CorrelationId) -> Result<()> {
+        let to = correlation_id.pid.clone();
+        send!(self.cluster_tx,
+              ClusterMsg::GetTopology(correlation_id),
+              Some(&to),
+              "ClusterMsg::GetTopology".to_string())
+    }
+
     /// Shutdown the node
+    ///
+    /// Registered services are told to shut down as well. They stop accepting connections and
+    /// close their open ones once pending writes are flushed, within their drain timeout. The
+    /// executor keeps routing envelopes until every service stopped, or the `shutdown_timeout` of
+    /// the executor config passes, and then shuts down the cluster server.
     pub fn shutdown(&self) {
         self.executor_tx.send(ExecutorMsg::Shutdown).unwrap();
-        self.cluster_tx.send(ClusterMsg::Shutdown).unwrap();
     }
 }
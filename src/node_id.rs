@@ -1,5 +1,6 @@
 use std::fmt::{Display, Error, Formatter};
 use std::str::FromStr;
+use socket::unix_path;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct NodeId {
@@ -22,9 +23,30 @@ impl FromStr for NodeId {
         if v.len() != 2 {
             return Err("Invalid NodeId format - Must be of form 'name@addr'".to_string())
         }
+        try!(check_ipv6_brackets(v[1]));
         Ok(NodeId {
             name: v[0].to_string(),
             addr: v[1].to_string()
         })
     }
 }
+
+/// IPv6 literals must be bracketed, as in `name@[::1]:11000`, to tell the address from the port
+fn check_ipv6_brackets(addr: &str) -> Result<(), String> {
+    if unix_path(addr).is_some() {
+        return Ok(());
+    }
+    if addr.starts_with("[") {
+        let valid = match addr.find("]") {
+            Some(end) => addr[end + 1..].starts_with(":") && addr.len() > end + 2,
+            None => false
+        };
+        if !valid {
+            return Err(format!("Invalid NodeId address {} - Must be of form '[ipv6]:port'", addr));
+        }
+    } else if addr.matches(":").count() > 1 {
+        return Err(format!("Invalid NodeId address {} - IPv6 addresses must be bracketed, \
+                            as in '[::1]:11000'", addr));
+    }
+    Ok(())
+}
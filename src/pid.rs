@@ -1,16 +1,78 @@
+use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Error, Formatter};
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 use node_id::NodeId;
 
+/// The group of the logical destinations created by `Pid::routed`
+pub const ROUTED_GROUP: &'static str = "rabble_routed";
+
 /// A globally unique process id
 ///
 /// Pids can be grouped together for various reasons. This grouping acts like a namespace. If
 /// a Process is not a member of a group, the `group` member of the Pid will be `None`.
-#[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+///
+/// Each process spawned with `Node::spawn` is assigned a unique generation. Envelopes addressed to
+/// a Pid with a generation are only delivered to that exact incarnation of the process. If the
+/// process has since been stopped, or restarted under the same name, they are dropped as dead
+/// letters. Pids without a generation are delivered to whichever process currently has the name.
+///
+/// The generation is not part of a Pid's identity, so it is ignored when comparing or hashing Pids.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Pid {
     pub group: Option<String>,
     pub name: String,
     pub node: NodeId,
+    #[serde(default)]
+    pub generation: Option<u64>
+}
+
+impl Pid {
+    /// A logical destination for `key`, which `Node::send` resolves to a concrete pid with the
+    /// router installed by `Node::set_router`
+    pub fn routed(key: &str) -> Pid {
+        Pid {
+            group: Some(ROUTED_GROUP.to_string()),
+            name: key.to_string(),
+            node: NodeId {name: String::new(), addr: String::new()},
+            generation: None
+        }
+    }
+
+    /// Return true if this pid is a logical destination created by `Pid::routed`
+    pub fn is_routed(&self) -> bool {
+        self.group.as_ref().map_or(false, |group| group == ROUTED_GROUP)
+    }
+
+    fn key(&self) -> (&Option<String>, &String, &NodeId) {
+        (&self.group, &self.name, &self.node)
+    }
+}
+
+impl PartialEq for Pid {
+    fn eq(&self, other: &Pid) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pid {}
+
+impl Hash for Pid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl PartialOrd for Pid {
+    fn partial_cmp(&self, other: &Pid) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pid {
+    fn cmp(&self, other: &Pid) -> Ordering {
+        self.key().cmp(&other.key())
+    }
 }
 
 /// Explicitly format Pid in the display format since it is huge when pretty printing and they are
@@ -39,12 +101,14 @@ impl FromStr for Pid {
             2 => Ok(Pid {
                 group: None,
                 name: v[0].to_string(),
-                node: try!(NodeId::from_str(v[1]))
+                node: try!(NodeId::from_str(v[1])),
+                generation: None
             }),
             3 => Ok(Pid {
                 group: Some(v[0].to_string()),
                 name: v[1].to_string(),
-                node: try!(NodeId::from_str(v[2]))
+                node: try!(NodeId::from_str(v[2])),
+                generation: None
             }),
             _ => return Err(
                 "Invalid Pid format - Must be of form 'name::node' or \
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use pid::Pid;
+use msg::Msg;
+use envelope::Envelope;
+use correlation_id::CorrelationId;
+use process::Process;
+use node::Node;
+use errors::*;
+
+/// A single step of a `Pipeline`
+pub trait Stage<T>: Send {
+    /// Transform a message from the previous stage into any number of messages for the next one
+    fn handle(&mut self, msg: T, output: &mut Vec<T>);
+}
+
+/// A chain of processes, possibly on different nodes, where the messages emitted by each stage are
+/// sent to the next
+///
+/// Stages apply backpressure with credits. Each stage grants its upstream stage `window` credits,
+/// and a stage only sends a message downstream while it has credit. A stage stops granting credit
+/// while it has `window` or more messages waiting for credit itself, so a slow stage eventually
+/// stalls every stage before it rather than growing unbounded queues. Messages sent to the first
+/// stage by anything other than a stage aren't subject to backpressure.
+///
+/// Since processes can only be spawned on the local node, the same pipeline should be built on
+/// every node hosting a stage. `spawn` only starts the stages whose pids are on the given node.
+pub struct Pipeline<T> {
+    window: usize,
+    stages: Vec<(Pid, Box<Stage<T>>)>
+}
+
+impl<'de, T> Pipeline<T>
+    where T: Serialize + Deserialize<'de> + Send + 'static + Debug + Clone
+{
+    pub fn new(window: usize) -> Pipeline<T> {
+        Pipeline {
+            window: window.max(1),
+            stages: Vec::new()
+        }
+    }
+
+    /// Append a stage running as the process `pid`
+    pub fn stage(mut self, pid: Pid, stage: Box<Stage<T>>) -> Pipeline<T> {
+        self.stages.push((pid, stage));
+        self
+    }
+
+    /// Spawn the stages that belong on `node` and return the pid of the first stage
+    pub fn spawn(self, node: &Node<T>) -> Result<Option<Pid>> {
+        let pids: Vec<Pid> = self.stages.iter().map(|&(ref pid, _)| pid.clone()).collect();
+        for (i, (pid, stage)) in self.stages.into_iter().enumerate() {
+            if pid.node != node.id {
+                continue;
+            }
+            let process = StageProcess {
+                pid: pid.clone(),
+                upstream: if i > 0 { Some(pids[i - 1].clone()) } else { None },
+                downstream: pids.get(i + 1).cloned(),
+                stage: stage,
+                window: self.window,
+                credits: 0,
+                pending: VecDeque::new(),
+                consumed: 0,
+                output: Vec::new()
+            };
+            node.spawn(&pid, Box::new(process))?;
+        }
+        Ok(pids.first().cloned())
+    }
+}
+
+struct StageProcess<T> {
+    pid: Pid,
+    upstream: Option<Pid>,
+    downstream: Option<Pid>,
+    stage: Box<Stage<T>>,
+    window: usize,
+    // Messages we may still send downstream
+    credits: usize,
+    // Messages waiting for credit
+    pending: VecDeque<T>,
+    // Messages received from upstream that haven't been credited back yet
+    consumed: usize,
+    output: Vec<T>
+}
+
+impl<'de, T> StageProcess<T>
+    where T: Serialize + Deserialize<'de> + Send + Debug + Clone
+{
+    fn flush(&mut self, output: &mut Vec<Envelope<T>>) {
+        let downstream = match self.downstream {
+            Some(ref downstream) => downstream,
+            None => {
+                // The last stage has nowhere to send messages
+                self.pending.clear();
+                return;
+            }
+        };
+        while self.credits > 0 {
+            match self.pending.pop_front() {
+                Some(msg) => {
+                    self.credits -= 1;
+                    output.push(Envelope::new(downstream.clone(),
+                                              self.pid.clone(),
+                                              Msg::User(msg),
+                                              None));
+                },
+                None => break
+            }
+        }
+    }
+
+    /// Grant credit upstream in batches, unless we are backed up
+    fn grant_credit(&mut self, output: &mut Vec<Envelope<T>>) {
+        if self.pending.len() >= self.window || self.consumed < (self.window + 1) / 2 {
+            return;
+        }
+        if let Some(ref upstream) = self.upstream {
+            output.push(Envelope::new(upstream.clone(),
+                                      self.pid.clone(),
+                                      Msg::Credit(self.consumed),
+                                      None));
+        }
+        self.consumed = 0;
+    }
+}
+
+impl<'de, T> Process<T> for StageProcess<T>
+    where T: Serialize + Deserialize<'de> + Send + Debug + Clone
+{
+    fn init(&mut self, _executor_pid: Pid) -> Vec<Envelope<T>> {
+        match self.upstream {
+            Some(ref upstream) => vec![Envelope::new(upstream.clone(),
+                                                     self.pid.clone(),
+                                                     Msg::Credit(self.window),
+                                                     None)],
+            None => Vec::new()
+        }
+    }
+
+    fn handle(&mut self,
+              msg: Msg<T>,
+              from: Pid,
+              _correlation_id: Option<CorrelationId>,
+              output: &mut Vec<Envelope<T>>)
+    {
+        match msg {
+            Msg::User(msg) => {
+                self.stage.handle(msg, &mut self.output);
+                self.pending.extend(self.output.drain(..));
+                if self.upstream.as_ref() == Some(&from) {
+                    self.consumed += 1;
+                }
+                self.flush(output);
+                self.grant_credit(output);
+            },
+            Msg::Credit(credits) => {
+                if self.downstream.as_ref() == Some(&from) {
+                    self.credits += credits;
+                    self.flush(output);
+                    self.grant_credit(output);
+                }
+            },
+            _ => ()
+        }
+    }
+}
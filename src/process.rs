@@ -2,6 +2,8 @@ use pid::Pid;
 use msg::Msg;
 use envelope::Envelope;
 use correlation_id::CorrelationId;
+use migration::Migratable;
+use log_context::LogContext;
 
 pub trait Process<T> : Send {
     /// Initialize process state if necessary
@@ -15,4 +17,25 @@ pub trait Process<T> : Send {
               from: Pid,
               correlation_id: Option<CorrelationId>,
               output: &mut Vec<Envelope<T>>);
+
+    /// Handle messages from other actors, with a context identifying the process and envelope
+    ///
+    /// `ctx.logger()` returns a logger that tags records with the node, pid and correlation id, so
+    /// logs of different processes can be told apart. The executor always calls this method, which
+    /// calls `handle` by default. Processes that log override it instead, and leave `handle`
+    /// `unreachable!()`.
+    fn handle_in_context(&mut self,
+                         _ctx: &LogContext,
+                         msg: Msg<T>,
+                         from: Pid,
+                         correlation_id: Option<CorrelationId>,
+                         output: &mut Vec<Envelope<T>>)
+    {
+        self.handle(msg, from, correlation_id, output)
+    }
+
+    /// Return self if the process implements `Migratable` and can be moved to another node
+    fn as_migratable(&self) -> Option<&Migratable<T>> {
+        None
+    }
 }
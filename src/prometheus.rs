@@ -0,0 +1,104 @@
+use std::fmt::Write;
+use metrics::Metric;
+
+// The percentiles reported for each histogram, and their Prometheus quantile labels
+const PERCENTILES: [(f64, &'static str); 4] =
+    [(50.0, "0.5"), (90.0, "0.9"), (99.0, "0.99"), (99.9, "0.999")];
+
+/// Write the metrics of a component in the Prometheus text exposition format
+///
+/// Each metric is named `rabble_<component>_<name>`, with any characters that aren't allowed in
+/// Prometheus names replaced by underscores, and labeled with the node it came from. Counters and
+/// gauges map directly. Histograms and sketches are written as summaries of the 50th, 90th, 99th
+/// and 99.9th percentiles. Meters are written as gauges of their rates per second, labeled with
+/// the `window` of the moving average. Every series of a labeled metric is written with its own labels after
+/// the node label.
+pub fn encode(node: &str, component: &str, metrics: &[(String, Metric)], out: &mut String) {
+    let node_label = format!("node=\"{}\"", escape_label(node));
+    for &(ref name, ref metric) in metrics {
+        let name = metric_name(&format!("rabble_{}_{}", component, name));
+        match *metric {
+            Metric::Labeled(ref series) => {
+                if let Some(&(_, ref first)) = series.first() {
+                    write_type(&name, first, out);
+                }
+                for &(ref labels, ref metric) in series {
+                    let mut series_labels = node_label.clone();
+                    for (label, value) in labels.iter() {
+                        let _ = write!(series_labels, ",{}=\"{}\"",
+                                       label_name(label), escape_label(value));
+                    }
+                    write_samples(&name, &series_labels, metric, out);
+                }
+            },
+            ref metric => {
+                write_type(&name, metric, out);
+                write_samples(&name, &node_label, metric, out);
+            }
+        }
+    }
+}
+
+fn write_type(name: &str, metric: &Metric, out: &mut String) {
+    let kind = match *metric {
+        Metric::Counter(_) => "counter",
+        Metric::Gauge(_) | Metric::Meter(_) => "gauge",
+        Metric::Histogram(_) | Metric::Sketch(_) => "summary",
+        Metric::Labeled(_) => return
+    };
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Write the samples of one series, labeled with `labels`
+fn write_samples(name: &str, labels: &str, metric: &Metric, out: &mut String) {
+    match *metric {
+        Metric::Counter(value) => {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        },
+        Metric::Gauge(value) => {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        },
+        Metric::Histogram(ref histogram) => {
+            for &(percentile, quantile) in PERCENTILES.iter() {
+                let _ = writeln!(out, "{}{{{},quantile=\"{}\"}} {}",
+                                 name, labels, quantile, histogram.percentile(percentile));
+            }
+            let count = histogram.count();
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.mean() * count as f64);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+        },
+        Metric::Sketch(ref sketch) => {
+            for &(percentile, quantile) in PERCENTILES.iter() {
+                let _ = writeln!(out, "{}{{{},quantile=\"{}\"}} {}",
+                                 name, labels, quantile, sketch.quantile(percentile / 100.0));
+            }
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sketch.sum());
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, sketch.count());
+        },
+        Metric::Meter(ref rates) => {
+            for &(window, rate) in [("1m", rates.m1), ("5m", rates.m5), ("15m", rates.m15)].iter() {
+                let _ = writeln!(out, "{}{{{},window=\"{}\"}} {}", name, labels, window, rate);
+            }
+        },
+        // Series of a labeled metric are never labeled themselves
+        Metric::Labeled(_) => ()
+    }
+}
+
+/// Replace the characters that aren't allowed in a Prometheus metric name
+fn metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect()
+}
+
+/// Replace the characters that aren't allowed in a Prometheus label name
+fn label_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
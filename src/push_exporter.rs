@@ -0,0 +1,276 @@
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+use slog;
+use metrics::{Metric, Labels};
+use metrics_registry::MetricsRegistry;
+use metrics_snapshot::{MetricsSnapshot, MetricsDelta};
+use errors::*;
+
+// The percentiles pushed for each histogram and sketch, and the suffix of their names
+const PERCENTILES: [(f64, &'static str); 4] =
+    [(50.0, "p50"), (90.0, "p90"), (99.0, "p99"), (99.9, "p999")];
+
+// The largest UDP payload sent, small enough to avoid fragmentation on common networks
+const MAX_DATAGRAM: usize = 1400;
+
+/// The line protocol of a push exporter
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PushProtocol {
+    /// StatsD lines, such as `rabble.node1.requests:5|c`, with labels as DogStatsD tags
+    Statsd,
+    /// Graphite plaintext lines, such as `rabble.node1.requests 5 1500000000`, with labels as
+    /// Graphite tags
+    Graphite
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PushTransport {
+    Udp,
+    Tcp
+}
+
+/// Configuration of a push exporter that sends the metrics of the node's `MetricsRegistry` to a
+/// StatsD or Graphite endpoint
+#[derive(Debug, Clone)]
+pub struct PushExporterConfig {
+    /// The `host:port` of the endpoint
+    pub addr: String,
+    pub protocol: PushProtocol,
+    pub transport: PushTransport,
+    /// The time between flushes in ms
+    pub interval: usize,
+    /// The prefix of every metric name. Defaults to `rabble.<node name>`.
+    pub prefix: Option<String>
+}
+
+impl PushExporterConfig {
+    /// Push to a StatsD endpoint over UDP every 10 seconds
+    pub fn statsd(addr: &str) -> PushExporterConfig {
+        PushExporterConfig {
+            addr: addr.to_string(),
+            protocol: PushProtocol::Statsd,
+            transport: PushTransport::Udp,
+            interval: 10000,
+            prefix: None
+        }
+    }
+
+    /// Push to a Graphite endpoint over TCP every 10 seconds
+    pub fn graphite(addr: &str) -> PushExporterConfig {
+        PushExporterConfig {
+            addr: addr.to_string(),
+            protocol: PushProtocol::Graphite,
+            transport: PushTransport::Tcp,
+            interval: 10000,
+            prefix: None
+        }
+    }
+}
+
+/// Periodically flushes the metrics of a registry to a StatsD or Graphite endpoint
+///
+/// Counters are pushed as their increase since the previous flush, and histograms as the
+/// percentiles and count of the values recorded since then. Gauges, sketches and meter rates are
+/// pushed as gauges of their current values. A TCP connection is opened on the first flush, and
+/// reopened on the next flush after a failed write.
+pub struct PushExporter {
+    config: PushExporterConfig,
+    prefix: String,
+    registry: MetricsRegistry,
+    previous: Option<MetricsSnapshot>,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+    logger: slog::Logger
+}
+
+impl PushExporter {
+    pub fn new(config: PushExporterConfig,
+               node_name: &str,
+               registry: MetricsRegistry,
+               logger: slog::Logger) -> PushExporter
+    {
+        let prefix = match config.prefix {
+            Some(ref prefix) => prefix.clone(),
+            None => format!("rabble.{}", sanitize(node_name))
+        };
+        PushExporter {
+            config: config,
+            prefix: prefix,
+            registry: registry,
+            previous: None,
+            udp: None,
+            tcp: None,
+            logger: logger.new(o!("component" => "push_exporter"))
+        }
+    }
+
+    /// Flush every interval until `stop` is disconnected, which happens when the node shuts down
+    pub fn run(mut self, stop: Receiver<()>) {
+        let interval = Duration::from_millis(self.config.interval as u64);
+        loop {
+            match stop.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(e) = self.flush() {
+                        warn!(self.logger, "Failed to push metrics";
+                              "addr" => self.config.addr.clone(), "error" => e.to_string());
+                    }
+                },
+                _ => return
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let snapshot = MetricsSnapshot::new(self.registry.snapshot());
+        let delta = match self.previous {
+            Some(ref previous) => snapshot.delta(previous),
+            // Everything recorded before the first flush is new
+            None => snapshot.delta(&MetricsSnapshot::at(snapshot.time, Vec::new()))
+        };
+        let lines = encode(self.config.protocol, &self.prefix, snapshot.time / 1000, &delta);
+        try!(match self.config.transport {
+            PushTransport::Udp => self.send_udp(&lines),
+            PushTransport::Tcp => self.send_tcp(&lines)
+        });
+        // The counters of a failed push are included in the delta of the next one
+        self.previous = Some(snapshot);
+        Ok(())
+    }
+
+    /// Send the lines in as few datagrams as possible
+    fn send_udp(&mut self, lines: &[String]) -> Result<()> {
+        if self.udp.is_none() {
+            let socket = try!(UdpSocket::bind("0.0.0.0:0"));
+            try!(socket.connect(&self.config.addr[..]));
+            self.udp = Some(socket);
+        }
+        let socket = self.udp.as_ref().unwrap();
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM {
+                try!(socket.send(datagram.as_bytes()));
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(line);
+        }
+        if !datagram.is_empty() {
+            try!(socket.send(datagram.as_bytes()));
+        }
+        Ok(())
+    }
+
+    fn send_tcp(&mut self, lines: &[String]) -> Result<()> {
+        if self.tcp.is_none() {
+            let stream = try!(TcpStream::connect(&self.config.addr[..]));
+            let timeout = Duration::from_millis(self.config.interval as u64);
+            try!(stream.set_write_timeout(Some(timeout)));
+            self.tcp = Some(stream);
+        }
+        let mut data = String::new();
+        for line in lines {
+            data.push_str(line);
+            data.push('\n');
+        }
+        let result = self.tcp.as_mut().unwrap().write_all(data.as_bytes());
+        if let Err(e) = result {
+            // Reconnect on the next flush
+            self.tcp = None;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// Encode the metrics of a delta as lines of the given protocol, with `time` in seconds since the
+/// UNIX epoch
+fn encode(protocol: PushProtocol, prefix: &str, time: u64, delta: &MetricsDelta) -> Vec<String> {
+    let mut lines = Vec::new();
+    for &(ref name, ref metric) in &delta.metrics {
+        let name = format!("{}.{}", prefix, sanitize(name));
+        match *metric {
+            Metric::Labeled(ref series) => {
+                for &(ref labels, ref metric) in series {
+                    encode_metric(protocol, &name, labels, time, metric, &mut lines);
+                }
+            },
+            ref metric => encode_metric(protocol, &name, &Labels::new(), time, metric, &mut lines)
+        }
+    }
+    lines
+}
+
+fn encode_metric(protocol: PushProtocol,
+                 name: &str,
+                 labels: &Labels,
+                 time: u64,
+                 metric: &Metric,
+                 lines: &mut Vec<String>)
+{
+    let mut push = |name: &str, value: String, kind: &str| {
+        lines.push(line(protocol, name, labels, time, &value, kind));
+    };
+    match *metric {
+        Metric::Counter(value) => push(name, value.to_string(), "c"),
+        Metric::Gauge(value) => push(name, value.to_string(), "g"),
+        Metric::Histogram(ref histogram) => {
+            for &(percentile, suffix) in PERCENTILES.iter() {
+                let value = histogram.percentile(percentile).to_string();
+                push(&format!("{}.{}", name, suffix), value, "g");
+            }
+            push(&format!("{}.count", name), histogram.count().to_string(), "c");
+        },
+        Metric::Sketch(ref sketch) => {
+            for &(percentile, suffix) in PERCENTILES.iter() {
+                let value = sketch.quantile(percentile / 100.0).to_string();
+                push(&format!("{}.{}", name, suffix), value, "g");
+            }
+            push(&format!("{}.count", name), sketch.count().to_string(), "g");
+        },
+        Metric::Meter(ref rates) => {
+            push(&format!("{}.count", name), rates.count.to_string(), "c");
+            push(&format!("{}.m1_rate", name), rates.m1.to_string(), "g");
+            push(&format!("{}.m5_rate", name), rates.m5.to_string(), "g");
+            push(&format!("{}.m15_rate", name), rates.m15.to_string(), "g");
+        },
+        // Series of a labeled metric are never labeled themselves
+        Metric::Labeled(_) => ()
+    }
+}
+
+/// Format a single line. `kind` is the StatsD metric type, which Graphite doesn't use.
+fn line(protocol: PushProtocol,
+        name: &str,
+        labels: &Labels,
+        time: u64,
+        value: &str,
+        kind: &str) -> String
+{
+    match protocol {
+        PushProtocol::Statsd => {
+            let mut line = format!("{}:{}|{}", name, value, kind);
+            for (i, (label, value)) in labels.iter().enumerate() {
+                line.push_str(if i == 0 { "|#" } else { "," });
+                line.push_str(&format!("{}:{}", sanitize(label), sanitize(value)));
+            }
+            line
+        },
+        PushProtocol::Graphite => {
+            let mut name = name.to_string();
+            for (label, value) in labels.iter() {
+                name.push_str(&format!(";{}={}", sanitize(label), sanitize(value)));
+            }
+            format!("{} {} {}", name, value, time)
+        }
+    }
+}
+
+/// Replace the characters that have a meaning in StatsD or Graphite lines
+fn sanitize(name: &str) -> String {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.';
+    name.chars().map(|c| if allowed(c) { c } else { '_' }).collect()
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::io::Read;
+use libc;
+use metrics::{Metric, Metrics};
+
+metrics!(ResourceMetrics {
+    cpu_time_ms: u64,
+    rss_bytes: i64,
+    open_fds: i64,
+    threads: i64
+});
+
+impl ResourceMetrics {
+    /// Sample resource usage of the current OS process
+    ///
+    /// Telemetry is read from procfs, so all metrics are 0 on platforms without it. `cpu_time_ms` is
+    /// the total user and system CPU time consumed so far. CPU usage is its rate of change.
+    pub fn collect() -> ResourceMetrics {
+        let mut metrics = ResourceMetrics::new();
+        if let Some(stat) = read_to_string("/proc/self/stat") {
+            metrics.parse_stat(&stat);
+        }
+        if let Ok(entries) = fs::read_dir("/proc/self/fd") {
+            // Don't count the fd used to read the directory
+            metrics.open_fds = entries.count() as i64 - 1;
+        }
+        metrics
+    }
+
+    fn parse_stat(&mut self, stat: &str) {
+        // The executable name is in parens and may itself contain spaces, so skip past it. The
+        // remaining fields start with the process state, which is field 3 in proc(5).
+        let fields: Vec<&str> = match stat.rfind(')') {
+            Some(i) => stat[i + 1..].split_whitespace().collect(),
+            None => return
+        };
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok()).unwrap_or(0);
+        let (ticks_per_sec, page_size) = unsafe {
+            (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE))
+        };
+        if ticks_per_sec > 0 {
+            let ticks = field(14) + field(15); // utime + stime
+            self.cpu_time_ms = ticks * 1000 / ticks_per_sec as u64;
+        }
+        self.threads = field(20) as i64;
+        if page_size > 0 {
+            self.rss_bytes = (field(24) * page_size as u64) as i64;
+        }
+    }
+}
+
+fn read_to_string(path: &str) -> Option<String> {
+    let mut s = String::new();
+    match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        Ok(_) => Some(s),
+        Err(_) => None
+    }
+}
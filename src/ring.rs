@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use node_id::NodeId;
+
+/// The metadata key under which a node advertises its ring weight. See `ClusterConfig::weight`.
+pub const WEIGHT_KEY: &'static str = "rabble.weight";
+
+/// A consistent hash ring over cluster members
+///
+/// Each node is placed on the ring at `vnodes` pseudo-random points. A key belongs to the node at
+/// the first point at or after the hash of the key, wrapping around at the end of the ring. Adding
+/// or removing a node therefore only moves the keys of the ranges adjacent to its points, roughly
+/// 1/N of all keys.
+///
+/// Nodes can be given a weight, in which case they are placed at `vnodes * weight` points and own
+/// proportionally more keys. A node's points are always the first N of the same sequence, so
+/// changing its weight only moves keys to or from that node.
+///
+/// Hashing uses 64 bit FNV-1a rather than the std hasher, so that every node computes the same ring
+/// regardless of the Rust version it was built with.
+#[derive(Debug, Clone)]
+pub struct Ring {
+    vnodes: usize,
+    nodes: HashSet<NodeId>,
+    weights: HashMap<NodeId, u32>,
+    points: BTreeMap<u64, NodeId>
+}
+
+impl Ring {
+    pub fn new(vnodes: usize) -> Ring {
+        Ring {
+            vnodes: vnodes,
+            nodes: HashSet::new(),
+            weights: HashMap::new(),
+            points: BTreeMap::new()
+        }
+    }
+
+    /// Replace the nodes in the ring, each with a weight of 1. Return true if the ring changed.
+    pub fn set_nodes(&mut self, nodes: &HashSet<NodeId>) -> bool {
+        let weights = nodes.iter().map(|node| (node.clone(), 1)).collect();
+        self.set_weighted_nodes(&weights)
+    }
+
+    /// Replace the nodes in the ring along with their weights. Nodes with a weight of 0 are left
+    /// out. Return true if the ring changed.
+    pub fn set_weighted_nodes(&mut self, weights: &HashMap<NodeId, u32>) -> bool {
+        let weights: HashMap<NodeId, u32> = weights.iter()
+            .filter(|&(_, &weight)| weight > 0)
+            .map(|(node, weight)| (node.clone(), *weight))
+            .collect();
+        if weights == self.weights {
+            return false;
+        }
+        let removed: Vec<NodeId> = self.weights.keys()
+            .filter(|node| !weights.contains_key(node))
+            .cloned()
+            .collect();
+        for node in removed {
+            self.set_points(&node, 0);
+        }
+        for (node, weight) in weights.iter() {
+            self.set_points(node, *weight);
+        }
+        self.weights = weights;
+        self.nodes = self.weights.keys().cloned().collect();
+        true
+    }
+
+    /// Change the weight of a single node, adding it if it isn't in the ring yet
+    pub fn set_weight(&mut self, node: NodeId, weight: u32) -> bool {
+        let mut weights = self.weights.clone();
+        weights.insert(node, weight);
+        self.set_weighted_nodes(&weights)
+    }
+
+    /// Return the weight of a node, or 0 if it isn't in the ring
+    pub fn weight(&self, node: &NodeId) -> u32 {
+        self.weights.get(node).cloned().unwrap_or(0)
+    }
+
+    pub fn weights(&self) -> &HashMap<NodeId, u32> {
+        &self.weights
+    }
+
+    pub fn add(&mut self, node: NodeId) {
+        self.set_weight(node, 1);
+    }
+
+    pub fn remove(&mut self, node: &NodeId) {
+        let mut weights = self.weights.clone();
+        weights.remove(node);
+        self.set_weighted_nodes(&weights);
+    }
+
+    pub fn nodes(&self) -> &HashSet<NodeId> {
+        &self.nodes
+    }
+
+    /// Add or remove points of `node` so that it has `vnodes * weight` of them
+    fn set_points(&mut self, node: &NodeId, weight: u32) {
+        let current = self.vnodes * self.weight(node) as usize;
+        let wanted = self.vnodes * weight as usize;
+        for i in wanted..current {
+            self.points.remove(&vnode_hash(node, i));
+        }
+        for i in current..wanted {
+            self.points.insert(vnode_hash(node, i), node.clone());
+        }
+    }
+
+    /// Return the node that owns `key`, or `None` if the ring is empty
+    pub fn get(&self, key: &[u8]) -> Option<&NodeId> {
+        let hash = fnv1a(key);
+        self.points.range(hash..).next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| node)
+    }
+}
+
+fn vnode_hash(node: &NodeId, i: usize) -> u64 {
+    fnv1a(format!("{}#{}", node, i).as_bytes())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
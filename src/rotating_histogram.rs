@@ -0,0 +1,100 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use histogram::{Histogram, TimeUnit};
+
+/// A histogram of recent values, kept as a ring of histograms that each cover a fixed interval
+///
+/// Values are recorded in the histogram of the current interval. Once the ring holds `buckets`
+/// histograms, the oldest is dropped whenever a new interval starts, so the percentiles of `last`
+/// and `recent` reflect the behavior of the last few minutes rather than of the whole lifetime of
+/// the process.
+#[derive(Debug, Clone)]
+pub struct RotatingHistogram {
+    unit: TimeUnit,
+    interval: Duration,
+    max_buckets: usize,
+    // The oldest histogram is at the front, and the current one at the back
+    buckets: VecDeque<Histogram>,
+    current_start: Instant
+}
+
+impl RotatingHistogram {
+    /// Create a histogram covering `buckets` intervals of length `interval`
+    ///
+    /// Panics if `interval` is zero or there are no buckets.
+    pub fn new(unit: TimeUnit, interval: Duration, buckets: usize) -> RotatingHistogram {
+        assert!(nanos(interval) > 0, "The interval of a rotating histogram must not be zero");
+        assert!(buckets > 0, "A rotating histogram needs at least one bucket");
+        let mut ring = VecDeque::with_capacity(buckets);
+        ring.push_back(Histogram::new(unit.clone()));
+        RotatingHistogram {
+            unit: unit,
+            interval: interval,
+            max_buckets: buckets,
+            buckets: ring,
+            current_start: Instant::now()
+        }
+    }
+
+    /// Record a single value, in the unit of the histogram
+    pub fn record(&mut self, value: u64) {
+        self.rotate(Instant::now());
+        self.buckets.back_mut().unwrap().record(value);
+    }
+
+    /// Record a duration, converted to the unit of the histogram
+    pub fn record_duration(&mut self, duration: Duration) {
+        let value = self.unit.from_duration(duration);
+        self.record(value);
+    }
+
+    /// Return the values recorded during the last `window`, rounded up to whole intervals
+    ///
+    /// Windows longer than all buckets together return every value still kept.
+    pub fn last(&self, window: Duration) -> Histogram {
+        // Intervals that passed without a value recorded have no bucket yet
+        let empty = self.intervals_since_current(Instant::now());
+        let wanted = (nanos(window) + nanos(self.interval) - 1) / nanos(self.interval);
+        let count = cmp::min(wanted.saturating_sub(empty) as usize, self.buckets.len());
+        let mut merged = Histogram::new(self.unit.clone());
+        for bucket in self.buckets.iter().rev().take(count) {
+            merged.merge(bucket);
+        }
+        merged
+    }
+
+    /// Return all values recorded in the intervals still kept
+    pub fn recent(&self) -> Histogram {
+        let window = self.interval * self.max_buckets as u32;
+        self.last(window)
+    }
+
+    /// Start a new bucket for every interval that passed since the current one started
+    fn rotate(&mut self, now: Instant) {
+        let elapsed = self.intervals_since_current(now);
+        if elapsed == 0 {
+            return;
+        }
+        for _ in 0..cmp::min(elapsed, self.max_buckets as u64) {
+            if self.buckets.len() == self.max_buckets {
+                self.buckets.pop_front();
+            }
+            self.buckets.push_back(Histogram::new(self.unit.clone()));
+        }
+        let advance = elapsed * nanos(self.interval);
+        self.current_start += Duration::new(advance / 1_000_000_000,
+                                            (advance % 1_000_000_000) as u32);
+    }
+
+    fn intervals_since_current(&self, now: Instant) -> u64 {
+        if now <= self.current_start {
+            return 0;
+        }
+        nanos(now - self.current_start) / nanos(self.interval)
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs().saturating_mul(1_000_000_000).saturating_add(duration.subsec_nanos() as u64)
+}
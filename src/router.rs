@@ -0,0 +1,14 @@
+use pid::Pid;
+
+/// Resolve logical destinations to concrete Pids.
+///
+/// A router is installed on a node with `Node::set_router` and consulted by `Node::send` for
+/// envelopes addressed to `Pid::routed(key)`. Envelopes that processes return from `handle` aren't
+/// resolved, so processes should look up the destination with their own copy of the router.
+/// This allows applications to implement their own placement policies, such as sending to the
+/// replica with the lowest latency or one in the same rack, without changing how envelopes are
+/// routed internally. Since the router is shared by all clones of a node it must be thread safe.
+pub trait Router: Send + Sync {
+    /// Return the pid that should receive messages for `key`, or `None` if there is no route.
+    fn route(&self, key: &str) -> Option<Pid>;
+}
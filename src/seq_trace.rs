@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use pid::Pid;
+use node_id::NodeId;
+use envelope::{Envelope, now_ms};
+use msg::Msg;
+
+/// The token carried by an envelope of a sequence trace
+///
+/// Every hop of a traced envelope, and of every envelope sent in response to it, is reported to
+/// `collector` as a `Msg::SeqTrace`. The serial is incremented each time a process sends an
+/// envelope while handling a traced one, so events can be ordered along the path of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SeqTrace {
+    pub label: u64,
+    pub serial: u64,
+    pub collector: Pid
+}
+
+/// A step in the path of a traced envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SeqTraceHop {
+    /// A process sent the envelope while handling a traced envelope
+    Send,
+    /// The envelope was delivered to a process or service
+    Receive,
+    /// The envelope was forwarded to the node a process migrated to
+    Forward,
+    /// The envelope was written to a connection to another node
+    LeaveNode,
+    /// The envelope was read from a connection to another node
+    EnterNode
+}
+
+/// A report of one hop of a traced envelope, sent to the collector of the trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeqTraceEvent {
+    pub label: u64,
+    pub serial: u64,
+    pub hop: SeqTraceHop,
+    /// The node the hop happened on
+    pub node: NodeId,
+    pub from: Pid,
+    pub to: Pid,
+    /// The wall clock time of the hop in ms since the UNIX epoch
+    pub time: u64
+}
+
+/// Return the envelope reporting a hop of `envelope` to its collector, if it's traced
+///
+/// The report is sent by `reporter`, the component of `node` the hop happened in. Reports aren't
+/// traced themselves.
+pub fn seq_trace_event<'de, T>(envelope: &Envelope<T>,
+                               hop: SeqTraceHop,
+                               node: &NodeId,
+                               reporter: &Pid) -> Option<Envelope<T>>
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    envelope.seq_trace.as_ref().map(|token| {
+        let event = SeqTraceEvent {
+            label: token.label,
+            serial: token.serial,
+            hop: hop,
+            node: node.clone(),
+            from: envelope.from.clone(),
+            to: envelope.to.clone(),
+            time: now_ms()
+        };
+        Envelope::new(token.collector.clone(), reporter.clone(), Msg::SeqTrace(event), None)
+    })
+}
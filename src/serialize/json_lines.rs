@@ -0,0 +1,99 @@
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::fmt::Debug;
+use serde::{Serialize, Deserialize};
+use serde_json;
+use errors::*;
+use serialize;
+
+const MAX_LINE_SIZE: usize = 64*1024*1024; // 64 MB
+const READ_SIZE: usize = 4096;
+
+/// Newline delimited JSON, as spoken by clients such as scripts and command line tools
+///
+/// Each message is encoded as a single line of JSON. Blank lines are ignored, and lines may end
+/// with `\r\n`.
+pub struct JsonLinesSerializer<T> {
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    writable: bool,
+    phantom: PhantomData<T>
+}
+
+impl<T> JsonLinesSerializer<T> {
+    /// Return the next complete line in the read buffer, without its line ending
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        while let Some(pos) = self.read_buf.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.read_buf.drain(..pos + 1).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+        None
+    }
+}
+
+impl<'de, T: Serialize + Deserialize<'de> + Debug + Clone> serialize::Serialize for JsonLinesSerializer<T> {
+    type Msg = T;
+
+    fn new() -> JsonLinesSerializer<T> {
+        JsonLinesSerializer {
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            writable: true,
+            phantom: PhantomData
+        }
+    }
+
+    fn read_msg<U: Read>(&mut self, reader: &mut U) -> Result<Option<T>> {
+        let mut buf = [0; READ_SIZE];
+        loop {
+            if let Some(line) = self.next_line() {
+                let mut deserializer = serde_json::Deserializer::from_reader(&line[..]);
+                let msg = try!(Deserialize::deserialize(&mut deserializer)
+                               .chain_err(|| "Failed to decode JSON line"));
+                return Ok(Some(msg));
+            }
+            if self.read_buf.len() > MAX_LINE_SIZE {
+                return Err("JSON line too long".into());
+            }
+            match reader.read(&mut buf) {
+                Ok(0) => return Err("Connection closed".into()),
+                Ok(n) => self.read_buf.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e).chain_err(|| "JSON lines serializer failed to read from socket")
+            }
+        }
+    }
+
+    fn write_msgs<U: Write>(&mut self, writer: &mut U, msg: Option<&T>) -> Result<bool> {
+        if let Some(msg) = msg {
+            try!(serde_json::to_writer(&mut self.write_buf, msg)
+                 .chain_err(|| format!("Failed to encode message {:?}", msg)));
+            self.write_buf.push(b'\n');
+        }
+        while self.writable && !self.write_buf.is_empty() {
+            match writer.write(&self.write_buf) {
+                Ok(0) => return Err("Connection closed".into()),
+                Ok(n) => {
+                    self.write_buf.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.writable = false,
+                Err(e) => return Err(e).chain_err(|| "Failed to write encoded message")
+            }
+        }
+        Ok(self.writable)
+    }
+
+    fn set_writable(&mut self) {
+        self.writable = true;
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
@@ -1,7 +1,9 @@
 mod serialize;
 mod msgpack;
 mod protobuf;
+mod json_lines;
 
 pub use self::serialize::Serialize;
 pub use self::msgpack::MsgpackSerializer;
 pub use self::protobuf::ProtobufSerializer;
+pub use self::json_lines::JsonLinesSerializer;
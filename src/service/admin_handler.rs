@@ -0,0 +1,625 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::fmt::Debug;
+use std::str;
+use serde::{Serialize, Deserialize};
+use serde_json::{self, Value, Map};
+use time::{SteadyTime, Duration};
+use amy::{Registrar, Notification, Event};
+use errors::*;
+use msg::Msg;
+use envelope::Envelope;
+use node::Node;
+use node_id::NodeId;
+use members::NodeMetadata;
+use cluster::{ClusterStatus, PeerState};
+use executor::ExecutorStatus;
+use pid::Pid;
+use correlation_id::CorrelationId;
+use metrics::Metric;
+use prometheus;
+use socket::{Listener, Stream};
+use super::{ServiceHandler, ServiceHealth};
+
+// How long to wait for the cluster server or executor to answer a request
+const REQUEST_TIMEOUT: i64 = 5000; // ms
+
+// How often requests are checked for timeouts
+const TICK_TIME: usize = 1000; // ms
+
+// The largest request accepted. Requests have no body, so this only limits the header.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+const READ_SIZE: usize = 4096;
+
+/// A parsed HTTP request line
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>
+}
+
+/// A request waiting on replies from the cluster server or executor
+enum Pending {
+    Status,
+    Members,
+    Processes,
+    // The metrics of each component, and the number of components that haven't answered yet
+    Metrics(MetricsFormat, Vec<(Pid, Vec<(String, Metric)>)>, usize),
+    Combined(Combined)
+}
+
+/// The replies collected for `/status.json`
+struct Combined {
+    cluster: Option<ClusterStatus>,
+    executor: Option<ExecutorStatus>,
+    metrics: Vec<(Pid, Vec<(String, Metric)>)>,
+    // The number of replies still expected from the cluster server, executor and components
+    remaining: usize
+}
+
+enum MetricsFormat {
+    Json,
+    Prometheus
+}
+
+struct Connection {
+    sock: Stream,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    writable: bool,
+    pending: Option<(Pending, SteadyTime)>,
+    // Set once the response has been queued. The connection is closed after it's written.
+    responded: bool
+}
+
+impl Connection {
+    fn new(sock: Stream) -> Connection {
+        Connection {
+            sock: sock,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            writable: true,
+            pending: None,
+            responded: false
+        }
+    }
+
+    /// Queue a JSON response and write as much of it as the socket accepts
+    fn respond(&mut self, id: usize, status: &str, body: &Value, registrar: &Registrar) -> Result<()> {
+        let body = try!(serde_json::to_vec_pretty(body));
+        self.respond_with(id, status, "application/json", &body, registrar)
+    }
+
+    fn respond_with(&mut self,
+                    id: usize,
+                    status: &str,
+                    content_type: &str,
+                    body: &[u8],
+                    registrar: &Registrar) -> Result<()>
+    {
+        let header = format!("HTTP/1.1 {}\r\n\
+                              Content-Type: {}\r\n\
+                              Content-Length: {}\r\n\
+                              Connection: close\r\n\r\n", status, content_type, body.len());
+        self.write_buf.extend_from_slice(header.as_bytes());
+        self.write_buf.extend_from_slice(body);
+        self.pending = None;
+        self.responded = true;
+        self.flush(id, registrar)
+    }
+
+    fn flush(&mut self, id: usize, registrar: &Registrar) -> Result<()> {
+        while !self.write_buf.is_empty() {
+            match self.sock.write(&self.write_buf) {
+                Ok(0) => return Err("Admin connection closed by client".into()),
+                Ok(n) => {
+                    self.write_buf.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.writable {
+                        self.writable = false;
+                        try!(registrar.reregister(id, &self.sock, Event::Write)
+                             .chain_err(|| "Failed to register socket for writing"));
+                    }
+                    return Ok(());
+                },
+                Err(e) => return Err(e.into())
+            }
+        }
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.responded && self.write_buf.is_empty()
+    }
+}
+
+/// A service handler exposing HTTP endpoints for inspecting and operating a node
+///
+/// All responses are JSON. The endpoints are:
+///
+///  * `GET /status` - The `ClusterStatus` of the node
+///  * `GET /members` - The members of the cluster and the state of the connection to each
+///  * `GET /processes` - The `ExecutorStatus` of the node and the services running on it
+///  * `GET /metrics` - The metrics of the cluster server, executor, services and the node's
+///    `MetricsRegistry`
+///  * `GET /metrics/prometheus` - The same metrics in the Prometheus text format, for scraping
+///  * `GET /status.json` - The node, its `ExecutorStatus` and services, its `ClusterStatus` and
+///    all metrics in a single document, for debugging and simple dashboards
+///  * `POST /join?node=name@addr` - Join the node to the cluster
+///  * `POST /leave?node=name@addr` - Gracefully remove a node from the cluster
+///
+/// The admin service has no authentication, so it should only listen on a loopback address or a
+/// unix domain socket, or otherwise be protected from untrusted clients.
+pub struct AdminHandler {
+    pid: Pid,
+    listener: Listener,
+    listener_id: usize,
+    timer_id: usize,
+    connections: HashMap<usize, Connection>
+}
+
+impl AdminHandler {
+    /// Create an AdminHandler listening on `addr`, a TCP address or a `unix:` socket path
+    pub fn new(pid: Pid, addr: &str) -> AdminHandler {
+        AdminHandler {
+            pid: pid,
+            listener: Listener::bind(addr).unwrap(),
+            listener_id: 0,
+            timer_id: 0,
+            connections: HashMap::new()
+        }
+    }
+
+    fn accept_connections(&mut self, registrar: &Registrar) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok(sock) => {
+                    try!(sock.set_nonblocking(true)
+                         .chain_err(|| "Failed to make socket nonblocking"));
+                    let id = try!(registrar.register(&sock, Event::Read)
+                                  .chain_err(|| "Failed to register new socket for reading"));
+                    self.connections.insert(id, Connection::new(sock));
+                },
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(())
+                    }
+                    return Err(e.into())
+                }
+            }
+        }
+    }
+
+    fn handle_connection_notification<'de, T>(&mut self,
+                                              node: &Node<T>,
+                                              notification: &Notification,
+                                              registrar: &Registrar) -> Result<()>
+        where T: Serialize + Deserialize<'de> + Debug + Clone
+    {
+        let id = notification.id;
+        let request = match self.connections.get_mut(&id) {
+            Some(connection) => {
+                if notification.event.writable() {
+                    try!(connection.flush(id, registrar));
+                }
+                if !notification.event.readable() || connection.responded {
+                    return Ok(());
+                }
+                try!(read(connection));
+                match try!(parse_request(&connection.read_buf)) {
+                    Some(request) => request,
+                    None => return Ok(())
+                }
+            },
+            None => return Ok(())
+        };
+        self.handle_request(node, id, request, registrar)
+    }
+
+    fn handle_request<'de, T>(&mut self,
+                              node: &Node<T>,
+                              id: usize,
+                              request: Request,
+                              registrar: &Registrar) -> Result<()>
+        where T: Serialize + Deserialize<'de> + Debug + Clone
+    {
+        let correlation_id = CorrelationId::connection(self.pid.clone(), id as u64);
+        let pending = match (&request.method[..], &request.path[..]) {
+            ("GET", "/status") => {
+                try!(node.cluster_status(correlation_id));
+                Pending::Status
+            },
+            ("GET", "/members") => {
+                try!(node.cluster_status(correlation_id));
+                Pending::Members
+            },
+            ("GET", "/processes") => {
+                try!(node.executor_status(correlation_id));
+                Pending::Processes
+            },
+            ("GET", "/metrics") | ("GET", "/metrics/prometheus") => {
+                let (collected, remaining) = try!(self.request_metrics(node, &correlation_id));
+                let format = if request.path == "/metrics" {
+                    MetricsFormat::Json
+                } else {
+                    MetricsFormat::Prometheus
+                };
+                Pending::Metrics(format, collected, remaining)
+            },
+            ("GET", "/status.json") => {
+                let (collected, remaining) = try!(self.request_metrics(node, &correlation_id));
+                try!(node.cluster_status(correlation_id.clone()));
+                try!(node.executor_status(correlation_id));
+                Pending::Combined(Combined {
+                    cluster: None,
+                    executor: None,
+                    metrics: collected,
+                    remaining: remaining + 2
+                })
+            },
+            ("POST", "/join") | ("POST", "/leave") => {
+                let (status, body) = match request.query.get("node").map(|n| n.parse::<NodeId>()) {
+                    Some(Ok(target)) => {
+                        let result = if request.path == "/join" {
+                            node.join(&target)
+                        } else {
+                            node.leave(&target)
+                        };
+                        match result {
+                            Ok(()) => ("200 OK", json!({"node": target.to_string()})),
+                            Err(e) => ("500 Internal Server Error", json!({"error": e.to_string()}))
+                        }
+                    },
+                    Some(Err(e)) => ("400 Bad Request", json!({"error": e})),
+                    None => ("400 Bad Request", json!({"error": "Missing node parameter"}))
+                };
+                return self.respond(id, status, &body, registrar);
+            },
+            _ => {
+                let body = json!({"error": format!("No endpoint for {} {}",
+                                                   request.method, request.path)});
+                return self.respond(id, "404 Not Found", &body, registrar);
+            }
+        };
+        if let Some(connection) = self.connections.get_mut(&id) {
+            connection.pending = Some((pending, SteadyTime::now()));
+        }
+        Ok(())
+    }
+
+    /// Ask the cluster server, executor and running services for their metrics
+    ///
+    /// Returns the metrics of the node's registry, which are read directly, and the number of
+    /// replies to wait for.
+    fn request_metrics<'de, T>(&self,
+                               node: &Node<T>,
+                               correlation_id: &CorrelationId)
+        -> Result<(Vec<(Pid, Vec<(String, Metric)>)>, usize)>
+        where T: Serialize + Deserialize<'de> + Debug + Clone
+    {
+        let mut components: Vec<Pid> = ["cluster_server", "executor"].iter()
+            .map(|name| Pid {
+                group: Some("rabble".to_string()),
+                name: name.to_string(),
+                node: node.id.clone(),
+                generation: None
+            })
+            .collect();
+        components.extend(node.service_info().into_iter()
+                          .filter(|info| info.health != ServiceHealth::Stopped)
+                          .map(|info| info.pid));
+        for to in &components {
+            let envelope = Envelope::new(to.clone(),
+                                         self.pid.clone(),
+                                         Msg::GetMetrics(None),
+                                         Some(correlation_id.clone()));
+            try!(node.send(envelope));
+        }
+        let registry = Pid {
+            group: Some("rabble".to_string()),
+            name: "registry".to_string(),
+            node: node.id.clone(),
+            generation: None
+        };
+        Ok((vec![(registry, node.metrics().snapshot())], components.len()))
+    }
+
+    fn respond(&mut self,
+               id: usize,
+               status: &str,
+               body: &Value,
+               registrar: &Registrar) -> Result<()>
+    {
+        if let Some(connection) = self.connections.get_mut(&id) {
+            try!(connection.respond(id, status, body, registrar));
+        }
+        Ok(())
+    }
+
+    /// Answer requests the cluster server or executor didn't reply to in time
+    fn tick(&mut self, registrar: &Registrar) -> Result<()> {
+        let now = SteadyTime::now();
+        let timeout = Duration::milliseconds(REQUEST_TIMEOUT);
+        let body = json!({"error": "Timed out waiting for a reply"});
+        for (id, connection) in self.connections.iter_mut() {
+            if connection.pending.as_ref().map_or(false, |&(_, started)| now - started > timeout) {
+                try!(connection.respond(*id, "504 Gateway Timeout", &body, registrar));
+            }
+        }
+        Ok(())
+    }
+
+    fn close_finished(&mut self, registrar: &Registrar) {
+        let done: Vec<usize> = self.connections.iter()
+            .filter(|&(_, connection)| connection.is_done())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in done {
+            let connection = self.connections.remove(&id).unwrap();
+            let _ = registrar.deregister(connection.sock);
+        }
+    }
+}
+
+impl<'de, T> ServiceHandler<T> for AdminHandler
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    fn init(&mut self, registrar: &Registrar, _node: &Node<T>) -> Result<()> {
+        self.listener_id = try!(registrar.register(&self.listener, Event::Read)
+                                .chain_err(|| "Failed to register listener"));
+        self.timer_id = try!(registrar.set_interval(TICK_TIME)
+                             .chain_err(|| "Failed to register request timer"));
+        Ok(())
+    }
+
+    fn handle_notification(&mut self,
+                           node: &Node<T>,
+                           notification: Notification,
+                           registrar: &Registrar) -> Result<()>
+    {
+        let result = if notification.id == self.listener_id {
+            self.accept_connections(registrar)
+        } else if notification.id == self.timer_id {
+            self.tick(registrar)
+        } else {
+            let result = self.handle_connection_notification(node, &notification, registrar);
+            if result.is_err() {
+                if let Some(connection) = self.connections.remove(&notification.id) {
+                    let _ = registrar.deregister(connection.sock);
+                }
+            }
+            result
+        };
+        self.close_finished(registrar);
+        result
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.listener.local_addr().ok()
+    }
+
+    fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Handle the replies of the cluster server and executor
+    fn handle_envelope(&mut self,
+                       node: &Node<T>,
+                       envelope: Envelope<T>,
+                       registrar: &Registrar) -> Result<()>
+    {
+        let id = match envelope.correlation_id.as_ref().and_then(|c| c.connection) {
+            Some(id) => id as usize,
+            None => return Err(format!("No connection id for envelope {:?}", envelope).into())
+        };
+        let json = |value: Value| -> Result<Option<(&'static str, Vec<u8>)>> {
+            Ok(Some(("application/json", try!(serde_json::to_vec_pretty(&value)))))
+        };
+        let response = match self.connections.get_mut(&id).and_then(|c| c.pending.as_mut()) {
+            Some(&mut (Pending::Status, _)) => match envelope.msg {
+                Msg::ClusterStatus(status) => try!(json(status_json(&status))),
+                _ => None
+            },
+            Some(&mut (Pending::Members, _)) => match envelope.msg {
+                Msg::ClusterStatus(status) => try!(json(json!({
+                    "members": node_names(&status.members),
+                    "peers": peers_json(&status.peers)
+                }))),
+                _ => None
+            },
+            Some(&mut (Pending::Processes, _)) => match envelope.msg {
+                Msg::ExecutorStatus(status) => try!(json(json!({
+                    "executor": try!(serde_json::to_value(status)),
+                    "services": try!(serde_json::to_value(node.service_info()))
+                }))),
+                _ => None
+            },
+            Some(&mut (Pending::Metrics(ref format, ref mut metrics, ref mut remaining), _)) => {
+                match envelope.msg {
+                    Msg::Metrics(data) => {
+                        metrics.push((envelope.from.clone(), data));
+                        *remaining -= 1;
+                        if *remaining > 0 {
+                            None
+                        } else {
+                            match *format {
+                                MetricsFormat::Json => try!(json(try!(metrics_json(metrics)))),
+                                MetricsFormat::Prometheus => {
+                                    let node_name = node.id.to_string();
+                                    let mut body = String::new();
+                                    for &(ref pid, ref data) in metrics.iter() {
+                                        prometheus::encode(&node_name, &pid.name, data, &mut body);
+                                    }
+                                    Some(("text/plain; version=0.0.4", body.into_bytes()))
+                                }
+                            }
+                        }
+                    },
+                    _ => None
+                }
+            },
+            Some(&mut (Pending::Combined(ref mut combined), _)) => {
+                match envelope.msg {
+                    Msg::ClusterStatus(status) => combined.cluster = Some(status),
+                    Msg::ExecutorStatus(status) => combined.executor = Some(status),
+                    Msg::Metrics(data) => combined.metrics.push((envelope.from.clone(), data)),
+                    _ => return Ok(())
+                }
+                combined.remaining -= 1;
+                if combined.remaining > 0 {
+                    None
+                } else {
+                    try!(json(try!(combined_json(node, combined))))
+                }
+            },
+            // The request already timed out, or the client went away
+            None => None
+        };
+        if let Some((content_type, body)) = response {
+            if let Some(connection) = self.connections.get_mut(&id) {
+                try!(connection.respond_with(id, "200 OK", content_type, &body, registrar));
+            }
+            self.close_finished(registrar);
+        }
+        Ok(())
+    }
+}
+
+/// Convert a `ClusterStatus` to JSON
+///
+/// Nodes are written as `name@addr` strings, since JSON objects can only have string keys.
+fn status_json(status: &ClusterStatus) -> Value {
+    let metadata: HashMap<String, &NodeMetadata> = status.metadata.iter()
+        .map(|(node, metadata)| (node.to_string(), metadata))
+        .collect();
+    json!({
+        "members": node_names(&status.members),
+        "established": node_names(&status.established),
+        "suspected": node_names(&status.suspected),
+        "failed": node_names(&status.failed),
+        "metadata": metadata,
+        "peers": peers_json(&status.peers),
+        "quorum": status.quorum,
+        "num_connections": status.num_connections
+    })
+}
+
+/// Convert the replies collected for `/status.json` to a single JSON document
+fn combined_json<'de, T>(node: &Node<T>, combined: &Combined) -> Result<Value>
+    where T: Serialize + Deserialize<'de> + Debug + Clone
+{
+    Ok(json!({
+        "node": {
+            "id": node.id.to_string(),
+            "executor": try!(serde_json::to_value(&combined.executor)),
+            "services": try!(serde_json::to_value(node.service_info()))
+        },
+        "cluster": combined.cluster.as_ref().map(status_json),
+        "metrics": try!(metrics_json(&combined.metrics))
+    }))
+}
+
+/// Convert the metrics of each component to a JSON object keyed by component name
+fn metrics_json(metrics: &[(Pid, Vec<(String, Metric)>)]) -> Result<Value> {
+    let mut components = Map::new();
+    for &(ref pid, ref data) in metrics {
+        let mut values = Map::new();
+        for &(ref name, ref metric) in data {
+            values.insert(name.clone(), try!(metric_json(metric)));
+        }
+        components.insert(pid.name.clone(), Value::Object(values));
+    }
+    Ok(Value::Object(components))
+}
+
+fn metric_json(metric: &Metric) -> Result<Value> {
+    match *metric {
+        // Encoded histograms are unreadable, so report their percentiles instead
+        Metric::Histogram(ref histogram) => Ok(json!({"Histogram": histogram.summary()})),
+        Metric::Labeled(ref series) => {
+            let mut values = Vec::with_capacity(series.len());
+            for &(ref labels, ref metric) in series {
+                values.push(json!({"labels": labels, "value": try!(metric_json(metric))}));
+            }
+            Ok(json!({"Labeled": values}))
+        },
+        ref metric => Ok(try!(serde_json::to_value(metric)))
+    }
+}
+
+fn node_names(nodes: &HashSet<NodeId>) -> Vec<String> {
+    let mut names: Vec<String> = nodes.iter().map(|node| node.to_string()).collect();
+    names.sort();
+    names
+}
+
+fn peers_json(peers: &HashMap<NodeId, PeerState>) -> HashMap<String, PeerState> {
+    peers.iter().map(|(node, state)| (node.to_string(), *state)).collect()
+}
+
+/// Read all available data from a client
+fn read(connection: &mut Connection) -> Result<()> {
+    let mut buf = [0; READ_SIZE];
+    loop {
+        match connection.sock.read(&mut buf) {
+            Ok(0) => return Err("Admin connection closed by client".into()),
+            Ok(n) => connection.read_buf.extend_from_slice(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e.into())
+        }
+    }
+}
+
+/// Parse the request line of a complete HTTP request. The headers are ignored.
+fn parse_request(buf: &[u8]) -> Result<Option<Request>> {
+    if !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_SIZE {
+            return Err("Admin request too large".into());
+        }
+        return Ok(None);
+    }
+    let request = String::from_utf8_lossy(buf);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err("Invalid admin request line".into())
+    };
+    let mut target = target.splitn(2, '?');
+    let path = target.next().unwrap_or("").to_string();
+    let query = target.next().unwrap_or("").split('&')
+        .filter_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => Some((k.to_string(), percent_decode(v))),
+                _ => None
+            }
+        })
+        .collect();
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path,
+        query: query
+    }))
+}
+
+/// Decode the `%XX` escapes of a query parameter, such as the `%40` in `name%40127.0.0.1:11000`
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
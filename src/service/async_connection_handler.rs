@@ -0,0 +1,42 @@
+use std::future::Future;
+use std::pin::Pin;
+use envelope::Envelope;
+use correlation_id::CorrelationId;
+use pid::Pid;
+use node::Node;
+
+/// The future returned by the callbacks of an `AsyncConnectionHandler`
+///
+/// It resolves to the handler itself, so the handler can be moved into the future and await
+/// while holding its own state, along with the messages to send.
+pub type HandlerFuture<C> = Pin<Box<Future<Output = (C, Vec<AsyncConnectionMsg<C>>)> + Send>>;
+
+/// An asynchronous counterpart to `ConnectionHandler`, run by an `AsyncTcpServerHandler`
+///
+/// Callbacks run as tasks on a tokio runtime rather than on the service thread, so they may await
+/// databases or other I/O without blocking other connections. Calls for the same connection never
+/// overlap and are made in the order their messages arrived.
+///
+/// `async fn` can't be used in traits, so callbacks take the handler by value and return a boxed
+/// future, usually written as `Box::pin(async move { ...; (self, output) })`.
+pub trait AsyncConnectionHandler: Sized + Send + 'static {
+    type Msg: Send + 'static;
+    type ClientMsg: Send + 'static;
+
+    /// Create the handler for connection `id` of the service with the given pid
+    ///
+    /// As with `ConnectionHandler::new`, handlers can keep a clone of the node to send envelopes or
+    /// register metrics, including from within their futures.
+    fn new(pid: Pid, id: u64, node: &Node<Self::Msg>) -> Self;
+    fn handle_envelope(self, Envelope<Self::Msg>) -> HandlerFuture<Self>;
+    fn handle_network_msg(self, Self::ClientMsg) -> HandlerFuture<Self>;
+}
+
+/// Messages returned from the callbacks of an `AsyncConnectionHandler`
+///
+/// These are the same as `ConnectionMsg`: envelopes routed to processes, or replies serialized and
+/// sent to the client.
+pub enum AsyncConnectionMsg<C: AsyncConnectionHandler> {
+    Envelope(Envelope<C::Msg>),
+    Client(C::ClientMsg, CorrelationId)
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::io;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use serde;
+use amy::{self, Registrar, Notification, Event};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender, UnboundedReceiver};
+use errors::*;
+use msg::Msg;
+use envelope::Envelope;
+use node::Node;
+use timer_wheel::TimerWheel;
+use pid::Pid;
+use correlation_id::CorrelationId;
+use socket::{Listener, Stream, SocketOptions};
+use serialize::Serialize;
+use super::{ServiceHandler, AsyncConnectionHandler, AsyncConnectionMsg, HandlerFuture};
+
+// The timer wheel expirations are accurate to within 1/TIMER_WHEEL_SLOTS of the timeout
+const TIMER_WHEEL_SLOTS: usize = 10;
+
+/// A message handed to the task of a connection
+enum Input<C: AsyncConnectionHandler> {
+    Network(C::ClientMsg),
+    Envelope(Envelope<C::Msg>)
+}
+
+/// The messages returned by a callback, along with the id of its connection
+type Output<C> = (usize, Vec<AsyncConnectionMsg<C>>);
+
+/// Runs the callbacks of a single connection one at a time, in the order their input arrived
+///
+/// The task ends once the connection is closed and its input sender dropped.
+struct ConnectionTask<C: AsyncConnectionHandler> {
+    id: usize,
+    // `None` while a callback is running, since the callback owns the handler
+    handler: Option<C>,
+    pending: Option<HandlerFuture<C>>,
+    rx: UnboundedReceiver<Input<C>>,
+    tx: amy::Sender<Output<C>>
+}
+
+// The handler is only ever moved into and out of the task by value, never pinned
+impl<C: AsyncConnectionHandler> Unpin for ConnectionTask<C> {}
+
+impl<C: AsyncConnectionHandler> Future for ConnectionTask<C> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let task = self.get_mut();
+        loop {
+            if let Some(mut pending) = task.pending.take() {
+                match pending.as_mut().poll(cx) {
+                    Poll::Ready((handler, output)) => {
+                        task.handler = Some(handler);
+                        if !output.is_empty() && task.tx.send((task.id, output)).is_err() {
+                            // The service has stopped
+                            return Poll::Ready(());
+                        }
+                    },
+                    Poll::Pending => {
+                        task.pending = Some(pending);
+                        return Poll::Pending;
+                    }
+                }
+            }
+            let handler = task.handler.take().unwrap();
+            task.pending = match task.rx.poll_recv(cx) {
+                Poll::Ready(Some(Input::Network(msg))) => Some(handler.handle_network_msg(msg)),
+                Poll::Ready(Some(Input::Envelope(envelope))) => {
+                    Some(handler.handle_envelope(envelope))
+                },
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => {
+                    task.handler = Some(handler);
+                    return Poll::Pending;
+                }
+            };
+        }
+    }
+}
+
+struct Connection<C, S>
+    where C: AsyncConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize
+{
+    id: usize,
+    tx: UnboundedSender<Input<C>>,
+    serializer: S,
+    sock: Stream,
+    timer_wheel_slot: usize
+}
+
+/// A service handler for a TCP server whose connections are handled by `AsyncConnectionHandler`s
+///
+/// Sockets are read and written on the service thread exactly as in `TcpServerHandler`, while the
+/// callbacks of each connection run as a task on the given tokio runtime. Replies and envelopes
+/// returned by callbacks are sent once the service thread is notified that they are ready.
+pub struct AsyncTcpServerHandler<C, S>
+    where C: AsyncConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize
+{
+    pid: Pid,
+    runtime: Handle,
+    listener: Listener,
+    listener_id: usize,
+    socket_options: SocketOptions,
+    connections: HashMap<usize, Connection<C, S>>,
+    connection_timeout: Option<usize>, // ms
+    connection_timer_id: Option<usize>,
+    connection_timer_wheel: Option<TimerWheel<usize>>,
+    request_timeout: usize, // ms
+    request_timer_id: usize,
+    request_timer_wheel: TimerWheel<CorrelationId>,
+    // Created in init()
+    output_tx: Option<amy::Sender<Output<C>>>,
+    output_rx: Option<amy::Receiver<Output<C>>>
+}
+
+impl<'de, C, S> AsyncTcpServerHandler<C, S>
+    where C: AsyncConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Create a new AsyncTcpServerHandler that runs connection callbacks on `runtime`
+    ///
+    /// The address and timeouts mean the same as for `TcpServerHandler::new`.
+    pub fn new(pid: Pid,
+               runtime: Handle,
+               addr: &str,
+               request_timeout: usize,
+               connection_timeout: Option<usize>) -> AsyncTcpServerHandler<C, S>
+    {
+        let mut connection_timer_wheel = None;
+        if connection_timeout.is_some() {
+            connection_timer_wheel = Some(TimerWheel::new(TIMER_WHEEL_SLOTS + 1));
+        }
+        let listener = Listener::bind(addr).unwrap();
+        AsyncTcpServerHandler {
+            pid: pid,
+            runtime: runtime,
+            listener: listener,
+            listener_id: 0,
+            socket_options: SocketOptions::default(),
+            connections: HashMap::new(),
+            connection_timeout: connection_timeout,
+            connection_timer_id: None,
+            connection_timer_wheel: connection_timer_wheel,
+            request_timeout: request_timeout,
+            request_timer_id: 0,
+            request_timer_wheel: TimerWheel::new(TIMER_WHEEL_SLOTS + 1),
+            output_tx: None,
+            output_rx: None
+        }
+    }
+
+    /// Set the options applied to accepted TCP connections
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    fn accept_connections(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok(socket) => {
+                    try!(self.new_connection(socket, node, registrar));
+                },
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(())
+                    }
+                    return Err(e.into())
+                }
+            }
+        }
+    }
+
+    /// Register the socket of a new connection and spawn the task that runs its handler
+    fn new_connection(&mut self,
+                      sock: Stream,
+                      node: &Node<C::Msg>,
+                      registrar: &Registrar) -> Result<()>
+    {
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        try!(self.socket_options.apply(&sock).chain_err(|| "Failed to set socket options"));
+        let output_tx = try!(self.output_tx.as_ref().unwrap().try_clone());
+        let id = try!(registrar.register(&sock, Event::Read)
+                      .chain_err(|| "Failed to register new socket for reading"));
+        let (tx, rx) = unbounded_channel();
+        self.runtime.spawn(ConnectionTask {
+            id: id,
+            handler: Some(C::new(self.pid.clone(), id as u64, node)),
+            pending: None,
+            rx: rx,
+            tx: output_tx
+        });
+        let slot = self.connection_timer_wheel.as_mut().map_or(0, |tw| tw.insert(id));
+        self.connections.insert(id, Connection {
+            id: id,
+            tx: tx,
+            serializer: S::new(),
+            sock: sock,
+            timer_wheel_slot: slot
+        });
+        Ok(())
+    }
+
+    fn handle_connection_notification(&mut self, notification: &Notification) -> Result<()> {
+        if let Some(connection) = self.connections.get_mut(&notification.id) {
+            if notification.event.writable() {
+                // Notify the serializer that the socket is writable again
+                connection.serializer.set_writable();
+                try!(connection.serializer.write_msgs(&mut connection.sock, None));
+            }
+
+            if notification.event.readable() {
+                while let Some(msg) = try!(connection.serializer.read_msg(&mut connection.sock)) {
+                    // The task only stops once the connection is removed
+                    let _ = connection.tx.send(Input::Network(msg));
+                }
+                if let Some(ref mut timer_wheel) = self.connection_timer_wheel {
+                    timer_wheel.remove(&connection.id, connection.timer_wheel_slot);
+                    connection.timer_wheel_slot = timer_wheel.insert(connection.id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the replies and envelopes returned by connection callbacks
+    fn handle_outputs(&mut self, node: &Node<C::Msg>) -> Result<()> {
+        while let Ok((id, msgs)) = self.output_rx.as_ref().unwrap().try_recv() {
+            let connection = match self.connections.get_mut(&id) {
+                Some(connection) => connection,
+                // The connection was closed while its callback was running
+                None => continue
+            };
+            for m in msgs {
+                match m {
+                    AsyncConnectionMsg::Envelope(envelope) => {
+                        if let Some(ref correlation_id) = envelope.correlation_id {
+                            self.request_timer_wheel.insert(correlation_id.clone());
+                        }
+                        try!(node.send(envelope));
+                    },
+                    AsyncConnectionMsg::Client(client_msg, _) => {
+                        try!(connection.serializer.write_msgs(&mut connection.sock,
+                                                              Some(&client_msg))
+                             .chain_err(|| format!("Failed to write client msg: {:?}",
+                                                   client_msg)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn connection_tick(&mut self, registrar: &Registrar) {
+        for id in self.connection_timer_wheel.as_mut().unwrap().expire() {
+            if let Some(connection) = self.connections.remove(&id) {
+                let _ = registrar.deregister(connection.sock);
+            }
+        }
+    }
+
+    /// Tell connections about their requests that timed out
+    fn request_tick(&mut self) {
+        for correlation_id in self.request_timer_wheel.expire() {
+            let conn_id = correlation_id.connection.as_ref().cloned().unwrap();
+            if let Some(connection) = self.connections.get(&(conn_id as usize)) {
+                let envelope = Envelope::new(self.pid.clone(),
+                                             self.pid.clone(),
+                                             Msg::Timeout,
+                                             Some(correlation_id));
+                let _ = connection.tx.send(Input::Envelope(envelope));
+            }
+        }
+    }
+}
+
+impl<'de, C, S> ServiceHandler<C::Msg> for AsyncTcpServerHandler<C, S>
+    where C: AsyncConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Register timers, the listen socket and the channel callbacks return their output on
+    fn init(&mut self,
+            registrar: &Registrar,
+            _node: &Node<C::Msg>) -> Result<()>
+    {
+        self.listener_id = try!(registrar.register(&self.listener, Event::Read)
+                                .chain_err(|| "Failed to register listener"));
+
+        let (tx, rx) = try!(registrar.channel().chain_err(|| "Failed to create output channel"));
+        self.output_tx = Some(tx);
+        self.output_rx = Some(rx);
+
+        let req_timeout = self.request_timeout / TIMER_WHEEL_SLOTS;
+        self.request_timer_id = try!(registrar.set_interval(req_timeout)
+                                  .chain_err(|| "Failed to register request timer"));
+
+        if let Some(timeout) = self.connection_timeout {
+            self.connection_timer_id = Some(try!(registrar.set_interval(timeout / TIMER_WHEEL_SLOTS)
+                              .chain_err(|| "Failed to register connection timer")));
+        }
+        Ok(())
+    }
+
+    fn handle_notification(&mut self,
+                           node: &Node<C::Msg>,
+                           notification: Notification,
+                           registrar: &Registrar) -> Result<()>
+    {
+        if notification.id == self.listener_id {
+            return self.accept_connections(node, registrar);
+        }
+
+        if self.output_rx.as_ref().map_or(false, |rx| rx.get_id() == notification.id) {
+            return self.handle_outputs(node);
+        }
+
+        if notification.id == self.request_timer_id {
+            self.request_tick();
+            return Ok(());
+        }
+
+        if self.connection_timer_id == Some(notification.id) {
+            self.connection_tick(registrar);
+            return Ok(());
+        }
+
+        if let Err(e) = self.handle_connection_notification(&notification) {
+            if let Some(connection) = self.connections.remove(&notification.id) {
+                let _ = registrar.deregister(connection.sock);
+            }
+            let errmsg = e.to_string();
+            return Err(e).chain_err(|| format!("{}: id {}", errmsg, notification.id));
+        }
+        Ok(())
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.listener.local_addr().ok()
+    }
+
+    fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Hand an envelope from a process or service to the task of its connection
+    fn handle_envelope(&mut self,
+                       _node: &Node<C::Msg>,
+                       envelope: Envelope<C::Msg>,
+                       _registrar: &Registrar) -> Result<()>
+    {
+        let conn_id = match envelope.correlation_id.as_ref().and_then(|c| c.connection) {
+            Some(conn_id) => conn_id,
+            None => return Err(format!("No connection id for envelope {:?}", envelope).into())
+        };
+        if let Some(connection) = self.connections.get(&(conn_id as usize)) {
+            let _ = connection.tx.send(Input::Envelope(envelope));
+        }
+        Ok(())
+    }
+}
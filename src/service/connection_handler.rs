@@ -1,15 +1,69 @@
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use serde::{Serialize, Deserialize};
 use envelope::Envelope;
 use correlation_id::CorrelationId;
+use node::Node;
 use pid::Pid;
+use errors::*;
+use super::rate_limit::{RateLimitExceeded, RateLimitAction};
 
 /// Implement this for a specific connection handler
 pub trait ConnectionHandler: Sized {
     type Msg;
     type ClientMsg;
 
-    fn new(pid: Pid, id: u64) -> Self;
+    /// Create the handler for connection `id` of the service with the given pid
+    ///
+    /// Handlers that spawn processes per connection, or look up registered names, can keep a clone
+    /// of the node rather than returning every request as a `ConnectionMsg::Envelope`.
+    fn new(pid: Pid, id: u64, node: &Node<Self::Msg>) -> Self;
     fn handle_envelope(&mut self, Envelope<Self::Msg>, &mut Vec<ConnectionMsg<Self>>);
     fn handle_network_msg(&mut self, Self::ClientMsg, &mut Vec<ConnectionMsg<Self>>);
+
+    /// Called when the service accepts the connection, with the address of the client
+    ///
+    /// Clients connected over a unix socket have no address. Handlers that allocate resources per
+    /// client can do so here. This callback is optional.
+    fn on_connected(&mut self, _peer_addr: Option<SocketAddr>) {
+    }
+
+    /// Called after the connection closed, with the reason it did
+    ///
+    /// The client is already gone, so only envelopes returned from this callback are sent. Most
+    /// handlers hold no state that outlives the connection, so this callback is optional.
+    fn on_closed(&mut self, _reason: CloseReason, _output: &mut Vec<ConnectionMsg<Self>>) {
+    }
+
+    /// Called when the service starts to shut down
+    ///
+    /// Client messages returned from this callback, such as a goodbye to the client, are flushed
+    /// before the connection is closed. This callback is optional.
+    fn handle_drain(&mut self, _output: &mut Vec<ConnectionMsg<Self>>) {
+    }
+
+    /// Called once the TLS handshake with the client completes, with the protocol negotiated via
+    /// ALPN, if any
+    ///
+    /// Handlers serving more than one protocol use this to pick the one to speak. It's never called
+    /// for plaintext connections.
+    fn handle_tls_handshake(&mut self, _alpn_protocol: Option<String>) {
+    }
+
+    /// Called instead of `handle_network_msg` when a message exceeds the rate limit of the service
+    ///
+    /// The handler decides whether to handle the message anyway, perhaps after logging a warning,
+    /// drop it, or disconnect the client. By default the message is dropped.
+    fn handle_rate_limited(&mut self, _exceeded: RateLimitExceeded) -> RateLimitAction {
+        RateLimitAction::Drop
+    }
+
+    /// A short description of the state of the connection, such as the request it's waiting on,
+    /// reported in reply to `Msg::GetConnections`. This callback is optional.
+    fn summary(&self) -> Option<String> {
+        None
+    }
 }
 
 /// Connection messages are returned from the callback functions for a Connection.
@@ -22,3 +76,53 @@ pub enum ConnectionMsg<C: ConnectionHandler>
     Envelope(Envelope<C::Msg>),
     Client(C::ClientMsg, CorrelationId)
 }
+
+/// The reason a client connection closed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseReason {
+    /// The client closed the connection, or the server did, for an outgoing connection
+    ClientClosed,
+    /// No message was received for the connection timeout of the service
+    IdleTimeout,
+    /// Reading from or writing to the connection failed
+    Error,
+    /// The connection handler chose to disconnect a client over its rate limit
+    RateLimited,
+    /// The service shut down
+    Drained,
+    /// A callback of the connection handler panicked
+    Panicked
+}
+
+/// Run callbacks of the connection handler for connection `id`, turning a panic into a
+/// `HandlerPanic` error, so that only the connection is lost rather than the whole service
+pub fn isolate<F, R>(id: usize, f: F) -> Result<R>
+    where F: FnOnce() -> Result<R>
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let msg = payload.downcast_ref::<&'static str>().map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(ErrorKind::HandlerPanic(id, msg).into())
+        }
+    }
+}
+
+/// Tell a handler its connection was closed and send the envelopes it returns
+pub fn close_handler<'de, C>(handler: &mut C,
+                             reason: CloseReason,
+                             node: &Node<C::Msg>) -> Result<()>
+    where C: ConnectionHandler,
+          C::Msg: Serialize + Deserialize<'de> + Clone + Debug
+{
+    let mut output = Vec::new();
+    handler.on_closed(reason, &mut output);
+    for m in output {
+        if let ConnectionMsg::Envelope(envelope) = m {
+            try!(node.send(envelope));
+        }
+    }
+    Ok(())
+}
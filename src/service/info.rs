@@ -0,0 +1,40 @@
+use pid::Pid;
+
+/// The health of a service as observed by its own event loop
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServiceHealth {
+    Running,
+    /// The last envelope or poll notification failed to be handled with the given error
+    Degraded(String),
+    /// The service is closing its connections after being shut down
+    Draining,
+    Stopped
+}
+
+/// A snapshot of a registered service, as returned by `Node::service_info`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    pub pid: Pid,
+    /// The address the service is listening on, if it listens on one
+    pub addr: Option<String>,
+    pub connections: usize,
+    pub health: ServiceHealth
+}
+
+/// A snapshot of an open client connection, as returned in reply to `Msg::GetConnections`
+///
+/// This is meant for tracking down stuck clients in production.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    /// The id of the connection, as used in correlation ids
+    pub id: u64,
+    /// The address of the client. Clients connected over unix sockets don't have one.
+    pub peer_addr: Option<String>,
+    pub uptime_ms: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// True if the socket is full, and messages are waiting to be written to it
+    pub write_blocked: bool,
+    /// A description of the connection state, provided by `ConnectionHandler::summary`
+    pub summary: Option<String>
+}
@@ -0,0 +1,13 @@
+use metrics::{Metric, Metrics};
+
+metrics!(ServiceMetrics {
+    connections: i64,
+    accepted_connections: u64,
+    rejected_connections: u64,
+    closed_connections: u64,
+    handler_panics: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    msgs_received: u64,
+    msgs_sent: u64
+});
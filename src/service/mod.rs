@@ -1,13 +1,57 @@
 mod service;
 mod connection_handler;
 mod service_handler;
+mod rate_limit;
+mod metrics;
+mod pending_requests;
+mod tls;
+mod proxy_protocol;
 mod tcp_server_handler;
+mod tcp_client_handler;
+pub mod websocket;
+mod ws_server_handler;
+mod udp_server_handler;
+mod admin_handler;
+mod info;
+#[cfg(feature = "tokio-runtime")]
+mod async_connection_handler;
+#[cfg(feature = "tokio-runtime")]
+mod async_tcp_server_handler;
 
 
 pub use self::service::Service;
 pub use self::connection_handler::{
     ConnectionHandler,
-    ConnectionMsg
+    ConnectionMsg,
+    CloseReason
 };
 pub use self::service_handler::ServiceHandler;
-pub use self::tcp_server_handler::TcpServerHandler;
+pub use self::tls::ServiceTlsConfig;
+pub use self::proxy_protocol::{ProxyHeader, ProxyHeaderReader};
+pub use self::metrics::ServiceMetrics;
+pub use self::pending_requests::PendingRequests;
+pub use self::rate_limit::{
+    RateLimit,
+    RateLimiter,
+    RateLimitExceeded,
+    RateLimitAction
+};
+pub use self::tcp_server_handler::{TcpServerHandler, ConnectionLimit};
+pub use self::tcp_client_handler::TcpClientHandler;
+pub use self::websocket::WsMessage;
+pub use self::ws_server_handler::WsServerHandler;
+pub use self::udp_server_handler::{
+    UdpServerHandler,
+    DatagramHandler,
+    DatagramMsg
+};
+pub use self::admin_handler::AdminHandler;
+pub use self::info::{ServiceInfo, ServiceHealth, ConnectionInfo};
+#[cfg(feature = "tokio-runtime")]
+pub use self::async_connection_handler::{
+    AsyncConnectionHandler,
+    AsyncConnectionMsg,
+    HandlerFuture
+};
+#[cfg(feature = "tokio-runtime")]
+pub use self::async_tcp_server_handler::AsyncTcpServerHandler;
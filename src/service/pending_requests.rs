@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use time::{SteadyTime, Duration};
+use correlation_id::CorrelationId;
+use super::{ConnectionHandler, ConnectionMsg};
+
+/// Tracks the in-flight requests of a connection, and answers clients whose requests time out
+///
+/// A connection handler inserts each request it sends upstream, along with the reply to send to the
+/// client if it times out. Timed out requests are answered whenever the handler calls `expire`,
+/// which it should do in each of its callbacks. Since the service sends a `Msg::Timeout` for every
+/// request after its request timeout, calling `timeout` on receipt of one guarantees that no
+/// request outlives that.
+pub struct PendingRequests<M> {
+    requests: HashMap<CorrelationId, (SteadyTime, M)>
+}
+
+impl<M> PendingRequests<M> {
+    pub fn new() -> PendingRequests<M> {
+        PendingRequests {
+            requests: HashMap::new()
+        }
+    }
+
+    /// Track a request, and reply `timeout_reply` to the client if it isn't completed within
+    /// `timeout` ms
+    pub fn insert(&mut self, correlation_id: CorrelationId, timeout: usize, timeout_reply: M) {
+        let deadline = SteadyTime::now() + Duration::milliseconds(timeout as i64);
+        self.requests.insert(correlation_id, (deadline, timeout_reply));
+    }
+
+    /// Stop tracking a request that received its reply
+    ///
+    /// Returns false if the request isn't pending, in which case it already timed out and the
+    /// reply should be dropped.
+    pub fn complete(&mut self, correlation_id: &CorrelationId) -> bool {
+        self.requests.remove(correlation_id).is_some()
+    }
+
+    /// Answer a pending request with its timeout reply
+    pub fn timeout<C>(&mut self, correlation_id: &CorrelationId, output: &mut Vec<ConnectionMsg<C>>)
+        where C: ConnectionHandler<ClientMsg=M>
+    {
+        if let Some((_, reply)) = self.requests.remove(correlation_id) {
+            output.push(ConnectionMsg::Client(reply, correlation_id.clone()));
+        }
+    }
+
+    /// Answer all requests whose timeout passed with their timeout replies
+    pub fn expire<C>(&mut self, output: &mut Vec<ConnectionMsg<C>>)
+        where C: ConnectionHandler<ClientMsg=M>
+    {
+        let now = SteadyTime::now();
+        let expired: Vec<CorrelationId> = self.requests.iter()
+            .filter(|&(_, &(deadline, _))| deadline <= now)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect();
+        for correlation_id in expired {
+            self.timeout(&correlation_id, output);
+        }
+    }
+
+    /// The number of requests in flight
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+impl<M> Default for PendingRequests<M> {
+    fn default() -> PendingRequests<M> {
+        PendingRequests::new()
+    }
+}
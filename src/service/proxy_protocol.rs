@@ -0,0 +1,130 @@
+use std::io::{self, Read};
+use std::net::{SocketAddr, Ipv4Addr, Ipv6Addr};
+
+/// The first 12 bytes of every PROXY protocol v2 header
+pub const PROXY_V2_SIGNATURE: [u8; 12] =
+    [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+// The signature, version and command, address family and length
+const FIXED_HEADER_SIZE: usize = 16;
+
+const CMD_LOCAL: u8 = 0x0;
+const CMD_PROXY: u8 = 0x1;
+const AF_INET: u8 = 0x1;
+const AF_INET6: u8 = 0x2;
+
+/// The addresses of the original connection, as reported by a load balancer in a PROXY protocol
+/// header
+///
+/// Both are `None` for health checks the load balancer makes itself, and for address families
+/// other than IPv4 and IPv6.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>
+}
+
+/// Incrementally reads a PROXY protocol v2 header from a nonblocking socket
+///
+/// Only the bytes of the header are read, so whatever the client sends after it is left in the
+/// socket.
+#[derive(Debug, Default)]
+pub struct ProxyHeaderReader {
+    buf: Vec<u8>
+}
+
+impl ProxyHeaderReader {
+    pub fn new() -> ProxyHeaderReader {
+        ProxyHeaderReader {
+            buf: Vec::with_capacity(FIXED_HEADER_SIZE)
+        }
+    }
+
+    /// Read from `reader` until the header is complete or the read would block
+    ///
+    /// Returns `Ok(None)` if more data is needed. An error is returned if the data isn't a valid
+    /// v2 header, or the connection closes before the header is complete.
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> io::Result<Option<ProxyHeader>> {
+        loop {
+            let needed = self.size() - self.buf.len();
+            if needed == 0 {
+                return parse(&self.buf).map(Some);
+            }
+            let start = self.buf.len();
+            self.buf.resize(start + needed, 0);
+            let n = match reader.read(&mut self.buf[start..]) {
+                Ok(n) => n,
+                Err(e) => {
+                    self.buf.truncate(start);
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(None);
+                    }
+                    return Err(e);
+                }
+            };
+            self.buf.truncate(start + n);
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "Connection closed before the PROXY header was read"));
+            }
+            if self.buf.len() >= PROXY_V2_SIGNATURE.len() &&
+                self.buf[..PROXY_V2_SIGNATURE.len()] != PROXY_V2_SIGNATURE[..]
+            {
+                return Err(invalid("Missing PROXY protocol v2 signature"));
+            }
+        }
+    }
+
+    /// The size of the whole header, as far as it's known yet
+    fn size(&self) -> usize {
+        if self.buf.len() < FIXED_HEADER_SIZE {
+            return FIXED_HEADER_SIZE;
+        }
+        FIXED_HEADER_SIZE + ((self.buf[14] as usize) << 8 | self.buf[15] as usize)
+    }
+}
+
+fn parse(buf: &[u8]) -> io::Result<ProxyHeader> {
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0F;
+    if version != 2 {
+        return Err(invalid(&format!("Unsupported PROXY protocol version {}", version)));
+    }
+    let unknown = ProxyHeader {
+        source: None,
+        destination: None
+    };
+    match command {
+        CMD_LOCAL => return Ok(unknown),
+        CMD_PROXY => (),
+        _ => return Err(invalid(&format!("Unknown PROXY protocol command {}", command)))
+    }
+    let addrs = &buf[FIXED_HEADER_SIZE..];
+    let port = |i: usize| (addrs[i] as u16) << 8 | addrs[i + 1] as u16;
+    match buf[13] >> 4 {
+        AF_INET if addrs.len() >= 12 => {
+            let ip = |i: usize| Ipv4Addr::new(addrs[i], addrs[i + 1], addrs[i + 2], addrs[i + 3]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(ip(0).into(), port(8))),
+                destination: Some(SocketAddr::new(ip(4).into(), port(10)))
+            })
+        },
+        AF_INET6 if addrs.len() >= 36 => {
+            let ip = |i: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addrs[i..i + 16]);
+                Ipv6Addr::from(octets)
+            };
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(ip(0).into(), port(32))),
+                destination: Some(SocketAddr::new(ip(16).into(), port(34)))
+            })
+        },
+        AF_INET | AF_INET6 => Err(invalid("PROXY protocol addresses are truncated")),
+        _ => Ok(unknown)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -0,0 +1,68 @@
+use time::SteadyTime;
+use cluster::{Throttle, TokenBucket};
+
+/// Limits on the rate of messages and bytes received on a single client connection
+///
+/// Each limit allows bursts of up to one second worth of traffic.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RateLimit {
+    pub msgs_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>
+}
+
+/// The limit that a message received from a client exceeded
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RateLimitExceeded {
+    Msgs,
+    Bytes
+}
+
+/// What the service does with a message that exceeded the rate limit of its connection
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RateLimitAction {
+    /// Handle the message anyway
+    Allow,
+    /// Discard the message
+    Drop,
+    /// Discard the message and close the connection
+    Disconnect
+}
+
+/// Enforces a `RateLimit` for a single connection
+pub struct RateLimiter {
+    msgs: Option<TokenBucket>,
+    bytes: Option<TokenBucket>
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        let now = SteadyTime::now();
+        let bucket = |rate| TokenBucket::new(Throttle::new(rate, rate), now);
+        RateLimiter {
+            msgs: limit.msgs_per_sec.map(&bucket),
+            bytes: limit.bytes_per_sec.map(&bucket)
+        }
+    }
+
+    /// Account for a message of `bytes` bytes
+    ///
+    /// Returns the exceeded limit if the message must not be handled. As with peer throttling, a
+    /// message larger than the remaining bytes is let through, and the bucket goes into debt.
+    /// Tokens are only taken from either bucket if neither limit is exceeded.
+    pub fn check(&mut self, bytes: usize) -> Option<RateLimitExceeded> {
+        let now = SteadyTime::now();
+        if !self.msgs.as_mut().map_or(true, |bucket| bucket.has_tokens(now)) {
+            return Some(RateLimitExceeded::Msgs);
+        }
+        if !self.bytes.as_mut().map_or(true, |bucket| bucket.has_tokens(now)) {
+            return Some(RateLimitExceeded::Bytes);
+        }
+        if let Some(ref mut bucket) = self.msgs {
+            bucket.take(1, now);
+        }
+        if let Some(ref mut bucket) = self.bytes {
+            bucket.take(bytes, now);
+        }
+        None
+    }
+}
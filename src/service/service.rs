@@ -1,13 +1,18 @@
 use std::fmt::Debug;
+use time::{SteadyTime, Duration};
 use amy::{self, Poller, Registrar};
 use pid::Pid;
 use serde::{Serialize, Deserialize};
 use msg::Msg;
+use metrics::filter_metrics;
 use envelope::Envelope;
 use node::Node;
 use errors::*;
 use slog;
-use super::ServiceHandler;
+use super::{ServiceHandler, ServiceInfo, ServiceHealth};
+
+// The default time a service waits for its connections to drain on shutdown
+const DRAIN_TIMEOUT: usize = 5000; // ms
 
 /// A system service that operates on a single thread. A service is registered via its pid
 /// with the executor and can send and receive messages to processes as well as other services.
@@ -19,6 +24,8 @@ pub struct Service<T, H> {
     poller: Poller,
     registrar: Registrar,
     handler: H,
+    health: ServiceHealth,
+    drain_timeout: usize, // ms
     logger: slog::Logger
 }
 
@@ -32,10 +39,10 @@ impl<'de, T, H> Service<T, H>
         let poller = Poller::new().unwrap();
         let mut registrar = poller.get_registrar()?;
         let (tx, rx) = registrar.channel()?;
-        node.register_service(&pid, &tx)?;
+        node.register_typed_service(&pid, &tx, H::accepts)?;
         handler.init(&registrar, &node)?;
         let logger = node.logger.new(o!("component" => "service", "pid" => pid.to_string()));
-        Ok(Service {
+        let service = Service {
             pid: pid,
             tx: tx,
             rx: rx,
@@ -43,8 +50,28 @@ impl<'de, T, H> Service<T, H>
             poller: poller,
             registrar: registrar,
             handler: handler,
+            health: ServiceHealth::Running,
+            drain_timeout: DRAIN_TIMEOUT,
             logger: logger
-        })
+        };
+        service.update_info();
+        Ok(service)
+    }
+
+    /// Set how long the service waits for its connections to drain when shut down, before closing
+    /// them anyway
+    pub fn set_drain_timeout(&mut self, timeout: usize) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Publish the current state of the service so that it is visible via `Node::service_info`
+    fn update_info(&self) {
+        self.node.update_service_info(ServiceInfo {
+            pid: self.pid.clone(),
+            addr: self.handler.addr(),
+            connections: self.handler.connection_count(),
+            health: self.health.clone()
+        });
     }
 
     pub fn wait(&mut self) {
@@ -56,11 +83,20 @@ impl<'de, T, H> Service<T, H>
                         if let ErrorKind::Shutdown(_) = *e.kind() {
                             info!(self.logger, "Service shutting down";
                                   "pid" => self.pid.to_string());
+                            self.drain();
+                            self.health = ServiceHealth::Stopped;
+                            self.update_info();
+                            // The executor waits for every service to stop before shutting down
+                            // the rest of the node, and may already be gone
+                            let _ = self.node.unregister_service(&self.pid);
                             return;
                         }
                         error!(self.logger,
                                "Failed to handle envelope";
-                               "error" => e.to_string())
+                               "error" => e.to_string());
+                        self.health = ServiceHealth::Degraded(e.to_string());
+                    } else {
+                        self.health = ServiceHealth::Running;
                     }
                 } else {
                     if let Err(e) = self.handler.handle_notification(&self.node,
@@ -68,10 +104,51 @@ impl<'de, T, H> Service<T, H>
                                                                      &self.registrar) {
                         warn!(self.logger,
                                "Failed to handle poll notification";
-                               "error" => e.to_string())
+                               "error" => e.to_string());
+                        self.health = ServiceHealth::Degraded(e.to_string());
+                    }
+                }
+            }
+            self.update_info();
+        }
+    }
+
+    /// Let the handler close its connections gracefully, until it's drained or the drain timeout
+    /// passes
+    ///
+    /// Envelopes are still delivered to the handler, so replies to outstanding requests can make
+    /// it to their clients.
+    fn drain(&mut self) {
+        self.health = ServiceHealth::Draining;
+        self.update_info();
+        if let Err(e) = self.handler.drain(&self.node, &self.registrar) {
+            warn!(self.logger, "Failed to drain service"; "error" => e.to_string());
+            return;
+        }
+        let deadline = SteadyTime::now() + Duration::milliseconds(self.drain_timeout as i64);
+        while !self.handler.is_drained() {
+            let now = SteadyTime::now();
+            if now >= deadline {
+                warn!(self.logger, "Timed out draining service";
+                      "connections" => self.handler.connection_count());
+                return;
+            }
+            let timeout = (deadline - now).num_milliseconds() as usize;
+            for notification in self.poller.wait(timeout).unwrap() {
+                let result = if notification.id == self.rx.get_id() {
+                    self.handle_envelopes()
+                } else {
+                    self.handler.handle_notification(&self.node, notification, &self.registrar)
+                };
+                if let Err(e) = result {
+                    match *e.kind() {
+                        ErrorKind::Shutdown(_) => (),
+                        _ => warn!(self.logger, "Error while draining service";
+                                   "error" => e.to_string())
                     }
                 }
             }
+            self.update_info();
         }
     }
 
@@ -80,6 +157,23 @@ impl<'de, T, H> Service<T, H>
             if let Msg::Shutdown = envelope.msg {
                 return Err(ErrorKind::Shutdown(self.pid.clone()).into());
             }
+            if let Msg::GetMetrics(ref filter) = envelope.msg {
+                let data = filter_metrics(filter.as_ref(), self.handler.metrics());
+                let reply = Envelope::new(envelope.reply_address().clone(),
+                                          self.pid.clone(),
+                                          Msg::Metrics(data),
+                                          envelope.correlation_id.clone());
+                try!(self.node.send(reply));
+                continue;
+            }
+            if let Msg::GetConnections = envelope.msg {
+                let reply = Envelope::new(envelope.reply_address().clone(),
+                                          self.pid.clone(),
+                                          Msg::Connections(self.handler.connections()),
+                                          envelope.correlation_id);
+                try!(self.node.send(reply));
+                continue;
+            }
             try!(self.handler.handle_envelope(&self.node, envelope, &self.registrar));
         }
         Ok(())
@@ -1,5 +1,8 @@
 use amy::{Notification, Registrar};
+use metrics::Metric;
+use super::ConnectionInfo;
 use envelope::Envelope;
+use msg::Msg;
 use node::Node;
 use errors::*;
 
@@ -26,4 +29,47 @@ pub trait ServiceHandler<T> {
     /// Handle any envelopes addressed to the service's Pid. All handlers must implement
     /// this function.
     fn handle_envelope(&mut self, &Node<T>, Envelope<T>, &Registrar) -> Result<()>;
+
+    /// Return true if the handler knows how to handle the message.
+    ///
+    /// The filter is registered with the executor, which replies to rejected requests with
+    /// `CallError::Unsupported` instead of routing them to the service. All messages are accepted
+    /// by default.
+    fn accepts(_msg: &Msg<T>) -> bool where Self: Sized {
+        true
+    }
+
+    /// Start a graceful shutdown of the handler.
+    ///
+    /// The handler should stop accepting connections, let its connection handlers know, and close
+    /// connections once their pending writes are flushed. Handlers without connections don't need
+    /// to implement this function.
+    fn drain(&mut self, &Node<T>, &Registrar) -> Result<()> {
+        Ok(())
+    }
+
+    /// Return true once a draining handler closed all its connections, so the service can stop.
+    fn is_drained(&self) -> bool {
+        true
+    }
+
+    /// The address the service listens on, if any. This is reported by `Node::service_info`.
+    fn addr(&self) -> Option<String> {
+        None
+    }
+
+    /// The number of open client connections. This is reported by `Node::service_info`.
+    fn connection_count(&self) -> usize {
+        0
+    }
+
+    /// The metrics of the handler, returned by the service in reply to `Msg::GetMetrics`.
+    fn metrics(&self) -> Vec<(String, Metric)> {
+        Vec::new()
+    }
+
+    /// The open client connections, returned by the service in reply to `Msg::GetConnections`.
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        Vec::new()
+    }
 }
@@ -0,0 +1,430 @@
+use std::fmt::Debug;
+use serde;
+use amy::{Registrar, Notification, Event};
+use time::SteadyTime;
+use errors::*;
+use msg::Msg;
+use envelope::Envelope;
+use node::Node;
+use timer_wheel::TimerWheel;
+use pid::Pid;
+use metrics::{Metric, Metrics};
+use histogram::{Histogram, TimeUnit};
+use correlation_id::CorrelationId;
+use socket::{Stream, SocketOptions};
+use cluster::{Transport, ReconnectPolicy};
+use backoff::Retry;
+use serialize::Serialize;
+use super::{ServiceHandler, ConnectionHandler, ConnectionMsg, CloseReason};
+use super::connection_handler::{close_handler, isolate};
+use super::tcp_server_handler::{MeteredSocket, add_traffic, handle_connection_msgs};
+use super::metrics::ServiceMetrics;
+use super::info::ConnectionInfo;
+
+// The timer wheel expirations are accurate to within 1/TIMER_WHEEL_SLOTS of the timeout
+const TIMER_WHEEL_SLOTS: usize = 10;
+
+/// A service handler for an outgoing TCP connection
+///
+/// This is the client side of `TcpServerHandler`. It connects to `addr`, a TCP address or a
+/// `unix:` socket path, and drives a `ConnectionHandler` with the messages the server sends, so a
+/// node can talk to message brokers or upstream APIs with the same programming model as its own
+/// servers. A connection that fails or is lost is reconnected after the delays of the
+/// `ReconnectPolicy`.
+///
+/// A single connection handler lives as long as the service, so it can keep state, such as
+/// subscriptions, across reconnects. It's told about each connection with `on_connected` and
+/// `on_closed`, where `CloseReason::ClientClosed` means the server closed the connection. Envelopes
+/// are passed to the handler whether or not it's connected, but client messages it returns while
+/// disconnected are dropped. Connections aren't encrypted.
+pub struct TcpClientHandler<C, S>
+    where C: ConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize
+{
+    pid: Pid,
+    addr: String,
+    socket_options: SocketOptions,
+    reconnect: ReconnectPolicy,
+    retry: Retry,
+    handler: Option<C>, // Created in init()
+    serializer: S,
+    sock: Option<MeteredSocket>,
+    sock_id: usize,
+    connected: bool,
+    opened: SteadyTime,
+    reconnect_timer_id: Option<usize>,
+    draining: bool,
+    metrics: ServiceMetrics,
+    connection_durations: Histogram,
+    request_timeout: usize, // ms
+    request_timer_id: usize,
+    request_timer_wheel: TimerWheel<CorrelationId>,
+    output: Vec<ConnectionMsg<C>>
+}
+
+impl<'de, C, S> TcpClientHandler<C, S>
+    where C: ConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Create a new TcpClientHandler
+    ///
+    /// The connection to `addr` is opened when the service starts. As with `TcpServerHandler`,
+    /// every request with a CorrelationId is tracked with a timer, and the connection handler
+    /// receives a `Msg::Timeout` if it isn't answered within `request_timeout` ms.
+    pub fn new(pid: Pid,
+               addr: &str,
+               request_timeout: usize,
+               reconnect: ReconnectPolicy) -> TcpClientHandler<C, S>
+    {
+        TcpClientHandler {
+            pid: pid,
+            addr: addr.to_string(),
+            socket_options: SocketOptions::default(),
+            retry: Retry::new(reconnect.backoff.clone()),
+            reconnect: reconnect,
+            handler: None,
+            serializer: S::new(),
+            sock: None,
+            sock_id: 0,
+            connected: false,
+            opened: SteadyTime::now(),
+            reconnect_timer_id: None,
+            draining: false,
+            metrics: ServiceMetrics::new(),
+            connection_durations: Histogram::new(TimeUnit::Milliseconds),
+            request_timeout: request_timeout,
+            request_timer_id: 0, // Dummy timer id for now. Will be set in init()
+            request_timer_wheel: TimerWheel::new(TIMER_WHEEL_SLOTS + 1),
+            output: Vec::new()
+        }
+    }
+
+    /// Set the options applied to the TCP connection
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    /// Return true once the connection to the server is established
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Start connecting to the server, and schedule another attempt if that fails right away
+    fn connect(&mut self, registrar: &Registrar) -> Result<()> {
+        if self.draining {
+            return Ok(());
+        }
+        let result = self.open(registrar);
+        if result.is_err() {
+            try!(self.schedule_reconnect(registrar));
+        }
+        result
+    }
+
+    fn open(&mut self, registrar: &Registrar) -> Result<()> {
+        let sock = try!(Stream::connect(&self.addr)
+                        .chain_err(|| format!("Failed to connect to {}", self.addr)));
+        try!(self.socket_options.apply(&sock).chain_err(|| "Failed to set socket options"));
+        // The socket becomes writable once the connection is established
+        self.sock_id = try!(registrar.register(&sock, Event::Both)
+                            .chain_err(|| "Failed to register socket"));
+        self.sock = Some(MeteredSocket::new(Transport::Plain(sock)));
+        self.serializer = S::new();
+        Ok(())
+    }
+
+    /// Start a timer for the next connection attempt, unless the policy gave up
+    fn schedule_reconnect(&mut self, registrar: &Registrar) -> Result<()> {
+        if let Some(max_attempts) = self.reconnect.max_attempts {
+            if self.retry.attempts() >= max_attempts {
+                return Err(format!("Gave up connecting to {} after {} attempts",
+                                   self.addr,
+                                   max_attempts).into());
+            }
+        }
+        let delay = self.retry.next_delay();
+        self.reconnect_timer_id = Some(try!(registrar.set_timeout(delay)
+                                            .chain_err(|| "Failed to register reconnect timer")));
+        Ok(())
+    }
+
+    /// The connection attempt completed successfully
+    fn established(&mut self, node: &Node<C::Msg>) -> Result<()> {
+        self.connected = true;
+        self.opened = SteadyTime::now();
+        self.retry.reset();
+        self.metrics.accepted_connections += 1;
+        let peer_addr = self.sock.as_ref().and_then(|sock| sock.transport.peer_addr());
+        self.handler.as_mut().unwrap().on_connected(peer_addr);
+        self.send_output(node)
+    }
+
+    /// Close the socket, tell the handler if it was connected, and schedule a reconnect
+    fn disconnect(&mut self,
+                  reason: CloseReason,
+                  node: &Node<C::Msg>,
+                  registrar: &Registrar) -> Result<()>
+    {
+        let sock = match self.sock.take() {
+            Some(sock) => sock,
+            None => return Ok(())
+        };
+        let closed = if self.connected {
+            self.connected = false;
+            self.metrics.closed_connections += 1;
+            add_traffic(&mut self.metrics, &sock);
+            let duration = SteadyTime::now() - self.opened;
+            self.connection_durations.record(duration.num_milliseconds() as u64);
+            let handler = self.handler.as_mut().unwrap();
+            isolate(self.sock_id, || close_handler(handler, reason, node))
+        } else {
+            Ok(())
+        };
+        let _ = registrar.deregister(sock);
+        if let Err(ref e) = closed {
+            self.reset_handler(e, node);
+        }
+        if !self.draining {
+            try!(self.schedule_reconnect(registrar));
+        }
+        closed
+    }
+
+    /// Replace a handler that panicked, since its state can't be trusted anymore
+    fn reset_handler(&mut self, e: &Error, node: &Node<C::Msg>) {
+        if let ErrorKind::HandlerPanic(..) = *e.kind() {
+            self.output.clear();
+            self.metrics.handler_panics += 1;
+            self.handler = Some(C::new(self.pid.clone(), 0, node));
+        }
+    }
+
+    fn handle_socket_notification(&mut self,
+                                  notification: &Notification,
+                                  node: &Node<C::Msg>) -> Result<()>
+    {
+        if !self.connected {
+            if let Some(e) = try!(self.sock.as_ref().unwrap().transport.take_error()) {
+                return Err(e).chain_err(|| format!("Failed to connect to {}", self.addr));
+            }
+            if !notification.event.writable() {
+                return Ok(());
+            }
+            try!(self.established(node));
+        }
+
+        if notification.event.writable() {
+            // Notify the serializer that the socket is writable again
+            self.serializer.set_writable();
+            try!(self.serializer.write_msgs(self.sock.as_mut().unwrap(), None));
+        }
+
+        if notification.event.readable() {
+            loop {
+                let msg = {
+                    let sock = self.sock.as_mut().unwrap();
+                    match try!(self.serializer.read_msg(sock)) {
+                        Some(msg) => msg,
+                        None => break
+                    }
+                };
+                self.sock.as_mut().unwrap().msgs_in += 1;
+                self.handler.as_mut().unwrap().handle_network_msg(msg, &mut self.output);
+                try!(self.send_output(node));
+            }
+            if self.sock.as_ref().unwrap().eof {
+                return Err("Server closed the connection".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Route envelopes from the handler, and send client messages if connected
+    fn send_output(&mut self, node: &Node<C::Msg>) -> Result<()> {
+        if self.connected {
+            if let Some(ref mut sock) = self.sock {
+                return handle_connection_msgs(&mut self.request_timer_wheel,
+                                              &mut self.output,
+                                              &mut self.serializer,
+                                              sock,
+                                              node);
+            }
+        }
+        for m in self.output.drain(..) {
+            if let ConnectionMsg::Envelope(envelope) = m {
+                if let Some(ref correlation_id) = envelope.correlation_id {
+                    self.request_timer_wheel.insert(correlation_id.clone());
+                }
+                try!(node.send(envelope));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a callback of the handler, dropping the connection if it panics
+    fn run_handler<F>(&mut self,
+                      node: &Node<C::Msg>,
+                      registrar: &Registrar,
+                      f: F) -> Result<()>
+        where F: FnOnce(&mut TcpClientHandler<C, S>) -> Result<()>
+    {
+        let result = isolate(self.sock_id, || f(self));
+        if let Err(ref e) = result {
+            if let ErrorKind::HandlerPanic(..) = *e.kind() {
+                let _ = self.disconnect(CloseReason::Panicked, node, registrar);
+                self.reset_handler(e, node);
+            }
+        }
+        result
+    }
+
+    /// Handle request timer events and see if any requests have timed out.
+    fn request_tick(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        for correlation_id in self.request_timer_wheel.expire() {
+            try!(self.run_handler(node, registrar, |client| {
+                let envelope = Envelope::new(client.pid.clone(),
+                                             client.pid.clone(),
+                                             Msg::Timeout,
+                                             Some(correlation_id));
+                client.handler.as_mut().unwrap().handle_envelope(envelope, &mut client.output);
+                client.send_output(node)
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<'de, C, S> ServiceHandler<C::Msg> for TcpClientHandler<C, S>
+    where C: ConnectionHandler<ClientMsg=S::Msg>,
+          S: Serialize,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Create the connection handler, register the request timer and start connecting to the
+    /// server
+    fn init(&mut self,
+            registrar: &Registrar,
+            node: &Node<C::Msg>) -> Result<()>
+    {
+        self.handler = Some(C::new(self.pid.clone(), 0, node));
+        let req_timeout = self.request_timeout / TIMER_WHEEL_SLOTS;
+        self.request_timer_id = try!(registrar.set_interval(req_timeout)
+                                  .chain_err(|| "Failed to register request timer"));
+        // A failed attempt is retried later, so it doesn't prevent the service from starting
+        let _ = self.connect(registrar);
+        Ok(())
+    }
+
+    fn handle_notification(&mut self,
+                           node: &Node<C::Msg>,
+                           notification: Notification,
+                           registrar: &Registrar) -> Result<()>
+    {
+        if notification.id == self.request_timer_id {
+            return self.request_tick(node, registrar);
+        }
+
+        if self.reconnect_timer_id == Some(notification.id) {
+            self.reconnect_timer_id = None;
+            return self.connect(registrar);
+        }
+
+        if self.sock.is_none() || notification.id != self.sock_id {
+            return Ok(());
+        }
+
+        let result = isolate(self.sock_id, || {
+            self.handle_socket_notification(&notification, node)
+        });
+        match result {
+            Ok(()) => {
+                if self.draining && self.serializer.is_writable() {
+                    try!(self.disconnect(CloseReason::Drained, node, registrar));
+                }
+                Ok(())
+            },
+            Err(e) => {
+                let server_closed = self.sock.as_ref().map_or(false, |sock| sock.eof);
+                let reason = match *e.kind() {
+                    ErrorKind::HandlerPanic(..) => CloseReason::Panicked,
+                    _ if server_closed => CloseReason::ClientClosed,
+                    _ => CloseReason::Error
+                };
+                let _ = self.disconnect(reason, node, registrar);
+                self.reset_handler(&e, node);
+                Err(e)
+            }
+        }
+    }
+
+    /// Pass an envelope from a process or service to the connection handler
+    fn handle_envelope(&mut self,
+                       node: &Node<C::Msg>,
+                       envelope: Envelope<C::Msg>,
+                       registrar: &Registrar) -> Result<()>
+    {
+        self.run_handler(node, registrar, |client| {
+            client.handler.as_mut().unwrap().handle_envelope(envelope, &mut client.output);
+            client.send_output(node)
+        })
+    }
+
+    /// Let the handler say goodbye, and close the connection once it's flushed
+    fn drain(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        self.draining = true;
+        if let Some(id) = self.reconnect_timer_id.take() {
+            let _ = registrar.cancel_timeout(id);
+        }
+        if self.connected {
+            let result = self.run_handler(node, registrar, |client| {
+                client.handler.as_mut().unwrap().handle_drain(&mut client.output);
+                client.send_output(node)
+            });
+            if result.is_ok() && !self.serializer.is_writable() {
+                // Wait for the pending writes to be flushed
+                return Ok(());
+            }
+        }
+        self.disconnect(CloseReason::Drained, node, registrar)
+    }
+
+    fn is_drained(&self) -> bool {
+        self.sock.is_none()
+    }
+
+    fn addr(&self) -> Option<String> {
+        Some(self.addr.clone())
+    }
+
+    fn connection_count(&self) -> usize {
+        if self.connected { 1 } else { 0 }
+    }
+
+    fn metrics(&self) -> Vec<(String, Metric)> {
+        let mut metrics = self.metrics.clone();
+        metrics.connections = self.connection_count() as i64;
+        if let Some(ref sock) = self.sock {
+            add_traffic(&mut metrics, sock);
+        }
+        let mut data = metrics.data();
+        data.push(("connection_duration_ms".to_string(),
+                   Metric::Histogram(self.connection_durations.clone())));
+        data
+    }
+
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        let sock = match (self.connected, &self.sock) {
+            (true, &Some(ref sock)) => sock,
+            _ => return Vec::new()
+        };
+        vec![ConnectionInfo {
+            id: self.sock_id as u64,
+            peer_addr: sock.transport.peer_addr().map(|addr| addr.to_string()),
+            uptime_ms: (SteadyTime::now() - self.opened).num_milliseconds() as u64,
+            bytes_received: sock.bytes_in,
+            bytes_sent: sock.bytes_out,
+            write_blocked: !self.serializer.is_writable(),
+            summary: self.handler.as_ref().and_then(|handler| handler.summary())
+        }]
+    }
+}
@@ -1,22 +1,98 @@
-use std::net::{TcpListener, TcpStream};
 use std::collections::HashMap;
-use std::io;
+use std::sync::Arc;
+use std::io::{self, Read, Write};
 use std::fmt::Debug;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::net::SocketAddr;
 use serde;
 use amy::{Registrar, Notification, Event};
+use rustls::{ServerConfig, ServerSession};
+use time::SteadyTime;
 use errors::*;
 use msg::Msg;
 use envelope::Envelope;
 use node::Node;
 use timer_wheel::TimerWheel;
 use pid::Pid;
+use metrics::{Metric, Metrics};
+use histogram::{Histogram, TimeUnit};
 use correlation_id::CorrelationId;
+use socket::{Listener, Stream, SocketOptions};
+use cluster::Transport;
 use serialize::Serialize;
-use super::{ServiceHandler, ConnectionHandler, ConnectionMsg};
+use super::{ServiceHandler, ConnectionHandler, ConnectionMsg, CloseReason};
+use super::connection_handler::{close_handler, isolate};
+use super::rate_limit::{RateLimit, RateLimiter, RateLimitAction};
+use super::tls::ServiceTlsConfig;
+use super::proxy_protocol::ProxyHeaderReader;
+use super::metrics::ServiceMetrics;
+use super::info::ConnectionInfo;
 
 // The timer wheel expirations are accurate to within 1/TIMER_WHEEL_SLOTS of the timeout
 const TIMER_WHEEL_SLOTS: usize = 10;
 
+/// A client socket that keeps count of the traffic on it
+pub struct MeteredSocket {
+    pub transport: Transport,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub msgs_in: u64,
+    pub msgs_out: u64,
+    // Set once the client closed its end of the connection
+    pub eof: bool
+}
+
+impl MeteredSocket {
+    pub fn new(transport: Transport) -> MeteredSocket {
+        MeteredSocket {
+            transport: transport,
+            bytes_in: 0,
+            bytes_out: 0,
+            msgs_in: 0,
+            msgs_out: 0,
+            eof: false
+        }
+    }
+}
+
+impl Read for MeteredSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.transport.read(buf));
+        if n == 0 && !buf.is_empty() {
+            self.eof = true;
+        }
+        self.bytes_in += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MeteredSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.transport.write(buf));
+        self.bytes_out += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+impl AsRawFd for MeteredSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.transport.as_raw_fd()
+    }
+}
+
+/// Reads from a client socket without going through TLS
+struct RawSocket<'a>(&'a mut MeteredSocket);
+
+impl<'a> Read for RawSocket<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.transport.read_raw(buf)
+    }
+}
+
 struct Connection<C, S>
     where C: ConnectionHandler<ClientMsg=S::Msg>,
           S: Serialize
@@ -24,8 +100,14 @@ struct Connection<C, S>
     id: usize,
     handler: C,
     serializer: S,
-    sock: TcpStream,
-    timer_wheel_slot: usize
+    sock: MeteredSocket,
+    handshaking: bool,
+    peer_addr: Option<SocketAddr>,
+    // Set until the PROXY protocol header is read, if the service expects one
+    proxy_header: Option<ProxyHeaderReader>,
+    opened: SteadyTime,
+    timer_wheel_slot: usize,
+    rate_limiter: Option<RateLimiter>
 }
 
 impl<C, S> Connection<C, S>
@@ -34,28 +116,113 @@ impl<C, S> Connection<C, S>
 {
     pub fn new(id: usize,
                handler: C,
-               sock: TcpStream,
-               slot: usize) -> Connection<C, S>
+               sock: Transport,
+               peer_addr: Option<SocketAddr>,
+               slot: usize,
+               rate_limit: Option<RateLimit>) -> Connection<C, S>
     {
         Connection {
             id: id,
             handler: handler,
             serializer: S::new(),
-            sock: sock,
-            timer_wheel_slot: slot
+            handshaking: sock.is_tls(),
+            sock: MeteredSocket::new(sock),
+            peer_addr: peer_addr,
+            proxy_header: None,
+            opened: SteadyTime::now(),
+            timer_wheel_slot: slot,
+            rate_limiter: rate_limit.map(RateLimiter::new)
+        }
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            id: self.id as u64,
+            peer_addr: self.peer_addr.map(|addr| addr.to_string()),
+            uptime_ms: (SteadyTime::now() - self.opened).num_milliseconds() as u64,
+            bytes_received: self.sock.bytes_in,
+            bytes_sent: self.sock.bytes_out,
+            write_blocked: !self.serializer.is_writable() || self.sock.transport.wants_write(),
+            summary: self.handler.summary()
+        }
+    }
+
+    /// Read the PROXY protocol header that precedes anything else the client sends
+    ///
+    /// Once it's complete, the handler is told the client address reported by the load balancer.
+    /// Returns false while more of the header is needed.
+    fn read_proxy_header(&mut self) -> Result<bool> {
+        let header = match self.proxy_header {
+            Some(ref mut reader) => reader.read(&mut RawSocket(&mut self.sock)),
+            None => return Ok(true)
+        };
+        let header = match header {
+            Ok(Some(header)) => header,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    self.sock.eof = true;
+                }
+                return Err(e).chain_err(|| "Failed to read PROXY protocol header");
+            }
+        };
+        self.proxy_header = None;
+        if header.source.is_some() {
+            self.peer_addr = header.source;
         }
+        self.handler.on_connected(self.peer_addr);
+        Ok(true)
     }
+
+    /// Tell the handler about the negotiated protocol once the TLS handshake completes
+    fn check_handshake(&mut self) {
+        if self.handshaking && !self.sock.transport.is_handshaking() {
+            self.handshaking = false;
+            self.handler.handle_tls_handshake(self.sock.transport.alpn_protocol());
+        }
+    }
+}
+
+/// What a `TcpServerHandler` does with new clients once its connection limit is reached
+pub enum ConnectionLimit<M> {
+    /// Leave new clients in the listen backlog until an open connection closes
+    StopAccepting,
+
+    /// Accept new clients and close their connections immediately, after sending them the message,
+    /// if any
+    Reject(Option<M>)
 }
 
 /// A service handler for an async TCP server
+///
+/// The server can listen on a unix domain socket instead, by giving an address of the form
+/// `unix:/path/to/socket`. Clients on the same host then avoid the TCP stack, and access is limited
+/// to users allowed to write the socket file.
+///
+/// TCP connections are encrypted if TLS is configured with `set_tls`. Connections over unix
+/// sockets never are.
+///
+/// A panic in a callback of a connection handler only closes its connection, with
+/// `CloseReason::Panicked`. The service keeps running and logs the panic.
+///
+/// The traffic and connection counts of the service, and a histogram of connection durations in
+/// ms, are returned in reply to `Msg::GetMetrics`. Byte counts are of unencrypted data.
 pub struct TcpServerHandler<C, S>
     where C: ConnectionHandler<ClientMsg=S::Msg>,
           S: Serialize
 {
     pid: Pid,
-    listener: TcpListener,
+    listener: Listener,
     listener_id: usize,
+    socket_options: SocketOptions,
     connections: HashMap<usize, Connection<C, S>>,
+    max_connections: Option<(usize, ConnectionLimit<S::Msg>)>,
+    rate_limit: Option<RateLimit>,
+    tls: Option<Arc<ServerConfig>>,
+    proxy_protocol: bool,
+    draining: bool,
+    metrics: ServiceMetrics,
+    connection_durations: Histogram,
     connection_timeout: Option<usize>, // ms
     connection_timer_id: Option<usize>,
     connection_timer_wheel: Option<TimerWheel<usize>>,
@@ -74,7 +241,7 @@ impl<'de, C, S> TcpServerHandler<C, S>
 {
     /// Create a new TcpServerHandler
     ///
-    /// Bind to `addr` and close a connection that hasn't received a message in `connection_timeout`
+    /// Bind to `addr`, a TCP address or a `unix:` socket path, and close a connection that hasn't received a message in `connection_timeout`
     /// ms. Note that the connection timeout is optional.
     ///
     /// Every request with a CorrelationId is also tracked with a timer. This `request_timeout` is
@@ -83,18 +250,51 @@ impl<'de, C, S> TcpServerHandler<C, S>
                addr: &str,
                request_timeout: usize,
                connection_timeout: Option<usize>) -> TcpServerHandler<C, S>
+    {
+        let listener = Listener::bind(addr).unwrap();
+        TcpServerHandler::with_listener(pid, listener, request_timeout, connection_timeout)
+    }
+
+    /// Create a new TcpServerHandler that shares its TCP address with other handlers
+    ///
+    /// The listener is bound with `SO_REUSEPORT`, so several instances of a service, each run by
+    /// its own `Service` on its own thread, can accept connections on the same port. The kernel
+    /// spreads new connections among them, which scales accept throughput across cores. Each
+    /// instance needs its own pid, and keeps its own connections and metrics.
+    ///
+    /// An error is returned if the address can't be bound, or is a `unix:` socket path.
+    pub fn new_shared(pid: Pid,
+                      addr: &str,
+                      request_timeout: usize,
+                      connection_timeout: Option<usize>) -> Result<TcpServerHandler<C, S>>
+    {
+        let listener = try!(Listener::bind_shared(addr)
+                            .chain_err(|| format!("Failed to bind shared listener to {}", addr)));
+        Ok(TcpServerHandler::with_listener(pid, listener, request_timeout, connection_timeout))
+    }
+
+    fn with_listener(pid: Pid,
+                     listener: Listener,
+                     request_timeout: usize,
+                     connection_timeout: Option<usize>) -> TcpServerHandler<C, S>
     {
         let mut connection_timer_wheel = None;
         if connection_timeout.is_some() {
             connection_timer_wheel = Some(TimerWheel::new(TIMER_WHEEL_SLOTS + 1));
         }
-        let listener = TcpListener::bind(addr).unwrap();
-        listener.set_nonblocking(true).unwrap();
         TcpServerHandler {
             pid: pid,
             listener: listener,
             listener_id: 0,
+            socket_options: SocketOptions::default(),
             connections: HashMap::new(),
+            max_connections: None,
+            rate_limit: None,
+            tls: None,
+            proxy_protocol: false,
+            draining: false,
+            metrics: ServiceMetrics::new(),
+            connection_durations: Histogram::new(TimeUnit::Milliseconds),
             connection_timeout: connection_timeout,
             connection_timer_id: None,
             connection_timer_wheel: connection_timer_wheel,
@@ -105,11 +305,68 @@ impl<'de, C, S> TcpServerHandler<C, S>
         }
     }
 
-    fn accept_connections(&mut self, registrar: &Registrar) -> Result<()> {
+    /// Set the options applied to accepted TCP connections
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    /// Limit the number of open client connections to `max`
+    ///
+    /// Without a limit, an aggressive set of clients can exhaust the file descriptors and memory
+    /// of the node.
+    pub fn set_max_connections(&mut self, max: usize, limit: ConnectionLimit<S::Msg>) {
+        self.max_connections = Some((max, limit));
+    }
+
+    /// Limit the rate of messages and bytes received on each connection
+    ///
+    /// Messages over the limit aren't passed to `handle_network_msg`. The connection handler decides
+    /// what happens to them in `handle_rate_limited`.
+    pub fn set_rate_limit(&mut self, limit: RateLimit) {
+        self.rate_limit = Some(limit);
+    }
+
+    /// Encrypt client connections with TLS
+    ///
+    /// An error is returned if the certificate or key can't be loaded.
+    pub fn set_tls(&mut self, config: ServiceTlsConfig) -> Result<()> {
+        self.tls = Some(try!(config.load()));
+        Ok(())
+    }
+
+    /// Expect every TCP connection to start with a PROXY protocol v2 header
+    ///
+    /// Behind an L4 load balancer, such as HAProxy or a cloud network load balancer, connections
+    /// come from the address of the load balancer. The header it sends carries the address of the
+    /// real client, which is passed to `ConnectionHandler::on_connected` once the header is read.
+    /// Connections that don't start with a valid header are closed. The header precedes the TLS
+    /// handshake, and isn't expected on unix socket connections.
+    pub fn set_proxy_protocol(&mut self, enabled: bool) {
+        self.proxy_protocol = enabled;
+    }
+
+    fn at_capacity(&self) -> bool {
+        self.max_connections.as_ref().map_or(false, |&(max, _)| self.connections.len() >= max)
+    }
+
+    fn accept_connections(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        if self.draining {
+            return Ok(());
+        }
         loop {
+            let at_capacity = self.at_capacity();
+            if at_capacity {
+                if let Some((_, ConnectionLimit::StopAccepting)) = self.max_connections {
+                    return Ok(());
+                }
+            }
             match self.listener.accept() {
-                Ok((socket, _)) => {
-                    try!(self.new_connection(socket, registrar));
+                Ok(socket) => {
+                    if at_capacity {
+                        self.reject_connection(socket);
+                    } else {
+                        try!(self.new_connection(socket, node, registrar));
+                    }
                 },
                 Err(e) => {
                     if e.kind() == io::ErrorKind::WouldBlock {
@@ -121,69 +378,185 @@ impl<'de, C, S> TcpServerHandler<C, S>
         }
     }
 
+    /// Send the rejection message, if any, and close the connection
+    ///
+    /// The message is only sent if it fits in the socket buffer, since the connection isn't kept
+    /// around to write the rest later. TLS clients are disconnected without a message, since it
+    /// can't be sent before the handshake.
+    fn reject_connection(&mut self, mut sock: Stream) {
+        self.metrics.rejected_connections += 1;
+        if let (&Stream::Tcp(_), &Some(_)) = (&sock, &self.tls) {
+            return;
+        }
+        if let Some((_, ConnectionLimit::Reject(Some(ref msg)))) = self.max_connections {
+            if sock.set_nonblocking(true).is_ok() {
+                let _ = S::new().write_msgs(&mut sock, Some(msg));
+            }
+        }
+    }
+
     /// Setup a new Connection object
     ///
     /// Make the socket nonblocking, register it for reads, and establish the connection timeout.
-    fn new_connection(&mut self, sock: TcpStream, registrar: &Registrar) -> Result<()> {
+    /// TLS connections are registered for writes as well, since handshake and application data
+    /// may be left buffered in the session.
+    fn new_connection(&mut self,
+                      sock: Stream,
+                      node: &Node<C::Msg>,
+                      registrar: &Registrar) -> Result<()>
+    {
         try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
-        let id = try!(registrar.register(&sock, Event::Read)
-                      .chain_err(|| "Failed to register new socket for reading"));
-        let handler = C::new(self.pid.clone(), id as u64);
+        try!(self.socket_options.apply(&sock).chain_err(|| "Failed to set socket options"));
+        let peer_addr = sock.peer_addr();
+        // The handler learns the client address once the PROXY protocol header is read
+        let proxied = self.proxy_protocol && !sock.is_unix();
+        let sock = match (sock, &self.tls) {
+            (Stream::Tcp(sock), &Some(ref tls)) => {
+                Transport::Tls(sock, Box::new(ServerSession::new(tls)))
+            },
+            (sock, _) => Transport::Plain(sock)
+        };
+        let event = if sock.is_tls() { Event::Both } else { Event::Read };
+        let id = try!(registrar.register(&sock, event)
+                      .chain_err(|| "Failed to register new socket"));
+        let mut handler = C::new(self.pid.clone(), id as u64, node);
+        let connected = isolate(id, || {
+            if !proxied {
+                handler.on_connected(peer_addr);
+            }
+            Ok(())
+        });
+        if let Err(e) = connected {
+            self.metrics.handler_panics += 1;
+            let _ = registrar.deregister(sock);
+            return Err(e);
+        }
         let slot = self.connection_timer_wheel.as_mut().map_or(0, |mut tw| tw.insert(id));
-        let connection = Connection::new(id, handler, sock, slot);
+        let mut connection = Connection::new(id, handler, sock, peer_addr, slot, self.rate_limit);
+        if proxied {
+            connection.proxy_header = Some(ProxyHeaderReader::new());
+        }
         self.connections.insert(id, connection);
+        self.metrics.accepted_connections += 1;
         Ok(())
     }
 
+    /// Remove a connection, tell its handler why it was closed, and record its traffic
+    fn close_connection(&mut self,
+                        id: usize,
+                        reason: CloseReason,
+                        node: &Node<C::Msg>,
+                        registrar: &Registrar) -> Result<()>
+    {
+        let mut connection = match self.connections.remove(&id) {
+            Some(connection) => connection,
+            None => return Ok(())
+        };
+        self.metrics.closed_connections += 1;
+        add_traffic(&mut self.metrics, &connection.sock);
+        let duration = SteadyTime::now() - connection.opened;
+        self.connection_durations.record(duration.num_milliseconds() as u64);
+        let closed = {
+            let handler = &mut connection.handler;
+            isolate(id, || close_handler(handler, reason, node))
+        };
+        let _ = registrar.deregister(connection.sock);
+        closed
+    }
+
+    /// Close the connection if the error is a panic of its handler
+    ///
+    /// The service keeps running, and the error is returned so that the service logs it.
+    fn close_if_panicked(&mut self,
+                         id: usize,
+                         result: Result<()>,
+                         node: &Node<C::Msg>,
+                         registrar: &Registrar) -> Result<()>
+    {
+        if let Err(ref e) = result {
+            if let ErrorKind::HandlerPanic(..) = *e.kind() {
+                // Drop any output from before the panic
+                self.output.clear();
+                self.metrics.handler_panics += 1;
+                let _ = self.close_connection(id, CloseReason::Panicked, node, registrar);
+            }
+        }
+        result
+    }
+
+    /// Returns true if the connection must be closed because it exceeded its rate limit
     fn handle_connection_notification(&mut self,
                                       notification: &Notification,
-                                      node: &Node<C::Msg>) -> Result<()>
+                                      node: &Node<C::Msg>) -> Result<bool>
     {
         if let Some(connection) = self.connections.get_mut(&notification.id) {
             if notification.event.writable() {
+                // Write out any data left buffered in the TLS session first
+                try!(connection.sock.transport.flush());
                 // Notify the serializer that the socket is writable again
                 connection.serializer.set_writable();
                 try!(connection.serializer.write_msgs(&mut connection.sock, None));
             }
 
             if notification.event.readable() {
-                try!(handle_readable(connection,
-                                     &mut self.request_timer_wheel,
-                                     node,
-                                     &mut self.output));
+                if !try!(connection.read_proxy_header()) {
+                    return Ok(false);
+                }
+                if try!(handle_readable(connection,
+                                        &mut self.request_timer_wheel,
+                                        node,
+                                        &mut self.output)) {
+                    return Ok(true);
+                }
                 update_connection_timeout(connection, &mut self.connection_timer_wheel);
             }
         }
+        Ok(false)
+    }
+
+    /// While draining, close the connections that have no pending writes left
+    fn close_drained(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        if !self.draining {
+            return Ok(());
+        }
+        let drained: Vec<usize> = self.connections.values()
+            .filter(|c| c.serializer.is_writable() && !c.sock.transport.wants_write())
+            .map(|c| c.id)
+            .collect();
+        for id in drained {
+            try!(self.close_connection(id, CloseReason::Drained, node, registrar));
+        }
         Ok(())
     }
 
-    fn connection_tick(&mut self, registrar: &Registrar) {
+    /// Close connections that haven't received a message within the connection timeout
+    fn connection_tick(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
         for id in self.connection_timer_wheel.as_mut().unwrap().expire() {
-            if let Some(connection) = self.connections.remove(&id) {
-                let _ = registrar.deregister(connection.sock);
-                // TODO: Log connection timeout
-            }
+            try!(self.close_connection(id, CloseReason::IdleTimeout, node, registrar));
         }
+        Ok(())
     }
 
     /// Handle request timer events and see if any requests have timed out.
-    fn request_tick(&mut self, node: &Node<C::Msg>) -> Result<()>{
+    fn request_tick(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()>{
         for correlation_id in self.request_timer_wheel.expire() {
-            let conn_id = correlation_id.connection.as_ref().unwrap();
-            if let Some(mut connection) = self.connections.get_mut(&(*conn_id as usize)) {
-                let envelope = Envelope {
-                    from: self.pid.clone(),
-                    to: self.pid.clone(),
-                    msg: Msg::Timeout,
-                    correlation_id: Some(correlation_id.clone())
-                };
-                connection.handler.handle_envelope(envelope, &mut self.output);
-                try!(handle_connection_msgs(&mut self.request_timer_wheel,
-                                            &mut self.output,
-                                            &mut connection.serializer,
-                                            &mut connection.sock,
-                                            node));
-            }
+            let conn_id = *correlation_id.connection.as_ref().unwrap() as usize;
+            let result = isolate(conn_id, || {
+                if let Some(mut connection) = self.connections.get_mut(&conn_id) {
+                    let envelope = Envelope::new(self.pid.clone(),
+                                                 self.pid.clone(),
+                                                 Msg::Timeout,
+                                                 Some(correlation_id.clone()));
+                    connection.handler.handle_envelope(envelope, &mut self.output);
+                    try!(handle_connection_msgs(&mut self.request_timer_wheel,
+                                                &mut self.output,
+                                                &mut connection.serializer,
+                                                &mut connection.sock,
+                                                node));
+                }
+                Ok(())
+            });
+            try!(self.close_if_panicked(conn_id, result, node, registrar));
         }
         Ok(())
     }
@@ -222,36 +595,109 @@ impl<'de, C, S> ServiceHandler<C::Msg> for TcpServerHandler<C, S>
                            registrar: &Registrar) -> Result<()>
     {
         if notification.id == self.listener_id {
-            return self.accept_connections(registrar);
+            return self.accept_connections(node, registrar);
         }
 
         if notification.id == self.request_timer_id {
-            return self.request_tick(&node);
+            return self.request_tick(&node, registrar);
         }
 
         if self.connection_timer_id.is_some()
             && notification.id == self.connection_timer_id.unwrap()
         {
-            self.connection_tick(&registrar);
-            return Ok(());
+            try!(self.connection_tick(&node, &registrar));
+            // Closed connections may have made room for clients waiting in the listen backlog
+            return self.accept_connections(node, registrar);
+        }
+
+        let result = isolate(notification.id, || {
+            self.handle_connection_notification(&notification, &node)
+        });
+        match result {
+            Ok(false) => self.close_drained(node, registrar),
+            Ok(true) => {
+                try!(self.close_connection(notification.id,
+                                           CloseReason::RateLimited,
+                                           node,
+                                           registrar));
+                self.accept_connections(node, registrar)
+            },
+            Err(e) => {
+                let client_closed = self.connections.get(&notification.id)
+                    .map_or(false, |connection| connection.sock.eof);
+                let reason = match *e.kind() {
+                    ErrorKind::HandlerPanic(..) => {
+                        self.output.clear();
+                        self.metrics.handler_panics += 1;
+                        CloseReason::Panicked
+                    },
+                    _ if client_closed => CloseReason::ClientClosed,
+                    _ => CloseReason::Error
+                };
+                let _ = self.close_connection(notification.id, reason, node, registrar);
+                let _ = self.accept_connections(node, registrar);
+                let errmsg = e.to_string();
+                Err(e).chain_err(|| format!("{}: id {}", errmsg, notification.id))
+            }
+        }
+
+    }
+
+    /// Stop accepting connections, and close each connection once the messages its handler
+    /// returns from `handle_drain` are flushed
+    fn drain(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        self.draining = true;
+        for connection in self.connections.values_mut() {
+            let (request_timer_wheel, output) = (&mut self.request_timer_wheel, &mut self.output);
+            // Connections that fail to write are closed below, since nothing is left to flush
+            let result = isolate(connection.id, || {
+                connection.handler.handle_drain(output);
+                handle_connection_msgs(request_timer_wheel,
+                                       output,
+                                       &mut connection.serializer,
+                                       &mut connection.sock,
+                                       node)
+            });
+            if result.is_err() {
+                output.clear();
+            }
         }
+        self.close_drained(node, registrar)
+    }
+
+    fn is_drained(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.listener.local_addr().ok()
+    }
+
+    fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
 
-        if let Err(e) = self.handle_connection_notification(&notification, &node) {
-            // Unwrap is correct here since the above call only fails if the connection exists
-            let connection = self.connections.remove(&notification.id).unwrap();
-            let _ = registrar.deregister(connection.sock);
-            let errmsg = e.to_string();
-            return Err(e).chain_err(|| format!("{}: id {}", errmsg, notification.id));
+    fn metrics(&self) -> Vec<(String, Metric)> {
+        let mut metrics = self.metrics.clone();
+        metrics.connections = self.connections.len() as i64;
+        for connection in self.connections.values() {
+            add_traffic(&mut metrics, &connection.sock);
         }
-        Ok (())
+        let mut data = metrics.data();
+        data.push(("connection_duration_ms".to_string(),
+                   Metric::Histogram(self.connection_durations.clone())));
+        data
+    }
 
+    fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections.values().map(|connection| connection.info()).collect()
     }
 
     /// Handle an envelope from a process or service
     fn handle_envelope(&mut self,
                        node: &Node<C::Msg>,
                        envelope: Envelope<C::Msg>,
-                       _registrar: &Registrar) -> Result<()>
+                       registrar: &Registrar) -> Result<()>
     {
         if envelope.correlation_id.is_none() {
             return Err(format!("No correlation id for envelope {:?}", envelope).into());
@@ -259,29 +705,59 @@ impl<'de, C, S> ServiceHandler<C::Msg> for TcpServerHandler<C, S>
         // Don't bother cancelling request timers... Just ignore the timeouts in the connection if
         // the request has already received its reply
         let conn_id = envelope.correlation_id.as_ref().unwrap().connection.as_ref().cloned().unwrap();
-        if let Some(mut connection) = self.connections.get_mut(&(conn_id as usize)) {
-            connection.handler.handle_envelope(envelope, &mut self.output);
-            try!(handle_connection_msgs(&mut self.request_timer_wheel,
-                                        &mut self.output,
-                                        &mut connection.serializer,
-                                        &mut connection.sock,
-                                        node));
-
-        }
-        Ok(())
+        let conn_id = conn_id as usize;
+        let result = isolate(conn_id, || {
+            if let Some(mut connection) = self.connections.get_mut(&conn_id) {
+                connection.handler.handle_envelope(envelope, &mut self.output);
+                try!(handle_connection_msgs(&mut self.request_timer_wheel,
+                                            &mut self.output,
+                                            &mut connection.serializer,
+                                            &mut connection.sock,
+                                            node));
+            }
+            Ok(())
+        });
+        self.close_if_panicked(conn_id, result, node, registrar)
     }
 }
 
+pub fn add_traffic(metrics: &mut ServiceMetrics, sock: &MeteredSocket) {
+    metrics.bytes_received += sock.bytes_in;
+    metrics.bytes_sent += sock.bytes_out;
+    metrics.msgs_received += sock.msgs_in;
+    metrics.msgs_sent += sock.msgs_out;
+}
+
 /// Handle any readable notifications.
+///
+/// Returns true if the connection handler asked to disconnect a client over its rate limit.
 fn handle_readable<'de, C, S>(connection: &mut Connection<C, S>,
                       request_timer_wheel: &mut TimerWheel<CorrelationId>,
                       node: &Node<C::Msg>,
-                      output: &mut Vec<ConnectionMsg<C>>) -> Result<()>
+                      output: &mut Vec<ConnectionMsg<C>>) -> Result<bool>
     where C: ConnectionHandler<ClientMsg=S::Msg>,
           S: Serialize,
           C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
 {
-    while let Some(msg) = try!(connection.serializer.read_msg(&mut connection.sock)) {
+    loop {
+        // Charge the bytes read for this message to the rate limit of the connection
+        let bytes_in = connection.sock.bytes_in;
+        let msg = try!(connection.serializer.read_msg(&mut connection.sock));
+        connection.check_handshake();
+        let msg = match msg {
+            Some(msg) => msg,
+            None => return Ok(false)
+        };
+        connection.sock.msgs_in += 1;
+        let bytes = (connection.sock.bytes_in - bytes_in) as usize;
+        let exceeded = connection.rate_limiter.as_mut().and_then(|limiter| limiter.check(bytes));
+        if let Some(exceeded) = exceeded {
+            match connection.handler.handle_rate_limited(exceeded) {
+                RateLimitAction::Allow => (),
+                RateLimitAction::Drop => continue,
+                RateLimitAction::Disconnect => return Ok(true)
+            }
+        }
         connection.handler.handle_network_msg(msg, output);
         try!(handle_connection_msgs(request_timer_wheel,
                                     output,
@@ -289,7 +765,6 @@ fn handle_readable<'de, C, S>(connection: &mut Connection<C, S>,
                                     &mut connection.sock,
                                     node));
     }
-    Ok(())
 }
 
 /// A new message has been received on a connection. Reset the timer.
@@ -306,11 +781,13 @@ fn update_connection_timeout<C, S>(connection: &mut Connection<C, S>,
 
 /// Send client replies and route envelopes
 ///
-/// For any envelopes with correlation ids, record them in the request timer wheel.
-fn handle_connection_msgs<'de, C, S>(request_timer_wheel: &mut TimerWheel<CorrelationId>,
+/// For any envelopes with correlation ids, record them in the request timer wheel. Returns an
+/// `ErrorKind::PeerSaturated` error if an envelope is addressed to a node whose outbound queue is
+/// full.
+pub fn handle_connection_msgs<'de, C, S>(request_timer_wheel: &mut TimerWheel<CorrelationId>,
                              msgs: &mut Vec<ConnectionMsg<C>>,
                              serializer: &mut S,
-                             sock: &mut TcpStream,
+                             sock: &mut MeteredSocket,
                              node: &Node<C::Msg>) -> Result<()>
     where C: ConnectionHandler<ClientMsg=S::Msg>,
           S: Serialize,
@@ -322,13 +799,14 @@ fn handle_connection_msgs<'de, C, S>(request_timer_wheel: &mut TimerWheel<Correl
                 if envelope.correlation_id.is_some() {
                     request_timer_wheel.insert(envelope.correlation_id.as_ref().unwrap().clone());
                 }
-                node.send(envelope).unwrap();
+                try!(node.send(envelope));
             },
             ConnectionMsg::Client(client_msg, _) => {
                 // Respond to the client
                 try!(serializer.write_msgs(sock, Some(&client_msg))
                      .chain_err(|| format!("Failed to write client msg: {:?}",
                                            client_msg)));
+                sock.msgs_out += 1;
             }
         }
     }
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use rustls::ServerConfig;
+use cluster::{load_certs, load_key};
+use errors::*;
+
+/// Paths to the PEM encoded certificate and key a service presents to its clients
+///
+/// With `client_ca_path` set, clients must present a certificate signed by that CA. The
+/// `alpn_protocols` are offered to clients in order of preference, and the one negotiated is passed
+/// to the connection handler once the handshake completes.
+#[derive(Debug, Clone)]
+pub struct ServiceTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub client_ca_path: Option<String>,
+    pub alpn_protocols: Vec<String>
+}
+
+impl ServiceTlsConfig {
+    pub fn new(cert_path: &str, key_path: &str) -> ServiceTlsConfig {
+        ServiceTlsConfig {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            client_ca_path: None,
+            alpn_protocols: Vec::new()
+        }
+    }
+
+    /// Require clients to present a certificate signed by the CA at `path`
+    pub fn client_ca(mut self, path: &str) -> ServiceTlsConfig {
+        self.client_ca_path = Some(path.to_string());
+        self
+    }
+
+    /// Negotiate one of `protocols` with clients via ALPN
+    pub fn alpn(mut self, protocols: &[&str]) -> ServiceTlsConfig {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Read and parse the certificates and key
+    pub fn load(&self) -> Result<Arc<ServerConfig>> {
+        let certs = try!(load_certs(&self.cert_path));
+        let key = try!(load_key(&self.key_path));
+        let mut config = ServerConfig::new();
+        config.set_single_cert(certs, key);
+        if let Some(ref path) = self.client_ca_path {
+            let roots = try!(load_certs(path));
+            config.set_client_auth_roots(roots, true);
+        }
+        if !self.alpn_protocols.is_empty() {
+            config.set_protocols(&self.alpn_protocols);
+        }
+        Ok(Arc::new(config))
+    }
+}
@@ -0,0 +1,134 @@
+use std::io;
+use std::net::{UdpSocket, SocketAddr};
+use std::fmt::Debug;
+use serde;
+use amy::{Registrar, Notification, Event};
+use errors::*;
+use envelope::Envelope;
+use node::Node;
+use pid::Pid;
+use super::ServiceHandler;
+
+// The largest possible UDP payload
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// Implement this to handle the datagrams received by a `UdpServerHandler`
+///
+/// There are no connections over UDP, so a single handler receives every datagram along with the
+/// address it came from.
+pub trait DatagramHandler: Sized {
+    type Msg;
+
+    fn new(pid: Pid) -> Self;
+    fn handle_envelope(&mut self, Envelope<Self::Msg>, &mut Vec<DatagramMsg<Self>>);
+    fn handle_datagram(&mut self, &[u8], SocketAddr, &mut Vec<DatagramMsg<Self>>);
+}
+
+/// Messages returned from the callbacks of a `DatagramHandler`
+///
+/// Envelopes are sent to processes, and datagrams to the given address.
+pub enum DatagramMsg<D: DatagramHandler> {
+    Envelope(Envelope<D::Msg>),
+    Datagram(Vec<u8>, SocketAddr)
+}
+
+/// A service handler for a UDP socket
+///
+/// Outgoing datagrams that can't be sent because the socket buffer is full are dropped, like any
+/// other datagram lost in the network.
+pub struct UdpServerHandler<D: DatagramHandler> {
+    socket: UdpSocket,
+    socket_id: usize,
+    handler: D,
+    output: Vec<DatagramMsg<D>>,
+    buf: Vec<u8>
+}
+
+impl<'de, D> UdpServerHandler<D>
+    where D: DatagramHandler,
+          D::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Create a new UdpServerHandler bound to `addr`
+    pub fn new(pid: Pid, addr: &str) -> UdpServerHandler<D> {
+        let socket = UdpSocket::bind(addr).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        UdpServerHandler {
+            socket: socket,
+            socket_id: 0,
+            handler: D::new(pid),
+            output: Vec::new(),
+            buf: vec![0; MAX_DATAGRAM_SIZE]
+        }
+    }
+
+    /// Read datagrams until the socket would block
+    fn receive(&mut self, node: &Node<D::Msg>) -> Result<()> {
+        loop {
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((len, from)) => {
+                    self.handler.handle_datagram(&self.buf[..len], from, &mut self.output);
+                    try!(self.handle_output(node));
+                },
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(());
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// Send datagrams and route envelopes
+    fn handle_output(&mut self, node: &Node<D::Msg>) -> Result<()> {
+        for m in self.output.drain(..) {
+            match m {
+                DatagramMsg::Envelope(envelope) => try!(node.send(envelope)),
+                DatagramMsg::Datagram(data, to) => {
+                    if let Err(e) = self.socket.send_to(&data, to) {
+                        if e.kind() != io::ErrorKind::WouldBlock {
+                            return Err(e).chain_err(|| format!("Failed to send datagram to {}", to));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, D> ServiceHandler<D::Msg> for UdpServerHandler<D>
+    where D: DatagramHandler,
+          D::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    fn init(&mut self, registrar: &Registrar, _node: &Node<D::Msg>) -> Result<()> {
+        self.socket_id = try!(registrar.register(&self.socket, Event::Read)
+                              .chain_err(|| "Failed to register UDP socket"));
+        Ok(())
+    }
+
+    fn handle_notification(&mut self,
+                           node: &Node<D::Msg>,
+                           notification: Notification,
+                           _registrar: &Registrar) -> Result<()>
+    {
+        if notification.id == self.socket_id {
+            return self.receive(node);
+        }
+        Ok(())
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.socket.local_addr().ok().map(|addr| addr.to_string())
+    }
+
+    /// Handle an envelope from a process or service
+    fn handle_envelope(&mut self,
+                       node: &Node<D::Msg>,
+                       envelope: Envelope<D::Msg>,
+                       _registrar: &Registrar) -> Result<()>
+    {
+        self.handler.handle_envelope(envelope, &mut self.output);
+        self.handle_output(node)
+    }
+}
@@ -0,0 +1,173 @@
+use std::str;
+use sha1::Sha1;
+use base64;
+use errors::*;
+
+// Appended to the key of the client to compute the accept header, as defined in RFC 6455
+const ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest upgrade request accepted from a client
+pub const MAX_HEADER_SIZE: usize = 8192;
+
+pub const CONTINUATION: u8 = 0x0;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xA;
+
+/// A complete message received from or sent to a WebSocket client
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>)
+}
+
+/// A single decoded WebSocket frame
+#[derive(Debug)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>
+}
+
+impl Frame {
+    pub fn is_control(&self) -> bool {
+        self.opcode & 0x8 != 0
+    }
+}
+
+/// Parse the HTTP upgrade request at the start of `buf`
+///
+/// Returns `Ok(None)` if the request isn't complete yet. Otherwise returns the `101 Switching
+/// Protocols` response to send, along with the length of the request.
+pub fn parse_upgrade(buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 4,
+        None => {
+            if buf.len() > MAX_HEADER_SIZE {
+                return Err("WebSocket upgrade request too large".into());
+            }
+            return Ok(None);
+        }
+    };
+    let request = try!(str::from_utf8(&buf[..end])
+                       .chain_err(|| "WebSocket upgrade request is not valid UTF-8"));
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    if !request_line.starts_with("GET ") || !request_line.ends_with(" HTTP/1.1") {
+        return Err(format!("Invalid WebSocket upgrade request: {}", request_line).into());
+    }
+
+    let (mut upgrade, mut connection, mut version, mut key) = (false, false, false, None);
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim()),
+            _ => continue
+        };
+        match &name[..] {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "connection" => {
+                connection = value.split(',').any(|v| v.trim().eq_ignore_ascii_case("upgrade"))
+            },
+            "sec-websocket-version" => version = value == "13",
+            "sec-websocket-key" => key = Some(value.to_string()),
+            _ => ()
+        }
+    }
+    let key = match key {
+        Some(ref key) if upgrade && connection && version => key,
+        _ => return Err("Missing or unsupported WebSocket upgrade headers".into())
+    };
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\n\
+                            Upgrade: websocket\r\n\
+                            Connection: Upgrade\r\n\
+                            Sec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+    Ok(Some((response.into_bytes(), end)))
+}
+
+/// Compute the `Sec-WebSocket-Accept` header for the `Sec-WebSocket-Key` of a client
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// Decode a single frame from the start of `buf`
+///
+/// Returns `Ok(None)` if the frame isn't complete yet, otherwise the frame and its length. Frames
+/// from clients must be masked, as required by RFC 6455.
+pub fn decode_frame(buf: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0F;
+    if buf[0] & 0x70 != 0 {
+        return Err("WebSocket frame uses reserved bits without an extension".into());
+    }
+    if buf[1] & 0x80 == 0 {
+        return Err("WebSocket frame from client is not masked".into());
+    }
+    let (len, mut pos) = match buf[1] & 0x7F {
+        126 => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            (((buf[2] as usize) << 8) | buf[3] as usize, 4)
+        },
+        127 => {
+            if buf.len() < 10 {
+                return Ok(None);
+            }
+            let len = buf[2..10].iter().fold(0u64, |len, &b| (len << 8) | b as u64);
+            (len as usize, 10)
+        },
+        len => (len as usize, 2)
+    };
+    if len > max_size {
+        return Err(format!("WebSocket frame of {} bytes is too large", len).into());
+    }
+    if buf.len() < pos + 4 + len {
+        return Ok(None);
+    }
+    let mut mask = [0; 4];
+    mask.copy_from_slice(&buf[pos..pos + 4]);
+    pos += 4;
+    let payload = buf[pos..pos + len].iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+    let frame = Frame {
+        fin: fin,
+        opcode: opcode,
+        payload: payload
+    };
+    if frame.is_control() && (!frame.fin || len > 125) {
+        return Err("WebSocket control frames must be unfragmented and short".into());
+    }
+    Ok(Some((frame, pos + len)))
+}
+
+/// Encode an unfragmented, unmasked frame, as sent by servers
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= 0xFFFF {
+        frame.push(126);
+        frame.push((payload.len() >> 8) as u8);
+        frame.push(payload.len() as u8);
+    } else {
+        frame.push(127);
+        let len = payload.len() as u64;
+        for i in (0..8).rev() {
+            frame.push((len >> (i * 8)) as u8);
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
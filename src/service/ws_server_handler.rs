@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::fmt::Debug;
+use std::mem;
+use serde;
+use amy::{Registrar, Notification, Event};
+use errors::*;
+use msg::Msg;
+use envelope::Envelope;
+use node::Node;
+use timer_wheel::TimerWheel;
+use pid::Pid;
+use correlation_id::CorrelationId;
+use socket::{Listener, Stream, SocketOptions};
+use super::{ServiceHandler, ConnectionHandler, ConnectionMsg, CloseReason};
+use super::connection_handler::close_handler;
+use super::websocket::{self, WsMessage, Frame};
+
+// The timer wheel expirations are accurate to within 1/TIMER_WHEEL_SLOTS of the timeout
+const TIMER_WHEEL_SLOTS: usize = 10;
+
+// The largest message accepted from a client, including all of its fragments
+const MAX_MESSAGE_SIZE: usize = 64*1024*1024; // 64 MB
+
+// The amount of data read from a socket at once
+const READ_SIZE: usize = 16*1024; // 16 KB
+
+struct Connection<C: ConnectionHandler<ClientMsg=WsMessage>> {
+    id: usize,
+    handler: C,
+    sock: Stream,
+    // False until the HTTP upgrade request has been answered
+    upgraded: bool,
+    read_buf: Vec<u8>,
+    // Data that couldn't be written to the socket yet
+    write_buf: Vec<u8>,
+    writable: bool,
+    // The opcode and payload received so far of a fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    // Set once a close frame was exchanged. The connection is dropped once `write_buf` is flushed.
+    closing: bool,
+    // Set once the client closed its end of the connection
+    eof: bool,
+    timer_wheel_slot: usize
+}
+
+impl<C: ConnectionHandler<ClientMsg=WsMessage>> Connection<C> {
+    pub fn new(id: usize, handler: C, sock: Stream, slot: usize) -> Connection<C> {
+        Connection {
+            id: id,
+            handler: handler,
+            sock: sock,
+            upgraded: false,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            writable: true,
+            fragments: None,
+            closing: false,
+            eof: false,
+            timer_wheel_slot: slot
+        }
+    }
+
+    /// Read all available data. Returns an error if the client closed the connection.
+    fn read(&mut self) -> Result<()> {
+        let mut buf = [0; READ_SIZE];
+        loop {
+            match self.sock.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Err("WebSocket connection closed by client".into());
+                },
+                Ok(n) => self.read_buf.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into())
+            }
+        }
+    }
+
+    /// Queue data and write as much as the socket accepts
+    fn write(&mut self, data: &[u8], registrar: &Registrar) -> Result<()> {
+        self.write_buf.extend_from_slice(data);
+        if self.writable {
+            try!(self.flush(registrar));
+        }
+        Ok(())
+    }
+
+    /// Write queued data until the socket would block, and only ask to be notified when the socket
+    /// becomes writable while there is data left
+    fn flush(&mut self, registrar: &Registrar) -> Result<()> {
+        while !self.write_buf.is_empty() {
+            match self.sock.write(&self.write_buf) {
+                Ok(0) => return Err("WebSocket connection closed by client".into()),
+                Ok(n) => {
+                    self.write_buf.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.writable {
+                        self.writable = false;
+                        try!(registrar.reregister(self.id, &self.sock, Event::Both)
+                             .chain_err(|| "Failed to register socket for writing"));
+                    }
+                    return Ok(());
+                },
+                Err(e) => return Err(e.into())
+            }
+        }
+        if !self.writable {
+            self.writable = true;
+            try!(registrar.reregister(self.id, &self.sock, Event::Read)
+                 .chain_err(|| "Failed to register socket for reading"));
+        }
+        Ok(())
+    }
+
+    /// Handle a frame and return the complete message it finished, if any
+    fn handle_frame(&mut self, frame: Frame, registrar: &Registrar) -> Result<Option<WsMessage>> {
+        match frame.opcode {
+            websocket::PING => {
+                let pong = websocket::encode_frame(websocket::PONG, &frame.payload);
+                try!(self.write(&pong, registrar));
+                return Ok(None);
+            },
+            websocket::PONG => return Ok(None),
+            websocket::CLOSE => {
+                // Echo the status code of the client, if any, to complete the closing handshake
+                let status = &frame.payload[..frame.payload.len().min(2)];
+                try!(self.write(&websocket::encode_frame(websocket::CLOSE, status), registrar));
+                self.closing = true;
+                return Ok(None);
+            },
+            websocket::CONTINUATION => {
+                match self.fragments {
+                    Some((_, ref mut data)) => data.extend_from_slice(&frame.payload),
+                    None => return Err("WebSocket continuation frame without a message".into())
+                }
+            },
+            websocket::TEXT | websocket::BINARY => {
+                if self.fragments.is_some() {
+                    return Err("WebSocket message started before the previous one ended".into());
+                }
+                self.fragments = Some((frame.opcode, frame.payload));
+            },
+            opcode => return Err(format!("Unknown WebSocket opcode {}", opcode).into())
+        }
+        if self.fragments.as_ref().map_or(0, |&(_, ref data)| data.len()) > MAX_MESSAGE_SIZE {
+            return Err("WebSocket message too large".into());
+        }
+        if !frame.fin {
+            return Ok(None);
+        }
+        let (opcode, data) = self.fragments.take().unwrap();
+        if opcode == websocket::TEXT {
+            let text = try!(String::from_utf8(data)
+                            .chain_err(|| "WebSocket text message is not valid UTF-8"));
+            return Ok(Some(WsMessage::Text(text)));
+        }
+        Ok(Some(WsMessage::Binary(data)))
+    }
+}
+
+/// A service handler for a WebSocket server, as specified in RFC 6455
+///
+/// Clients connect with an HTTP upgrade request on any path. Every complete text or binary message
+/// is passed to `ConnectionHandler::handle_network_msg`, and client messages returned by the
+/// handler are sent back as a single frame. Pings are answered automatically. Extensions and
+/// subprotocols aren't supported. TLS isn't either, so browsers must connect with `ws://`, or
+/// through a proxy that terminates TLS.
+pub struct WsServerHandler<C: ConnectionHandler<ClientMsg=WsMessage>> {
+    pid: Pid,
+    listener: Listener,
+    listener_id: usize,
+    socket_options: SocketOptions,
+    connections: HashMap<usize, Connection<C>>,
+    connection_timeout: Option<usize>, // ms
+    connection_timer_id: Option<usize>,
+    connection_timer_wheel: Option<TimerWheel<usize>>,
+    request_timeout: usize, // ms
+    request_timer_id: usize,
+    request_timer_wheel: TimerWheel<CorrelationId>,
+    output: Vec<ConnectionMsg<C>>
+}
+
+impl<'de, C> WsServerHandler<C>
+    where C: ConnectionHandler<ClientMsg=WsMessage>,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Create a new WsServerHandler
+    ///
+    /// The address and timeouts mean the same as for `TcpServerHandler::new`.
+    pub fn new(pid: Pid,
+               addr: &str,
+               request_timeout: usize,
+               connection_timeout: Option<usize>) -> WsServerHandler<C>
+    {
+        let mut connection_timer_wheel = None;
+        if connection_timeout.is_some() {
+            connection_timer_wheel = Some(TimerWheel::new(TIMER_WHEEL_SLOTS + 1));
+        }
+        let listener = Listener::bind(addr).unwrap();
+        WsServerHandler {
+            pid: pid,
+            listener: listener,
+            listener_id: 0,
+            socket_options: SocketOptions::default(),
+            connections: HashMap::new(),
+            connection_timeout: connection_timeout,
+            connection_timer_id: None,
+            connection_timer_wheel: connection_timer_wheel,
+            request_timeout: request_timeout,
+            request_timer_id: 0,
+            request_timer_wheel: TimerWheel::new(TIMER_WHEEL_SLOTS + 1),
+            output: Vec::new()
+        }
+    }
+
+    /// Set the options applied to accepted TCP connections
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    fn accept_connections(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok(socket) => {
+                    try!(self.new_connection(socket, node, registrar));
+                },
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        return Ok(())
+                    }
+                    return Err(e.into())
+                }
+            }
+        }
+    }
+
+    fn new_connection(&mut self,
+                      sock: Stream,
+                      node: &Node<C::Msg>,
+                      registrar: &Registrar) -> Result<()>
+    {
+        try!(sock.set_nonblocking(true).chain_err(|| "Failed to make socket nonblocking"));
+        try!(self.socket_options.apply(&sock).chain_err(|| "Failed to set socket options"));
+        let id = try!(registrar.register(&sock, Event::Read)
+                      .chain_err(|| "Failed to register new socket for reading"));
+        let mut handler = C::new(self.pid.clone(), id as u64, node);
+        handler.on_connected(sock.peer_addr());
+        let slot = self.connection_timer_wheel.as_mut().map_or(0, |tw| tw.insert(id));
+        self.connections.insert(id, Connection::new(id, handler, sock, slot));
+        Ok(())
+    }
+
+    fn handle_connection_notification(&mut self,
+                                      notification: &Notification,
+                                      node: &Node<C::Msg>,
+                                      registrar: &Registrar) -> Result<()>
+    {
+        if let Some(connection) = self.connections.get_mut(&notification.id) {
+            if notification.event.writable() {
+                try!(connection.flush(registrar));
+            }
+
+            if notification.event.readable() {
+                try!(connection.read());
+                try!(handle_readable(connection,
+                                     &mut self.request_timer_wheel,
+                                     node,
+                                     &mut self.output,
+                                     registrar));
+                if let Some(ref mut timer_wheel) = self.connection_timer_wheel {
+                    timer_wheel.remove(&connection.id, connection.timer_wheel_slot);
+                    connection.timer_wheel_slot = timer_wheel.insert(connection.id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop connections that finished the closing handshake
+    fn remove_closed(&mut self,
+                     id: usize,
+                     node: &Node<C::Msg>,
+                     registrar: &Registrar) -> Result<()>
+    {
+        let closed = self.connections.get(&id)
+            .map_or(false, |connection| connection.closing && connection.write_buf.is_empty());
+        if closed {
+            let mut connection = self.connections.remove(&id).unwrap();
+            let closed = close_handler(&mut connection.handler, CloseReason::ClientClosed, node);
+            let _ = registrar.deregister(connection.sock);
+            try!(closed);
+        }
+        Ok(())
+    }
+
+    /// Close connections that haven't received a message within the connection timeout
+    fn connection_tick(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        for id in self.connection_timer_wheel.as_mut().unwrap().expire() {
+            if let Some(mut connection) = self.connections.remove(&id) {
+                let closed = close_handler(&mut connection.handler, CloseReason::IdleTimeout, node);
+                let _ = registrar.deregister(connection.sock);
+                try!(closed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle request timer events and see if any requests have timed out.
+    fn request_tick(&mut self, node: &Node<C::Msg>, registrar: &Registrar) -> Result<()> {
+        for correlation_id in self.request_timer_wheel.expire() {
+            let conn_id = correlation_id.connection.as_ref().cloned().unwrap();
+            if let Some(connection) = self.connections.get_mut(&(conn_id as usize)) {
+                let envelope = Envelope::new(self.pid.clone(),
+                                             self.pid.clone(),
+                                             Msg::Timeout,
+                                             Some(correlation_id.clone()));
+                connection.handler.handle_envelope(envelope, &mut self.output);
+                try!(handle_connection_msgs(&mut self.request_timer_wheel,
+                                            &mut self.output,
+                                            connection,
+                                            node,
+                                            registrar));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, C> ServiceHandler<C::Msg> for WsServerHandler<C>
+    where C: ConnectionHandler<ClientMsg=WsMessage>,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    /// Register timers and the listen socket
+    fn init(&mut self,
+            registrar: &Registrar,
+            _node: &Node<C::Msg>) -> Result<()>
+    {
+        self.listener_id = try!(registrar.register(&self.listener, Event::Read)
+                                .chain_err(|| "Failed to register listener"));
+
+        let req_timeout = self.request_timeout / TIMER_WHEEL_SLOTS;
+        self.request_timer_id = try!(registrar.set_interval(req_timeout)
+                                  .chain_err(|| "Failed to register request timer"));
+
+        if let Some(timeout) = self.connection_timeout {
+            self.connection_timer_id = Some(try!(registrar.set_interval(timeout / TIMER_WHEEL_SLOTS)
+                              .chain_err(|| "Failed to register connection timer")));
+        }
+        Ok(())
+    }
+
+    fn handle_notification(&mut self,
+                           node: &Node<C::Msg>,
+                           notification: Notification,
+                           registrar: &Registrar) -> Result<()>
+    {
+        if notification.id == self.listener_id {
+            return self.accept_connections(node, registrar);
+        }
+
+        if notification.id == self.request_timer_id {
+            return self.request_tick(node, registrar);
+        }
+
+        if self.connection_timer_id == Some(notification.id) {
+            return self.connection_tick(node, registrar);
+        }
+
+        if let Err(e) = self.handle_connection_notification(&notification, node, registrar) {
+            if let Some(mut connection) = self.connections.remove(&notification.id) {
+                let reason = if connection.eof { CloseReason::ClientClosed } else { CloseReason::Error };
+                let _ = close_handler(&mut connection.handler, reason, node);
+                let _ = registrar.deregister(connection.sock);
+            }
+            let errmsg = e.to_string();
+            return Err(e).chain_err(|| format!("{}: id {}", errmsg, notification.id));
+        }
+        self.remove_closed(notification.id, node, registrar)
+    }
+
+    fn addr(&self) -> Option<String> {
+        self.listener.local_addr().ok()
+    }
+
+    fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Handle an envelope from a process or service
+    fn handle_envelope(&mut self,
+                       node: &Node<C::Msg>,
+                       envelope: Envelope<C::Msg>,
+                       registrar: &Registrar) -> Result<()>
+    {
+        let conn_id = match envelope.correlation_id.as_ref().and_then(|c| c.connection) {
+            Some(conn_id) => conn_id as usize,
+            None => return Err(format!("No connection id for envelope {:?}", envelope).into())
+        };
+        if let Some(connection) = self.connections.get_mut(&conn_id) {
+            connection.handler.handle_envelope(envelope, &mut self.output);
+            try!(handle_connection_msgs(&mut self.request_timer_wheel,
+                                        &mut self.output,
+                                        connection,
+                                        node,
+                                        registrar));
+        }
+        Ok(())
+    }
+}
+
+/// Answer the upgrade request if it hasn't been yet, then handle all complete frames
+fn handle_readable<'de, C>(connection: &mut Connection<C>,
+                           request_timer_wheel: &mut TimerWheel<CorrelationId>,
+                           node: &Node<C::Msg>,
+                           output: &mut Vec<ConnectionMsg<C>>,
+                           registrar: &Registrar) -> Result<()>
+    where C: ConnectionHandler<ClientMsg=WsMessage>,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    let mut pos = 0;
+    if !connection.upgraded {
+        match try!(websocket::parse_upgrade(&connection.read_buf)) {
+            Some((response, len)) => {
+                try!(connection.write(&response, registrar));
+                connection.upgraded = true;
+                pos = len;
+            },
+            None => return Ok(())
+        }
+    }
+    let buf = mem::replace(&mut connection.read_buf, Vec::new());
+    while !connection.closing {
+        let (frame, len) = match try!(websocket::decode_frame(&buf[pos..], MAX_MESSAGE_SIZE)) {
+            Some(decoded) => decoded,
+            None => break
+        };
+        pos += len;
+        if let Some(msg) = try!(connection.handle_frame(frame, registrar)) {
+            connection.handler.handle_network_msg(msg, output);
+            try!(handle_connection_msgs(request_timer_wheel, output, connection, node, registrar));
+        }
+    }
+    connection.read_buf = buf[pos..].to_vec();
+    Ok(())
+}
+
+/// Send client replies and route envelopes
+///
+/// For any envelopes with correlation ids, record them in the request timer wheel.
+fn handle_connection_msgs<'de, C>(request_timer_wheel: &mut TimerWheel<CorrelationId>,
+                                  msgs: &mut Vec<ConnectionMsg<C>>,
+                                  connection: &mut Connection<C>,
+                                  node: &Node<C::Msg>,
+                                  registrar: &Registrar) -> Result<()>
+    where C: ConnectionHandler<ClientMsg=WsMessage>,
+          C::Msg: serde::Serialize + serde::Deserialize<'de> + Clone + Debug
+{
+    for m in msgs.drain(..) {
+        match m {
+            ConnectionMsg::Envelope(envelope) => {
+                if let Some(ref correlation_id) = envelope.correlation_id {
+                    request_timer_wheel.insert(correlation_id.clone());
+                }
+                try!(node.send(envelope));
+            },
+            ConnectionMsg::Client(WsMessage::Text(text), _) => {
+                let frame = websocket::encode_frame(websocket::TEXT, text.as_bytes());
+                try!(connection.write(&frame, registrar));
+            },
+            ConnectionMsg::Client(WsMessage::Binary(data), _) => {
+                let frame = websocket::encode_frame(websocket::BINARY, &data);
+                try!(connection.write(&frame, registrar));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use pid::Pid;
+use executor::ExecutorMsg;
+use errors::*;
+
+/// A lightweight notification from a service, or any other thread, to a local process
+///
+/// Signals carry no data. Notifying only bumps a shared counter, and a message is sent to the
+/// executor only when no earlier notification is still pending. The process receives a single
+/// `Msg::Signal(count)` with the number of notifications since its last delivery, no matter how
+/// many arrived in between. This makes signals suitable for hot paths, such as telling a process
+/// that a connection was accepted, where sending an envelope per event would be wasteful.
+///
+/// Signals are created with `Node::signal`.
+pub struct Signal<T> {
+    pid: Pid,
+    pending: Arc<AtomicUsize>,
+    executor_tx: Sender<ExecutorMsg<T>>
+}
+
+impl<T> Signal<T> {
+    pub fn new(pid: Pid, executor_tx: Sender<ExecutorMsg<T>>) -> Signal<T> {
+        Signal {
+            pid: pid,
+            pending: Arc::new(AtomicUsize::new(0)),
+            executor_tx: executor_tx
+        }
+    }
+
+    /// The process notified by this signal
+    pub fn pid(&self) -> &Pid {
+        &self.pid
+    }
+
+    /// Notify the process
+    pub fn notify(&self) -> Result<()> {
+        if self.pending.fetch_add(1, Ordering::AcqRel) != 0 {
+            // The executor hasn't delivered the previous notification yet
+            return Ok(());
+        }
+        if let Err(_) = self.executor_tx.send(ExecutorMsg::Signal(self.pid.clone(),
+                                                                  self.pending.clone())) {
+            return Err(ErrorKind::SendError(format!("ExecutorMsg::Signal({}, ..)", self.pid),
+                                            Some(self.pid.clone())).into());
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Signal<T> {
+    fn clone(&self) -> Signal<T> {
+        Signal {
+            pid: self.pid.clone(),
+            pending: self.pending.clone(),
+            executor_tx: self.executor_tx.clone()
+        }
+    }
+}
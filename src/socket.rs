@@ -0,0 +1,305 @@
+use std::io::{self, Read, Write};
+use std::fs;
+use std::fmt::Display;
+use std::net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use net2::{TcpBuilder, TcpStreamExt};
+use net2::unix::UnixTcpBuilderExt;
+use libc::EINPROGRESS;
+
+/// Addresses starting with this prefix are paths of unix domain sockets rather than TCP addresses
+pub const UNIX_PREFIX: &'static str = "unix:";
+
+// The same backlog std uses for `TcpListener::bind`
+const LISTEN_BACKLOG: i32 = 128;
+
+/// Return the socket path if `addr` is a unix domain socket address
+pub fn unix_path(addr: &str) -> Option<&str> {
+    if addr.starts_with(UNIX_PREFIX) {
+        Some(&addr[UNIX_PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+/// Join a host and port into an address, bracketing IPv6 literals
+pub fn host_port<P: Display>(host: &str, port: P) -> String {
+    if host.contains(":") && !host.starts_with("[") {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Resolve a TCP address to the first socket address it maps to
+pub fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    try!(addr.to_socket_addrs()).next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Other, format!("{} resolves to no addresses", addr))
+    })
+}
+
+/// Options applied to TCP sockets when they are created or accepted
+///
+/// Unset options keep the operating system defaults. None of them apply to unix domain sockets.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm, so small messages aren't delayed waiting for more data
+    pub nodelay: bool,
+
+    /// The size of the kernel send buffer in bytes
+    pub send_buffer_size: Option<usize>,
+
+    /// The size of the kernel receive buffer in bytes
+    pub recv_buffer_size: Option<usize>,
+
+    /// Enable TCP keepalive, probing an idle connection after this many ms
+    pub keepalive: Option<u32>,
+
+    /// Give up on outgoing connections that aren't established within this many ms. Only used by
+    /// the cluster server, which checks once a second.
+    pub connect_timeout: usize
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            keepalive: None,
+            connect_timeout: 5000
+        }
+    }
+}
+
+impl SocketOptions {
+    pub fn apply(&self, sock: &Stream) -> io::Result<()> {
+        let sock = match *sock {
+            Stream::Tcp(ref sock) => sock,
+            Stream::Unix(_) => return Ok(())
+        };
+        try!(sock.set_nodelay(self.nodelay));
+        if let Some(size) = self.send_buffer_size {
+            try!(sock.set_send_buffer_size(size));
+        }
+        if let Some(size) = self.recv_buffer_size {
+            try!(sock.set_recv_buffer_size(size));
+        }
+        sock.set_keepalive_ms(self.keepalive)
+    }
+}
+
+/// A nonblocking listener on either a TCP address or a unix domain socket
+///
+/// Access to a unix domain socket is controlled by the permissions of the socket file and its
+/// directory, so only local users allowed to write the file can connect.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener)
+}
+
+impl Listener {
+    /// Bind to `addr`, which is a TCP address or a path prefixed with `unix:`
+    ///
+    /// A socket file left behind by a previous run is removed first. A socket file that a running
+    /// server still accepts connections on is left alone, and binding fails.
+    pub fn bind(addr: &str) -> io::Result<Listener> {
+        let listener = match unix_path(addr) {
+            Some(path) => {
+                try!(remove_stale_socket(path));
+                Listener::Unix(try!(UnixListener::bind(path)))
+            },
+            None => Listener::Tcp(try!(bind_tcp(addr)))
+        };
+        try!(listener.set_nonblocking(true));
+        Ok(listener)
+    }
+
+    /// Bind to a TCP address with `SO_REUSEPORT` set
+    ///
+    /// Any number of listeners bound this way can share the same address, and the kernel spreads
+    /// incoming connections among them. Unix domain socket addresses can't be shared.
+    pub fn bind_shared(addr: &str) -> io::Result<Listener> {
+        if unix_path(addr).is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} can't be shared between listeners", addr)));
+        }
+        let listener = Listener::Tcp(try!(bind_tcp_with(&try!(resolve(addr)), true)));
+        try!(listener.set_nonblocking(true));
+        Ok(listener)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(ref listener) => listener.set_nonblocking(nonblocking)
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.accept().map(|(sock, _)| Stream::Tcp(sock)),
+            Listener::Unix(ref listener) => listener.accept().map(|(sock, _)| Stream::Unix(sock))
+        }
+    }
+
+    /// Return the address in the same form it was bound with
+    pub fn local_addr(&self) -> io::Result<String> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.local_addr().map(|addr| addr.to_string()),
+            Listener::Unix(ref listener) => {
+                let addr = try!(listener.local_addr());
+                let path = try!(addr.as_pathname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Other, "unnamed unix socket")
+                }));
+                Ok(format!("{}{}", UNIX_PREFIX, path.display()))
+            }
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Listener::Tcp(ref listener) => listener.as_raw_fd(),
+            Listener::Unix(ref listener) => listener.as_raw_fd()
+        }
+    }
+}
+
+/// Bind a TCP listener
+///
+/// A listener on an IPv6 address is dual-stack, so it also accepts IPv4 connections as IPv4-mapped
+/// addresses. Binding `[::]:port` listens on every address of both stacks.
+fn bind_tcp(addr: &str) -> io::Result<TcpListener> {
+    let addr = try!(resolve(addr));
+    if addr.is_ipv4() {
+        return TcpListener::bind(addr);
+    }
+    bind_tcp_with(&addr, false)
+}
+
+fn bind_tcp_with(addr: &SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let builder = if addr.is_ipv4() {
+        try!(TcpBuilder::new_v4())
+    } else {
+        let builder = try!(TcpBuilder::new_v6());
+        try!(builder.only_v6(false));
+        builder
+    };
+    try!(builder.reuse_address(true));
+    if reuse_port {
+        try!(builder.reuse_port(true));
+    }
+    try!(builder.bind(addr));
+    builder.listen(LISTEN_BACKLOG)
+}
+
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                return Ok(());
+            }
+            fs::remove_file(path)
+        },
+        _ => Ok(())
+    }
+}
+
+/// A connected TCP or unix domain socket
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream)
+}
+
+impl Stream {
+    /// Start a nonblocking connection to `addr`, which is a TCP address or a path prefixed with
+    /// `unix:`
+    ///
+    /// Connecting to a unix domain socket completes immediately. A TCP connection is established
+    /// once the socket becomes writable, unless `take_error` then returns an error.
+    pub fn connect(addr: &str) -> io::Result<Stream> {
+        if let Some(path) = unix_path(addr) {
+            let sock = try!(UnixStream::connect(path));
+            try!(sock.set_nonblocking(true));
+            return Ok(Stream::Unix(sock));
+        }
+        let addr = try!(resolve(addr));
+        let builder = if addr.is_ipv4() { TcpBuilder::new_v4() } else { TcpBuilder::new_v6() };
+        let sock = try!(try!(builder).to_tcp_stream());
+        try!(sock.set_nonblocking(true));
+        if let Err(e) = sock.connect(addr) {
+            if e.raw_os_error() != Some(EINPROGRESS) {
+                return Err(e);
+            }
+        }
+        Ok(Stream::Tcp(sock))
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref sock) => sock.set_nonblocking(nonblocking),
+            Stream::Unix(ref sock) => sock.set_nonblocking(nonblocking)
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        match *self {
+            Stream::Tcp(_) => false,
+            Stream::Unix(_) => true
+        }
+    }
+
+    /// Return and clear the pending error of the socket
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match *self {
+            Stream::Tcp(ref sock) => sock.take_error(),
+            Stream::Unix(ref sock) => sock.take_error()
+        }
+    }
+
+    /// The address of the remote end of a TCP connection. Unix socket peers don't have one.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            Stream::Tcp(ref sock) => sock.peer_addr().ok(),
+            Stream::Unix(_) => None
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut sock) => sock.read(buf),
+            Stream::Unix(ref mut sock) => sock.read(buf)
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut sock) => sock.write(buf),
+            Stream::Unix(ref mut sock) => sock.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut sock) => sock.flush(),
+            Stream::Unix(ref mut sock) => sock.flush()
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Stream::Tcp(ref sock) => sock.as_raw_fd(),
+            Stream::Unix(ref sock) => sock.as_raw_fd()
+        }
+    }
+}
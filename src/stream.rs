@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use pid::Pid;
+use cluster::ClusterMsg;
+use errors::*;
+
+/// Identifies a stream. Ids are only unique among the streams opened on the same node.
+pub type StreamId = u64;
+
+// The largest amount of data sent in a single chunk
+const CHUNK_SIZE: usize = 64*1024; // 64 KiB
+
+// The default number of bytes that may be sent but not yet acknowledged by the receiving node
+const DEFAULT_WINDOW: usize = 1024*1024; // 1 MiB
+
+/// A piece of a stream sent between cluster servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChunk {
+    pub id: StreamId,
+    pub from: Pid,
+    pub to: Pid,
+    pub seq: u64,
+    pub data: Vec<u8>,
+    pub last: bool
+}
+
+/// The flow control state of a stream, shared between its handle and the cluster server
+#[derive(Debug, Default)]
+pub struct StreamState {
+    // Bytes written to the stream that the receiving node hasn't acknowledged yet
+    pub unacked: AtomicUsize,
+    // Set once the stream can no longer deliver data, because the connection to the receiving
+    // node was lost
+    pub closed: AtomicBool
+}
+
+/// The sending side of a stream of bytes to a process, created with `Node::open_stream`
+///
+/// Streams move large blobs, such as state transfers or files, without the user having to split
+/// them into envelopes. Data is sent to the cluster server in chunks that bypass batching, and the
+/// receiving process gets a `Msg::StreamChunk` envelope from the pid that opened the stream for
+/// every chunk, in order, with `last` set on the final one.
+///
+/// Streams are flow controlled. At most `window` bytes may be in flight before the receiving node
+/// acknowledges them, and `write` accepts fewer bytes than it was given, possibly none, once the
+/// window is full. Acknowledgements are sent when chunks are handed to the receiving executor,
+/// not when the process handles them.
+pub struct StreamHandle<T> {
+    id: StreamId,
+    from: Pid,
+    to: Pid,
+    seq: u64,
+    window: usize,
+    state: Arc<StreamState>,
+    cluster_tx: Sender<ClusterMsg<T>>
+}
+
+impl<T> StreamHandle<T> {
+    pub fn new(id: StreamId,
+               from: Pid,
+               to: Pid,
+               state: Arc<StreamState>,
+               cluster_tx: Sender<ClusterMsg<T>>) -> StreamHandle<T> {
+        StreamHandle {
+            id: id,
+            from: from,
+            to: to,
+            seq: 0,
+            window: DEFAULT_WINDOW,
+            state: state,
+            cluster_tx: cluster_tx
+        }
+    }
+
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// The process receiving the stream
+    pub fn to(&self) -> &Pid {
+        &self.to
+    }
+
+    /// Set the number of bytes that may be in flight before the receiving node acknowledges them
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+    }
+
+    /// Return the number of bytes `write` would currently accept
+    pub fn available(&self) -> usize {
+        self.window.saturating_sub(self.state.unacked.load(Ordering::Acquire))
+    }
+
+    /// Return true once all data written has been acknowledged by the receiving node
+    pub fn is_acked(&self) -> bool {
+        self.state.unacked.load(Ordering::Acquire) == 0
+    }
+
+    /// Send as much of `data` as the window allows and return the number of bytes sent
+    ///
+    /// Returns an `ErrorKind::StreamClosed` error if the connection to the receiving node was lost.
+    /// Whether any data written before that was delivered is unknown.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        if self.state.closed.load(Ordering::Acquire) {
+            return Err(ErrorKind::StreamClosed(self.id).into());
+        }
+        let len = data.len().min(self.available());
+        for chunk in data[..len].chunks(CHUNK_SIZE) {
+            self.state.unacked.fetch_add(chunk.len(), Ordering::AcqRel);
+            self.send(chunk.to_vec(), false)?;
+        }
+        Ok(len)
+    }
+
+    /// Tell the receiving process that the stream is complete
+    pub fn finish(mut self) -> Result<()> {
+        if self.state.closed.load(Ordering::Acquire) {
+            return Err(ErrorKind::StreamClosed(self.id).into());
+        }
+        self.send(Vec::new(), true)
+    }
+
+    fn send(&mut self, data: Vec<u8>, last: bool) -> Result<()> {
+        let chunk = StreamChunk {
+            id: self.id,
+            from: self.from.clone(),
+            to: self.to.clone(),
+            seq: self.seq,
+            data: data,
+            last: last
+        };
+        self.seq += 1;
+        if let Err(_) = self.cluster_tx.send(ClusterMsg::Stream(chunk)) {
+            return Err(ErrorKind::SendError(format!("ClusterMsg::Stream({}, ..)", self.id),
+                                            Some(self.to.clone())).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use rand::{self, Rng};
+use time;
+use pid::Pid;
+
+/// The trace flag that marks a trace as sampled, meaning its spans should be recorded
+pub const FLAG_SAMPLED: u8 = 0x01;
+
+/// A W3C trace context carried by an envelope
+///
+/// The trace id identifies a whole request flow, across processes and nodes. The span id identifies
+/// the handling of the message that caused this envelope to be sent, and becomes the parent of
+/// the span in which its destination handles it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub flags: u8
+}
+
+impl TraceContext {
+    /// Start a new trace with a random trace id
+    pub fn new(sampled: bool) -> TraceContext {
+        let mut trace_id = [0; 16];
+        random_id(&mut trace_id);
+        let mut span_id = [0; 8];
+        random_id(&mut span_id);
+        TraceContext {
+            trace_id: trace_id,
+            span_id: span_id,
+            flags: if sampled { FLAG_SAMPLED } else { 0 }
+        }
+    }
+
+    /// Return a context for a new span in the same trace
+    pub fn child(&self) -> TraceContext {
+        let mut span_id = [0; 8];
+        random_id(&mut span_id);
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: span_id,
+            flags: self.flags
+        }
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    /// Format the context as the value of a `traceparent` HTTP header
+    pub fn to_traceparent(&self) -> String {
+        let mut header = String::with_capacity(55);
+        header.push_str("00-");
+        push_hex(&mut header, &self.trace_id);
+        header.push('-');
+        push_hex(&mut header, &self.span_id);
+        header.push('-');
+        push_hex(&mut header, &[self.flags]);
+        header
+    }
+
+    /// Parse the value of a `traceparent` HTTP header
+    ///
+    /// Returns `None` if the header is malformed, or either id is all zeros.
+    pub fn from_traceparent(header: &str) -> Option<TraceContext> {
+        let parts: Vec<&str> = header.trim().split('-').collect();
+        if parts.len() < 4 || parts[0].len() != 2 || parts[0] == "ff" {
+            return None;
+        }
+        // Later versions may append fields, but must keep the ones of version 00
+        if parts[0] == "00" && parts.len() != 4 {
+            return None;
+        }
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        let mut flags = [0; 1];
+        if !parse_hex(parts[1], &mut trace_id) ||
+            !parse_hex(parts[2], &mut span_id) ||
+            !parse_hex(parts[3], &mut flags)
+        {
+            return None;
+        }
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: trace_id,
+            span_id: span_id,
+            flags: flags[0]
+        })
+    }
+}
+
+/// The handling of a traced envelope by a process
+///
+/// Times are in ns since the UNIX epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub context: TraceContext,
+    pub parent_span_id: [u8; 8],
+    pub pid: Pid,
+    pub from: Pid,
+    pub start: u64,
+    pub end: u64
+}
+
+/// Receives the spans of sampled traces, to forward them to OpenTelemetry or another tracing system
+///
+/// Spans are exported from the executor thread as soon as a process finishes handling an envelope,
+/// so implementations should hand them off to another thread rather than block on I/O.
+pub trait SpanExporter: Send {
+    fn export(&mut self, span: Span);
+}
+
+/// Return the current wall clock time in ns since the UNIX epoch
+pub fn now_ns() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1_000_000_000 + now.nsec as u64
+}
+
+/// Fill `id` with random bytes, making sure they aren't all zero, which W3C reserves as invalid
+fn random_id(id: &mut [u8]) {
+    let mut rng = rand::thread_rng();
+    while id.iter().all(|b| *b == 0) {
+        rng.fill_bytes(id);
+    }
+}
+
+fn push_hex(s: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        // Writing to a String can't fail
+        let _ = write!(s, "{:02x}", b);
+    }
+}
+
+fn parse_hex(s: &str, out: &mut [u8]) -> bool {
+    // W3C only allows lowercase hex digits
+    let is_hex = |c: u8| (b'0' <= c && c <= b'9') || (b'a' <= c && c <= b'f');
+    if s.len() != out.len() * 2 || !s.bytes().all(is_hex) {
+        return false;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        match u8::from_str_radix(&s[2 * i..2 * i + 2], 16) {
+            Ok(b) => *byte = b,
+            Err(_) => return false
+        }
+    }
+    true
+}
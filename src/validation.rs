@@ -0,0 +1,175 @@
+use std::fmt::{self, Display, Formatter};
+use std::net::ToSocketAddrs;
+use node_id::NodeId;
+use config::Config;
+use socket::{Listener, unix_path};
+use cluster::{SplitBrainStrategy, TlsContext, Proxy};
+
+/// A single problem found while validating a `Config`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConfigProblem {
+    /// The config field or resource with the problem, e.g. `cluster.seeds`
+    pub field: String,
+    pub message: String
+}
+
+/// The outcome of validating the configuration of a node before it's started
+///
+/// All checks are run, so every problem is reported at once.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ValidationReport {
+    pub problems: Vec<ConfigProblem>
+}
+
+impl ValidationReport {
+    pub fn new() -> ValidationReport {
+        ValidationReport {
+            problems: Vec::new()
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Record a problem with `field` unless `ok` is true
+    pub fn check(&mut self, ok: bool, field: &str, message: String) {
+        if !ok {
+            self.problems.push(ConfigProblem {
+                field: field.to_string(),
+                message: message
+            });
+        }
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "no problems");
+        }
+        write!(f, "{} problem(s):", self.problems.len())?;
+        for problem in self.problems.iter() {
+            write!(f, "\n  {}: {}", problem.field, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that a node can be started with the given configuration
+///
+/// This verifies that the cluster address can be bound, that all seeds resolve, that any TLS
+/// material can be loaded, and that the configured values are consistent with each other.
+pub fn validate(node_id: &NodeId, config: &Config) -> ValidationReport {
+    let mut report = ValidationReport::new();
+    match config.cluster.bind_addr {
+        Some(ref addr) => check_addr(&mut report, "cluster.bind_addr", addr),
+        None => check_addr(&mut report, "node_id.addr", &node_id.addr)
+    }
+    match config.cluster.proxy {
+        // Seeds are resolved by the proxy
+        Some(Proxy::Socks5(ref addr)) | Some(Proxy::HttpConnect(ref addr)) => {
+            if let Err(e) = addr.to_socket_addrs() {
+                report.check(false, "cluster.proxy", format!("cannot resolve {}: {}", addr, e));
+            }
+        },
+        None => check_seeds(&mut report, node_id, &config.cluster.seeds)
+    }
+    if let Some(ref tls) = config.cluster.tls {
+        if let Err(e) = TlsContext::load(tls) {
+            report.check(false, "cluster.tls", e.to_string());
+        }
+    }
+
+    let cluster = &config.cluster;
+    report.check(cluster.probe_timeout < cluster.probe_interval,
+                 "cluster.probe_timeout",
+                 format!("must be less than probe_interval ({} ms), got {} ms",
+                         cluster.probe_interval, cluster.probe_timeout));
+    report.check(cluster.phi_threshold > 0.0,
+                 "cluster.phi_threshold",
+                 format!("must be positive, got {}", cluster.phi_threshold));
+    report.check(cluster.heartbeat_interval > 0,
+                 "cluster.heartbeat_interval",
+                 "must be positive".to_string());
+    report.check(cluster.max_missed_heartbeats > 0,
+                 "cluster.max_missed_heartbeats",
+                 "must be at least 1".to_string());
+    report.check(cluster.max_queued_bytes > 0,
+                 "cluster.max_queued_bytes",
+                 "must be positive".to_string());
+    report.check(cluster.max_queued_msgs > 0,
+                 "cluster.max_queued_msgs",
+                 "must be positive".to_string());
+    if let Some(ref batching) = cluster.batching {
+        report.check(batching.max_envelopes > 0,
+                     "cluster.batching",
+                     "max_envelopes must be positive".to_string());
+        report.check(batching.max_delay > 0,
+                     "cluster.batching",
+                     "max_delay must be positive".to_string());
+    }
+    report.check(cluster.socket.connect_timeout > 0,
+                 "cluster.socket.connect_timeout",
+                 "must be positive".to_string());
+    if let Some(throttle) = cluster.throttle {
+        report.check(throttle.bytes_per_sec > 0 && throttle.burst > 0,
+                     "cluster.throttle",
+                     "bytes_per_sec and burst must be positive".to_string());
+    }
+    report.check(cluster.chunk_size > 0, "cluster.chunk_size", "must be positive".to_string());
+    report.check(cluster.phi_window > 0, "cluster.phi_window", "must be positive".to_string());
+    report.check(cluster.virtual_nodes > 0,
+                 "cluster.virtual_nodes",
+                 "must be positive".to_string());
+    report.check(cluster.min_members > 0,
+                 "cluster.min_members",
+                 "must be at least 1".to_string());
+    if let Some(expected_size) = cluster.expected_size {
+        report.check(expected_size >= cluster.min_members,
+                     "cluster.expected_size",
+                     format!("must be at least min_members ({}), got {}",
+                             cluster.min_members, expected_size));
+    }
+    if let Some(SplitBrainStrategy::StaticQuorum(quorum)) = cluster.split_brain {
+        report.check(quorum > 0,
+                     "cluster.split_brain",
+                     "a static quorum must be at least 1".to_string());
+    }
+    if let Some(ref push) = config.push_exporter {
+        report.check(push.interval > 0,
+                     "push_exporter.interval",
+                     "must be positive".to_string());
+        if let Err(e) = push.addr.to_socket_addrs() {
+            report.check(false,
+                         "push_exporter.addr",
+                         format!("cannot resolve {}: {}", push.addr, e));
+        }
+    }
+    report
+}
+
+fn check_addr(report: &mut ValidationReport, field: &str, addr: &str) {
+    match Listener::bind(addr) {
+        // The listener is dropped immediately so the cluster server can bind the address
+        Ok(_) => (),
+        Err(e) => report.check(false, field, format!("cannot bind {}: {}", addr, e))
+    }
+}
+
+fn check_seeds(report: &mut ValidationReport, node_id: &NodeId, seeds: &[NodeId]) {
+    for seed in seeds.iter().filter(|&seed| seed != node_id) {
+        // There's nothing to resolve for a unix socket, and it doesn't exist until the seed starts
+        if unix_path(&seed.addr).is_some() {
+            continue;
+        }
+        match seed.addr.to_socket_addrs() {
+            Ok(mut addrs) => report.check(addrs.next().is_some(),
+                                          "cluster.seeds",
+                                          format!("{} resolves to no addresses", seed)),
+            Err(e) => report.check(false,
+                                   "cluster.seeds",
+                                   format!("cannot resolve {}: {}", seed, e))
+        }
+    }
+}
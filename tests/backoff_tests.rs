@@ -0,0 +1,42 @@
+extern crate rabble;
+
+use rabble::{Backoff, Retry, Pid, NodeId, Envelope, Msg};
+
+#[test]
+fn delays_grow_exponentially_up_to_max() {
+    let backoff = Backoff::new(100, 1000, 2, 0);
+    let delays: Vec<usize> = (1..7).map(|attempt| backoff.delay(attempt)).collect();
+    assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+}
+
+#[test]
+fn jitter_stays_within_bounds() {
+    let backoff = Backoff::new(1000, 1000, 2, 20);
+    for _ in 0..1000 {
+        let delay = backoff.delay(1);
+        assert!(delay >= 800 && delay <= 1200);
+    }
+}
+
+#[test]
+fn retry_starts_timers_and_resets() {
+    let node = NodeId {name: "node1".to_string(), addr: "127.0.0.1:11000".to_string()};
+    let executor = Pid {
+        group: Some("rabble".to_string()),
+        name: "executor".to_string(),
+        node: node.clone(),
+        generation: None
+    };
+    let pid = Pid {name: "some-process".to_string(), group: None, node: node, generation: None};
+    let mut retry = Retry::new(Backoff::new(100, 1000, 2, 0));
+
+    let envelope: Envelope<()> = retry.retry_after(executor.clone(), pid.clone(), None);
+    assert_eq!(envelope.to, executor);
+    assert_eq!(envelope.from, pid);
+    assert_eq!(envelope.msg, Msg::StartTimer(100));
+    assert_eq!(retry.next_delay(), 200);
+    assert_eq!(retry.attempts(), 2);
+
+    retry.reset();
+    assert_eq!(retry.next_delay(), 100);
+}
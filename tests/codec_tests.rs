@@ -0,0 +1,51 @@
+extern crate rabble;
+
+use rabble::{Envelope, Msg, NodeId, Pid, Priority};
+use rabble::{Codec, ExternalMsg, MsgpackCodec, CborCodec, BincodeCodec};
+
+fn pid(name: &str) -> Pid {
+    Pid {
+        name: name.to_string(),
+        group: None,
+        node: NodeId {name: "node1".to_string(), addr: "127.0.0.1:11000".to_string()},
+        generation: None
+    }
+}
+
+fn codecs() -> Vec<Box<Codec<String>>> {
+    vec![Box::new(MsgpackCodec), Box::new(CborCodec), Box::new(BincodeCodec)]
+}
+
+fn envelope(msg: &str) -> Envelope<String> {
+    Envelope::new(pid("a"), pid("b"), Msg::User(msg.to_string()), None)
+}
+
+#[test]
+fn envelopes_round_trip() {
+    let envelope = envelope("hello").with_ttl(1000).with_priority(Priority::Low);
+    for codec in codecs() {
+        let encoded = codec.encode(&ExternalMsg::Envelope(envelope.clone())).unwrap();
+        match codec.decode(&encoded).unwrap() {
+            ExternalMsg::Envelope(decoded) => assert_eq!(decoded, envelope, "{}", codec.name()),
+            msg => panic!("{} decoded {:?}", codec.name(), msg)
+        }
+    }
+}
+
+#[test]
+fn batches_round_trip() {
+    let batch = vec![envelope("one"), envelope("two").with_header("tenant", b"acme".to_vec())];
+    for codec in codecs() {
+        let encoded = codec.encode(&ExternalMsg::Batch(batch.clone())).unwrap();
+        match codec.decode(&encoded).unwrap() {
+            ExternalMsg::Batch(decoded) => assert_eq!(decoded, batch, "{}", codec.name()),
+            msg => panic!("{} decoded {:?}", codec.name(), msg)
+        }
+    }
+}
+
+#[test]
+fn codecs_have_distinct_names() {
+    let names: Vec<String> = codecs().iter().map(|codec| codec.name().to_string()).collect();
+    assert_eq!(names, vec!["msgpack", "cbor", "bincode"]);
+}
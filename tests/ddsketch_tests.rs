@@ -0,0 +1,47 @@
+extern crate rabble;
+
+use rabble::DDSketch;
+
+fn assert_within(actual: f64, expected: f64, accuracy: f64) {
+    assert!((actual - expected).abs() <= expected * accuracy,
+            "{} is not within {} of {}", actual, accuracy, expected);
+}
+
+#[test]
+fn quantiles_are_within_the_relative_accuracy() {
+    let mut sketch = DDSketch::new(0.01);
+    for i in 1..10001 {
+        sketch.record(i as f64);
+    }
+    assert_eq!(sketch.count(), 10000);
+    assert_within(sketch.quantile(0.5), 5000.0, 0.01);
+    assert_within(sketch.quantile(0.99), 9900.0, 0.01);
+    assert_eq!(sketch.quantile(0.0), 1.0);
+    assert_eq!(sketch.quantile(1.0), 10000.0);
+}
+
+#[test]
+fn merged_sketches_cover_both_inputs() {
+    let mut low = DDSketch::new(0.02);
+    let mut high = DDSketch::new(0.02);
+    for i in 0..100 {
+        low.record(i as f64);
+        high.record(1_000_000.0 + i as f64);
+    }
+    low.merge(&high).unwrap();
+    assert_eq!(low.count(), 200);
+    assert_within(low.quantile(0.25), 49.0, 0.02);
+    assert_within(low.quantile(0.75), 1_000_049.0, 0.02);
+    assert!(low.merge(&DDSketch::new(0.01)).is_err());
+}
+
+#[test]
+fn collapsing_bins_keeps_high_quantiles_accurate() {
+    let mut sketch = DDSketch::with_max_bins(0.01, 100);
+    let mut value = 1.0;
+    for _ in 0..1000 {
+        sketch.record(value);
+        value *= 1.05;
+    }
+    assert_within(sketch.quantile(0.99), 1.05f64.powi(989), 0.01);
+}
@@ -0,0 +1,78 @@
+extern crate rabble;
+
+use std::io::Cursor;
+use rabble::{Envelope, Msg, NodeId, Pid, Priority};
+use rabble::{Codec, ExternalMsg, MsgpackCodec, CborCodec, BincodeCodec};
+use rabble::serialize::{Serialize, JsonLinesSerializer};
+
+fn pid(name: &str) -> Pid {
+    Pid {
+        name: name.to_string(),
+        group: None,
+        node: NodeId {name: "node1".to_string(), addr: "127.0.0.1:11000".to_string()},
+        generation: None
+    }
+}
+
+#[test]
+fn headers_are_set_replaced_and_removed() {
+    let mut envelope: Envelope<u32> = Envelope::new(pid("a"), pid("b"), Msg::User(1), None)
+        .with_header("tenant", b"acme".to_vec());
+    envelope.set_header("tenant", b"initech".to_vec());
+    assert_eq!(envelope.header("tenant"), Some(&b"initech"[..]));
+    assert_eq!(envelope.headers.len(), 1);
+    assert_eq!(envelope.remove_header("tenant"), Some(b"initech".to_vec()));
+    assert_eq!(envelope.header("tenant"), None);
+}
+
+#[test]
+fn headers_round_trip() {
+    let mut serializer: JsonLinesSerializer<Envelope<u32>> = JsonLinesSerializer::new();
+    let plain = Envelope::new(pid("a"), pid("b"), Msg::User(1), None);
+    let with_header = plain.clone().with_header("auth", vec![1, 2, 3]);
+    let mut writer = Vec::new();
+    serializer.write_msgs(&mut writer, Some(&plain)).unwrap();
+    serializer.write_msgs(&mut writer, Some(&with_header)).unwrap();
+
+    let mut reader = Cursor::new(writer);
+    assert_eq!(serializer.read_msg(&mut reader).unwrap(), Some(plain));
+    assert_eq!(serializer.read_msg(&mut reader).unwrap(), Some(with_header));
+}
+
+#[test]
+fn envelopes_with_and_without_headers_round_trip_through_every_codec() {
+    let plain = Envelope::new(pid("a"), pid("b"), Msg::User(1), None);
+    let with_header = plain.clone().with_header("auth", vec![1, 2, 3]);
+    let codecs: Vec<Box<Codec<u32>>> =
+        vec![Box::new(MsgpackCodec), Box::new(CborCodec), Box::new(BincodeCodec)];
+    for codec in codecs {
+        let batch = ExternalMsg::Batch(vec![plain.clone(), with_header.clone(), plain.clone()]);
+        let encoded = codec.encode(&batch).unwrap();
+        match codec.decode(&encoded).unwrap() {
+            ExternalMsg::Batch(envelopes) => {
+                assert_eq!(envelopes, vec![plain.clone(), with_header.clone(), plain.clone()],
+                           "codec {}", codec.name());
+            },
+            msg => panic!("codec {} decoded {:?}", codec.name(), msg)
+        }
+    }
+}
+
+#[test]
+fn control_messages_have_high_priority() {
+    let user: Envelope<u32> = Envelope::new(pid("a"), pid("b"), Msg::User(1), None);
+    let shutdown: Envelope<u32> = Envelope::new(pid("a"), pid("b"), Msg::Shutdown, None);
+    assert_eq!(user.priority, Priority::Normal);
+    assert_eq!(shutdown.priority, Priority::High);
+    assert_eq!(user.with_priority(Priority::Low).priority, Priority::Low);
+    assert!(Priority::High > Priority::Normal && Priority::Normal > Priority::Low);
+}
+
+#[test]
+fn replies_go_to_reply_to_if_set() {
+    let request: Envelope<u32> = Envelope::new(pid("worker"), pid("router"), Msg::User(1), None);
+    assert_eq!(request.reply_address(), &pid("router"));
+    let forwarded = request.with_reply_to(pid("client"));
+    assert_eq!(forwarded.reply_address(), &pid("client"));
+    assert_eq!(forwarded.from, pid("router"));
+}
@@ -0,0 +1,78 @@
+extern crate rabble;
+
+use std::thread;
+use std::time::Duration;
+use rabble::{Histogram, HistogramConfig, RotatingHistogram, TimeUnit};
+
+fn histogram(values: &[u64]) -> Histogram {
+    let mut histogram = Histogram::new(TimeUnit::Microseconds);
+    for value in values {
+        histogram.record(*value);
+    }
+    histogram
+}
+
+#[test]
+fn merge_and_subtract_are_inverses() {
+    let mut merged = histogram(&[10, 20, 30]);
+    let other = histogram(&[1000, 2000]);
+    merged.merge(&other);
+    assert_eq!(merged.count(), 5);
+    assert!(merged.percentile(100.0) >= 2000);
+    merged.subtract(&other).unwrap();
+    assert_eq!(merged.count(), 3);
+    assert_eq!(merged.percentile(100.0), 30);
+}
+
+#[test]
+fn subtracting_values_that_were_never_recorded_fails() {
+    let mut h = histogram(&[10]);
+    assert!(h.subtract(&histogram(&[10, 10])).is_err());
+    let mut ms = Histogram::new(TimeUnit::Milliseconds);
+    assert!(ms.subtract(&Histogram::new(TimeUnit::Microseconds)).is_err());
+}
+
+#[test]
+fn interval_snapshots() {
+    let mut h = histogram(&[10, 20]);
+    let earlier = h.clone();
+    h.record(30);
+    let interval = h.since(&earlier).unwrap();
+    assert_eq!(interval.count(), 1);
+    assert_eq!(interval.percentile(50.0), 30);
+
+    let interval = h.take_interval();
+    assert_eq!(interval.count(), 3);
+    assert_eq!(h.count(), 0);
+}
+
+#[test]
+fn rotating_histograms_drop_old_intervals() {
+    let interval = Duration::from_millis(50);
+    let mut h = RotatingHistogram::new(TimeUnit::Microseconds, interval, 2);
+    h.record(10);
+    assert_eq!(h.recent().count(), 1);
+    thread::sleep(interval);
+    h.record(20);
+    assert_eq!(h.last(interval).count(), 1);
+    assert_eq!(h.recent().count(), 2);
+    // Once two more intervals pass, nothing recorded is still kept
+    thread::sleep(interval * 2);
+    assert_eq!(h.recent().count(), 0);
+}
+
+#[test]
+fn fixed_bounds() {
+    let config = HistogramConfig {
+        sigfigs: 1,
+        lowest_discernible: 1,
+        highest_trackable: 1000,
+        auto_resize: false
+    };
+    let mut h = Histogram::with_config(TimeUnit::Milliseconds, config).unwrap();
+    h.record(100_000);
+    assert_eq!(h.count(), 1);
+    assert!(h.percentile(100.0) < 2000);
+    let invalid = HistogramConfig { sigfigs: 6, ..HistogramConfig::default() };
+    assert!(Histogram::with_config(TimeUnit::Milliseconds, invalid).is_err());
+}
@@ -0,0 +1,30 @@
+extern crate rabble;
+
+use std::io::Cursor;
+use rabble::serialize::{Serialize, JsonLinesSerializer};
+
+#[test]
+fn reads_one_msg_per_line() {
+    let mut serializer: JsonLinesSerializer<Vec<u32>> = JsonLinesSerializer::new();
+    let mut reader = Cursor::new(b"[1,2]\r\n\n[3]\n[4".to_vec());
+    assert_eq!(serializer.read_msg(&mut reader).unwrap(), Some(vec![1, 2]));
+    assert_eq!(serializer.read_msg(&mut reader).unwrap(), Some(vec![3]));
+    // The last line is incomplete, and the reader is at EOF
+    assert!(serializer.read_msg(&mut reader).is_err());
+}
+
+#[test]
+fn invalid_json_is_an_error() {
+    let mut serializer: JsonLinesSerializer<Vec<u32>> = JsonLinesSerializer::new();
+    let mut reader = Cursor::new(b"[1,\n".to_vec());
+    assert!(serializer.read_msg(&mut reader).is_err());
+}
+
+#[test]
+fn writes_one_msg_per_line() {
+    let mut serializer: JsonLinesSerializer<Vec<u32>> = JsonLinesSerializer::new();
+    let mut writer = Vec::new();
+    assert!(serializer.write_msgs(&mut writer, Some(&vec![1, 2])).unwrap());
+    assert!(serializer.write_msgs(&mut writer, Some(&vec![3])).unwrap());
+    assert_eq!(writer, b"[1,2]\n[3]\n".to_vec());
+}
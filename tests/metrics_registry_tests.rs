@@ -0,0 +1,105 @@
+extern crate rabble;
+
+use std::thread;
+use rabble::{MetricsRegistry, Metric, MetricsFilter, Labels, TimeUnit};
+
+#[test]
+fn registering_twice_returns_the_same_metric() {
+    let registry = MetricsRegistry::new();
+    let counter = registry.counter("requests");
+    counter.inc();
+    registry.counter("requests").add(2);
+    assert_eq!(counter.get(), 3);
+}
+
+#[test]
+fn snapshot_is_sorted_by_name() {
+    let registry = MetricsRegistry::new();
+    registry.gauge("queue_depth").set(-4);
+    registry.counter("errors").inc();
+    registry.histogram("latency_us", TimeUnit::Microseconds).record(100);
+    let snapshot = registry.snapshot();
+    let names: Vec<&str> = snapshot.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["errors", "latency_us", "queue_depth"]);
+    assert_eq!(snapshot[0].1, Metric::Counter(1));
+    assert_eq!(snapshot[2].1, Metric::Gauge(-4));
+    match snapshot[1].1 {
+        Metric::Histogram(ref histogram) => assert_eq!(histogram.count(), 1),
+        ref metric => panic!("Expected a histogram, got {:?}", metric)
+    }
+}
+
+#[test]
+fn counters_are_shared_between_threads() {
+    let registry = MetricsRegistry::new();
+    let handles: Vec<_> = (0..4).map(|_| {
+        let counter = registry.counter("hits");
+        thread::spawn(move || for _ in 0..1000 { counter.inc(); })
+    }).collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(registry.counter("hits").get(), 4000);
+}
+
+#[test]
+#[should_panic]
+fn registering_a_name_as_another_kind_panics() {
+    let registry = MetricsRegistry::new();
+    registry.counter("connections");
+    registry.gauge("connections");
+}
+
+#[test]
+fn labeled_series_are_reported_together() {
+    let registry = MetricsRegistry::new();
+    let ok = Labels::new().with("service", "api").with("status", "ok");
+    let error = Labels::from(&[("status", "error"), ("service", "api")][..]);
+    registry.counter_with_labels("requests", &ok).add(3);
+    registry.counter_with_labels("requests", &error).inc();
+    registry.counter_with_labels("requests", &ok).inc();
+    let snapshot = registry.snapshot();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(snapshot[0].1, Metric::Labeled(vec![(error, Metric::Counter(1)),
+                                                   (ok, Metric::Counter(4))]));
+}
+
+#[test]
+#[should_panic]
+fn series_of_a_name_must_be_of_the_same_kind() {
+    let registry = MetricsRegistry::new();
+    registry.counter_with_labels("requests", &Labels::new().with("status", "ok"));
+    registry.gauge_with_labels("requests", &Labels::new().with("status", "error"));
+}
+
+#[test]
+fn meters_report_their_count() {
+    let registry = MetricsRegistry::new();
+    let meter = registry.meter("messages");
+    meter.mark(5);
+    registry.tick();
+    match registry.snapshot()[0].1 {
+        Metric::Meter(ref rates) => assert_eq!(rates.count, 5),
+        ref metric => panic!("Expected a meter, got {:?}", metric)
+    }
+}
+
+#[test]
+fn filters_select_by_prefix_and_labels() {
+    let registry = MetricsRegistry::new();
+    registry.counter("cluster_frames_sent").inc();
+    registry.gauge("queue_depth").set(3);
+    registry.counter_with_labels("requests", &Labels::new().with("status", "ok")).inc();
+    registry.counter_with_labels("requests", &Labels::new().with("status", "error")).inc();
+
+    let by_prefix = MetricsFilter::new().prefix("cluster_").prefix("queue");
+    let names: Vec<String> =
+        by_prefix.apply(registry.snapshot()).into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["cluster_frames_sent".to_string(), "queue_depth".to_string()]);
+
+    let by_label = MetricsFilter::new().label("status", "ok");
+    assert_eq!(by_label.apply(registry.snapshot()),
+               vec![("requests".to_string(),
+                     Metric::Labeled(vec![(Labels::new().with("status", "ok"),
+                                           Metric::Counter(1))]))]);
+}
@@ -0,0 +1,24 @@
+extern crate rabble;
+
+use rabble::{MetricsSnapshot, Metric};
+
+#[test]
+fn delta_of_counters_and_gauges() {
+    let earlier = MetricsSnapshot::at(1000, vec![("requests".to_string(), Metric::Counter(10)),
+                                                 ("restarts".to_string(), Metric::Counter(7)),
+                                                 ("queue".to_string(), Metric::Gauge(5))]);
+    let later = MetricsSnapshot::at(3000, vec![("requests".to_string(), Metric::Counter(30)),
+                                               ("restarts".to_string(), Metric::Counter(2)),
+                                               ("queue".to_string(), Metric::Gauge(3)),
+                                               ("errors".to_string(), Metric::Counter(4))]);
+    let delta = later.delta(&earlier);
+    assert_eq!(delta.elapsed_ms, 2000);
+    assert_eq!(delta.get("requests"), Some(&Metric::Counter(20)));
+    assert_eq!(delta.rate("requests"), Some(10.0));
+    // A counter that went down was reset
+    assert_eq!(delta.get("restarts"), Some(&Metric::Counter(2)));
+    assert_eq!(delta.get("queue"), Some(&Metric::Gauge(-2)));
+    // New metrics start from zero
+    assert_eq!(delta.get("errors"), Some(&Metric::Counter(4)));
+    assert_eq!(delta.rate("queue"), None);
+}
@@ -213,7 +213,8 @@ fn create_replica_pids(nodes: &Vec<CrNode>) -> Vec<Pid> {
         Pid {
             name: name.to_string(),
             group: None,
-            node: node.id.clone()
+            node: node.id.clone(),
+            generation: None
         }
     }).collect()
 }
@@ -0,0 +1,33 @@
+extern crate rabble;
+
+use rabble::NodeId;
+
+#[test]
+fn parses_ipv4_and_hostname_addresses() {
+    let node: NodeId = "node1@127.0.0.1:11000".parse().unwrap();
+    assert_eq!(node.name, "node1");
+    assert_eq!(node.addr, "127.0.0.1:11000");
+    let node: NodeId = "node2@localhost:11000".parse().unwrap();
+    assert_eq!(node.addr, "localhost:11000");
+}
+
+#[test]
+fn parses_bracketed_ipv6_addresses() {
+    let node: NodeId = "node1@[::1]:11000".parse().unwrap();
+    assert_eq!(node.name, "node1");
+    assert_eq!(node.addr, "[::1]:11000");
+    assert_eq!(node.to_string(), "node1@[::1]:11000");
+}
+
+#[test]
+fn rejects_unbracketed_or_portless_ipv6_addresses() {
+    assert!("node1@::1:11000".parse::<NodeId>().is_err());
+    assert!("node1@[::1]".parse::<NodeId>().is_err());
+    assert!("node1@[::1:11000".parse::<NodeId>().is_err());
+}
+
+#[test]
+fn parses_unix_socket_addresses() {
+    let node: NodeId = "node1@unix:/tmp/node1.sock".parse().unwrap();
+    assert_eq!(node.addr, "unix:/tmp/node1.sock");
+}
@@ -0,0 +1,56 @@
+extern crate rabble;
+
+use rabble::{Pid, Node, NodeId, Envelope, CorrelationId, ConnectionHandler, ConnectionMsg, PendingRequests};
+
+struct Handler;
+
+impl ConnectionHandler for Handler {
+    type Msg = ();
+    type ClientMsg = String;
+
+    fn new(_pid: Pid, _id: u64, _node: &Node<()>) -> Handler {
+        Handler
+    }
+
+    fn handle_envelope(&mut self, _: Envelope<()>, _: &mut Vec<ConnectionMsg<Handler>>) {}
+    fn handle_network_msg(&mut self, _: String, _: &mut Vec<ConnectionMsg<Handler>>) {}
+}
+
+fn correlation_id(request: u64) -> CorrelationId {
+    let node = NodeId {name: "node1".to_string(), addr: "127.0.0.1:11000".to_string()};
+    let pid = Pid {name: "api".to_string(), group: None, node: node, generation: None};
+    CorrelationId::request(pid, 1, request)
+}
+
+fn replies(output: Vec<ConnectionMsg<Handler>>) -> Vec<String> {
+    output.into_iter().map(|msg| match msg {
+        ConnectionMsg::Client(reply, _) => reply,
+        ConnectionMsg::Envelope(_) => panic!("Expected a client reply")
+    }).collect()
+}
+
+#[test]
+fn expired_requests_get_timeout_replies() {
+    let mut pending = PendingRequests::new();
+    pending.insert(correlation_id(1), 0, "timeout 1".to_string());
+    pending.insert(correlation_id(2), 60000, "timeout 2".to_string());
+    let mut output = Vec::new();
+    pending.expire::<Handler>(&mut output);
+    assert_eq!(replies(output), vec!["timeout 1".to_string()]);
+    assert_eq!(pending.len(), 1);
+
+    // Replies for requests that timed out are dropped
+    assert!(!pending.complete(&correlation_id(1)));
+    assert!(pending.complete(&correlation_id(2)));
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn timeout_msgs_answer_pending_requests() {
+    let mut pending = PendingRequests::new();
+    pending.insert(correlation_id(1), 60000, "timeout".to_string());
+    let mut output = Vec::new();
+    pending.timeout::<Handler>(&correlation_id(1), &mut output);
+    pending.timeout::<Handler>(&correlation_id(1), &mut output);
+    assert_eq!(replies(output), vec!["timeout".to_string()]);
+}
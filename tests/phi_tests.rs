@@ -0,0 +1,74 @@
+extern crate rabble;
+extern crate time;
+
+use time::{Duration, SteadyTime};
+use rabble::{NodeId, PhiAccrual};
+
+fn node() -> NodeId {
+    NodeId {name: "peer".to_string(), addr: "127.0.0.1:11000".to_string()}
+}
+
+/// Record heartbeats at the given intervals in ms, and return the time of the last one
+fn heartbeats(detector: &mut PhiAccrual, start: SteadyTime, intervals: &[i64]) -> SteadyTime {
+    let mut now = start;
+    detector.heartbeat(&node(), now);
+    for interval in intervals {
+        now = now + Duration::milliseconds(*interval);
+        detector.heartbeat(&node(), now);
+    }
+    now
+}
+
+#[test]
+fn phi_needs_an_interval_first() {
+    let mut detector = PhiAccrual::new(100, 10, 0);
+    let now = SteadyTime::now();
+    assert_eq!(detector.phi(&node(), now), None);
+    detector.heartbeat(&node(), now);
+    assert_eq!(detector.phi(&node(), now), None);
+    detector.heartbeat(&node(), now + Duration::milliseconds(100));
+    assert!(detector.phi(&node(), now + Duration::milliseconds(100)).is_some());
+}
+
+#[test]
+fn phi_grows_with_silence() {
+    let mut detector = PhiAccrual::new(100, 10, 0);
+    let last = heartbeats(&mut detector, SteadyTime::now(), &[100; 10]);
+    let phi = |ms| detector.phi(&node(), last + Duration::milliseconds(ms)).unwrap();
+    assert!(phi(50) < 1.0);
+    assert!(phi(100) < phi(150));
+    assert!(phi(150) < phi(200));
+    assert!(phi(200) > 8.0);
+}
+
+#[test]
+fn jittery_peers_need_longer_silences_to_be_suspected() {
+    let start = SteadyTime::now();
+    let mut regular = PhiAccrual::new(100, 10, 0);
+    let regular_last = heartbeats(&mut regular, start, &[100; 10]);
+    let mut jittery = PhiAccrual::new(100, 10, 0);
+    let jittery_last = heartbeats(&mut jittery, start, &[50, 150, 50, 150, 50, 150, 50, 150]);
+    let silence = Duration::milliseconds(200);
+    assert!(jittery.phi(&node(), jittery_last + silence).unwrap() <
+            regular.phi(&node(), regular_last + silence).unwrap());
+}
+
+#[test]
+fn acceptable_pauses_lower_phi() {
+    let start = SteadyTime::now();
+    let mut strict = PhiAccrual::new(100, 10, 0);
+    let strict_last = heartbeats(&mut strict, start, &[100; 10]);
+    let mut lenient = PhiAccrual::new(100, 10, 100);
+    let lenient_last = heartbeats(&mut lenient, start, &[100; 10]);
+    let silence = Duration::milliseconds(150);
+    assert!(lenient.phi(&node(), lenient_last + silence).unwrap() <
+            strict.phi(&node(), strict_last + silence).unwrap());
+}
+
+#[test]
+fn removed_peers_lose_their_history() {
+    let mut detector = PhiAccrual::new(100, 10, 0);
+    let last = heartbeats(&mut detector, SteadyTime::now(), &[100; 3]);
+    detector.remove(&node());
+    assert_eq!(detector.phi(&node(), last), None);
+}
@@ -0,0 +1,114 @@
+extern crate rabble;
+extern crate time;
+
+use std::collections::HashSet;
+use time::{Duration, SteadyTime};
+use rabble::{BroadcastId, NodeId, Plumtree, PlumtreeMsg};
+
+const GRAFT_TIMEOUT: usize = 100; // ms
+
+fn node(name: &str) -> NodeId {
+    NodeId {name: name.to_string(), addr: format!("{}:11000", name)}
+}
+
+fn tree(peers: &[&str]) -> Plumtree {
+    let mut tree = Plumtree::new(node("me"), 0, 10, GRAFT_TIMEOUT);
+    let peers: HashSet<NodeId> = peers.iter().map(|name| node(name)).collect();
+    tree.set_peers(&peers);
+    tree
+}
+
+fn gossip(origin: &str, seq: u64, payload: &[u8]) -> PlumtreeMsg {
+    PlumtreeMsg::Gossip {
+        id: BroadcastId {origin: node(origin), seq: seq},
+        round: 0,
+        payload: payload.to_vec()
+    }
+}
+
+fn recipients(output: &[(NodeId, PlumtreeMsg)]) -> HashSet<NodeId> {
+    output.iter().map(|&(ref node, _)| node.clone()).collect()
+}
+
+#[test]
+fn broadcasts_are_pushed_to_all_peers_but_not_delivered_locally() {
+    let mut tree = tree(&["a", "b"]);
+    let mut output = Vec::new();
+    tree.broadcast(b"hello".to_vec(), &mut output);
+    assert_eq!(recipients(&output), vec![node("a"), node("b")].into_iter().collect());
+    assert!(tree.drain_delivered().is_empty());
+}
+
+#[test]
+fn gossip_is_delivered_once_and_forwarded_to_other_peers() {
+    let mut tree = tree(&["a", "b", "c"]);
+    let mut output = Vec::new();
+    tree.handle(node("a"), gossip("a", 0, b"hello"), SteadyTime::now(), &mut output);
+    assert_eq!(tree.drain_delivered(), vec![b"hello".to_vec()]);
+    assert_eq!(recipients(&output), vec![node("b"), node("c")].into_iter().collect());
+}
+
+#[test]
+fn duplicates_prune_the_link_they_arrived_on() {
+    let mut tree = tree(&["a", "b"]);
+    let mut output = Vec::new();
+    tree.handle(node("a"), gossip("a", 0, b"hello"), SteadyTime::now(), &mut output);
+    output.clear();
+    tree.handle(node("b"), gossip("a", 0, b"hello"), SteadyTime::now(), &mut output);
+    assert_eq!(tree.drain_delivered().len(), 1);
+    match output.pop() {
+        Some((ref to, PlumtreeMsg::Prune)) => assert_eq!(*to, node("b")),
+        msg => panic!("Expected a prune, got {:?}", msg)
+    }
+    assert!(tree.lazy_peers().contains(&node("b")));
+
+    // Lazy peers only get announcements
+    output.clear();
+    tree.broadcast(b"world".to_vec(), &mut output);
+    for (to, msg) in output {
+        match msg {
+            PlumtreeMsg::Gossip {..} => assert_eq!(to, node("a")),
+            PlumtreeMsg::IHave {..} => assert_eq!(to, node("b")),
+            msg => panic!("Unexpected {:?}", msg)
+        }
+    }
+}
+
+#[test]
+fn missing_payloads_are_grafted_from_their_announcer() {
+    let mut tree = tree(&["a", "b"]);
+    let mut output = Vec::new();
+    let now = SteadyTime::now();
+    tree.handle(node("b"), PlumtreeMsg::Prune, now, &mut output);
+    let id = BroadcastId {origin: node("a"), seq: 0};
+    tree.handle(node("b"), PlumtreeMsg::IHave {ids: vec![id.clone()]}, now, &mut output);
+
+    tree.tick(now + Duration::milliseconds(GRAFT_TIMEOUT as i64 / 2), &mut output);
+    assert!(output.is_empty());
+    tree.tick(now + Duration::milliseconds(GRAFT_TIMEOUT as i64), &mut output);
+    match output.pop() {
+        Some((ref to, PlumtreeMsg::Graft {ref ids})) => {
+            assert_eq!(*to, node("b"));
+            assert_eq!(*ids, vec![id]);
+        },
+        msg => panic!("Expected a graft, got {:?}", msg)
+    }
+    assert!(tree.eager_peers().contains(&node("b")));
+}
+
+#[test]
+fn grafts_are_answered_from_the_cache() {
+    let mut tree = tree(&["a", "b"]);
+    let mut output = Vec::new();
+    tree.handle(node("a"), gossip("a", 0, b"hello"), SteadyTime::now(), &mut output);
+    output.clear();
+    let graft = PlumtreeMsg::Graft {ids: vec![BroadcastId {origin: node("a"), seq: 0}]};
+    tree.handle(node("b"), graft, SteadyTime::now(), &mut output);
+    match output.pop() {
+        Some((ref to, PlumtreeMsg::Gossip {ref payload, ..})) => {
+            assert_eq!(*to, node("b"));
+            assert_eq!(*payload, b"hello".to_vec());
+        },
+        msg => panic!("Expected the payload, got {:?}", msg)
+    }
+}
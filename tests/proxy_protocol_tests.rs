@@ -0,0 +1,93 @@
+extern crate rabble;
+
+use std::io::{self, Read};
+use rabble::{ProxyHeader, ProxyHeaderReader};
+
+const SIGNATURE: [u8; 12] =
+    [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+/// A nonblocking socket that has only received the first `available` bytes of the data
+struct Partial {
+    data: Vec<u8>,
+    pos: usize,
+    available: usize
+}
+
+impl Read for Partial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.available {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+        }
+        let n = buf.len().min(self.available - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn ipv4_header() -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+    // PROXY command, TCP over IPv4, 12 bytes of addresses
+    header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+    header.extend_from_slice(&[10, 0, 0, 1, 192, 168, 1, 1, 0x30, 0x39, 0x01, 0xBB]);
+    header
+}
+
+#[test]
+fn ipv4_header_leaves_client_data_unread() {
+    let mut data = ipv4_header();
+    data.extend_from_slice(b"hello");
+    let mut sock = &data[..];
+    let header = ProxyHeaderReader::new().read(&mut sock).unwrap().unwrap();
+    assert_eq!(header, ProxyHeader {
+        source: Some("10.0.0.1:12345".parse().unwrap()),
+        destination: Some("192.168.1.1:443".parse().unwrap())
+    });
+    assert_eq!(sock, b"hello");
+}
+
+#[test]
+fn ipv6_header() {
+    let mut data = SIGNATURE.to_vec();
+    data.extend_from_slice(&[0x21, 0x21, 0x00, 0x24]);
+    let mut source = [0; 16];
+    source[0] = 0x20;
+    source[1] = 0x01;
+    source[15] = 1;
+    data.extend_from_slice(&source);
+    data.extend_from_slice(&[0; 15]);
+    data.push(1);
+    data.extend_from_slice(&[0x30, 0x39, 0x01, 0xBB]);
+    let header = ProxyHeaderReader::new().read(&mut &data[..]).unwrap().unwrap();
+    assert_eq!(header.source, Some("[2001::1]:12345".parse().unwrap()));
+    assert_eq!(header.destination, Some("[::1]:443".parse().unwrap()));
+}
+
+#[test]
+fn header_split_across_reads() {
+    let data = ipv4_header();
+    let len = data.len();
+    let mut sock = Partial {data: data, pos: 0, available: 0};
+    let mut reader = ProxyHeaderReader::new();
+    while sock.available < len {
+        assert_eq!(reader.read(&mut sock).unwrap(), None);
+        sock.available = (sock.available + 5).min(len);
+    }
+    let header = reader.read(&mut sock).unwrap().unwrap();
+    assert_eq!(header.source, Some("10.0.0.1:12345".parse().unwrap()));
+}
+
+#[test]
+fn local_command_has_no_addresses() {
+    let mut data = SIGNATURE.to_vec();
+    data.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+    let header = ProxyHeaderReader::new().read(&mut &data[..]).unwrap().unwrap();
+    assert_eq!(header, ProxyHeader {source: None, destination: None});
+}
+
+#[test]
+fn missing_signature_is_rejected() {
+    let data = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    let err = ProxyHeaderReader::new().read(&mut &data[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,31 @@
+extern crate rabble;
+
+use rabble::{RateLimit, RateLimiter, RateLimitExceeded};
+
+#[test]
+fn msgs_over_the_limit_are_rejected() {
+    let mut limiter = RateLimiter::new(RateLimit {msgs_per_sec: Some(3), bytes_per_sec: None});
+    for _ in 0..3 {
+        assert_eq!(limiter.check(10), None);
+    }
+    assert_eq!(limiter.check(10), Some(RateLimitExceeded::Msgs));
+}
+
+#[test]
+fn bytes_over_the_limit_are_rejected() {
+    let mut limiter = RateLimiter::new(RateLimit {msgs_per_sec: None, bytes_per_sec: Some(1000)});
+    assert_eq!(limiter.check(600), None);
+    // The remaining 400 bytes let a larger message through, leaving the limiter in debt
+    assert_eq!(limiter.check(600), None);
+    assert_eq!(limiter.check(1), Some(RateLimitExceeded::Bytes));
+}
+
+#[test]
+fn msgs_rejected_for_size_dont_use_up_msg_tokens() {
+    let mut limiter = RateLimiter::new(RateLimit {msgs_per_sec: Some(2), bytes_per_sec: Some(100)});
+    assert_eq!(limiter.check(200), None);
+    // A message token is left, so every message is rejected for its size rather than its count
+    for _ in 0..5 {
+        assert_eq!(limiter.check(1), Some(RateLimitExceeded::Bytes));
+    }
+}
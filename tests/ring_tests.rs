@@ -0,0 +1,82 @@
+extern crate rabble;
+
+use std::collections::{HashMap, HashSet};
+use rabble::{NodeId, Ring};
+
+fn node_ids(n: usize) -> HashSet<NodeId> {
+    (0..n).map(|i| NodeId {
+        name: format!("node{}", i),
+        addr: format!("127.0.0.1:{}", 11000 + i)
+    }).collect()
+}
+
+fn owners(ring: &Ring, keys: &[String]) -> Vec<NodeId> {
+    keys.iter().map(|k| ring.get(k.as_bytes()).unwrap().clone()).collect()
+}
+
+#[test]
+fn empty_ring_has_no_owner() {
+    let ring = Ring::new(100);
+    assert_eq!(ring.get(b"key"), None);
+}
+
+#[test]
+fn keys_are_spread_over_all_nodes() {
+    let mut ring = Ring::new(100);
+    ring.set_nodes(&node_ids(5));
+    let keys: Vec<String> = (0..10000).map(|i| format!("key{}", i)).collect();
+    let mut counts = HashMap::new();
+    for owner in owners(&ring, &keys) {
+        *counts.entry(owner).or_insert(0) += 1;
+    }
+    assert_eq!(counts.len(), 5);
+    for (_, count) in counts {
+        // Each node should own roughly 2000 keys
+        assert!(count > 1000 && count < 3000);
+    }
+}
+
+#[test]
+fn adding_a_node_only_moves_keys_to_that_node() {
+    let nodes = node_ids(5);
+    let mut ring = Ring::new(100);
+    ring.set_nodes(&nodes);
+    let keys: Vec<String> = (0..10000).map(|i| format!("key{}", i)).collect();
+    let before = owners(&ring, &keys);
+
+    let new_node = NodeId {name: "node5".to_string(), addr: "127.0.0.1:11005".to_string()};
+    ring.add(new_node.clone());
+    assert!(!ring.set_nodes(&ring.nodes().clone()));
+    let after = owners(&ring, &keys);
+
+    let moved = before.iter().zip(after.iter()).filter(|&(b, a)| b != a).count();
+    assert!(before.iter().zip(after.iter()).all(|(b, a)| b == a || *a == new_node));
+    assert!(moved > 0 && moved < 3000);
+
+    ring.remove(&new_node);
+    assert_eq!(owners(&ring, &keys), before);
+}
+
+#[test]
+fn weights_are_proportional_and_rebalance_incrementally() {
+    let mut weights: HashMap<NodeId, u32> = node_ids(4).into_iter().map(|n| (n, 1)).collect();
+    let mut ring = Ring::new(100);
+    ring.set_weighted_nodes(&weights);
+    let keys: Vec<String> = (0..10000).map(|i| format!("key{}", i)).collect();
+    let before = owners(&ring, &keys);
+
+    let heavy = NodeId {name: "node0".to_string(), addr: "127.0.0.1:11000".to_string()};
+    weights.insert(heavy.clone(), 4);
+    assert!(ring.set_weighted_nodes(&weights));
+    assert_eq!(ring.weight(&heavy), 4);
+    let after = owners(&ring, &keys);
+
+    // Only keys moving to the heavier node change owner
+    assert!(before.iter().zip(after.iter()).all(|(b, a)| b == a || *a == heavy));
+    // The heavy node should own about 4/7 of the keys
+    let owned = after.iter().filter(|&owner| *owner == heavy).count();
+    assert!(owned > 4500 && owned < 7000);
+
+    ring.set_weight(heavy, 1);
+    assert_eq!(owners(&ring, &keys), before);
+}
@@ -33,7 +33,12 @@ const API_SERVER_IP: &'static str  = "127.0.0.1:22001";
 #[test]
 fn chain_replication() {
     let node_id = NodeId {name: "node1".to_string(), addr: CLUSTER_SERVER_IP.to_string()};
-    let test_pid = Pid { name: "test-runner".to_string(), group: None, node: node_id.clone()};
+    let test_pid = Pid {
+        name: "test-runner".to_string(),
+        group: None,
+        node: node_id.clone(),
+        generation: None
+    };
     let (node, mut handles) = rabble::rouse::<RabbleUserMsg>(node_id, None);
 
     let pids = create_replica_pids(&node.id);
@@ -60,12 +65,7 @@ fn shutdown(node: Node<RabbleUserMsg>,
             service_pid: Pid,
             service_tx: Sender<Envelope<RabbleUserMsg>>)
 {
-    let shutdown_envelope = Envelope {
-        to: service_pid,
-        from: test_pid,
-        msg: Msg::Shutdown,
-        correlation_id: None
-    };
+    let shutdown_envelope = Envelope::new(service_pid, test_pid, Msg::Shutdown, None);
     service_tx.send(shutdown_envelope).unwrap();
     node.shutdown();
 
@@ -76,7 +76,8 @@ fn create_replica_pids(node_id: &NodeId) -> Vec<Pid> {
         Pid {
             name: name.to_string(),
             group: None,
-            node: node_id.clone()
+            node: node_id.clone(),
+            generation: None
         }
     }).collect()
 }
@@ -0,0 +1,125 @@
+extern crate rabble;
+
+use std::collections::HashSet;
+use rabble::{ClusterConfig, MemberState, MemberUpdate, NodeId, Swim, SwimMsg};
+
+fn node(name: &str) -> NodeId {
+    NodeId {name: name.to_string(), addr: format!("{}:11000", name)}
+}
+
+fn swim(members: &[&str]) -> Swim {
+    let mut swim = Swim::new(node("me"), ClusterConfig::default());
+    let all: HashSet<NodeId> = members.iter().map(|name| node(name)).collect();
+    swim.set_members(&all);
+    swim
+}
+
+fn update(name: &str, state: MemberState, incarnation: u64) -> MemberUpdate {
+    MemberUpdate {
+        node: node(name),
+        state: state,
+        incarnation: incarnation
+    }
+}
+
+fn updates(msg: &SwimMsg) -> &Vec<MemberUpdate> {
+    match *msg {
+        SwimMsg::Ping {ref updates, ..} |
+        SwimMsg::PingReq {ref updates, ..} |
+        SwimMsg::Ack {ref updates, ..} => updates
+    }
+}
+
+#[test]
+fn pings_are_acked() {
+    let mut swim = swim(&["me", "a"]);
+    let mut output = Vec::new();
+    swim.handle(node("a"), SwimMsg::Ping {seq: 7, updates: Vec::new()}, &mut output);
+    assert_eq!(output.len(), 1);
+    match output[0] {
+        (ref to, SwimMsg::Ack {seq, ..}) => {
+            assert_eq!(*to, node("a"));
+            assert_eq!(seq, 7);
+        },
+        ref msg => panic!("Expected an ack, got {:?}", msg)
+    }
+}
+
+#[test]
+fn ping_reqs_are_relayed_to_the_target_and_acked_to_the_requester() {
+    let mut swim = swim(&["me", "a", "b"]);
+    let mut output = Vec::new();
+    let msg = SwimMsg::PingReq {seq: 3, target: node("b"), updates: Vec::new()};
+    swim.handle(node("a"), msg, &mut output);
+    let relay_seq = match output.pop() {
+        Some((ref to, SwimMsg::Ping {seq, ..})) if *to == node("b") => seq,
+        msg => panic!("Expected a ping to b, got {:?}", msg)
+    };
+    swim.handle(node("b"), SwimMsg::Ack {seq: relay_seq, updates: Vec::new()}, &mut output);
+    match output.pop() {
+        Some((ref to, SwimMsg::Ack {seq, ..})) if *to == node("a") => assert_eq!(seq, 3),
+        msg => panic!("Expected an ack to a, got {:?}", msg)
+    }
+}
+
+#[test]
+fn alive_updates_about_unknown_nodes_are_joins() {
+    let mut swim = swim(&["me", "a"]);
+    let mut output = Vec::new();
+    let msg = SwimMsg::Ping {seq: 1, updates: vec![update("b", MemberState::Alive, 0)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert_eq!(swim.drain_joins(), vec![node("b")]);
+    assert_eq!(swim.state(&node("b")), Some(MemberState::Alive));
+
+    // Suspicions can't introduce members
+    let msg = SwimMsg::Ping {seq: 2, updates: vec![update("c", MemberState::Suspect, 0)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert!(swim.drain_joins().is_empty());
+    assert_eq!(swim.state(&node("c")), None);
+}
+
+#[test]
+fn removed_nodes_are_not_added_back_by_gossip() {
+    let mut swim = swim(&["me", "a", "b"]);
+    let remaining: HashSet<NodeId> = vec![node("me"), node("a")].into_iter().collect();
+    swim.set_members(&remaining);
+    let mut output = Vec::new();
+    let msg = SwimMsg::Ping {seq: 1, updates: vec![update("b", MemberState::Alive, 5)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert!(swim.drain_joins().is_empty());
+    assert_eq!(swim.state(&node("b")), None);
+
+    // Joining explicitly clears the removal
+    swim.join(&node("b"));
+    assert_eq!(swim.state(&node("b")), Some(MemberState::Alive));
+}
+
+#[test]
+fn higher_incarnations_and_stronger_states_win() {
+    let mut swim = swim(&["me", "a", "b"]);
+    let mut output = Vec::new();
+    let msg = SwimMsg::Ping {seq: 1, updates: vec![update("b", MemberState::Suspect, 0)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert_eq!(swim.state(&node("b")), Some(MemberState::Suspect));
+
+    // An alive update with the same incarnation doesn't clear the suspicion, a newer one does
+    let msg = SwimMsg::Ping {seq: 2, updates: vec![update("b", MemberState::Alive, 0)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert_eq!(swim.state(&node("b")), Some(MemberState::Suspect));
+    let msg = SwimMsg::Ping {seq: 3, updates: vec![update("b", MemberState::Alive, 1)]};
+    swim.handle(node("a"), msg, &mut output);
+    assert_eq!(swim.state(&node("b")), Some(MemberState::Alive));
+
+    assert_eq!(swim.drain_changes(),
+               vec![update("b", MemberState::Suspect, 0), update("b", MemberState::Alive, 1)]);
+}
+
+#[test]
+fn suspicions_of_this_node_are_refuted() {
+    let mut swim = swim(&["me", "a"]);
+    let mut output = Vec::new();
+    let msg = SwimMsg::Ping {seq: 1, updates: vec![update("me", MemberState::Suspect, 0)]};
+    swim.handle(node("a"), msg, &mut output);
+    let (_, ref ack) = output[0];
+    assert!(updates(ack).contains(&update("me", MemberState::Alive, 1)));
+}
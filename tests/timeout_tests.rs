@@ -37,7 +37,8 @@ use rabble::{
     Msg,
     Node,
     NodeId,
-    CorrelationId
+    CorrelationId,
+    CallError
 };
 use rabble::serialize::{Serialize, MsgpackSerializer};
 
@@ -52,7 +53,8 @@ fn connection_timeout() {
     let (service_pid, service_tx, service_handle) = api_server::start(node.clone());
     handles.push(service_handle);
 
-    run_client_operation_against_nonexistant_pid_and_wait_for_timeout(node.id.clone());
+    run_client_operation_against_nonexistant_pid_and_wait_for_no_process(node.id.clone());
+    run_client_operation_against_silent_pid_and_wait_for_timeout(&node);
 
     shutdown(node, service_pid, service_tx);
 
@@ -103,7 +105,8 @@ fn process_timeout() {
     let pid = Pid {
         name: "some-process".to_string(),
         group: None,
-        node: node_id
+        node: node_id,
+        generation: None
     };
 
     let (tx, rx) = mpsc::channel();
@@ -125,8 +128,43 @@ fn process_timeout() {
     }
 }
 
-fn run_client_operation_against_nonexistant_pid_and_wait_for_timeout(node_id: NodeId) {
-    let pid = Pid {name: "fake-pid".to_string(), group: None, node: node_id};
+/// A process that never replies, so that requests to it time out
+struct SilentProcess;
+
+impl Process<RabbleUserMsg> for SilentProcess {
+    fn handle(&mut self,
+              _: Msg<RabbleUserMsg>,
+              _: Pid,
+              _: Option<CorrelationId>,
+              _: &mut Vec<Envelope<RabbleUserMsg>>)
+    {
+    }
+}
+
+fn run_client_operation_against_nonexistant_pid_and_wait_for_no_process(node_id: NodeId) {
+    let pid = Pid {name: "fake-pid".to_string(), group: None, node: node_id, generation: None};
+    let mut sock = TcpStream::connect(API_SERVER_IP).unwrap();
+    sock.set_nonblocking(true).unwrap();
+    let mut serializer = MsgpackSerializer::new();
+    send(&mut sock, &mut serializer, ApiClientMsg::Op(pid.clone(), 0));
+    assert_eq!(true, wait_for(Duration::seconds(10), || {
+        if let Ok(Some(ApiClientMsg::Error(CallError::NoProcess(ref to)))) =
+            serializer.read_msg(&mut sock)
+        {
+            return *to == pid;
+        }
+        false
+    }));
+}
+
+fn run_client_operation_against_silent_pid_and_wait_for_timeout(node: &Node<RabbleUserMsg>) {
+    let pid = Pid {
+        name: "silent-pid".to_string(),
+        group: None,
+        node: node.id.clone(),
+        generation: None
+    };
+    node.spawn(&pid, Box::new(SilentProcess)).unwrap();
     let mut sock = TcpStream::connect(API_SERVER_IP).unwrap();
     sock.set_nonblocking(true).unwrap();
     let mut serializer = MsgpackSerializer::new();
@@ -144,13 +182,13 @@ fn shutdown(node: Node<RabbleUserMsg>,
             service_tx: Sender<Envelope<RabbleUserMsg>>)
 {
     // A made up pid to represent the test.
-    let from = Pid {name: "test-runner".to_string(), group: None, node: node.id.clone()};
-    let shutdown_envelope = Envelope {
-        to: service_pid,
-        from: from,
-        msg: Msg::Shutdown,
-        correlation_id: None
+    let from = Pid {
+        name: "test-runner".to_string(),
+        group: None,
+        node: node.id.clone(),
+        generation: None
     };
+    let shutdown_envelope = Envelope::new(service_pid, from, Msg::Shutdown, None);
     service_tx.send(shutdown_envelope).unwrap();
     node.shutdown();
 }
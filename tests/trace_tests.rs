@@ -0,0 +1,34 @@
+extern crate rabble;
+
+use rabble::TraceContext;
+
+const TRACEPARENT: &'static str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn traceparent_round_trips() {
+    let context = TraceContext::from_traceparent(TRACEPARENT).unwrap();
+    assert_eq!(context.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+    assert!(context.is_sampled());
+    assert_eq!(context.to_traceparent(), TRACEPARENT);
+}
+
+#[test]
+fn rejects_invalid_traceparents() {
+    // Uppercase hex digits
+    assert!(TraceContext::from_traceparent(&TRACEPARENT.to_uppercase()).is_none());
+    // All zero trace id
+    assert!(TraceContext::from_traceparent(
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+    // Extra fields in version 00
+    assert!(TraceContext::from_traceparent(&format!("{}-00", TRACEPARENT)).is_none());
+    assert!(TraceContext::from_traceparent("00-4bf92f35-00f067aa0ba902b7-01").is_none());
+}
+
+#[test]
+fn child_spans_share_the_trace() {
+    let root = TraceContext::new(false);
+    let child = root.child();
+    assert_eq!(child.trace_id, root.trace_id);
+    assert!(child.span_id != root.span_id);
+    assert!(!child.is_sampled());
+}
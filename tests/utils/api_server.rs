@@ -27,7 +27,8 @@ pub fn start(node: Node<RabbleUserMsg>)
     let server_pid = Pid {
         name: "api-server".to_string(),
         group: None,
-        node: node.id.clone()
+        node: node.id.clone(),
+        generation: None
     };
 
     // Start the API tcp server
@@ -53,7 +54,7 @@ impl ConnectionHandler for ApiServerConnectionHandler {
     type Msg = RabbleUserMsg;
     type ClientMsg = ApiClientMsg;
 
-    fn new(pid: Pid, id: u64) -> ApiServerConnectionHandler {
+    fn new(pid: Pid, id: u64, _node: &Node<RabbleUserMsg>) -> ApiServerConnectionHandler {
         ApiServerConnectionHandler {
             pid: pid,
             id: id,
@@ -77,6 +78,9 @@ impl ConnectionHandler for ApiServerConnectionHandler {
             Msg::Timeout => {
                 output.push(ConnectionMsg::Client(ApiClientMsg::Timeout, correlation_id));
             },
+            Msg::CallError(error) => {
+                output.push(ConnectionMsg::Client(ApiClientMsg::Error(error), correlation_id));
+            },
             _ => unreachable!()
         }
     }
@@ -1,4 +1,4 @@
-use rabble::Pid;
+use rabble::{Pid, CallError};
 
 // Msg type parameter for messages sent to processes and services
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -17,6 +17,7 @@ pub enum ApiClientMsg {
     OpComplete,
     GetHistory(Pid),
     History(Vec<usize>),
-    Timeout
+    Timeout,
+    Error(CallError)
 }
 
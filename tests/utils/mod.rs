@@ -109,7 +109,8 @@ pub fn test_pid(node_id: NodeId) -> Pid {
     Pid {
         name: "test-runner".to_string(),
         group: None,
-        node: node_id
+        node: node_id,
+        generation: None
     }
 }
 
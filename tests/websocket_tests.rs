@@ -0,0 +1,99 @@
+extern crate rabble;
+
+use rabble::websocket::{accept_key, parse_upgrade, decode_frame, encode_frame, TEXT, PING};
+
+// The sample key and accept value from section 1.3 of RFC 6455
+const SAMPLE_KEY: &'static str = "dGhlIHNhbXBsZSBub25jZQ==";
+const SAMPLE_ACCEPT: &'static str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+// A masked text frame containing "Hello", from section 5.7 of RFC 6455
+const MASKED_HELLO: [u8; 11] = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+
+fn upgrade_request(key: &str) -> String {
+    format!("GET /chat HTTP/1.1\r\n\
+             Host: server.example.com\r\n\
+             Upgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n", key)
+}
+
+/// Mask `payload` with `mask` and frame it as a client would
+fn client_frame(opcode: u8, fin: bool, mask: [u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut frame = encode_frame(opcode, payload);
+    let header = frame.len() - payload.len();
+    if !fin {
+        frame[0] &= 0x7F;
+    }
+    frame[1] |= 0x80;
+    let masked: Vec<u8> = payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+    frame.truncate(header);
+    frame.extend_from_slice(&mask);
+    frame.extend_from_slice(&masked);
+    frame
+}
+
+#[test]
+fn accept_key_matches_rfc_sample() {
+    assert_eq!(accept_key(SAMPLE_KEY), SAMPLE_ACCEPT);
+}
+
+#[test]
+fn upgrade_requests_are_answered_once_complete() {
+    let request = upgrade_request(SAMPLE_KEY);
+    assert!(parse_upgrade(&request.as_bytes()[..request.len() - 2]).unwrap().is_none());
+
+    let mut buf = request.clone().into_bytes();
+    buf.extend_from_slice(&MASKED_HELLO);
+    let (response, len) = parse_upgrade(&buf).unwrap().unwrap();
+    assert_eq!(len, request.len());
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(response.contains(&format!("Sec-WebSocket-Accept: {}\r\n", SAMPLE_ACCEPT)));
+}
+
+#[test]
+fn upgrade_requests_without_websocket_headers_are_rejected() {
+    let request = upgrade_request(SAMPLE_KEY).replace("Sec-WebSocket-Version: 13", "");
+    assert!(parse_upgrade(request.as_bytes()).is_err());
+    let request = upgrade_request(SAMPLE_KEY).replace("GET", "POST");
+    assert!(parse_upgrade(request.as_bytes()).is_err());
+}
+
+#[test]
+fn masked_frames_are_unmasked() {
+    assert!(decode_frame(&MASKED_HELLO[..6], 1024).unwrap().is_none());
+    let (frame, len) = decode_frame(&MASKED_HELLO, 1024).unwrap().unwrap();
+    assert_eq!(len, MASKED_HELLO.len());
+    assert!(frame.fin);
+    assert_eq!(frame.opcode, TEXT);
+    assert_eq!(frame.payload, b"Hello".to_vec());
+}
+
+#[test]
+fn extended_lengths_are_decoded() {
+    let payload = vec![7; 300];
+    let encoded = client_frame(TEXT, true, [1, 2, 3, 4], &payload);
+    let (frame, len) = decode_frame(&encoded, 1024).unwrap().unwrap();
+    assert_eq!(len, encoded.len());
+    assert_eq!(frame.payload, payload);
+}
+
+#[test]
+fn unmasked_frames_are_rejected() {
+    assert!(decode_frame(&encode_frame(TEXT, b"Hello"), 1024).is_err());
+}
+
+#[test]
+fn frames_over_the_size_limit_are_rejected_before_they_arrive() {
+    let encoded = client_frame(TEXT, true, [1, 2, 3, 4], &vec![0; 300]);
+    assert!(decode_frame(&encoded[..4], 100).is_err());
+}
+
+#[test]
+fn control_frames_must_be_short_and_unfragmented() {
+    assert!(decode_frame(&client_frame(PING, false, [1, 2, 3, 4], b"ping"), 1024).is_err());
+    assert!(decode_frame(&client_frame(PING, true, [1, 2, 3, 4], &vec![0; 126]), 1024).is_err());
+    let ping = client_frame(PING, true, [1, 2, 3, 4], b"ping");
+    assert!(decode_frame(&ping, 1024).unwrap().is_some());
+}